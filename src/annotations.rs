@@ -0,0 +1,436 @@
+use crate::convert::svg_text;
+use anyhow::{Context, Result};
+use resvg::usvg::{roxmltree, Size, Transform};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+// Side of the icon of a sticky note, in points
+const NOTE_SIZE: f32 = 24.0;
+
+// Slack for coordinates on the very edge of the page, in points
+const EDGE: f32 = 0.5;
+
+// How an annotation shows on the page
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    // A sticky note icon, the default for points
+    Note,
+    // A box around the area, the default for rects
+    Square,
+    Highlight,
+    // The text written on the page
+    FreeText,
+}
+
+// One comment of the sidecar file, as the review tool exports it.
+// Coordinates are in the user units of the SVG, y pointing down.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    // File name or page id of the page it belongs to
+    pub page: String,
+    // x, y, width and height of the area it is about
+    #[serde(default)]
+    pub rect: Option<[f32; 4]>,
+    #[serde(default)]
+    pub point: Option<[f32; 2]>,
+    #[serde(default)]
+    pub kind: Option<Kind>,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub text: String,
+    // RFC 3339, e.g. 2026-03-01T14:30:00Z
+    #[serde(default)]
+    pub modified: Option<String>,
+}
+
+impl Entry {
+    fn describe(&self) -> String {
+        match &self.author {
+            Some(author) => format!("Annotation by {} for {:?}", author, self.page),
+            None => format!("Annotation for {:?}", self.page),
+        }
+    }
+}
+
+// An annotation placed on its page, in points from the page's top left
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageAnnotation {
+    pub kind: Kind,
+    // left, top, right and bottom
+    pub rect: [f32; 4],
+    pub author: Option<String>,
+    pub contents: String,
+    // PDF date, D:YYYYMMDDHHmmSS with its offset
+    pub modified: Option<String>,
+}
+
+// The entries of a sidecar file, waiting for their pages
+pub struct Annotations {
+    entries: Vec<Entry>,
+}
+
+impl Annotations {
+    // Read a JSON array of entries. Entries without a rect or a point, or
+    // with both, make the file fail to load.
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read(crate::paths::long_path(path))
+            .with_context(|| format!("Failed to read annotations: {:?}", path))?;
+        Self::parse(&json).with_context(|| format!("Invalid annotations file: {:?}", path))
+    }
+
+    pub fn parse(json: &[u8]) -> Result<Self> {
+        let entries: Vec<Entry> = serde_json::from_slice(json)?;
+        for (index, entry) in entries.iter().enumerate() {
+            if entry.rect.is_some() == entry.point.is_some() {
+                anyhow::bail!(
+                    "entry {} for {:?} needs either a rect or a point",
+                    index + 1,
+                    entry.page
+                );
+            }
+        }
+        Ok(Annotations { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // The entries of a page, named by its id or, as review tools know
+    // them, its file name
+    fn of<'a>(&'a self, id: &'a str, path: &'a Path) -> impl Iterator<Item = &'a Entry> + 'a {
+        let name = path.file_name().and_then(|name| name.to_str());
+        self.entries
+            .iter()
+            .filter(move |entry| entry.page == id || Some(entry.page.as_str()) == name)
+    }
+
+    pub fn has(&self, id: &str, path: &Path) -> bool {
+        self.of(id, path).next().is_some()
+    }
+
+    // The entries of page `id` at `path` placed on it, and warnings for
+    // those that aren't. `placement` takes SVG user units to points from
+    // the top left of a `width` by `height` page.
+    pub fn place(
+        &self,
+        id: &str,
+        path: &Path,
+        placement: Transform,
+        width: f32,
+        height: f32,
+    ) -> (Vec<PageAnnotation>, Vec<String>) {
+        let mut placed = Vec::new();
+        let mut warnings = Vec::new();
+        for entry in self.of(id, path) {
+            let map = |x: f32, y: f32| {
+                let mut point = resvg::tiny_skia::Point::from_xy(x, y);
+                placement.map_point(&mut point);
+                (point.x, point.y)
+            };
+            let (left, top, right, bottom, kind) = match (entry.rect, entry.point) {
+                (Some([x, y, w, h]), _) => {
+                    let (x0, y0) = map(x, y);
+                    let (x1, y1) = map(x + w, y + h);
+                    let kind = entry.kind.unwrap_or(Kind::Square);
+                    (x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1), kind)
+                }
+                (None, Some([x, y])) => {
+                    let (x, y) = map(x, y);
+                    (x, y, x, y, entry.kind.unwrap_or(Kind::Note))
+                }
+                (None, None) => continue,
+            };
+            let inside = |value: f32, size: f32| (-EDGE..=size + EDGE).contains(&value);
+            if ![left, right].iter().all(|&x| inside(x, width))
+                || ![top, bottom].iter().all(|&y| inside(y, height))
+            {
+                warnings.push(format!(
+                    "{} skipped, it is off the {:.0}x{:.0} pt page at ({:.0}, {:.0})",
+                    entry.describe(),
+                    width,
+                    height,
+                    left,
+                    top
+                ));
+                continue;
+            }
+            let modified = entry.modified.as_deref().and_then(|modified| {
+                let date = pdf_date(modified);
+                if date.is_none() {
+                    warnings.push(format!(
+                        "{} has a modification date that isn't RFC 3339: {:?}",
+                        entry.describe(),
+                        modified
+                    ));
+                }
+                date
+            });
+            // Notes are an icon at the point, spilling to the left and up
+            // when there is no room for it
+            let rect = match kind {
+                Kind::Note if left == right => {
+                    let left = left.min(width - NOTE_SIZE).max(0.0);
+                    let top = top.min(height - NOTE_SIZE).max(0.0);
+                    [left, top, left + NOTE_SIZE, top + NOTE_SIZE]
+                }
+                _ => [
+                    left.max(0.0),
+                    top.max(0.0),
+                    right.min(width),
+                    bottom.min(height),
+                ],
+            };
+            placed.push(PageAnnotation {
+                kind,
+                rect,
+                author: entry.author.clone(),
+                contents: entry.text.clone(),
+                modified,
+            });
+        }
+        (placed, warnings)
+    }
+
+    // Entries whose page is none of `pages`, given by id and path
+    pub fn unmatched(&self, pages: &[(&str, &Path)]) -> Vec<String> {
+        self.entries
+            .iter()
+            .filter(|entry| {
+                !pages
+                    .iter()
+                    .any(|&(id, path)| self.of(id, path).any(|of| std::ptr::eq(of, *entry)))
+            })
+            .map(|entry| format!("{} matches no page", entry.describe()))
+            .collect()
+    }
+}
+
+// Where the user units of an SVG are on its canvas of `size`, from the
+// root's viewBox and preserveAspectRatio. usvg applies the same but keeps
+// it to itself.
+pub fn view_box_transform(data: &[u8], size: Size) -> Transform {
+    let view_box = || -> Option<Transform> {
+        let text = svg_text(data).ok()?;
+        let options = roxmltree::ParsingOptions {
+            allow_dtd: true,
+            ..roxmltree::ParsingOptions::default()
+        };
+        let doc = roxmltree::Document::parse_with_options(&text, options).ok()?;
+        let root = doc.root_element();
+        let numbers: Vec<f32> = root
+            .attribute("viewBox")?
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|part| !part.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .ok()?;
+        let [x, y, w, h] = numbers[..] else {
+            return None;
+        };
+        if w <= 0.0 || h <= 0.0 {
+            return None;
+        }
+        let (sx, sy) = (size.width() / w, size.height() / h);
+        let aspect = root.attribute("preserveAspectRatio").unwrap_or("xMidYMid");
+        let mut parts = aspect.split_whitespace().filter(|part| *part != "defer");
+        let align = parts.next().unwrap_or("xMidYMid");
+        if align == "none" {
+            return Some(Transform::from_row(sx, 0.0, 0.0, sy, -x * sx, -y * sy));
+        }
+        let scale = match parts.next() {
+            Some("slice") => sx.max(sy),
+            _ => sx.min(sy),
+        };
+        // Where the leftover room goes: none, half or all of it before
+        let share = |axis: &str| {
+            if align.contains(&format!("{axis}Min")) {
+                0.0
+            } else if align.contains(&format!("{axis}Max")) {
+                1.0
+            } else {
+                0.5
+            }
+        };
+        let dx = (size.width() - w * scale) * share("x");
+        let dy = (size.height() - h * scale) * share("Y");
+        Some(Transform::from_row(
+            scale,
+            0.0,
+            0.0,
+            scale,
+            dx - x * scale,
+            dy - y * scale,
+        ))
+    };
+    view_box().unwrap_or_default()
+}
+
+// An RFC 3339 time as a PDF date
+fn pdf_date(time: &str) -> Option<String> {
+    let bytes = time.as_bytes();
+    let digits = |range: std::ops::Range<usize>| {
+        let part = time.get(range)?;
+        part.bytes().all(|b| b.is_ascii_digit()).then_some(part)
+    };
+    let (year, month, day) = (digits(0..4)?, digits(5..7)?, digits(8..10)?);
+    let (hour, minute, second) = (digits(11..13)?, digits(14..16)?, digits(17..19)?);
+    if [4, 7].iter().any(|&at| bytes[at] != b'-')
+        || !matches!(bytes[10], b'T' | b't' | b' ')
+        || [13, 16].iter().any(|&at| bytes[at] != b':')
+    {
+        return None;
+    }
+    // Fractions of a second are dropped
+    let mut rest = &time[19..];
+    if rest.starts_with('.') {
+        rest = rest.trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    }
+    let offset = match rest {
+        "Z" | "z" => "Z".to_string(),
+        _ => {
+            let (sign, offset) = rest.split_at_checked(1)?;
+            let (hours, minutes) = offset.split_once(':')?;
+            let valid = ["+", "-"].contains(&sign)
+                && [hours, minutes]
+                    .iter()
+                    .all(|part| part.len() == 2 && part.bytes().all(|b| b.is_ascii_digit()));
+            if !valid {
+                return None;
+            }
+            format!("{sign}{hours}'{minutes}'")
+        }
+    };
+    Some(format!(
+        "D:{year}{month}{day}{hour}{minute}{second}{offset}"
+    ))
+}
+
+#[test]
+fn test_place_annotations() {
+    let json = br#"[
+        {"page": "slide-1.svg", "point": [10, 10], "author": "Ann", "text": "Typo", "modified": "2026-03-01T14:30:00.250+01:00"},
+        {"page": "deck/slide-1.svg", "rect": [20, 30, 40, 10], "kind": "highlight", "text": "Check"},
+        {"page": "slide-1.svg", "rect": [90, 0, 50, 50], "author": "Bob"},
+        {"page": "slide-1.svg", "point": [99, 74], "modified": "yesterday"},
+        {"page": "missing.svg", "point": [1, 1]}
+    ]"#;
+    let annotations = Annotations::parse(json).unwrap();
+    assert_eq!(annotations.len(), 5);
+    let slide = ("deck/slide-1.svg", Path::new("in/deck/slide-1.svg"));
+    assert!(annotations.has(slide.0, slide.1));
+    assert!(!annotations.has("slide-2.svg", "in/slide-2.svg".as_ref()));
+
+    // A 100x75 drawing scaled by 4 onto a 400x300 pt page
+    let (placed, warnings) = annotations.place(
+        slide.0,
+        slide.1,
+        Transform::from_scale(4.0, 4.0),
+        400.0,
+        300.0,
+    );
+    assert_eq!(
+        placed,
+        [
+            PageAnnotation {
+                kind: Kind::Note,
+                rect: [40.0, 40.0, 64.0, 64.0],
+                author: Some("Ann".to_string()),
+                contents: "Typo".to_string(),
+                modified: Some("D:20260301143000+01'00'".to_string()),
+            },
+            PageAnnotation {
+                kind: Kind::Highlight,
+                rect: [80.0, 120.0, 240.0, 160.0],
+                author: None,
+                contents: "Check".to_string(),
+                modified: None,
+            },
+            // Notes near the edge keep their icon on the page
+            PageAnnotation {
+                kind: Kind::Note,
+                rect: [376.0, 276.0, 400.0, 300.0],
+                author: None,
+                contents: String::new(),
+                modified: None,
+            },
+        ]
+    );
+    assert_eq!(
+        warnings,
+        [
+            "Annotation by Bob for \"slide-1.svg\" skipped, it is off the 400x300 pt page at (360, 0)",
+            "Annotation for \"slide-1.svg\" has a modification date that isn't RFC 3339: \"yesterday\""
+        ]
+    );
+    assert_eq!(
+        annotations.unmatched(&[slide]),
+        ["Annotation for \"missing.svg\" matches no page"]
+    );
+
+    for invalid in [
+        &br#"[{"page": "a.svg"}]"#[..],
+        br#"[{"page": "a.svg", "point": [1, 2], "rect": [1, 2, 3, 4]}]"#,
+        br#"[{"page": "a.svg", "point": [1, 2], "colour": "red"}]"#,
+        br#"{"page": "a.svg"}"#,
+    ] {
+        assert!(Annotations::parse(invalid).is_err());
+    }
+    assert_eq!(
+        pdf_date("2026-03-01T14:30:00Z").as_deref(),
+        Some("D:20260301143000Z")
+    );
+    assert_eq!(
+        pdf_date("2026-03-01 14:30:00-05:30").as_deref(),
+        Some("D:20260301143000-05'30'")
+    );
+    for time in [
+        "2026-03-01",
+        "2026-03-01T14:30:00",
+        "2026-03-01T14:30:00+1",
+        "20260301T143000Z",
+    ] {
+        assert_eq!(pdf_date(time), None, "{time}");
+    }
+
+    // viewBox units onto the canvas, as usvg draws them
+    let size = Size::from_wh(200.0, 100.0).unwrap();
+    let svg = |attributes: &str| {
+        format!(r#"<svg xmlns="http://www.w3.org/2000/svg" {attributes}/>"#).into_bytes()
+    };
+    let map = |attributes: &str, x: f32, y: f32| {
+        let mut point = resvg::tiny_skia::Point::from_xy(x, y);
+        view_box_transform(&svg(attributes), size).map_point(&mut point);
+        (point.x, point.y)
+    };
+    assert_eq!(map(r#"width="200" height="100""#, 5.0, 5.0), (5.0, 5.0));
+    assert_eq!(map(r#"viewBox="10 10 50 50""#, 10.0, 60.0), (50.0, 100.0));
+    assert_eq!(
+        map(
+            r#"viewBox="0,0,50,50" preserveAspectRatio="xMinYMin""#,
+            50.0,
+            0.0
+        ),
+        (100.0, 0.0)
+    );
+    assert_eq!(
+        map(
+            r#"viewBox="0 0 50 50" preserveAspectRatio="none""#,
+            50.0,
+            50.0
+        ),
+        (200.0, 100.0)
+    );
+    assert_eq!(
+        map(
+            r#"viewBox="0 0 50 50" preserveAspectRatio="xMaxYMax slice""#,
+            0.0,
+            50.0
+        ),
+        (0.0, 100.0)
+    );
+}
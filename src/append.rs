@@ -0,0 +1,271 @@
+use crate::paths;
+use anyhow::{anyhow, Context, Result};
+use lopdf::xref::XrefType;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// A PDF that --append-to puts the new pages into. It is loaded before
+// anything renders, so a document that can't take them fails the run early,
+// and it may well be the output file that is about to be replaced.
+pub struct Existing {
+    path: PathBuf,
+    document: Document,
+    // Root of the page tree, which the new pages become kids of
+    root: ObjectId,
+    // Index among the kids of the root the new pages go at; None puts them
+    // after the last
+    at: Option<usize>,
+    pages: usize,
+}
+
+impl Existing {
+    // Load the PDF at `path`, to put the new pages before its page
+    // `insert_at`, counted from 1, or after its last page
+    pub fn load(path: &Path, insert_at: Option<usize>) -> Result<Existing> {
+        let document = Document::load(paths::long_path(path))
+            .with_context(|| format!("Failed to load PDF to append to: {:?}", path))?;
+        if document.is_encrypted() {
+            anyhow::bail!("{:?} is encrypted, pages can't be added to it", path);
+        }
+        let root = document
+            .catalog()
+            .and_then(|catalog| catalog.get(b"Pages"))
+            .and_then(Object::as_reference)
+            .map_err(|_| anyhow!("{:?} has no page tree", path))?;
+        let kids = kids(&document, root).map_err(|err| anyhow!("{:?} {}", path, err))?;
+        let pages = document.get_pages().len();
+
+        // Appended pages only add to the root, however deep the tree is.
+        // Inserted ones would have to go into whichever node holds the page
+        // they go before, and every node above it would count them.
+        if let Some(at) = insert_at {
+            let nested = kids.iter().any(|&kid| {
+                document
+                    .get_dictionary(kid)
+                    .is_ok_and(|kid| !is_name(kid, b"Type", b"Page"))
+            });
+            if nested {
+                anyhow::bail!(
+                    "{:?} has nested page tree nodes, which --insert-at can't splice into; leave it out to append the pages at the end",
+                    path
+                );
+            }
+            if at == 0 || at > pages + 1 {
+                anyhow::bail!(
+                    "--insert-at {} is outside {:?}, which has {} pages; pass 1 to {}",
+                    at,
+                    path,
+                    pages,
+                    pages + 1
+                );
+            }
+        }
+        Ok(Existing {
+            path: path.to_path_buf(),
+            document,
+            root,
+            at: insert_at.map(|at| at - 1),
+            pages,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+
+    // Pages of the document that come before the new ones
+    pub fn pages_before(&self) -> usize {
+        self.at.unwrap_or(self.pages)
+    }
+
+    // Write the document with the pages of the PDF `new` put in to `out`.
+    // Only what the new pages use comes along; the catalog, document info
+    // and outline of `new` are left behind for those of the document.
+    pub fn merge(&self, new: &[u8], out: &mut dyn Write) -> Result<()> {
+        let context = || format!("Failed to add the pages to {:?}", self.path);
+        let mut document = self.document.clone();
+        let mut added = Document::load_mem(new).with_context(context)?;
+        // Ids above any of the document's, so nothing is overwritten
+        added.renumber_objects_with(document.max_id + 1);
+        let pages: Vec<ObjectId> = added.get_pages().into_values().collect();
+
+        // Pages hang off the root, and inherit what it sets unless they set
+        // it themselves. Ours never rotate or crop.
+        let rotate = self.root_has(b"Rotate");
+        let crop_box = self.root_has(b"CropBox");
+        for &page in &pages {
+            let page = added.get_dictionary_mut(page).with_context(context)?;
+            page.set("Parent", Object::Reference(self.root));
+            if rotate && !page.has(b"Rotate") {
+                page.set("Rotate", Object::Integer(0));
+            }
+            if crop_box && !page.has(b"CropBox") {
+                if let Ok(media_box) = page.get(b"MediaBox").cloned() {
+                    page.set("CropBox", media_box);
+                }
+            }
+        }
+
+        // Move over the objects reachable from the pages. The root is the
+        // document's, so it is never found among those of `added`.
+        let mut wanted = pages.clone();
+        let mut moved = HashSet::new();
+        while let Some(id) = wanted.pop() {
+            if !moved.insert(id) {
+                continue;
+            }
+            if let Some(object) = added.objects.remove(&id) {
+                references(&object, &mut wanted);
+                document.objects.insert(id, object);
+            }
+        }
+        document.max_id = document.max_id.max(added.max_id);
+
+        let root = document
+            .get_dictionary_mut(self.root)
+            .with_context(context)?;
+        let mut kids = match root.get(b"Kids").and_then(Object::as_array) {
+            Ok(kids) => kids.clone(),
+            Err(_) => Vec::new(),
+        };
+        let at = self.at.unwrap_or(kids.len()).min(kids.len());
+        kids.splice(at..at, pages.iter().copied().map(Object::Reference));
+        let count = root.get(b"Count").and_then(Object::as_i64).unwrap_or(0);
+        root.set("Kids", Object::Array(kids));
+        root.set("Count", Object::Integer(count + pages.len() as i64));
+
+        // The new pages may use what needs PDF 1.5, as our own documents
+        // declare. The document is written out whole, so a trailer of
+        // incremental updates or a cross-reference stream would point at
+        // offsets of the old file.
+        if document.version.as_str() < "1.5" {
+            document.version = "1.5".to_string();
+        }
+        let keep = [b"Root".as_slice(), b"Info", b"ID"];
+        document.trailer = Dictionary::from_iter(
+            document
+                .trailer
+                .iter()
+                .filter(|(key, _)| keep.contains(&key.as_slice()))
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
+        document.reference_table.cross_reference_type = XrefType::CrossReferenceTable;
+        let mut out = out;
+        document.save_to(&mut out).with_context(context)
+    }
+
+    fn root_has(&self, key: &[u8]) -> bool {
+        self.document
+            .get_dictionary(self.root)
+            .is_ok_and(|root| root.has(key))
+    }
+}
+
+// The kids of the page tree node `node`
+fn kids(document: &Document, node: ObjectId) -> Result<Vec<ObjectId>, &'static str> {
+    let node = document
+        .get_dictionary(node)
+        .map_err(|_| "has a missing page tree")?;
+    if !is_name(node, b"Type", b"Pages") {
+        return Err("has a page tree root that is not of type Pages");
+    }
+    node.get(b"Kids")
+        .and_then(Object::as_array)
+        .map_err(|_| "has a page tree root without kids")?
+        .iter()
+        .map(|kid| {
+            kid.as_reference()
+                .map_err(|_| "has a page that is no reference")
+        })
+        .collect()
+}
+
+fn is_name(dict: &Dictionary, key: &[u8], name: &[u8]) -> bool {
+    dict.get(key).and_then(Object::as_name).ok() == Some(name)
+}
+
+// Every object `object` refers to, pushed onto `ids`
+fn references(object: &Object, ids: &mut Vec<ObjectId>) {
+    match object {
+        Object::Reference(id) => ids.push(*id),
+        Object::Array(items) => items.iter().for_each(|item| references(item, ids)),
+        Object::Dictionary(dict) => dict.iter().for_each(|(_, value)| references(value, ids)),
+        Object::Stream(stream) => stream
+            .dict
+            .iter()
+            .for_each(|(_, value)| references(value, ids)),
+        _ => {}
+    }
+}
+
+#[test]
+fn test_append_pages() {
+    use crate::convert::RenderedImage;
+    use crate::verify;
+    use crate::writer::{ContainerWriter, Format, ImageOptions, PdfWriter};
+
+    // A PDF of pages `widths` wide, each one high
+    let pdf = |widths: &[u32]| {
+        let mut writer = Box::new(PdfWriter::new(1.0, ImageOptions::default()));
+        for &width in widths {
+            let page = RenderedImage {
+                width,
+                height: 1,
+                rgb_data: vec![128; width as usize * 3],
+                alpha: None,
+            };
+            let page = writer.encoder().encode(&page, "page.svg").unwrap();
+            writer.add_page(page).unwrap();
+        }
+        let mut pdf = Vec::new();
+        writer.finish(&mut pdf).unwrap();
+        pdf
+    };
+    let widths = |pdf: &[u8]| -> Vec<f32> {
+        let doc = Document::load_mem(pdf).unwrap();
+        doc.get_pages()
+            .into_values()
+            .map(|page| {
+                let dict = doc.get_dictionary(page).unwrap();
+                let media_box = dict.get(b"MediaBox").unwrap().as_array().unwrap();
+                media_box[2].as_float().unwrap()
+            })
+            .collect()
+    };
+
+    let dir = std::env::temp_dir().join(format!("svg2pdf-append-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("existing.pdf");
+    std::fs::write(&path, pdf(&[10, 20, 30])).unwrap();
+
+    // Spliced in before the second page, and counted by the root
+    let existing = Existing::load(&path, Some(2)).unwrap();
+    assert_eq!(existing.pages(), 3);
+    let mut merged = Vec::new();
+    existing.merge(&pdf(&[40, 50]), &mut merged).unwrap();
+    assert_eq!(widths(&merged), [10.0, 40.0, 50.0, 20.0, 30.0]);
+    assert_eq!(verify::check(Format::Pdf, &merged, 5), Vec::<String>::new());
+    assert!(Existing::load(&path, Some(5)).is_err());
+
+    // Past 32 pages the tree nests, which only takes pages at the end
+    std::fs::write(&path, pdf(&[10; 40])).unwrap();
+    let error = Existing::load(&path, Some(1)).err().unwrap().to_string();
+    assert!(error.contains("nested page tree nodes"), "{error}");
+    let mut merged = Vec::new();
+    Existing::load(&path, None)
+        .unwrap()
+        .merge(&pdf(&[40]), &mut merged)
+        .unwrap();
+    assert_eq!(widths(&merged).last(), Some(&40.0));
+    assert_eq!(
+        verify::check(Format::Pdf, &merged, 41),
+        Vec::<String>::new()
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
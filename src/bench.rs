@@ -0,0 +1,303 @@
+use crate::convert::{self, RenderArgs, RunOptions};
+use crate::inputs::Inputs;
+use anyhow::{Context, Result};
+use clap::Args;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+#[derive(Args)]
+pub struct BenchArgs {
+    /// Number of SVG files to generate
+    #[arg(long, default_value = "50")]
+    pub files: usize,
+
+    /// Path segments per generated file
+    #[arg(long, default_value = "200")]
+    pub complexity: usize,
+
+    /// Text elements per generated file
+    #[arg(long, default_value = "0")]
+    pub text: usize,
+
+    /// Fill shapes with linear and radial gradients
+    #[arg(long)]
+    pub gradients: bool,
+
+    /// Seed for the corpus generator
+    #[arg(long, default_value = "1")]
+    pub seed: u64,
+
+    /// Number of measured runs
+    #[arg(long, default_value = "5")]
+    pub runs: usize,
+
+    /// Number of unmeasured warm-up runs
+    #[arg(long, default_value = "1")]
+    pub warmup: usize,
+
+    /// Keep the generated corpus instead of deleting it afterwards
+    #[arg(long)]
+    pub keep_corpus: bool,
+
+    #[command(flatten)]
+    pub render: RenderArgs,
+}
+
+// Parameters of the synthetic corpus
+pub struct CorpusSpec {
+    pub files: usize,
+    pub complexity: usize,
+    pub text: usize,
+    pub gradients: bool,
+    pub seed: u64,
+}
+
+// Small deterministic generator (SplitMix64) so corpora are reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform value in [0, max)
+    fn below(&mut self, max: u32) -> u32 {
+        (self.next_u64() % max as u64) as u32
+    }
+}
+
+const WORDS: [&str; 8] = [
+    "lorem",
+    "ipsum",
+    "dolor",
+    "sit",
+    "amet",
+    "consectetur",
+    "adipiscing",
+    "elit",
+];
+
+// Generate the SVG source for file number `index` of the corpus
+pub fn generate_svg(spec: &CorpusSpec, index: usize) -> String {
+    let mut rng = Rng(spec.seed ^ (index as u64).wrapping_mul(0xA076_1D64_78BD_642F));
+    let (width, height) = (960, 720);
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#
+    );
+
+    let color = |rng: &mut Rng| format!("#{:06x}", rng.below(0x100_0000));
+
+    if spec.gradients {
+        svg.push_str("<defs>");
+        for i in 0..4 {
+            let (a, b) = (color(&mut rng), color(&mut rng));
+            if i % 2 == 0 {
+                let _ = write!(
+                    svg,
+                    r#"<linearGradient id="g{i}" x1="0" y1="0" x2="1" y2="1"><stop offset="0" stop-color="{a}"/><stop offset="1" stop-color="{b}"/></linearGradient>"#
+                );
+            } else {
+                let _ = write!(
+                    svg,
+                    r#"<radialGradient id="g{i}"><stop offset="0" stop-color="{a}"/><stop offset="1" stop-color="{b}"/></radialGradient>"#
+                );
+            }
+        }
+        svg.push_str("</defs>");
+    }
+
+    // Split the requested segment count into paths of up to 20 segments
+    let mut remaining = spec.complexity;
+    let mut path_index = 0;
+    while remaining > 0 {
+        let segments = remaining.min(20);
+        remaining -= segments;
+
+        let mut d = format!("M{} {}", rng.below(width), rng.below(height));
+        for _ in 0..segments {
+            if rng.below(2) == 0 {
+                let _ = write!(d, " L{} {}", rng.below(width), rng.below(height));
+            } else {
+                let _ = write!(
+                    d,
+                    " C{} {} {} {} {} {}",
+                    rng.below(width),
+                    rng.below(height),
+                    rng.below(width),
+                    rng.below(height),
+                    rng.below(width),
+                    rng.below(height)
+                );
+            }
+        }
+        d.push_str(" Z");
+
+        let fill = if spec.gradients {
+            format!("url(#g{})", path_index % 4)
+        } else {
+            color(&mut rng)
+        };
+        let _ = write!(
+            svg,
+            r#"<path d="{d}" fill="{fill}" fill-opacity="0.6" stroke="{}" stroke-width="{}"/>"#,
+            color(&mut rng),
+            1 + rng.below(4)
+        );
+        path_index += 1;
+    }
+
+    for _ in 0..spec.text {
+        let words: Vec<&str> = (0..3 + rng.below(6))
+            .map(|_| WORDS[rng.below(WORDS.len() as u32) as usize])
+            .collect();
+        let _ = write!(
+            svg,
+            r#"<text x="{}" y="{}" font-family="sans-serif" font-size="{}" fill="{}">{}</text>"#,
+            rng.below(width / 2),
+            20 + rng.below(height - 20),
+            10 + rng.below(30),
+            color(&mut rng),
+            words.join(" ")
+        );
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+// Write the corpus into `dir`, returning the total number of bytes
+fn write_corpus(spec: &CorpusSpec, dir: &Path) -> Result<u64> {
+    let mut total = 0;
+    for index in 0..spec.files {
+        let svg = generate_svg(spec, index);
+        let path = dir.join(format!("bench-{:05}.svg", index + 1));
+        fs::write(&path, &svg).with_context(|| format!("Failed to write {:?}", path))?;
+        total += svg.len() as u64;
+    }
+    Ok(total)
+}
+
+// Mean and sample standard deviation
+fn mean_stddev(samples: &[f64]) -> (f64, f64) {
+    let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+    if samples.len() < 2 {
+        return (mean, 0.0);
+    }
+    let variance =
+        samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (samples.len() - 1) as f64;
+    (mean, variance.sqrt())
+}
+
+pub fn run(args: &BenchArgs) -> Result<()> {
+    if args.files == 0 || args.runs == 0 {
+        anyhow::bail!("--files and --runs must be at least 1");
+    }
+
+    let spec = CorpusSpec {
+        files: args.files,
+        complexity: args.complexity,
+        text: args.text,
+        gradients: args.gradients,
+        seed: args.seed,
+    };
+
+    // Generate the corpus in a fresh temp dir
+    let dir: PathBuf = std::env::temp_dir().join(format!(
+        "svg2pdf-bench-{}-{}",
+        args.seed,
+        std::process::id()
+    ));
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    let corpus_bytes = write_corpus(&spec, &dir)?;
+    println!(
+        "Generated {} files ({:.2} MB) in {:?} (seed {}, complexity {}, text {}, gradients {})",
+        spec.files,
+        corpus_bytes as f64 / 1e6,
+        dir,
+        spec.seed,
+        spec.complexity,
+        spec.text,
+        spec.gradients
+    );
+
+    let output = dir.join("bench-output.pdf");
+    let inputs = Inputs::dir(&dir);
+    let opt = convert::load_options();
+    let result = (|| -> Result<Vec<f64>> {
+        for run in 0..args.warmup {
+            let started = Instant::now();
+            convert::convert_dir(&opt, &inputs, &output, &args.render, &RunOptions::default())?;
+            println!(
+                "warm-up {}/{}: {:.3} s",
+                run + 1,
+                args.warmup,
+                started.elapsed().as_secs_f64()
+            );
+        }
+
+        let mut samples = Vec::with_capacity(args.runs);
+        for run in 0..args.runs {
+            let started = Instant::now();
+            convert::convert_dir(&opt, &inputs, &output, &args.render, &RunOptions::default())?;
+            let seconds = started.elapsed().as_secs_f64();
+            println!("run {}/{}: {:.3} s", run + 1, args.runs, seconds);
+            samples.push(seconds);
+        }
+        Ok(samples)
+    })();
+
+    if args.keep_corpus {
+        println!("Corpus kept in {:?}", dir);
+    } else {
+        let _ = fs::remove_dir_all(&dir);
+    }
+    let samples = result?;
+
+    let (mean, stddev) = mean_stddev(&samples);
+    let pages_per_sec: Vec<f64> = samples.iter().map(|s| spec.files as f64 / s).collect();
+    let mb_per_sec: Vec<f64> = samples
+        .iter()
+        .map(|s| corpus_bytes as f64 / 1e6 / s)
+        .collect();
+    let (pages_mean, pages_stddev) = mean_stddev(&pages_per_sec);
+    let (mb_mean, mb_stddev) = mean_stddev(&mb_per_sec);
+
+    println!("time:       {:.3} s ± {:.3} s", mean, stddev);
+    println!(
+        "throughput: {:.1} ± {:.1} pages/s",
+        pages_mean, pages_stddev
+    );
+    println!("            {:.2} ± {:.2} MB/s", mb_mean, mb_stddev);
+    Ok(())
+}
+
+#[test]
+fn test_generator_is_deterministic() {
+    let spec = CorpusSpec {
+        files: 2,
+        complexity: 45,
+        text: 3,
+        gradients: true,
+        seed: 7,
+    };
+    assert_eq!(generate_svg(&spec, 0), generate_svg(&spec, 0));
+    assert_ne!(generate_svg(&spec, 0), generate_svg(&spec, 1));
+
+    let other = CorpusSpec { seed: 8, ..spec };
+    assert_ne!(
+        generate_svg(&other, 0),
+        generate_svg(&CorpusSpec { seed: 7, ..other }, 0)
+    );
+
+    // The generated markup must parse
+    let svg = generate_svg(&CorpusSpec { seed: 7, ..other }, 1);
+    assert_eq!(svg.matches("<path").count(), 3);
+    assert_eq!(svg.matches("<text").count(), 3);
+    resvg::usvg::Tree::from_str(&svg, &resvg::usvg::Options::default()).expect("valid SVG");
+}
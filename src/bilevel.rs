@@ -0,0 +1,376 @@
+// 1-bit pages of --color-mode bilevel, stored as CCITT Group 4 in both PDF
+// (CCITTFaxDecode) and TIFF (compression 4). Archives of scanned paper
+// expect them, and text and line art shrink to a fraction of 8-bit RGB.
+
+use crate::convert::RenderedImage;
+use clap::ValueEnum;
+use fax::{Color, VecWriter};
+
+// Pixels at least this bright turn white, darker ones black
+pub const THRESHOLD: u8 = 128;
+
+// How grays become black and white. Thresholding keeps text and lines
+// crisp but turns gradients into bands; dithering mixes black and white
+// dots so an area keeps its gray on average.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dither {
+    // Split at THRESHOLD
+    #[default]
+    None,
+    // Each pixel passes what it was rounded by on to its neighbors right
+    // and below
+    FloydSteinberg,
+    // Thresholds vary over an 8x8 Bayer matrix, giving a regular pattern
+    // that stays the same from page to page
+    Ordered,
+}
+
+// Order in which the pixels of an 8x8 tile turn white as gray rises
+const BAYER: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+// Pixels of a page packed 8 to a byte, the leftmost in the highest bit and
+// a set bit black. Every row starts on a byte of its own, the bits past
+// the width left clear.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bitmap {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl Bitmap {
+    pub fn new(width: u32, height: u32) -> Self {
+        Bitmap {
+            width,
+            height,
+            data: vec![0; stride(width) * height as usize],
+        }
+    }
+
+    pub fn is_black(&self, x: u32, y: u32) -> bool {
+        let (byte, bit) = locate(self.width, x, y);
+        self.data[byte] & bit != 0
+    }
+
+    pub fn set_black(&mut self, x: u32, y: u32) {
+        let (byte, bit) = locate(self.width, x, y);
+        self.data[byte] |= bit;
+    }
+
+    // The colors of row `y`, left to right
+    fn row(&self, y: u32) -> impl Iterator<Item = Color> + '_ {
+        (0..self.width).map(move |x| match self.is_black(x, y) {
+            true => Color::Black,
+            false => Color::White,
+        })
+    }
+}
+
+// Bytes a packed row of `width` pixels takes
+pub fn stride(width: u32) -> usize {
+    (width as usize).div_ceil(8)
+}
+
+// The byte of pixel `x`, `y` and the bit of it within that byte
+fn locate(width: u32, x: u32, y: u32) -> (usize, u8) {
+    let byte = y as usize * stride(width) + x as usize / 8;
+    (byte, 0x80 >> (x % 8))
+}
+
+// Brightness of an RGB pixel, with the weights of ITU-R BT.601 in
+// thousandths
+pub fn luma(rgb: &[u8]) -> u8 {
+    let sum = 299 * rgb[0] as u32 + 587 * rgb[1] as u32 + 114 * rgb[2] as u32;
+    ((sum + 500) / 1000) as u8
+}
+
+// Brightness `luma` at opacity `alpha` over white
+fn on_white(luma: u8, alpha: u8) -> u8 {
+    let (luma, alpha) = (luma as u32, alpha as u32);
+    ((luma * alpha + 255 * (255 - alpha) + 127) / 255) as u8
+}
+
+// The color a page of the single RGB `color` becomes, black or white, or
+// None where dithering turns it into a pattern of both
+pub fn fill_color(color: [u8; 3], dither: Dither) -> Option<[u8; 3]> {
+    match (luma(&color), dither) {
+        (0, _) => Some([0; 3]),
+        (255, _) => Some([255; 3]),
+        (gray, Dither::None) if gray < THRESHOLD => Some([0; 3]),
+        (_, Dither::None) => Some([255; 3]),
+        _ => None,
+    }
+}
+
+// Every pixel of `image` black or white as `dither` has it. Transparent
+// pixels are put on white first.
+pub fn to_bitmap(image: &RenderedImage, dither: Dither) -> Bitmap {
+    let gray: Vec<u8> = image
+        .rgb_data
+        .chunks_exact(3)
+        .enumerate()
+        .map(|(index, pixel)| {
+            let alpha = image.alpha.as_ref().map_or(255, |alpha| alpha[index]);
+            on_white(luma(pixel), alpha)
+        })
+        .collect();
+    let mut bitmap = Bitmap::new(image.width, image.height);
+    match dither {
+        Dither::None => pack(&gray, &mut bitmap, |_, _| THRESHOLD),
+        Dither::Ordered => pack(&gray, &mut bitmap, ordered_threshold),
+        Dither::FloydSteinberg => diffuse(&gray, &mut bitmap),
+    }
+    bitmap
+}
+
+// Set the pixels of `bitmap` darker than the threshold at their place
+fn pack(gray: &[u8], bitmap: &mut Bitmap, threshold: impl Fn(u32, u32) -> u8) {
+    let width = bitmap.width.max(1) as usize;
+    for (index, &gray) in gray.iter().enumerate() {
+        let (x, y) = ((index % width) as u32, (index / width) as u32);
+        if gray < threshold(x, y) {
+            bitmap.set_black(x, y);
+        }
+    }
+}
+
+// Spread evenly over 2 to 254, so black stays black and white white
+fn ordered_threshold(x: u32, y: u32) -> u8 {
+    BAYER[y as usize % 8][x as usize % 8] * 4 + 2
+}
+
+// Floyd-Steinberg error diffusion, left to right on every row. Errors are
+// kept in sixteenths, the unit of its weights, and what a pixel adds up to
+// is clamped to 0-255, so errors don't pile up along hard edges. They are
+// carried over the whole page, which is rendered in one piece, so there
+// are no tile or strip seams for them to stop at.
+fn diffuse(gray: &[u8], bitmap: &mut Bitmap) {
+    let width = bitmap.width as usize;
+    // Errors for this row and the next, with a pixel of room either side
+    let mut current = vec![0i32; width + 2];
+    let mut below = vec![0i32; width + 2];
+    for (y, row) in gray.chunks_exact(width.max(1)).enumerate() {
+        for (x, &gray) in row.iter().enumerate() {
+            let value = (gray as i32 + current[x + 1].div_euclid(16)).clamp(0, 255);
+            let black = value < THRESHOLD as i32;
+            if black {
+                bitmap.set_black(x as u32, y as u32);
+            }
+            let error = value - if black { 0 } else { 255 };
+            current[x + 2] += error * 7;
+            below[x] += error * 3;
+            below[x + 1] += error * 5;
+            below[x + 2] += error;
+        }
+        std::mem::swap(&mut current, &mut below);
+        below.fill(0);
+    }
+}
+
+// CCITT Group 4 (T.6) coding of `bitmap`, ending in the end-of-block code
+pub fn encode_g4(bitmap: &Bitmap) -> Vec<u8> {
+    let mut encoder = fax::encoder::Encoder::new(VecWriter::with_capacity(bitmap.data.len()));
+    for y in 0..bitmap.height {
+        let Ok(()) = encoder.encode_line(bitmap.row(y), bitmap.width);
+    }
+    let Ok(writer) = encoder.finish();
+    writer.finish()
+}
+
+// Undo encode_g4, None unless `data` holds `height` rows
+pub fn decode_g4(data: &[u8], width: u32, height: u32) -> Option<Bitmap> {
+    let mut bitmap = Bitmap::new(width, height);
+    let mut y = 0;
+    fax::decoder::decode_g4(data.iter().copied(), width, Some(height), |transitions| {
+        if y < height {
+            for (x, color) in fax::decoder::pels(transitions, width).enumerate() {
+                if color == Color::Black {
+                    bitmap.set_black(x as u32, y);
+                }
+            }
+        }
+        y += 1;
+    })?;
+    (y == height).then_some(bitmap)
+}
+
+#[test]
+fn test_rows_are_packed_to_whole_bytes() {
+    assert_eq!(stride(1), 1);
+    assert_eq!(stride(8), 1);
+    assert_eq!(stride(9), 2);
+    assert_eq!(locate(10, 0, 0), (0, 0x80));
+    assert_eq!(locate(10, 7, 0), (0, 0x01));
+    assert_eq!(locate(10, 8, 0), (1, 0x80));
+    assert_eq!(locate(10, 0, 1), (2, 0x80));
+    assert_eq!(locate(10, 9, 2), (5, 0x40));
+
+    // Black, white, black from the left of 10-pixel rows; the pad bits stay
+    // clear whatever the dithering
+    let image = RenderedImage {
+        width: 10,
+        height: 2,
+        rgb_data: [[0, 0, 0], [255; 3], [0, 0, 0]]
+            .into_iter()
+            .chain(std::iter::repeat_n([255; 3], 6))
+            .chain([[0, 0, 0]])
+            .cycle()
+            .take(20)
+            .flatten()
+            .collect(),
+        alpha: None,
+    };
+    for dither in [Dither::None, Dither::FloydSteinberg, Dither::Ordered] {
+        let bitmap = to_bitmap(&image, dither);
+        assert_eq!(
+            bitmap.data,
+            [0b1010_0000, 0b0100_0000, 0b1010_0000, 0b0100_0000],
+            "{dither:?}"
+        );
+    }
+
+    // 13 pixels of mid gray leave the last 3 bits of every row clear
+    let image = RenderedImage {
+        width: 13,
+        height: 4,
+        rgb_data: vec![128; 13 * 4 * 3],
+        alpha: None,
+    };
+    for dither in [Dither::FloydSteinberg, Dither::Ordered] {
+        let bitmap = to_bitmap(&image, dither);
+        assert_eq!(bitmap.data.len(), 8);
+        assert!(bitmap
+            .data
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .all(|byte| byte & 0b111 == 0));
+        assert!(bitmap.data.iter().any(|&byte| byte != 0), "{dither:?}");
+    }
+}
+
+#[test]
+fn test_ordered_dithering() {
+    // Every place of the tile has a threshold of its own
+    let mut order: Vec<u8> = BAYER.iter().flatten().copied().collect();
+    order.sort();
+    assert_eq!(order, (0..64).collect::<Vec<u8>>());
+    assert_eq!(ordered_threshold(0, 0), 2);
+    assert_eq!(ordered_threshold(8, 11), ordered_threshold(0, 3));
+    assert_eq!(ordered_threshold(4, 3), 250);
+
+    // n of the 64 pixels of a tile are below a gray just above the nth
+    // threshold, and turn black
+    let tile = |gray: u8| {
+        let image = RenderedImage {
+            width: 8,
+            height: 8,
+            rgb_data: vec![gray; 8 * 8 * 3],
+            alpha: None,
+        };
+        let bitmap = to_bitmap(&image, Dither::Ordered);
+        bitmap
+            .data
+            .iter()
+            .map(|byte| byte.count_ones())
+            .sum::<u32>()
+    };
+    assert_eq!(tile(0), 64);
+    assert_eq!(tile(128), 32);
+    assert_eq!(tile(192), 16);
+    assert_eq!(tile(255), 0);
+}
+
+#[test]
+fn test_dithering_keeps_mid_gray() {
+    let (width, height) = (64, 64);
+    let image = RenderedImage {
+        width,
+        height,
+        rgb_data: vec![128; (width * height * 3) as usize],
+        alpha: None,
+    };
+    let black = |bitmap: &Bitmap| {
+        let rows = 0..height;
+        rows.map(|y| (0..width).filter(|&x| bitmap.is_black(x, y)).count())
+            .collect::<Vec<_>>()
+    };
+    // Mid gray is just bright enough to threshold to white all over
+    let plain = to_bitmap(&image, Dither::None);
+    assert!(black(&plain).iter().all(|&count| count == 0));
+    // Diffused, about half of every row turns black, the last rows as much
+    // as the first since the error runs on down the whole page
+    let diffused = to_bitmap(&image, Dither::FloydSteinberg);
+    assert_ne!(diffused.data, plain.data);
+    let rows = black(&diffused);
+    assert!(
+        rows.iter().all(|&count| (28..=36).contains(&count)),
+        "{rows:?}"
+    );
+    let total: usize = rows.iter().sum();
+    assert!(
+        total.abs_diff((width * height / 2) as usize) <= 64,
+        "{total}"
+    );
+}
+
+#[test]
+fn test_fill_colors() {
+    assert_eq!(fill_color([10, 10, 10], Dither::None), Some([0; 3]));
+    assert_eq!(fill_color([250, 250, 250], Dither::None), Some([255; 3]));
+    // Only pure black and white stay a single color once dithered
+    assert_eq!(fill_color([0, 0, 0], Dither::Ordered), Some([0; 3]));
+    assert_eq!(fill_color([255; 3], Dither::FloydSteinberg), Some([255; 3]));
+    assert_eq!(fill_color([250, 250, 250], Dither::FloydSteinberg), None);
+    assert_eq!(fill_color([10, 10, 10], Dither::Ordered), None);
+}
+
+#[test]
+fn test_luma_weighs_the_channels() {
+    assert_eq!(luma(&[0, 0, 0]), 0);
+    assert_eq!(luma(&[255, 255, 255]), 255);
+    // Green counts most, blue least
+    assert_eq!(luma(&[0, 255, 0]), 150);
+    assert_eq!(luma(&[0, 0, 255]), 29);
+    assert_eq!(fill_color([0, 255, 0], Dither::None), Some([255; 3]));
+    assert_eq!(fill_color([255, 0, 0], Dither::None), Some([0; 3]));
+    assert_eq!(fill_color([127; 3], Dither::None), Some([0; 3]));
+    assert_eq!(fill_color([128; 3], Dither::None), Some([255; 3]));
+
+    // Black fades to white as it turns transparent
+    assert_eq!(on_white(0, 255), 0);
+    assert_eq!(on_white(0, 0), 255);
+    assert_eq!(on_white(0, 128), 127);
+    assert_eq!(on_white(200, 51), 244);
+}
+
+#[test]
+fn test_g4_round_trip() {
+    // A frame with a diagonal through it, across a width off the byte grid
+    let (width, height) = (37, 23);
+    let mut bitmap = Bitmap::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            if x == 0 || y == 0 || x == width - 1 || y == height - 1 || x == y + 7 {
+                bitmap.set_black(x, y);
+            }
+        }
+    }
+    let coded = encode_g4(&bitmap);
+    assert!(coded.len() < bitmap.data.len());
+    assert_eq!(decode_g4(&coded, width, height), Some(bitmap));
+
+    // A blank page is a few bytes however big it is
+    let blank = Bitmap::new(2000, 1000);
+    assert!(encode_g4(&blank).len() < 1000);
+    assert_eq!(decode_g4(&encode_g4(&blank), 2000, 1000), Some(blank));
+}
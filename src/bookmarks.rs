@@ -0,0 +1,234 @@
+use crate::names;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+// Where the titles of a PDF's outline come from
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BookmarkMode {
+    // No outline
+    #[default]
+    None,
+    // A bookmark for every page, titled by its file name without extension
+    Filename,
+    // The bookmarks a manifest lists
+    Manifest,
+}
+
+// A bookmark of a manifest: the page it leads to, by file name or page id,
+// and how deep it is nested, 1 for the top level
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    pub file: String,
+    pub title: String,
+    #[serde(default = "top_level")]
+    pub level: usize,
+}
+
+fn top_level() -> usize {
+    1
+}
+
+// The bookmarks to give the pages
+#[derive(Clone, Debug)]
+pub enum Bookmarks {
+    Filename,
+    // In outline order; a page may have several, e.g. a section and itself.
+    // Pages not listed have none.
+    Manifest(Vec<Entry>),
+}
+
+impl Bookmarks {
+    // A manifest: a JSON array of entries with a file, title and optional
+    // level, or CSV lines of file,title[,level]
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(crate::paths::long_path(path))
+            .with_context(|| format!("Failed to read bookmarks: {:?}", path))?;
+        let json = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+            || text.trim_start().starts_with('[');
+        let entries = if json {
+            serde_json::from_str(&text).map_err(anyhow::Error::from)
+        } else {
+            parse_csv(&text)
+        }
+        .with_context(|| format!("Invalid bookmarks file: {:?}", path))?;
+        if let Some(entry) = entries.iter().find(|entry| entry.level == 0) {
+            anyhow::bail!(
+                "Invalid bookmarks file: {:?}: the level of {:?} must be 1 or more",
+                path,
+                entry.title
+            );
+        }
+        Ok(Bookmarks::Manifest(entries))
+    }
+
+    // Titles and levels of the bookmarks of page `id` at `path`, in order
+    pub fn of(&self, id: &str, path: &Path) -> Vec<(String, usize)> {
+        match self {
+            Bookmarks::Filename => vec![(names::stem(id).into_owned(), 1)],
+            Bookmarks::Manifest(entries) => entries
+                .iter()
+                .filter(|entry| matches(entry, id, path))
+                .map(|entry| (entry.title.clone(), entry.level))
+                .collect(),
+        }
+    }
+
+    // Manifest entries for none of `pages`, given by id and path
+    pub fn unmatched(&self, pages: &[(&str, &Path)]) -> Vec<String> {
+        let Bookmarks::Manifest(entries) = self else {
+            return Vec::new();
+        };
+        entries
+            .iter()
+            .filter(|entry| !pages.iter().any(|&(id, path)| matches(entry, id, path)))
+            .map(|entry| {
+                format!(
+                    "Bookmark {:?} for {:?} matches no page",
+                    entry.title, entry.file
+                )
+            })
+            .collect()
+    }
+}
+
+// Whether `entry` is for page `id`, named by its id or its file name as
+// annotations are
+fn matches(entry: &Entry, id: &str, path: &Path) -> bool {
+    let name = path.file_name().and_then(|name| name.to_str());
+    entry.file == id || Some(entry.file.as_str()) == name
+}
+
+// Lines of file,title[,level], with an optional header line. Fields with
+// commas or quotes are quoted, quotes in them doubled.
+fn parse_csv(text: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let number = number + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = csv_fields(line).with_context(|| format!("line {}", number))?;
+        if number == 1
+            && fields
+                .first()
+                .is_some_and(|field| field.eq_ignore_ascii_case("file"))
+        {
+            continue;
+        }
+        let (file, title, level) = match fields.as_slice() {
+            [file, title] => (file, title, None),
+            [file, title, level] => (file, title, Some(level)),
+            _ => anyhow::bail!("line {}: expected file,title[,level]", number),
+        };
+        let level = match level.map(|level| level.trim()) {
+            None | Some("") => 1,
+            Some(level) => level
+                .parse()
+                .with_context(|| format!("line {}: invalid level {:?}", number, level))?,
+        };
+        entries.push(Entry {
+            file: file.to_string(),
+            title: title.to_string(),
+            level,
+        });
+    }
+    Ok(entries)
+}
+
+fn csv_fields(line: &str) -> Result<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        let mut field = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => anyhow::bail!("unterminated quote"),
+                }
+            }
+            if !matches!(chars.peek(), None | Some(',')) {
+                anyhow::bail!("text after a closing quote");
+            }
+        } else {
+            while let Some(&c) = chars.peek().filter(|&&c| c != ',') {
+                field.push(c);
+                chars.next();
+            }
+        }
+        fields.push(field);
+        if chars.next().is_none() {
+            return Ok(fields);
+        }
+    }
+}
+
+#[test]
+fn test_bookmark_manifests() {
+    let entries = parse_csv(
+        "file,title,level\nslide-1.svg,Introduction\nslide-1.svg,\"Welcome, all\",2\n\ndeck/slide-2.svg,\"Die \"\"Größe\"\"\",2\n",
+    )
+    .unwrap();
+    let titles: Vec<_> = entries
+        .iter()
+        .map(|entry| (entry.title.as_str(), entry.level))
+        .collect();
+    assert_eq!(
+        titles,
+        [
+            ("Introduction", 1),
+            ("Welcome, all", 2),
+            ("Die \"Größe\"", 2)
+        ]
+    );
+    assert!(parse_csv("a.svg").is_err());
+    assert!(parse_csv("a.svg,\"open").is_err());
+    assert!(parse_csv("a.svg,Title,deep").is_err());
+
+    let dir = std::env::temp_dir().join(format!("svg2pdf-bookmarks-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let manifest = dir.join("bookmarks.json");
+    fs::write(
+        &manifest,
+        r#"[{"file": "slide-1.svg", "title": "Part 1"}, {"file": "deck/slide-2.svg", "title": "Two", "level": 2}, {"file": "gone.svg", "title": "Gone"}]"#,
+    )
+    .unwrap();
+    let bookmarks = Bookmarks::load(&manifest).unwrap();
+    // By file name or by page id
+    assert_eq!(
+        bookmarks.of("deck/slide-1.svg", Path::new("in/deck/slide-1.svg")),
+        [("Part 1".to_string(), 1)]
+    );
+    assert_eq!(
+        bookmarks.of("deck/slide-2.svg", Path::new("elsewhere.svg")),
+        [("Two".to_string(), 2)]
+    );
+    assert!(bookmarks.of("x.svg", Path::new("x.svg")).is_empty());
+    let pages = [("deck/slide-1.svg", Path::new("in/deck/slide-1.svg"))];
+    assert_eq!(bookmarks.unmatched(&pages).len(), 2);
+
+    fs::write(
+        &manifest,
+        r#"[{"file": "a.svg", "title": "A", "level": 0}]"#,
+    )
+    .unwrap();
+    assert!(Bookmarks::load(&manifest).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(
+        Bookmarks::Filename.of("deck/slide-1.svg#Notes", Path::new("slide-1.svg")),
+        [("slide-1#Notes".to_string(), 1)]
+    );
+}
@@ -0,0 +1,236 @@
+use std::fs;
+use std::sync::{Condvar, Mutex};
+
+// Share of the available memory rendered pages may take by default
+const DEFAULT_SHARE: u64 = 2;
+
+// Used when the available memory can't be detected
+const FALLBACK_LIMIT: u64 = 2048 * 1024 * 1024;
+
+// MemAvailable from /proc/meminfo, in bytes
+pub fn available_memory(meminfo: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+// Available memory as the system reports it
+pub fn detect_memory() -> Option<u64> {
+    available_memory(&fs::read_to_string("/proc/meminfo").ok()?)
+}
+
+// Default --max-in-flight-mb: half of the available memory
+pub fn default_limit_mb(available: Option<u64>) -> u64 {
+    let limit = available.map_or(FALLBACK_LIMIT, |available| available / DEFAULT_SHARE);
+    (limit / (1024 * 1024)).max(1)
+}
+
+// A worker holds an RGBA pixmap and the RGB page flattened from it
+const WORKER_BYTES_PER_PIXEL: u64 = 4 + 3;
+const PAGE_BYTES_PER_PIXEL: u64 = 3;
+
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+// Peak memory of a run, worked out before it starts
+#[derive(Clone, Copy)]
+pub struct Estimate {
+    pub workers: u64,
+    pub page_pixels: u64,
+    pub pages: u64,
+    // Limit on rendered pages waiting to be written
+    pub in_flight_limit: u64,
+    // Whether the writer keeps the whole document in memory until the end
+    pub document: bool,
+    // Limit on pages kept to reuse for duplicate files
+    pub dedupe_limit: u64,
+}
+
+impl Estimate {
+    fn parts(&self) -> [(String, u64); 4] {
+        let page = self.page_pixels * PAGE_BYTES_PER_PIXEL;
+        [
+            (
+                format!(
+                    "{} workers x {} px x {} B/px",
+                    self.workers, self.page_pixels, WORKER_BYTES_PER_PIXEL
+                ),
+                self.workers * self.page_pixels * WORKER_BYTES_PER_PIXEL,
+            ),
+            (
+                format!(
+                    "pages waiting to be written, at most {:.0} MiB",
+                    mib(self.in_flight_limit)
+                ),
+                self.in_flight_limit.min(self.pages * page),
+            ),
+            (
+                format!("document of {} pages x {:.1} MiB", self.pages, mib(page)),
+                if self.document { self.pages * page } else { 0 },
+            ),
+            (
+                format!(
+                    "pages kept for duplicates, at most {:.0} MiB",
+                    mib(self.dedupe_limit)
+                ),
+                self.dedupe_limit.min(self.pages * page),
+            ),
+        ]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.parts().iter().map(|(_, bytes)| bytes).sum()
+    }
+
+    // Most workers, up to the current count, that fit into `available`
+    pub fn fit_workers(&self, available: u64) -> Option<u64> {
+        (1..=self.workers)
+            .rev()
+            .find(|&workers| Estimate { workers, ..*self }.total() <= available)
+    }
+
+    // The math behind the estimate, one line per part
+    pub fn explain(&self, available: u64, source: &str) -> String {
+        let mut text = format!("Estimated peak memory: {:.1} MiB\n", mib(self.total()));
+        for (label, bytes) in self.parts() {
+            text.push_str(&format!("  {:<50} {:>10.1} MiB\n", label, mib(bytes)));
+        }
+        text.push_str(&format!(
+            "Available: {:.1} MiB ({})",
+            mib(available),
+            source
+        ));
+        text
+    }
+}
+
+#[derive(Default)]
+struct State {
+    in_flight: usize,
+    // Pages written so far, i.e. the index of the next page to write
+    written: usize,
+    peak: usize,
+    waits: usize,
+    stopped: bool,
+}
+
+// Bytes of rendered pages that are waiting to be written. Workers block
+// while the limit is exhausted, except for the page written next, which
+// is always let through so the output keeps moving.
+pub struct Budget {
+    limit: usize,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Budget {
+    pub fn new(limit: Option<usize>) -> Self {
+        Budget {
+            limit: limit.unwrap_or(usize::MAX),
+            state: Mutex::new(State::default()),
+            changed: Condvar::new(),
+        }
+    }
+
+    // Reserve `bytes` for page `index`, blocking while they don't fit
+    pub fn acquire(&self, index: usize, bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        let fits = |state: &State| {
+            state.stopped
+                || index == state.written
+                || state.in_flight.saturating_add(bytes) <= self.limit
+        };
+        if !fits(&state) {
+            state.waits += 1;
+            state = self
+                .changed
+                .wait_while(state, |state| !fits(state))
+                .unwrap();
+        }
+        state.in_flight += bytes;
+        state.peak = state.peak.max(state.in_flight);
+    }
+
+    // Count `bytes` for a page without waiting for room. For pages started
+    // ahead of their turn, whose workers might otherwise all wait while the
+    // page written next has not been handed out yet.
+    pub fn reserve(&self, bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight += bytes;
+        state.peak = state.peak.max(state.in_flight);
+    }
+
+    // The next page, of `bytes`, has been written
+    pub fn written(&self, bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= bytes;
+        state.written += 1;
+        self.changed.notify_all();
+    }
+
+    // Let every waiting worker through, e.g. after a failure
+    pub fn stop(&self) {
+        self.state.lock().unwrap().stopped = true;
+        self.changed.notify_all();
+    }
+
+    // Most bytes in flight at once, and how often a worker had to wait
+    pub fn peak(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.peak, state.waits)
+    }
+}
+
+#[test]
+fn test_estimate_reduces_workers() {
+    let estimate = Estimate {
+        workers: 8,
+        page_pixels: 1000,
+        pages: 10,
+        in_flight_limit: 5000,
+        document: true,
+        dedupe_limit: 0,
+    };
+    // 8 x 7000 for the workers, 5000 waiting and 30000 for the document
+    assert_eq!(estimate.total(), 56_000 + 5_000 + 30_000);
+    assert_eq!(estimate.fit_workers(1_000_000), Some(8));
+    assert_eq!(estimate.fit_workers(35_000 + 4 * 7_000), Some(4));
+    assert_eq!(estimate.fit_workers(35_000), None);
+    assert!(estimate
+        .explain(35_000, "test")
+        .contains("8 workers x 1000 px x 7 B/px"));
+}
+
+#[test]
+fn test_budget_lets_next_page_through() {
+    use std::sync::Arc;
+
+    assert_eq!(
+        available_memory("MemTotal: 16 kB\nMemAvailable:    8 kB\n"),
+        Some(8 * 1024)
+    );
+
+    let budget = Arc::new(Budget::new(Some(100)));
+    budget.acquire(0, 60);
+    // Larger than the limit, but it is the page written after page 0
+    let next = {
+        let budget = budget.clone();
+        std::thread::spawn(move || budget.acquire(1, 200))
+    };
+    let later = {
+        let budget = budget.clone();
+        std::thread::spawn(move || budget.acquire(2, 50))
+    };
+    budget.written(60);
+    next.join().unwrap();
+    budget.written(200);
+    later.join().unwrap();
+    budget.written(50);
+
+    // Page 2 never fits next to page 1
+    let (peak, _) = budget.peak();
+    assert!((200..=260).contains(&peak), "peak {peak}");
+}
@@ -0,0 +1,572 @@
+use crate::convert::{self, RenderArgs, RenderedImage};
+use crate::fonts;
+use crate::paths;
+use crate::writer::{EncodedPage, Encoding};
+use resvg::usvg::fontdb;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+// Bump when the entry format or anything else that affects cached pages changes
+const CACHE_VERSION: &str = "svg2pdf-page-cache-4";
+const MAGIC: &[u8; 6] = b"S2PC2\n";
+
+// Encodings entries store by their index here. Vector pages are drawn from
+// the parsed drawing, so they are never cached.
+const ENCODINGS: [Encoding; 7] = [
+    Encoding::Raw,
+    Encoding::Flate,
+    Encoding::Jpeg,
+    Encoding::Jpx,
+    Encoding::Png,
+    Encoding::Fax,
+    Encoding::Fill,
+];
+
+// Identifies a cached page: the source contents plus everything that
+// influences its pixels and how they were encoded
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CacheKey([u8; 32]);
+
+impl CacheKey {
+    fn hex(&self) -> String {
+        hex(&self.0)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+// Hash of every option that affects rendered pixels, including the set of
+// available fonts for pages with text. The tool version is deliberately not
+// part of it so manifests from different versions can be compared.
+pub fn options_hash(args: &RenderArgs, fontdb: Option<&fontdb::Database>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_vec(args).expect("render args serialize"));
+    if let Some(fontdb) = fontdb {
+        hasher.update(fonts_fingerprint(fontdb));
+    }
+    hex(&hasher.finalize())
+}
+
+// The options hashes of a run. Fonts only matter for pages with text, so
+// they are fingerprinted, and loaded, once the first such page comes along.
+pub struct OptionsHashes<'a> {
+    args: &'a RenderArgs,
+    without_fonts: String,
+    with_fonts: OnceLock<String>,
+    // How the run encodes its pages, see PageCache::key
+    encoding: String,
+}
+
+impl<'a> OptionsHashes<'a> {
+    pub fn new(args: &'a RenderArgs, encoding: String) -> Self {
+        OptionsHashes {
+            args,
+            without_fonts: options_hash(args, None),
+            with_fonts: OnceLock::new(),
+            encoding,
+        }
+    }
+
+    pub fn encoding(&self) -> &str {
+        &self.encoding
+    }
+
+    pub fn for_page(&self, data: &[u8]) -> &str {
+        if !convert::may_have_text(data) {
+            return &self.without_fonts;
+        }
+        self.with_fonts
+            .get_or_init(|| options_hash(self.args, Some(convert::font_database())))
+    }
+
+    // The hash for the run as a whole: with fonts if any page had text
+    pub fn run(self) -> String {
+        self.with_fonts.into_inner().unwrap_or(self.without_fonts)
+    }
+}
+
+// SHA-256 of rendered pixel data, with the alpha of transparent pages
+pub fn pixel_hash(image: &RenderedImage) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(&image.rgb_data);
+    if let Some(alpha) = &image.alpha {
+        hasher.update(alpha);
+    }
+    hex(&hasher.finalize())
+}
+
+// Fingerprint of the font database: face names, sources and file stamps,
+// and the families it resolves
+fn fonts_fingerprint(fontdb: &fontdb::Database) -> [u8; 32] {
+    let mut faces: Vec<String> = fontdb
+        .faces()
+        .map(|face| {
+            let source = match &face.source {
+                fontdb::Source::File(path) | fontdb::Source::SharedFile(path, _) => {
+                    let stamp = fs::metadata(path)
+                        .map(|meta| format!("{}:{:?}", meta.len(), meta.modified().ok()))
+                        .unwrap_or_default();
+                    format!("{}@{}", path.display(), stamp)
+                }
+                fontdb::Source::Binary(data) => format!("binary:{}", (**data).as_ref().len()),
+            };
+            format!("{}#{}|{}", source, face.index, face.post_script_name)
+        })
+        .collect();
+    faces.sort();
+
+    let mut hasher = Sha256::new();
+    for face in faces {
+        hasher.update(face);
+        hasher.update([0]);
+    }
+    // Which faces text gets also depends on the families standing in for
+    // the generic ones and for text naming none
+    let generic = [
+        fontdb::Family::Serif,
+        fontdb::Family::SansSerif,
+        fontdb::Family::Cursive,
+        fontdb::Family::Fantasy,
+        fontdb::Family::Monospace,
+    ];
+    for family in &generic {
+        hasher.update(fontdb.family_name(family));
+        hasher.update([0]);
+    }
+    hasher.update(fonts::config().default_family());
+    hasher.finalize().into()
+}
+
+// A page as the cache keeps it: encoded for the writer, with what runs want
+// to know of its pixels
+pub struct CachedPage {
+    pub width: u32,
+    pub height: u32,
+    pub pixel_hash: String,
+    pub blank: Option<[u8; 3]>,
+    // Stored for runs that wanted one, the others leave it out
+    pub thumbnail: Option<Vec<u8>>,
+    // None where the run wrote no document or dropped the page
+    pub encoded: Option<EncodedPage>,
+}
+
+// Encoded pages reused across runs: an in-memory layer for long-lived
+// sessions (--watch) and an on-disk layer for --incremental
+pub struct PageCache {
+    memory: Option<Mutex<HashMap<CacheKey, Arc<CachedPage>>>>,
+    dir: Option<PathBuf>,
+    used: Mutex<HashSet<CacheKey>>,
+    // Entries the finished run used, which PageCache::prune keeps
+    last_run: Mutex<HashSet<CacheKey>>,
+    hits: AtomicUsize,
+}
+
+impl PageCache {
+    pub fn new(memory: bool, dir: Option<PathBuf>) -> Self {
+        PageCache {
+            memory: memory.then(Mutex::default),
+            dir,
+            used: Mutex::default(),
+            last_run: Mutex::default(),
+            hits: AtomicUsize::new(0),
+        }
+    }
+
+    // `encoding` says how the pages are encoded, blank ones found and
+    // dropped, as pages go into the cache only once they are
+    pub fn key(&self, data: &[u8], options_hash: &str, encoding: &str) -> CacheKey {
+        let mut hasher = Sha256::new();
+        hasher.update(CACHE_VERSION);
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        hasher.update(options_hash);
+        hasher.update([0]);
+        hasher.update(encoding);
+        hasher.update([0]);
+        hasher.update(data);
+        CacheKey(hasher.finalize().into())
+    }
+
+    fn entry_path(dir: &Path, key: &CacheKey) -> PathBuf {
+        let hex = key.hex();
+        dir.join(&hex[..2]).join(format!("{}.page", &hex[2..]))
+    }
+
+    // The page of `key` if it has at most `max_pixels`. Larger pages are
+    // misses, to be rendered and fail under the limit of this run.
+    pub fn get(&self, key: &CacheKey, max_pixels: u64) -> Option<Arc<CachedPage>> {
+        self.used.lock().unwrap().insert(*key);
+
+        let cached = self
+            .memory
+            .as_ref()
+            .and_then(|memory| memory.lock().unwrap().get(key).cloned())
+            .or_else(|| {
+                // Unreadable or corrupted entries are treated as misses
+                let dir = self.dir.as_ref()?;
+                let page = Arc::new(read_entry(&Self::entry_path(dir, key))?);
+                if let Some(memory) = &self.memory {
+                    memory.lock().unwrap().insert(*key, Arc::clone(&page));
+                }
+                Some(page)
+            })
+            .filter(|page| page.width as u64 * page.height as u64 <= max_pixels);
+        if cached.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        cached
+    }
+
+    pub fn insert(&self, key: CacheKey, page: CachedPage) {
+        self.used.lock().unwrap().insert(key);
+        if let Some(dir) = &self.dir {
+            // Failing to write the cache must not fail the conversion
+            if let Err(err) = write_entry(&Self::entry_path(dir, &key), &page) {
+                eprintln!("Warning: failed to write cache entry: {}", err);
+            }
+        }
+        if let Some(memory) = &self.memory {
+            memory.lock().unwrap().insert(key, Arc::new(page));
+        }
+    }
+
+    // Number of hits since the last call, and forget in-memory pages that
+    // were not used by the finished run
+    pub fn finish_run(&self) -> usize {
+        let used = std::mem::take(&mut *self.used.lock().unwrap());
+        if let Some(memory) = &self.memory {
+            memory.lock().unwrap().retain(|key, _| used.contains(key));
+        }
+        *self.last_run.lock().unwrap() = used;
+        self.hits.swap(0, Ordering::Relaxed)
+    }
+
+    // Delete the entries on disk that the finished run didn't use, such as
+    // those of changed or removed files. Returns how many went, and their
+    // bytes.
+    pub fn prune(&self) -> std::io::Result<(usize, u64)> {
+        let mut pruned = (0, 0);
+        let Some(dir) = &self.dir else {
+            return Ok(pruned);
+        };
+        let kept: HashSet<String> = self
+            .last_run
+            .lock()
+            .unwrap()
+            .iter()
+            .map(CacheKey::hex)
+            .collect();
+        let shards = match fs::read_dir(paths::long_path(dir)) {
+            Ok(shards) => shards,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(pruned),
+            Err(err) => return Err(err),
+        };
+        for shard in shards {
+            let shard = shard?;
+            // Only what entry_path names, whatever else is in the directory
+            let prefix = shard.file_name().to_string_lossy().into_owned();
+            if prefix.len() != 2 || !prefix.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                continue;
+            }
+            for entry in fs::read_dir(shard.path())? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                // Temp files of entries still being written are left alone
+                let Some(rest) = name.strip_suffix(".page") else {
+                    continue;
+                };
+                if kept.contains(&format!("{}{}", prefix, rest)) {
+                    continue;
+                }
+                let bytes = entry.metadata().map_or(0, |metadata| metadata.len());
+                fs::remove_file(entry.path())?;
+                pruned.0 += 1;
+                pruned.1 += bytes;
+            }
+            // Fails for the shards that still have entries
+            let _ = fs::remove_dir(shard.path());
+        }
+        Ok(pruned)
+    }
+}
+
+// Append `bytes` to an entry, with its length, or that there are none
+fn put_bytes(entry: &mut Vec<u8>, bytes: Option<&[u8]>) {
+    match bytes {
+        Some(bytes) => {
+            entry.push(1);
+            entry.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            entry.extend_from_slice(bytes);
+        }
+        None => entry.push(0),
+    }
+}
+
+// Entries are the magic, the SHA-256 of the rest, and then the page: its
+// size, pixel hash, blank color, thumbnail and encoded page, if any
+fn write_entry(path: &Path, page: &CachedPage) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(paths::long_path(parent))?;
+    }
+
+    let mut body = Vec::new();
+    body.extend_from_slice(&page.width.to_le_bytes());
+    body.extend_from_slice(&page.height.to_le_bytes());
+    put_bytes(&mut body, Some(page.pixel_hash.as_bytes()));
+    put_bytes(&mut body, page.blank.as_ref().map(|color| &color[..]));
+    put_bytes(&mut body, page.thumbnail.as_deref());
+    match &page.encoded {
+        Some(encoded) => {
+            let encoding = ENCODINGS
+                .iter()
+                .position(|&encoding| encoding == encoded.encoding)
+                .ok_or_else(|| std::io::Error::other("vector pages aren't cached"))?;
+            body.push(encoding as u8 + 1);
+            body.extend_from_slice(&encoded.width.to_le_bytes());
+            body.extend_from_slice(&encoded.height.to_le_bytes());
+            put_bytes(&mut body, encoded.reason.as_deref().map(str::as_bytes));
+            put_bytes(&mut body, Some(&encoded.data));
+            put_bytes(&mut body, encoded.mask.as_deref());
+        }
+        None => body.push(0),
+    }
+
+    let mut entry = Vec::with_capacity(MAGIC.len() + 32 + body.len());
+    entry.extend_from_slice(MAGIC);
+    entry.extend_from_slice(&Sha256::digest(&body));
+    entry.extend_from_slice(&body);
+
+    // Write to a temp file first so readers never see a partial entry
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(paths::long_path(&tmp), entry)?;
+    fs::rename(paths::long_path(&tmp), paths::long_path(path))
+}
+
+// Reads the parts of an entry in turn, None past its end
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.0.len() {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn bytes(&mut self) -> Option<Option<&'a [u8]>> {
+        match self.byte()? {
+            0 => Some(None),
+            1 => {
+                let len = u64::from_le_bytes(self.take(8)?.try_into().ok()?);
+                Some(Some(self.take(usize::try_from(len).ok()?)?))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn read_entry(path: &Path) -> Option<CachedPage> {
+    let entry = fs::read(paths::long_path(path)).ok()?;
+    let mut reader = Reader(&entry);
+    if reader.take(MAGIC.len())? != MAGIC {
+        return None;
+    }
+    // Truncated or changed entries, sizes and all, don't match their hash
+    let digest = reader.take(32)?;
+    if Sha256::digest(reader.0)[..] != *digest {
+        return None;
+    }
+
+    let width = reader.u32()?;
+    let height = reader.u32()?;
+    let pixel_hash = String::from_utf8(reader.bytes()??.to_vec()).ok()?;
+    let blank = match reader.bytes()? {
+        Some(color) => Some(color.try_into().ok()?),
+        None => None,
+    };
+    let thumbnail = reader.bytes()?.map(<[u8]>::to_vec);
+    let encoded = match reader.byte()? {
+        0 => None,
+        encoding => {
+            let encoding = *ENCODINGS.get(encoding as usize - 1)?;
+            let (width, height) = (reader.u32()?, reader.u32()?);
+            let reason = match reader.bytes()? {
+                Some(reason) => Some(String::from_utf8(reason.to_vec()).ok()?),
+                None => None,
+            };
+            let data = reader.bytes()??.to_vec();
+            let mask = reader.bytes()?.map(<[u8]>::to_vec);
+            Some(EncodedPage {
+                width,
+                height,
+                encoding,
+                data,
+                reason,
+                vector: None,
+                mask,
+            })
+        }
+    };
+    Some(CachedPage {
+        width,
+        height,
+        pixel_hash,
+        blank,
+        thumbnail,
+        encoded,
+    })
+}
+
+// A cached page of `width` by `height`, flate-encoded with `mask`
+#[cfg(test)]
+fn page(width: u32, height: u32, mask: Option<Vec<u8>>) -> CachedPage {
+    CachedPage {
+        width,
+        height,
+        pixel_hash: "00ff".to_string(),
+        blank: None,
+        thumbnail: None,
+        encoded: Some(EncodedPage {
+            width,
+            height,
+            encoding: Encoding::Flate,
+            data: vec![1, 2, 3, 4, 5, 6],
+            reason: Some("line art".to_string()),
+            vector: None,
+            mask,
+        }),
+    }
+}
+
+#[test]
+fn test_disk_cache_round_trip() {
+    let dir = std::env::temp_dir().join(format!("svg2pdf-cache-test-{}", std::process::id()));
+    let cache = PageCache::new(false, Some(dir.clone()));
+    let key = cache.key(b"<svg/>", "options", "pdf");
+    assert!(key != cache.key(b"<svg/>", "other options", "pdf"));
+    // Pages encoded otherwise are others too
+    assert!(key != cache.key(b"<svg/>", "options", "raw"));
+
+    assert!(cache.get(&key, u64::MAX).is_none());
+    cache.insert(key, page(2, 1, None));
+    let cached = cache.get(&key, u64::MAX).expect("cache hit");
+    let encoded = cached.encoded.as_ref().unwrap();
+    assert_eq!(
+        (encoded.encoding, &encoded.data, encoded.reason.as_deref()),
+        (Encoding::Flate, &vec![1, 2, 3, 4, 5, 6], Some("line art"))
+    );
+    assert_eq!((cached.width, cached.pixel_hash.as_str()), (2, "00ff"));
+    assert_eq!(cache.finish_run(), 1);
+
+    // A truncated entry falls back to a miss instead of failing
+    let path = PageCache::entry_path(&dir, &key);
+    let entry = fs::read(&path).unwrap();
+    fs::write(&path, &entry[..entry.len() - 3]).unwrap();
+    assert!(cache.get(&key, u64::MAX).is_none());
+
+    // The masks of transparent pages, blank colors and thumbnails are kept,
+    // and so are pages without an encoded page
+    let key = cache.key(b"<svg/>", "options", "transparent");
+    cache.insert(key, page(2, 1, Some(vec![0, 128])));
+    let cached = cache.get(&key, u64::MAX).expect("cache hit");
+    assert_eq!(cached.encoded.as_ref().unwrap().mask, Some(vec![0, 128]));
+    let key = cache.key(b"<svg/>", "options", "none");
+    cache.insert(
+        key,
+        CachedPage {
+            blank: Some([255, 0, 0]),
+            thumbnail: Some(b"png".to_vec()),
+            encoded: None,
+            ..page(2, 1, None)
+        },
+    );
+    let cached = cache.get(&key, u64::MAX).expect("cache hit");
+    assert_eq!(
+        (
+            cached.blank,
+            cached.thumbnail.as_deref(),
+            cached.encoded.is_none()
+        ),
+        (Some([255, 0, 0]), Some(&b"png"[..]), true)
+    );
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_prune_unused_entries() {
+    let dir = std::env::temp_dir().join(format!("svg2pdf-prune-test-{}", std::process::id()));
+    let cache = PageCache::new(false, Some(dir.clone()));
+    let keys: Vec<_> = (0..3)
+        .map(|index| cache.key(format!("<svg id='{index}'/>").as_bytes(), "options", "pdf"))
+        .collect();
+    for &key in &keys {
+        cache.insert(key, page(1, 1, None));
+    }
+    cache.finish_run();
+    fs::write(dir.join("notes.txt"), "not ours").unwrap();
+
+    // The next run only uses the first two, the last goes with its shard
+    for key in &keys[..2] {
+        assert!(cache.get(key, u64::MAX).is_some());
+    }
+    assert_eq!(cache.finish_run(), 2);
+    let (count, bytes) = cache.prune().unwrap();
+    assert_eq!(count, 1);
+    assert!(bytes > 0);
+    assert!(!PageCache::entry_path(&dir, &keys[2]).exists());
+    assert!(PageCache::entry_path(&dir, &keys[0]).exists());
+    assert!(dir.join("notes.txt").exists());
+    assert_eq!(cache.prune().unwrap().0, 0);
+
+    // Without a directory, or before it exists, there is nothing to prune
+    assert_eq!(PageCache::new(true, None).prune().unwrap(), (0, 0));
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(cache.prune().unwrap(), (0, 0));
+}
+
+#[test]
+fn test_corrupted_entry_header() {
+    let dir = std::env::temp_dir().join(format!("svg2pdf-header-test-{}", std::process::id()));
+    let cache = PageCache::new(false, Some(dir.clone()));
+    let key = cache.key(b"<svg/>", "options", "pdf");
+    cache.insert(key, page(2, 1, None));
+    assert!(cache.get(&key, 2).is_some());
+    // Pages larger than the limit of the run are misses
+    assert!(cache.get(&key, 1).is_none());
+
+    // Sizes that were changed don't match the hash of the entry
+    let path = PageCache::entry_path(&dir, &key);
+    let entry = fs::read(&path).unwrap();
+    let sizes = MAGIC.len() + 32;
+    let with_size = |width: u32, height: u32| {
+        let mut corrupted = entry.clone();
+        corrupted[sizes..sizes + 4].copy_from_slice(&width.to_le_bytes());
+        corrupted[sizes + 4..sizes + 8].copy_from_slice(&height.to_le_bytes());
+        fs::write(&path, corrupted).unwrap();
+    };
+    for limit in [u64::MAX, 1 << 28] {
+        with_size(u32::MAX, u32::MAX);
+        assert!(cache.get(&key, limit).is_none());
+        with_size(1, 1);
+        assert!(cache.get(&key, limit).is_none());
+    }
+    with_size(2, 1);
+    assert!(cache.get(&key, 2).is_some());
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,1368 @@
+use crate::append::Existing;
+use crate::bilevel::Dither;
+use crate::bookmarks::{BookmarkMode, Bookmarks};
+use crate::cache::PageCache;
+use crate::convert::{
+    self, Background, Copies, EmptyOutput, OnError, Quality, RenderArgs, RunOptions, SortOrder,
+};
+use crate::export::ImageExport;
+use crate::fonts::{self, FontArgs};
+use crate::inputs::{self, Inputs};
+#[cfg(feature = "jp2")]
+use crate::jp2;
+use crate::metadata::{self, Metadata};
+use crate::presets::{self, PrintPreset};
+use crate::print::{Duplex, PrintSetup};
+use crate::progress::ProgressMode;
+use crate::retry::{self, RetryPolicy};
+#[cfg(feature = "serve")]
+use crate::serve;
+use crate::writer::{
+    self, ColorMode, Format, ImageFormat, ImageOptions, JpegSubsampling, TiffCompression,
+};
+use crate::{
+    annotations, bench, budget, compare, dedupe, doctor, hashes, html, output, paths, report,
+    timings, watch,
+};
+use anyhow::{Context, Result};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[derive(Parser)]
+#[command(
+    author,
+    version,
+    about = "Convert SVG files to PDF",
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// SVG files, directories of them and glob patterns such as 'diagrams/**/*.svg', taken in the order given
+    #[arg(
+        value_name = "INPUT",
+        required_unless_present_any = ["input_dir", "files_from", "list_print_presets"]
+    )]
+    inputs: Vec<PathBuf>,
+
+    /// Also take the inputs listed in LIST, or on stdin for -, one per line and in that order; blank lines and lines starting with # are left out
+    #[arg(long, value_name = "LIST")]
+    files_from: Option<PathBuf>,
+
+    /// Take the SVG files in subdirectories of input directories too
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Deprecated: pass the directory as an INPUT instead
+    #[arg(short, long, value_name = "DIR")]
+    input_dir: Option<PathBuf>,
+
+    /// Output file, or - for stdout, with the messages of the run on stderr
+    #[arg(short, long, required_unless_present_any = ["no_pdf", "list_print_presets"])]
+    output: Option<PathBuf>,
+
+    /// Add the pages to the end of PDF and write the result to the output, which may be PDF itself; the document keeps its own info and outline
+    #[arg(
+        long,
+        value_name = "PDF",
+        conflicts_with_all = ["no_pdf", "watch", "verify", "title", "author", "subject", "keywords", "bookmarks"]
+    )]
+    append_to: Option<PathBuf>,
+
+    /// Put the pages of --append-to before page N of the document instead, counted from 1; needs a document with all its pages in one page tree node
+    #[arg(long, value_name = "N", requires = "append_to", value_parser = clap::value_parser!(u32).range(1..))]
+    insert_at: Option<u32>,
+
+    /// Page order within each directory or pattern: natural sorts by name with numbers by value (slide-9 before slide-10), lexical by the bytes of the name, mtime oldest first
+    #[arg(long, value_enum, default_value_t = SortOrder::Natural)]
+    sort: SortOrder,
+
+    /// Put the pages in the opposite of --sort order
+    #[arg(long)]
+    reverse: bool,
+
+    /// Write the same bytes for the same inputs: the document says nothing of when it was written unless SOURCE_DATE_EPOCH pins its dates, and --sort mtime is refused
+    #[arg(long)]
+    deterministic: bool,
+
+    /// Succeed when there is no page to write, e.g. when the inputs name no SVG: write a placeholder page, or with =none no document at all
+    #[arg(long, value_enum, value_name = "OUTPUT", num_args = 0..=1, default_missing_value = "placeholder")]
+    allow_empty: Option<EmptyOutput>,
+
+    /// SVG to render as the placeholder page with --allow-empty [default: a blank page]
+    #[arg(long, value_name = "SVG", requires = "allow_empty")]
+    placeholder: Option<PathBuf>,
+
+    /// Create missing parent directories of the output files
+    #[arg(long)]
+    create_dirs: bool,
+
+    /// Read the document back after writing it and check that every page decodes; a document that fails is renamed to NAME.invalid
+    #[arg(long, conflicts_with = "no_pdf")]
+    verify: bool,
+
+    /// Put review comments on the pages of the PDF: a JSON array of entries with a page (file name or page id), a rect [x, y, width, height] or a point [x, y] in SVG user units, and text, author, modified and kind (note, square, highlight or free-text)
+    #[arg(long, value_name = "FILE", conflicts_with = "no_pdf")]
+    annotations: Option<PathBuf>,
+
+    /// Title of the PDF, in its document info and XMP metadata
+    #[arg(long, conflicts_with = "no_pdf")]
+    title: Option<String>,
+
+    /// Author of the PDF
+    #[arg(long, conflicts_with = "no_pdf")]
+    author: Option<String>,
+
+    /// Subject of the PDF
+    #[arg(long, conflicts_with = "no_pdf")]
+    subject: Option<String>,
+
+    /// Keywords of the PDF, e.g. 'quarterly, sales'
+    #[arg(long, conflicts_with = "no_pdf")]
+    keywords: Option<String>,
+
+    /// Give the PDF an outline of bookmarks: one per page titled by its file name without extension, or those listed by --bookmark-manifest
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        default_value_t,
+        conflicts_with = "no_pdf"
+    )]
+    bookmarks: BookmarkMode,
+
+    /// Bookmarks for --bookmarks manifest: a JSON array of entries with a file (file name or page id), a title and an optional level from 1, or CSV lines of file,title[,level]. Listed in outline order; files not listed get no bookmark
+    #[arg(long, value_name = "FILE", required_if_eq("bookmarks", "manifest"))]
+    bookmark_manifest: Option<PathBuf>,
+
+    /// Draw PDF pages as vector paths, so they stay sharp at any zoom; filters, masks, gradients, patterns and embedded images fall back to images of their own. Text becomes outlines and can't be selected
+    #[arg(long, conflicts_with = "no_pdf")]
+    vector: bool,
+
+    #[command(flatten)]
+    render: RenderArgs,
+
+    #[command(flatten)]
+    fonts: FontArgs,
+
+    /// Output document format
+    #[arg(long, value_enum, default_value_t = Format::Pdf)]
+    format: Format,
+
+    /// Defaults for a kind of print job, see --list-print-presets; flags given as well win
+    #[arg(long, value_enum, value_name = "PRESET")]
+    print_preset: Option<PrintPreset>,
+
+    /// Show what each --print-preset sets, and exit
+    #[arg(long)]
+    list_print_presets: bool,
+
+    /// Draw crop marks at the corners of each PDF page's trim, outside its --bleed
+    #[arg(long, conflicts_with = "no_pdf")]
+    crop_marks: bool,
+
+    /// Ask PDF viewers to print pages at their actual size rather than fitted to the paper
+    #[arg(long, conflicts_with = "no_pdf")]
+    no_print_scaling: bool,
+
+    /// How PDF viewers should print pages on both sides of the paper
+    #[arg(long, value_enum, conflicts_with = "no_pdf")]
+    duplex: Option<Duplex>,
+
+    /// Colors pages keep: bilevel turns every pixel black or white and stores PDF and TIFF pages 1 bit a pixel in CCITT Group 4, whatever --image-format says
+    #[arg(long, value_enum, default_value_t = ColorMode::Color)]
+    color_mode: ColorMode,
+
+    /// How bilevel pages show grays: none splits them at mid gray, which keeps text crisp; floyd-steinberg and ordered mix black and white dots, so gradients and photos keep their shades
+    #[arg(long, value_enum, default_value_t = Dither::None)]
+    dither: Dither,
+
+    /// How page images are stored in a PDF; auto picks per page and keeps text and line art lossless
+    #[arg(long, value_enum, default_value_t = ImageFormat::Flate)]
+    image_format: ImageFormat,
+
+    /// Store pages whose id matches PATTERN (* and ? wildcards) as FORMAT, e.g. 'photos/*=jpeg'; repeatable
+    #[arg(long, value_name = "PATTERN=FORMAT", value_parser = writer::parse_override)]
+    image_format_for: Vec<(String, ImageFormat)>,
+
+    /// JPEG quality from 1 to 100 [default: from --quality]
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    jpeg_quality: Option<u8>,
+
+    /// Color samples JPEG pages keep: 444 keeps colored text and hairlines sharp, 420 saves a third or more of the bytes [default: 444 for line art, 420 for photographic pages]
+    #[arg(long, value_enum, value_name = "SAMPLING")]
+    jpeg_subsampling: Option<JpegSubsampling>,
+
+    /// Store JPEG 2000 pages losslessly
+    #[cfg(feature = "jp2")]
+    #[arg(long, conflicts_with = "jp2_rate")]
+    jp2_lossless: bool,
+
+    /// Compression ratio of lossy JPEG 2000 pages, e.g. 20 for 20:1
+    #[cfg(feature = "jp2")]
+    #[arg(long, value_name = "N", default_value_t = jp2::DEFAULT_RATE, value_parser = jp2::parse_rate)]
+    jp2_rate: f32,
+
+    /// Largest difference (0-255) of any color channel across a page for it to count as blank; blank PDF pages are stored as a plain fill
+    #[arg(long, default_value = "0")]
+    blank_tolerance: u8,
+
+    /// Leave blank pages out of the output
+    #[arg(long)]
+    drop_blank_pages: bool,
+
+    /// Put every page into the document N times in a row; PDF copies share the page image
+    #[arg(long, value_name = "N", default_value = "1", value_parser = convert::parse_copies)]
+    copies: u32,
+
+    /// Put pages whose id matches PATTERN (* and ? wildcards) in N times instead, e.g. 'sign*.svg=3'; repeatable
+    #[arg(long, value_name = "PATTERN=N", value_parser = convert::parse_copies_override)]
+    copies_for: Vec<(String, u32)>,
+
+    /// Compression of TIFF pages
+    #[arg(long, value_enum, default_value_t = TiffCompression::Lzw)]
+    tiff_compression: TiffCompression,
+
+    /// How to show progress: a bar on terminals and plain lines elsewhere by default; json prints a JSON object per file for programs to follow
+    #[arg(long, value_enum, default_value_t = ProgressMode::Auto)]
+    progress: ProgressMode,
+
+    /// Show a live dashboard (same as --progress tui)
+    #[arg(long, conflicts_with = "progress")]
+    tui: bool,
+
+    /// Record per-file stage timings and print the slowest files
+    #[arg(long)]
+    timings: bool,
+
+    /// Number of slowest files to list per stage with --timings
+    #[arg(long, default_value = "5", requires = "timings")]
+    timings_top: usize,
+
+    /// Write a chrome://tracing compatible trace of the run
+    #[arg(long)]
+    trace_file: Option<PathBuf>,
+
+    /// Rebuild the output whenever an SVG the inputs may name changes
+    #[arg(long)]
+    watch: bool,
+
+    /// Reuse pages of unchanged files from previous runs
+    #[arg(long)]
+    incremental: bool,
+
+    /// Page cache directory (implies --incremental) [default: .svg2pdf-cache next to the output]
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+
+    /// Never read or write the page cache
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Delete the page cache entries the run didn't use, such as those of changed or removed files
+    #[arg(long, conflicts_with_all = ["no_cache", "watch"])]
+    cache_prune: bool,
+
+    /// Parse and render every file, even byte-identical copies of another
+    #[arg(long)]
+    no_dedupe: bool,
+
+    /// Leave out files byte-identical to an earlier one, instead of repeating their page
+    #[arg(long)]
+    dedupe_inputs: bool,
+
+    /// Render every top-level Inkscape layer of a file as its own page, named FILE#LAYER
+    #[arg(long)]
+    explode_layers: bool,
+
+    /// Leave out layers whose name matches PATTERN (* and ? wildcards) with --explode-layers; repeatable
+    #[arg(long, value_name = "PATTERN", requires = "explode_layers")]
+    layer_filter: Vec<String>,
+
+    /// Fail instead of rendering files that use SVG features resvg can't render, such as scripts or foreignObject
+    #[arg(long)]
+    fail_on_unsupported: bool,
+
+    /// What to do with a file that fails to convert: abort the run, skip it, or put a blank page in its place so pages still match the inputs
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = OnError::Abort)]
+    on_error: OnError,
+
+    /// Exit successfully even when files failed with --on-error skip or blank
+    #[arg(long)]
+    ignore_failures: bool,
+
+    /// Render files in input order, without starting much larger files first
+    #[arg(long)]
+    no_reorder_work: bool,
+
+    /// Write a manifest with a SHA-256 of every page's rendered pixels
+    #[arg(long)]
+    hashes: Option<PathBuf>,
+
+    /// Write a JSON report of the run: an entry per input file with its page number, pixel size, render time, status and any error or warnings, totals and the size of the output
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+
+    /// Also write every page as a PNG (with transparency) into this directory
+    #[arg(long, conflicts_with = "watch")]
+    export_images: Option<PathBuf>,
+
+    /// File name of exported pages: {index}, {index:04}, {stem} and {name} are replaced
+    #[arg(
+        long,
+        default_value = "{index:04}-{stem}.png",
+        requires = "export_images"
+    )]
+    image_template: String,
+
+    /// Write an index.html gallery of the pages next to the output
+    #[arg(long)]
+    html_index: bool,
+
+    /// Write a small PNG of the first page, e.g. for build notifications
+    #[arg(long)]
+    preview: Option<PathBuf>,
+
+    /// Longest side of the --preview image in pixels
+    #[arg(long, default_value = "512", requires = "preview")]
+    preview_size: u32,
+
+    /// Render workers, each with a page of pixels in memory at a time [default: one per logical CPU]
+    #[arg(short, long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    jobs: Option<u32>,
+
+    /// Fail files whose pages would have more than N pixels, e.g. drawings declaring a huge size, before any memory is taken for them; with --on-error skip or blank they are left out like other failures
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_pixels: Option<u64>,
+
+    /// Threads reading SVG files ahead of rendering; 0 reads them in the render workers
+    #[arg(long, default_value = "2")]
+    io_threads: usize,
+
+    /// Times to try reading a file again after an interrupted or timed out read or a stale network file handle
+    #[arg(long, value_name = "N", default_value = "0")]
+    retries: u32,
+
+    /// Wait before the first retry of a read, doubled before each one after it, e.g. 500ms or 2s
+    #[arg(long, value_name = "DELAY", default_value = "500ms", value_parser = retry::parse_delay)]
+    retry_delay: Duration,
+
+    /// Memory rendered pages may take while waiting to be written [default: half the available memory]
+    #[arg(long)]
+    max_in_flight_mb: Option<u64>,
+
+    /// Memory to plan for in MB, when the detected amount is wrong (e.g. in containers)
+    #[arg(long)]
+    assume_memory: Option<u64>,
+
+    /// Print the effective settings of the run
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Only export page images, don't write a PDF
+    #[arg(long, requires = "export_images", conflicts_with = "output")]
+    no_pdf: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Benchmark the conversion on a generated synthetic corpus
+    Bench(bench::BenchArgs),
+
+    /// Compare a PDF against a reference page by page
+    Compare(compare::CompareArgs),
+
+    /// Check fonts, permissions and resources and try a test render
+    Doctor(doctor::DoctorArgs),
+
+    /// Serve conversions over HTTP
+    #[cfg(feature = "serve")]
+    Serve(serve::ServeArgs),
+}
+
+// Fill in the options `preset` stands for where the command line has none
+fn apply_print_preset(preset: PrintPreset, args: &mut Cli, matches: &ArgMatches) {
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    let options = preset.options();
+    if !given("image_format") {
+        args.image_format = options.image_format;
+    }
+    let render = &mut args.render;
+    if !given("quality") {
+        render.quality = options.quality;
+    }
+    render.dpi = render.dpi.or(Some(options.dpi));
+    // --page-size pages are sized already
+    if render.paper.is_none() && render.page_size.is_none() {
+        render.paper = options
+            .paper
+            .map(|paper| convert::parse_paper(paper).expect("presets name known papers"));
+    }
+    if render.paper.is_some() {
+        render.orientation = render.orientation.or(options.orientation);
+    }
+    // The margin needs a page to leave it on
+    if render.paper.is_some() || render.page_size.is_some() || render.nup.is_some() {
+        render.margin = render.margin.or(options.margin);
+    }
+    // Only PDFs have trim boxes and viewer preferences, and only pages
+    // sized by their drawings a bleed
+    if args.format != Format::Pdf {
+        return;
+    }
+    if render.paper.is_none() && render.page_size.is_none() && render.nup.is_none() {
+        render.bleed = render.bleed.or(options.bleed);
+    }
+    args.crop_marks |= options.crop_marks && render.nup.is_none();
+    args.no_print_scaling |= options.no_print_scaling;
+    args.duplex = args.duplex.or(options.duplex);
+}
+
+// The svg2pdf command line tool
+pub fn main() -> Result<()> {
+    // TODO: Darken the stroke lines to see better.
+    let matches = Cli::command().get_matches();
+    let mut args = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    if args.list_print_presets {
+        print!("{}", presets::list());
+        return Ok(());
+    }
+    if let Some(preset) = args.print_preset {
+        apply_print_preset(preset, &mut args, &matches);
+    }
+    fonts::configure(args.fonts.clone())?;
+    args.render.check().map_err(anyhow::Error::msg)?;
+    let sized_by = match (&args.render.nup, &args.render.paper) {
+        (Some(_), _) => Some("--nup"),
+        (None, Some(_)) => Some("--paper"),
+        (None, None) => None,
+    };
+    if let Some(option) = sized_by {
+        if matches.value_source("scale") == Some(ValueSource::CommandLine) {
+            eprintln!("Warning: --scale is ignored, {} sizes the pages", option);
+        }
+    }
+
+    match &args.command {
+        Some(Command::Bench(bench_args)) => return bench::run(bench_args),
+        Some(Command::Compare(compare_args)) => {
+            // A failed comparison is reported by compare itself
+            if !compare::run(compare_args)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Doctor(doctor_args)) => {
+            if !doctor::run(doctor_args)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        #[cfg(feature = "serve")]
+        Some(Command::Serve(serve_args)) => return serve::run(serve_args),
+        None => {}
+    }
+
+    // Inputs are required by clap unless a subcommand was given, and the
+    // old --input-dir comes first
+    let mut paths = args.inputs.clone();
+    if let Some(dir) = &args.input_dir {
+        eprintln!(
+            "Warning: --input-dir is deprecated, pass {:?} as an argument instead",
+            dir
+        );
+        paths.insert(0, dir.clone());
+    }
+    if let Some(list) = &args.files_from {
+        paths.extend(inputs::read_list(list)?);
+    }
+    let inputs = Inputs {
+        paths,
+        recursive: args.recursive,
+    };
+    let export = args
+        .export_images
+        .clone()
+        .map(|dir| ImageExport::new(dir, &args.image_template))
+        .transpose()?;
+    let output = match (&args.output, &export) {
+        (Some(output), _) => output.clone(),
+        // Only reached with --no-pdf, where the output just anchors the cache
+        (None, Some(export)) => export.dir().join("pages.pdf"),
+        (None, None) => unreachable!("output is required without --no-pdf"),
+    };
+
+    // Resolve the on-disk page cache location
+    let cache_dir = if args.no_cache || !(args.incremental || args.cache_dir.is_some()) {
+        None
+    } else {
+        Some(args.cache_dir.clone().unwrap_or_else(|| {
+            output
+                .parent()
+                .unwrap_or(".".as_ref())
+                .join(".svg2pdf-cache")
+        }))
+    };
+
+    // The document can go to stdout, but not be rewritten or read back there
+    let to_stdout = !args.no_pdf && paths::is_stdio(&output);
+    if to_stdout {
+        if args.watch {
+            anyhow::bail!("--watch needs an output file to rewrite, not stdout");
+        }
+        if args.verify {
+            anyhow::bail!("--verify needs an output file to read back, not stdout");
+        }
+        if std::io::stdout().is_terminal() {
+            anyhow::bail!(
+                "Not writing a {} to a terminal; redirect stdout or pass an output file",
+                args.format.name()
+            );
+        }
+    }
+
+    // Fail on outputs that can't be written now, rather than after rendering
+    let index_path = output.parent().unwrap_or("".as_ref()).join("index.html");
+    let outputs = [
+        (!args.no_pdf && !to_stdout).then_some(output.as_path()),
+        args.html_index.then_some(index_path.as_path()),
+        args.hashes.as_deref(),
+        args.report.as_deref(),
+        args.trace_file.as_deref(),
+        args.preview.as_deref(),
+    ];
+    for path in outputs.into_iter().flatten() {
+        output::check_writable(path, args.create_dirs)?;
+    }
+
+    if args.cache_prune && cache_dir.is_none() {
+        anyhow::bail!("--cache-prune needs --incremental or --cache-dir");
+    }
+
+    // The gallery references exported images below its directory and embeds
+    // thumbnails of everything else
+    let index_dir = index_path.parent().unwrap_or("".as_ref());
+    let mut quality = args.render.quality.settings();
+    quality.dpi = args.render.dpi();
+    let thumbnails = args.html_index
+        && quality.thumbnails
+        && export
+            .as_ref()
+            .is_none_or(|export| export.dir().strip_prefix(index_dir).is_err());
+
+    let (available, memory_source) = match args.assume_memory {
+        Some(mb) => (Some(mb * 1024 * 1024), "from --assume-memory"),
+        None => (
+            budget::detect_memory(),
+            "MemAvailable, override with --assume-memory",
+        ),
+    };
+    let max_in_flight_mb = args
+        .max_in_flight_mb
+        .unwrap_or_else(|| budget::default_limit_mb(available));
+
+    // Refuse runs that can't fit into memory rather than being killed late
+    let jobs = args
+        .jobs
+        .map_or_else(rayon::current_num_threads, |jobs| jobs as usize);
+    let estimate = budget::Estimate {
+        workers: jobs as u64,
+        page_pixels: convert::page_pixels(&args.render),
+        pages: inputs.count()? as u64,
+        in_flight_limit: max_in_flight_mb * 1024 * 1024,
+        // PDF pages are written out as they come, TIFF and CBZ are built in memory
+        document: !args.no_pdf && args.format != Format::Pdf,
+        dedupe_limit: if args.no_dedupe {
+            0
+        } else {
+            dedupe::MEMORY_LIMIT as u64
+        },
+    };
+    let workers = fit_workers(args.jobs, &estimate, available, memory_source, args.verbose)?;
+
+    // Always say so for draft runs, so a draft isn't shipped by accident
+    if args.verbose || args.render.quality == Quality::Draft {
+        eprintln!(
+            "Quality {}: scale {}, {}",
+            format!("{:?}", args.render.quality).to_lowercase(),
+            args.render.scale,
+            quality
+        );
+    }
+
+    // Reproducible builds pin dates with SOURCE_DATE_EPOCH, which is no use
+    // when it can't be read
+    if let Ok(value) = std::env::var(metadata::SOURCE_DATE_EPOCH) {
+        match metadata::parse_epoch(&value) {
+            Err(err) if args.deterministic => anyhow::bail!("{err}"),
+            Err(err) => eprintln!("Warning: {err}, the document is dated now"),
+            Ok(_) => {}
+        }
+    }
+    if args.deterministic && args.sort == SortOrder::Mtime {
+        anyhow::bail!(
+            "--deterministic can't sort by mtime, which checkouts and copies change; pass --sort natural or lexical"
+        );
+    }
+    if args.annotations.is_some() && args.format != Format::Pdf {
+        anyhow::bail!(
+            "--annotations needs PDF output, {} has no annotations",
+            args.format.name()
+        );
+    }
+    if args.format != Format::Pdf {
+        let print_flags = [
+            ("--bleed", args.render.bleed.is_some()),
+            ("--crop-marks", args.crop_marks),
+            ("--no-print-scaling", args.no_print_scaling),
+            ("--duplex", args.duplex.is_some()),
+        ];
+        if let Some((flag, _)) = print_flags.iter().find(|(_, given)| *given) {
+            anyhow::bail!(
+                "{flag} needs PDF output, {} has no trim box or viewer preferences",
+                args.format.name()
+            );
+        }
+    }
+    if args.crop_marks && args.render.nup.is_some() {
+        anyhow::bail!("--crop-marks needs a page for each drawing, not --nup sheets");
+    }
+    let metadata = Metadata {
+        title: args.title.clone(),
+        author: args.author.clone(),
+        subject: args.subject.clone(),
+        keywords: args.keywords.clone(),
+        undated: args.deterministic,
+    };
+    let described = [
+        &metadata.title,
+        &metadata.author,
+        &metadata.subject,
+        &metadata.keywords,
+    ]
+    .iter()
+    .any(|value| value.is_some());
+    if described && args.format != Format::Pdf {
+        anyhow::bail!(
+            "--title, --author, --subject and --keywords need PDF output, {} has no document info",
+            args.format.name()
+        );
+    }
+    if args.bookmarks != BookmarkMode::None && args.format != Format::Pdf {
+        anyhow::bail!(
+            "--bookmarks needs PDF output, {} has no outline",
+            args.format.name()
+        );
+    }
+    let bookmarks = match (args.bookmarks, &args.bookmark_manifest) {
+        (BookmarkMode::Manifest, Some(manifest)) => Some(Bookmarks::load(manifest)?),
+        (_, Some(_)) => anyhow::bail!("--bookmark-manifest needs --bookmarks manifest"),
+        (BookmarkMode::Filename, None) => Some(Bookmarks::Filename),
+        // clap asks for the manifest of --bookmarks manifest
+        (BookmarkMode::None | BookmarkMode::Manifest, None) => None,
+    };
+    if args.render.background == Background::Transparent && args.format == Format::Tiff {
+        anyhow::bail!("--background transparent needs PDF or CBZ output, TIFF pages are opaque");
+    }
+    if args.render.nup.is_some() && args.format != Format::Pdf {
+        anyhow::bail!(
+            "--nup needs PDF output, {} has a page per drawing",
+            args.format.name()
+        );
+    }
+    if args.vector && args.format != Format::Pdf {
+        anyhow::bail!(
+            "--vector needs PDF output, {} pages are images",
+            args.format.name()
+        );
+    }
+    if args.color_mode == ColorMode::Bilevel {
+        if args.format == Format::Cbz {
+            anyhow::bail!("--color-mode bilevel needs PDF or TIFF output, CBZ pages are PNG");
+        }
+        if args.vector {
+            anyhow::bail!("--vector keeps the colors of the drawing, use --color-mode color");
+        }
+        if args.render.background == Background::Transparent {
+            anyhow::bail!("--background transparent needs color pages, bilevel ones are opaque");
+        }
+    } else if args.dither != Dither::None {
+        anyhow::bail!("--dither needs --color-mode bilevel, color pages keep their grays");
+    }
+    if args.append_to.is_some() && args.format != Format::Pdf {
+        anyhow::bail!("--append-to needs PDF output, not {}", args.format.name());
+    }
+    let annotations = args
+        .annotations
+        .as_deref()
+        .map(annotations::Annotations::load)
+        .transpose()?;
+    let existing = args
+        .append_to
+        .as_deref()
+        .map(|path| Existing::load(path, args.insert_at.map(|at| at as usize)))
+        .transpose()?;
+
+    let opt = convert::load_options();
+    // Builds of --watch keep their pages in memory for the next one too
+    let cache = match args.watch {
+        true => Some(PageCache::new(true, cache_dir)),
+        false => cache_dir.map(|dir| PageCache::new(false, Some(dir))),
+    };
+    let progress_mode = if args.tui {
+        ProgressMode::Tui
+    } else {
+        args.progress
+    };
+    let progress = progress_mode.reporter();
+    let run = RunOptions {
+        progress: progress.as_deref(),
+        cache: cache.as_ref(),
+        // The report lists them too
+        pixel_hashes: args.hashes.is_some() || args.report.is_some(),
+        export: export.as_ref(),
+        no_pdf: args.no_pdf,
+        format: args.format,
+        tiff_compression: args.tiff_compression,
+        images: ImageOptions {
+            format: args.image_format,
+            color_mode: args.color_mode,
+            dither: args.dither,
+            jpeg_quality: args
+                .jpeg_quality
+                .unwrap_or(args.render.quality.settings().jpeg_quality),
+            jpeg_subsampling: args.jpeg_subsampling,
+            #[cfg(feature = "jp2")]
+            jp2: match args.jp2_lossless {
+                true => jp2::Jp2Compression::Lossless,
+                false => jp2::Jp2Compression::Rate(args.jp2_rate),
+            },
+            overrides: args.image_format_for.clone(),
+        },
+        thumbnails,
+        preview: args.preview.as_ref().map(|_| args.preview_size),
+        io_threads: args.io_threads,
+        max_in_flight: Some((max_in_flight_mb * 1024 * 1024) as usize),
+        workers,
+        max_pixels: args.max_pixels,
+        dedupe: !args.no_dedupe,
+        blank_tolerance: args.blank_tolerance,
+        drop_blank_pages: args.drop_blank_pages,
+        reorder_work: !args.no_reorder_work,
+        explode_layers: args.explode_layers,
+        skip_layers: args.layer_filter.clone(),
+        dedupe_inputs: args.dedupe_inputs,
+        fail_on_unsupported: args.fail_on_unsupported,
+        allow_empty: args.allow_empty,
+        placeholder: args.placeholder.as_deref(),
+        copies: Copies {
+            all: args.copies,
+            overrides: args.copies_for.clone(),
+        },
+        retry: RetryPolicy {
+            retries: args.retries,
+            delay: args.retry_delay,
+        },
+        verify: args.verify,
+        annotations: annotations.as_ref(),
+        vector: args.vector,
+        sort: args.sort,
+        reverse: args.reverse,
+        on_error: args.on_error,
+        metadata,
+        bookmarks: bookmarks.as_ref(),
+        append_to: existing.as_ref(),
+        cancel: None,
+        // convert_dir adds the bleed of the pages
+        print: PrintSetup {
+            crop_marks: args.crop_marks,
+            no_print_scaling: args.no_print_scaling,
+            duplex: args.duplex,
+            ..PrintSetup::default()
+        },
+    };
+    if args.watch {
+        return watch::run(&opt, &inputs, &output, &args.render, &run);
+    }
+    let started = Instant::now();
+    let conversion = convert::convert_dir(&opt, &inputs, &output, &args.render, &run)?;
+    let elapsed = started.elapsed();
+
+    // What the run did, on stderr when stdout has the document
+    let mut out: Box<dyn Write> = match to_stdout {
+        true => Box::new(std::io::stderr()),
+        false => Box::new(std::io::stdout()),
+    };
+
+    for page in &conversion.pages {
+        for warning in &page.warnings {
+            eprintln!("Warning: {:?}: {}", page.path, warning);
+        }
+    }
+    if !conversion.failed.is_empty() {
+        eprintln!(
+            "{} files failed to convert and {}:",
+            conversion.failed.len(),
+            match args.on_error {
+                OnError::Blank => "have blank pages in their place",
+                _ => "were left out",
+            }
+        );
+        for file in &conversion.failed {
+            eprintln!("  {}: {:#}", file.id, file.error);
+        }
+    }
+    if conversion.empty {
+        match args.allow_empty {
+            Some(EmptyOutput::NoFile) => writeln!(
+                out,
+                "No pages to write, so no {} was written",
+                args.format.name()
+            )?,
+            _ => writeln!(
+                out,
+                "No pages to write, {} created with a placeholder page",
+                args.format.name()
+            )?,
+        }
+    } else if !args.no_pdf {
+        let pages = conversion.pages.iter().map(|page| page.copies).sum::<u32>() as usize;
+        match args.render.sheet() {
+            Some(sheet) => writeln!(
+                out,
+                "{} created successfully with {} drawings on {} pages!",
+                args.format.name(),
+                pages,
+                sheet.pages(pages)
+            )?,
+            None => writeln!(
+                out,
+                "{} created successfully with {} pages!",
+                args.format.name(),
+                pages
+            )?,
+        }
+        if let Some(existing) = &existing {
+            let added = args
+                .render
+                .sheet()
+                .map_or(pages, |sheet| sheet.pages(pages));
+            writeln!(
+                out,
+                "Added to the {} pages of {:?}, {} pages in all",
+                existing.pages(),
+                existing.path(),
+                existing.pages() + added
+            )?;
+        }
+        if args.verify {
+            writeln!(out, "{} read back and verified", args.format.name())?;
+        }
+    }
+    if let Some(export) = &export {
+        let written = conversion
+            .pages
+            .iter()
+            .filter(|page| page.image_path.is_some())
+            .count();
+        writeln!(out, "{} page images written to {:?}", written, export.dir())?;
+    }
+    // What --image-format auto and the overrides decided
+    if args.image_format == ImageFormat::Auto || !args.image_format_for.is_empty() {
+        let mut counts = std::collections::BTreeMap::new();
+        for page in &conversion.pages {
+            let Some(encoding) = page.encoding else {
+                continue;
+            };
+            let name = format!("{:?}", encoding).to_lowercase();
+            if args.verbose {
+                match &page.encoding_reason {
+                    Some(reason) => writeln!(out, "  {}: {} ({})", page.id, name, reason)?,
+                    None => writeln!(out, "  {}: {}", page.id, name)?,
+                }
+            }
+            *counts.entry(name).or_insert(0) += 1;
+        }
+        let counts: Vec<_> = counts
+            .iter()
+            .map(|(name, count)| format!("{} {}", count, name))
+            .collect();
+        if !counts.is_empty() {
+            writeln!(out, "Page images stored as: {}", counts.join(", "))?;
+        }
+    }
+    // Help tuning --max-in-flight-mb
+    if args.verbose || conversion.budget_waits > 0 {
+        writeln!(
+            out,
+            "Rendered pages waiting to be written peaked at {:.1} MiB of {} MiB allowed{}",
+            conversion.peak_in_flight as f64 / (1024.0 * 1024.0),
+            max_in_flight_mb,
+            match conversion.budget_waits {
+                0 => String::new(),
+                waits => format!(", workers waited for room {} times", waits),
+            }
+        )?;
+    }
+    if !conversion.skipped.is_empty() {
+        let skipped: Vec<_> = conversion
+            .skipped
+            .iter()
+            .map(|duplicate| format!("{} (same as {})", duplicate.id, duplicate.original))
+            .collect();
+        writeln!(
+            out,
+            "Skipped {} duplicate files: {}",
+            skipped.len(),
+            skipped.join(", ")
+        )?;
+    }
+    // Which features were missing from how many pages
+    let mut unsupported = std::collections::BTreeMap::new();
+    for page in &conversion.pages {
+        for feature in &page.unsupported {
+            *unsupported.entry(feature.name).or_insert(0) += 1;
+        }
+    }
+    if !unsupported.is_empty() {
+        let features: Vec<_> = unsupported
+            .iter()
+            .map(|(name, pages)| format!("{} ({} pages)", name, pages))
+            .collect();
+        writeln!(
+            out,
+            "Pages rendered without unsupported SVG features: {}",
+            features.join(", ")
+        )?;
+    }
+    let expanded: Vec<_> = conversion
+        .pages
+        .iter()
+        .filter_map(|page| Some(format!("{} ({})", page.id, page.expanded?)))
+        .collect();
+    if !expanded.is_empty() {
+        writeln!(
+            out,
+            "Expanded {} pages to fit content past their canvas: {}",
+            expanded.len(),
+            expanded.join(", ")
+        )?;
+    }
+    let repeated: Vec<_> = conversion
+        .pages
+        .iter()
+        .filter(|page| page.copies > 1)
+        .map(|page| format!("{} ({} copies)", page.id, page.copies))
+        .collect();
+    if !repeated.is_empty() {
+        writeln!(
+            out,
+            "Repeated {} pages: {}",
+            repeated.len(),
+            repeated.join(", ")
+        )?;
+    }
+    if let Some(annotations) = &annotations {
+        let placed: Vec<_> = conversion
+            .pages
+            .iter()
+            .map(|page| page.annotations)
+            .filter(|&placed| placed > 0)
+            .collect();
+        writeln!(
+            out,
+            "Placed {} of {} annotations on {} pages",
+            placed.iter().sum::<usize>(),
+            annotations.len(),
+            placed.len()
+        )?;
+        for stray in &conversion.stray_annotations {
+            eprintln!("Warning: {}", stray);
+        }
+    }
+    // Fonts only load for files with text
+    if let Some(fontdb) = convert::loaded_fonts().filter(|_| args.fonts.is_custom()) {
+        writeln!(
+            out,
+            "Loaded {} font faces from {}",
+            fontdb.len(),
+            args.fonts.sources()
+        )?;
+    }
+    if let Some(bookmarks) = &bookmarks {
+        let pages: Vec<_> = conversion
+            .pages
+            .iter()
+            .map(|page| (page.id.as_str(), page.path.as_path()))
+            .collect();
+        for stray in bookmarks.unmatched(&pages) {
+            eprintln!("Warning: {}", stray);
+        }
+    }
+    let retried: Vec<_> = conversion
+        .pages
+        .iter()
+        .chain(&conversion.dropped)
+        .filter(|page| page.timings.retries > 0)
+        .map(|page| format!("{} ({} retries)", page.id, page.timings.retries))
+        .collect();
+    if !retried.is_empty() {
+        writeln!(
+            out,
+            "Read {} files after retrying: {}",
+            retried.len(),
+            retried.join(", ")
+        )?;
+    }
+    if !conversion.dropped.is_empty() {
+        let dropped: Vec<_> = conversion
+            .dropped
+            .iter()
+            .map(|page| page.id.as_str())
+            .collect();
+        writeln!(
+            out,
+            "Dropped {} blank pages: {}",
+            dropped.len(),
+            dropped.join(", ")
+        )?;
+    }
+    let blank = conversion
+        .pages
+        .iter()
+        .filter(|page| page.blank.is_some())
+        .count();
+    if blank > 0 && !args.no_pdf && args.format == Format::Pdf {
+        writeln!(out, "{} blank pages stored as a plain fill", blank)?;
+    }
+    if conversion.started_early > 0 {
+        writeln!(
+            out,
+            "{} large files started first",
+            conversion.started_early
+        )?;
+    }
+    if conversion.dedupe_hits > 0 {
+        writeln!(
+            out,
+            "{} pages reused from identical files",
+            conversion.dedupe_hits
+        )?;
+    }
+    if cache.is_some() {
+        writeln!(
+            out,
+            "{} pages reused from the cache, {} rendered",
+            conversion.cache_hits,
+            conversion.rendered()
+        )?;
+    }
+    if let Some(cache) = cache.as_ref().filter(|_| args.cache_prune) {
+        let (count, bytes) = cache.prune().context("Failed to prune the page cache")?;
+        writeln!(
+            out,
+            "Pruned {} unused cache entries, {:.1} MiB",
+            count,
+            bytes as f64 / (1024.0 * 1024.0)
+        )?;
+    }
+
+    let file_timings: Vec<_> = conversion
+        .pages
+        .iter()
+        .chain(&conversion.dropped)
+        .map(|page| page.timings.clone())
+        .collect();
+    if args.timings {
+        timings::print_summary(&mut out, &file_timings, args.timings_top)?;
+    }
+    if let Some(trace_file) = &args.trace_file {
+        timings::write_trace(trace_file, &file_timings)?;
+        writeln!(out, "Trace written to {:?}", trace_file)?;
+    }
+    if let Some(preview) = &args.preview {
+        // The preview is a convenience; it never fails the run
+        match conversion
+            .preview
+            .as_ref()
+            .map(|pixmap| pixmap.save_png(preview))
+        {
+            Some(Ok(())) => writeln!(out, "Preview written to {:?}", preview)?,
+            Some(Err(err)) => eprintln!("Warning: failed to write preview {:?}: {}", preview, err),
+            None => eprintln!("Warning: no preview written to {:?}", preview),
+        }
+    }
+    if args.html_index {
+        let document = (!args.no_pdf && !to_stdout).then_some(output.as_path());
+        html::write_index(&index_path, &conversion, document)?;
+        writeln!(out, "HTML index written to {:?}", index_path)?;
+    }
+    if let Some(hashes) = &args.hashes {
+        hashes::write_manifest(hashes, &conversion)?;
+        writeln!(out, "Page hashes written to {:?}", hashes)?;
+    }
+    if let Some(path) = &args.report {
+        let numbering = report::Numbering {
+            before: existing.as_ref().map_or(0, Existing::pages_before),
+            existing: existing.as_ref().map_or(0, Existing::pages),
+            sheet: args.render.sheet(),
+        };
+        // No document is left when there was nothing to write, see --allow-empty
+        let no_file = conversion.empty && args.allow_empty == Some(EmptyOutput::NoFile);
+        let document =
+            (!args.no_pdf && !no_file).then(|| report::output_entry(&output, args.format.name()));
+        let report = report::build(&conversion, numbering, elapsed, document);
+        report::write_report(path, &report)?;
+        writeln!(out, "Report written to {:?}", path)?;
+    }
+    if !conversion.failed.is_empty() && !args.ignore_failures {
+        anyhow::bail!(
+            "{} files failed to convert; pass --ignore-failures to accept that",
+            conversion.failed.len()
+        );
+    }
+    Ok(())
+}
+
+// Render workers of --jobs, or as many of them as fit into `available`
+// bytes; None leaves them to rayon unless that many wouldn't fit
+fn fit_workers(
+    jobs: Option<u32>,
+    estimate: &budget::Estimate,
+    available: Option<u64>,
+    memory_source: &str,
+    verbose: bool,
+) -> Result<Option<usize>> {
+    let workers = jobs.map(|jobs| jobs as usize);
+    let Some(available) = available else {
+        return Ok(workers);
+    };
+    let explanation = estimate.explain(available, memory_source);
+    if estimate.total() <= available {
+        if verbose {
+            eprintln!("{explanation}");
+        }
+        return Ok(workers);
+    }
+    match estimate.fit_workers(available) {
+        Some(fit) => {
+            eprintln!("{explanation}\nReducing parallelism to {fit} workers to fit");
+            Ok(Some(fit as usize))
+        }
+        None => anyhow::bail!(
+            "{explanation}\nNot enough memory even with one worker; lower --max-in-flight-mb or --quality, or split the input"
+        ),
+    }
+}
+
+// Kept as it was written, lints and all
+#[test]
+#[allow(
+    deprecated,
+    unused_mut,
+    unused_variables,
+    clippy::field_reassign_with_default,
+    clippy::needless_borrows_for_generic_args
+)]
+fn test_scale_svg() {
+    use resvg::tiny_skia::{Color, Pixmap, Transform};
+    use resvg::usvg::{fontdb, Options, Tree};
+    use std::sync::Arc;
+
+    let mut fontdb = fontdb::Database::new();
+    fontdb.load_system_fonts();
+
+    let mut opt = Options::default();
+    opt.fontdb = Arc::from(fontdb);
+    let opt = Arc::new(opt);
+
+    // Sample SVG content (a simple rectangle)
+    let svg_data = r#"
+        <svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+            <rect x="10" y="10" width="30" height="30" fill="blue" />
+        </svg>
+        "#;
+
+    // Parse the SVG into a tree
+    let mut options = Options::default();
+
+    let tree =
+        Tree::from_data((&svg_data).as_ref(), &opt).expect("Parsing SVG failed with context");
+
+    // Define the scaling factor
+    let scale_factor = 2.0;
+    let size = tree.size();
+    let width = (size.width() * scale_factor) as u32;
+    let height = (size.height() * scale_factor) as u32;
+
+    let mut pixmap = Pixmap::new(width, height).expect("Failed to create pixel buffer");
+
+    let mut pixmap_mut = pixmap.as_mut();
+    pixmap_mut.fill(Color::from_rgba8(255, 255, 255, 255));
+
+    let transform = Transform::from_scale(scale_factor, scale_factor);
+
+    // Apply the scaling transformation
+    resvg::render(&tree, transform, &mut pixmap_mut);
+    let rgb_data: Vec<u8> = pixmap
+        .data()
+        .chunks(4)
+        .flat_map(|chunk| chunk[0..3].to_vec())
+        .collect();
+
+    // Verify scaling by checking the width and height of the root element
+    let image_svg = format!(
+        r#"
+        <svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}">
+            <image href="data:image/png;base64,{}" width="{width}" height="{height}" />
+        </svg>
+        "#,
+        base64::encode(&pixmap.encode_png().expect("Failed to encode PNG")),
+    );
+
+    // Assert root size
+    assert_eq!(width, 200);
+    assert_eq!(height, 200);
+}
+
+#[test]
+fn test_scaled_pixels() {
+    use base64::Engine;
+    use resvg::tiny_skia::{Color, Pixmap, Transform};
+    use resvg::usvg::{Options, Tree};
+
+    let svg_data = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+        <rect x="10" y="10" width="30" height="30" fill="blue"/>
+    </svg>"#;
+    let tree = Tree::from_data(svg_data.as_bytes(), &Options::default()).unwrap();
+    let mut pixmap = Pixmap::new(200, 200).unwrap();
+    pixmap.fill(Color::WHITE);
+    resvg::render(&tree, Transform::from_scale(2.0, 2.0), &mut pixmap.as_mut());
+
+    // The rectangle covers 20 to 80 once scaled, in RGB without alpha
+    let rgb_data: Vec<u8> = pixmap
+        .data()
+        .chunks(4)
+        .flat_map(|chunk| chunk[0..3].to_vec())
+        .collect();
+    assert_eq!(rgb_data.len(), 200 * 200 * 3);
+    let at = |x: usize, y: usize| &rgb_data[(y * 200 + x) * 3..][..3];
+    assert_eq!(at(50, 50), [0, 0, 255]);
+    assert_eq!(at(90, 50), [255, 255, 255]);
+
+    let png = pixmap.encode_png().unwrap();
+    let encoded = base64::engine::general_purpose::STANDARD.encode(&png);
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .unwrap();
+    assert_eq!(decoded, png);
+}
+
+#[test]
+fn test_jobs_fit_into_memory() {
+    let estimate = |workers| budget::Estimate {
+        workers,
+        page_pixels: 1000,
+        pages: 10,
+        in_flight_limit: 5000,
+        document: false,
+        dedupe_limit: 0,
+    };
+    let fit = |jobs: Option<u32>, available| {
+        let workers = jobs.map_or(16, u64::from);
+        fit_workers(jobs, &estimate(workers), available, "test", false)
+    };
+    // Each worker takes 7000 bytes, on top of the 5000 waiting
+    assert_eq!(fit(Some(8), None).unwrap(), Some(8));
+    assert_eq!(fit(Some(8), Some(1_000_000)).unwrap(), Some(8));
+    assert_eq!(fit(Some(8), Some(5_000 + 3 * 7_000)).unwrap(), Some(3));
+    // Without --jobs, rayon's default is only lowered when it doesn't fit
+    assert_eq!(fit(None, Some(1_000_000)).unwrap(), None);
+    assert_eq!(fit(None, Some(5_000 + 10 * 7_000)).unwrap(), Some(10));
+    let err = fit(Some(8), Some(5_000)).unwrap_err().to_string();
+    assert!(err.contains("even with one worker"), "{err}");
+}
+
+#[test]
+fn test_print_presets_fill_in_options() {
+    use crate::convert::Orientation;
+
+    let parse = |flags: &[&str]| {
+        let argv = ["svg2pdf", "in.svg", "-o", "out.pdf"].iter().chain(flags);
+        let matches = Cli::command().try_get_matches_from(argv).unwrap();
+        let mut args = Cli::from_arg_matches(&matches).unwrap();
+        if let Some(preset) = args.print_preset {
+            apply_print_preset(preset, &mut args, &matches);
+        }
+        args
+    };
+    let a4 = convert::parse_paper("a4").unwrap();
+
+    let offset = parse(&["--print-preset", "offset"]);
+    assert_eq!(offset.render.dpi, Some(300.0));
+    assert_eq!(offset.render.quality, Quality::Best);
+    assert_eq!(offset.image_format, ImageFormat::Flate);
+    assert!(offset.render.paper.is_none() && offset.render.margin.is_none());
+    assert_eq!(offset.render.bleed, Some(3.0));
+    assert!(offset.crop_marks && offset.no_print_scaling && offset.duplex.is_none());
+
+    let office = parse(&["--print-preset", "office"]);
+    assert_eq!(office.render.dpi, Some(150.0));
+    assert_eq!(office.render.quality, Quality::Normal);
+    assert_eq!(office.image_format, ImageFormat::Auto);
+    assert_eq!(office.render.paper.map(|paper| paper.width), Some(a4.width));
+    assert_eq!(office.render.orientation, Some(Orientation::Auto));
+    assert_eq!(office.render.margin, Some(10.0));
+    assert!(office.render.bleed.is_none() && !office.crop_marks);
+    assert!(office.no_print_scaling);
+    assert_eq!(office.duplex, Some(Duplex::LongEdge));
+    // Without a preset nothing changes
+    let plain = parse(&[]);
+    assert_eq!(plain.render.dpi, None);
+    assert!(plain.render.paper.is_none() && plain.render.margin.is_none());
+
+    // Flags given as well win, explicit defaults included
+    let given = parse(&[
+        "--print-preset",
+        "office",
+        "--dpi",
+        "200",
+        "--quality",
+        "normal",
+        "--image-format",
+        "flate",
+        "--paper",
+        "letter",
+        "--orientation",
+        "portrait",
+        "--margin",
+        "0",
+    ]);
+    assert_eq!(given.render.dpi, Some(200.0));
+    assert_eq!(given.image_format, ImageFormat::Flate);
+    assert_eq!(given.render.paper.map(|paper| paper.width), Some(612.0));
+    assert_eq!(given.render.orientation, Some(Orientation::Portrait));
+    assert_eq!(given.render.margin, Some(0.0));
+    let draft = parse(&["--print-preset", "offset", "--quality", "draft"]);
+    assert_eq!(draft.render.quality, Quality::Draft);
+    assert_eq!(draft.render.dpi, Some(300.0));
+    // --page-size keeps its pages, the margin goes on them
+    let sized = parse(&["--print-preset", "office", "--page-size", "300x200"]);
+    assert!(sized.render.paper.is_none() && sized.render.orientation.is_none());
+    assert_eq!(sized.render.margin, Some(10.0));
+    // Sheets get no bleed or crop marks, and TIFFs none of the PDF settings
+    let sheets = parse(&["--print-preset", "offset", "--nup", "2x1"]);
+    assert!(sheets.render.bleed.is_none() && !sheets.crop_marks);
+    assert!(sheets.no_print_scaling);
+    let tiff = parse(&["--print-preset", "offset", "--format", "tiff"]);
+    assert!(tiff.render.bleed.is_none() && !tiff.crop_marks && !tiff.no_print_scaling);
+
+    // Listing them needs no inputs
+    let matches = Cli::command().try_get_matches_from(["svg2pdf", "--list-print-presets"]);
+    assert!(matches.is_ok());
+}
@@ -0,0 +1,404 @@
+use crate::convert::RenderedImage;
+use crate::predictor;
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use flate2::read::ZlibDecoder;
+use lopdf::{Document, Object, Stream};
+use resvg::tiny_skia::{IntSize, Pixmap};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+#[derive(Args)]
+pub struct CompareArgs {
+    /// PDF to check
+    new: PathBuf,
+
+    /// PDF to compare against
+    reference: PathBuf,
+
+    /// Largest share of differing pixels a page may have, e.g. 0.5% or 0.005
+    #[arg(long, default_value = "0%", value_parser = parse_threshold)]
+    threshold: f64,
+
+    /// Per-channel difference (0-255) below which a pixel counts as unchanged
+    #[arg(long, default_value = "0")]
+    tolerance: u8,
+
+    /// Directory for difference heat maps of pages over the threshold
+    #[arg(long, default_value = "compare-diffs")]
+    diff_dir: PathBuf,
+}
+
+// Accepts a percentage ("0.5%") or a fraction ("0.005")
+fn parse_threshold(value: &str) -> Result<f64, String> {
+    let (number, scale) = match value.trim().strip_suffix('%') {
+        Some(number) => (number, 100.0),
+        None => (value.trim(), 1.0),
+    };
+    let threshold = number
+        .trim()
+        .parse::<f64>()
+        .map_err(|err| format!("invalid threshold {:?}: {}", value, err))?
+        / scale;
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(format!("threshold {:?} must be between 0% and 100%", value));
+    }
+    Ok(threshold)
+}
+
+// How much two rasters of the same size differ
+#[derive(Debug, PartialEq)]
+struct DiffMetrics {
+    // Share of pixels with any channel differing by more than the tolerance
+    differing: f64,
+    // Mean absolute channel difference, 0-1
+    mean_error: f64,
+    max_error: u8,
+}
+
+fn diff_metrics(new: &RenderedImage, reference: &RenderedImage, tolerance: u8) -> DiffMetrics {
+    let pixels = (new.width as usize * new.height as usize).max(1);
+    let mut differing = 0;
+    let mut total_error = 0u64;
+    let mut max_error = 0;
+    for (a, b) in new.rgb_data.chunks(3).zip(reference.rgb_data.chunks(3)) {
+        let error = pixel_error(a, b);
+        if error > tolerance {
+            differing += 1;
+        }
+        max_error = max_error.max(error);
+        total_error += a
+            .iter()
+            .zip(b)
+            .map(|(a, b)| a.abs_diff(*b) as u64)
+            .sum::<u64>();
+    }
+    DiffMetrics {
+        differing: differing as f64 / pixels as f64,
+        mean_error: total_error as f64 / (pixels as f64 * 3.0 * 255.0),
+        max_error,
+    }
+}
+
+// Largest channel difference of one pixel
+fn pixel_error(a: &[u8], b: &[u8]) -> u8 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| a.abs_diff(*b))
+        .max()
+        .unwrap_or(0)
+}
+
+// Faded reference with differing pixels in red, brighter for larger differences
+fn heat_map(new: &RenderedImage, reference: &RenderedImage, tolerance: u8) -> Result<Pixmap> {
+    let mut rgba = Vec::with_capacity(reference.rgb_data.len() / 3 * 4);
+    for (a, b) in new.rgb_data.chunks(3).zip(reference.rgb_data.chunks(3)) {
+        let error = pixel_error(a, b);
+        if error > tolerance {
+            rgba.extend_from_slice(&[128 + error / 2, 0, 0, 255]);
+        } else {
+            let luma = (b[0] as u32 * 299 + b[1] as u32 * 587 + b[2] as u32 * 114) / 1000;
+            let faded = (192 + luma / 4) as u8;
+            rgba.extend_from_slice(&[faded, faded, faded, 255]);
+        }
+    }
+    let size = IntSize::from_wh(reference.width, reference.height)
+        .context("Cannot create a heat map for an empty page")?;
+    Pixmap::from_vec(rgba, size).context("Failed to create heat map")
+}
+
+// Decode the pixels of every page, in page order. A page that cannot be
+// decoded is reported on its own instead of failing the whole document.
+fn load_pages(path: &Path) -> Result<Vec<Result<PageRaster>>> {
+    let doc = Document::load(path).with_context(|| format!("Failed to load PDF: {:?}", path))?;
+    Ok(doc
+        .get_pages()
+        .into_values()
+        .map(|page_id| page_image(&doc, page_id))
+        .collect())
+}
+
+// A decoded page: an image, or a blank page filled with a single color
+enum PageRaster {
+    Image(RenderedImage),
+    Fill([u8; 3]),
+}
+
+// Our pages are a single DeviceRGB image XObject, so the page raster is the
+// image itself. Blank pages have no image and only fill the page.
+fn page_image(doc: &Document, page_id: lopdf::ObjectId) -> Result<PageRaster> {
+    let page = doc.get_dictionary(page_id)?;
+    let resources = doc.dereference(page.get(b"Resources")?)?.1.as_dict()?;
+    let Ok(xobjects) = resources.get(b"XObject") else {
+        return page_fill(doc, page_id).map(PageRaster::Fill);
+    };
+    let xobjects = doc.dereference(xobjects)?.1.as_dict()?;
+    let image = xobjects
+        .iter()
+        .filter_map(|(_, object)| doc.dereference(object).ok()?.1.as_stream().ok())
+        .find(|stream| stream.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Image"))
+        .context("Page has no image; only PDFs written by svg2pdf can be compared")?;
+    decode_image(image).map(PageRaster::Image)
+}
+
+// The color a blank page is filled with
+fn page_fill(doc: &Document, page_id: lopdf::ObjectId) -> Result<[u8; 3]> {
+    let content = lopdf::content::Content::decode(&doc.get_page_content(page_id)?)?;
+    let operation = content
+        .operations
+        .iter()
+        .find(|operation| operation.operator == "rg")
+        .context(
+            "Page has neither an image nor a fill; only PDFs written by svg2pdf can be compared",
+        )?;
+    let channels = operation
+        .operands
+        .iter()
+        .map(|operand| Ok((operand.as_float()? * 255.0).round() as u8))
+        .collect::<Result<Vec<_>>>()?;
+    channels
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Fill color does not have 3 channels"))
+}
+
+// A page of `color`, `width` x `height` pixels
+fn solid(color: [u8; 3], width: u32, height: u32) -> RenderedImage {
+    RenderedImage {
+        width,
+        height,
+        rgb_data: color.repeat(width as usize * height as usize),
+        alpha: None,
+    }
+}
+
+fn decode_image(image: &Stream) -> Result<RenderedImage> {
+    let dict = &image.dict;
+    let width = dict.get(b"Width")?.as_i64()? as u32;
+    let height = dict.get(b"Height")?.as_i64()? as u32;
+
+    let color_space = dict.get(b"ColorSpace").and_then(Object::as_name);
+    let bits = dict.get(b"BitsPerComponent").and_then(Object::as_i64);
+    if color_space.ok() != Some(b"DeviceRGB") || bits.ok() != Some(8) {
+        bail!("Unsupported image format: only 8-bit DeviceRGB images can be compared");
+    }
+
+    let filters = image.filters().unwrap_or_default();
+    let rgb_data = match filters.as_slice() {
+        [] => image.content.clone(),
+        [filter] if filter == "FlateDecode" => {
+            let mut data = Vec::new();
+            ZlibDecoder::new(image.content.as_slice())
+                .read_to_end(&mut data)
+                .context("Failed to decompress image")?;
+            // PNG predictors, see writer::flate_page
+            let predictor = dict
+                .get(b"DecodeParms")
+                .and_then(Object::as_dict)
+                .and_then(|parms| parms.get(b"Predictor"))
+                .and_then(Object::as_i64);
+            match predictor {
+                Err(_) | Ok(1) => data,
+                Ok(10..=15) => predictor::unfilter_rows(&data, width)?,
+                Ok(predictor) => bail!("Unsupported image predictor {}", predictor),
+            }
+        }
+        [filter] if filter == "DCTDecode" => {
+            let mut decoder = jpeg_decoder::Decoder::new(image.content.as_slice());
+            let data = decoder.decode().context("Failed to decode JPEG image")?;
+            if decoder.info().map(|info| info.pixel_format)
+                != Some(jpeg_decoder::PixelFormat::RGB24)
+            {
+                bail!("Unsupported JPEG image: only RGB can be compared");
+            }
+            data
+        }
+        _ => bail!("Unsupported image filter {:?}", filters),
+    };
+
+    if rgb_data.len() != width as usize * height as usize * 3 {
+        bail!(
+            "Image data is {} bytes, expected {} for {}x{}",
+            rgb_data.len(),
+            width as usize * height as usize * 3,
+            width,
+            height
+        );
+    }
+    Ok(RenderedImage {
+        width,
+        height,
+        rgb_data,
+        alpha: None,
+    })
+}
+
+// Compare `new` against `reference` page by page. Returns whether every
+// page matched within the threshold.
+pub fn run(args: &CompareArgs) -> Result<bool> {
+    let new_pages = load_pages(&args.new)?;
+    let reference_pages = load_pages(&args.reference)?;
+
+    let mut passed = true;
+    if new_pages.len() != reference_pages.len() {
+        println!(
+            "Page count differs: {:?} has {} pages, {:?} has {}",
+            args.new,
+            new_pages.len(),
+            args.reference,
+            reference_pages.len()
+        );
+        passed = false;
+    }
+
+    let mut offending = 0;
+    for (index, (new, reference)) in new_pages.iter().zip(&reference_pages).enumerate() {
+        let page = index + 1;
+        let (new, reference) = match (new, reference) {
+            (Ok(new), Ok(reference)) => (new, reference),
+            (Err(err), _) | (_, Err(err)) => {
+                println!("page {page}: cannot decode: {:#}", err);
+                passed = false;
+                continue;
+            }
+        };
+        // Blank pages take the size of the page they are compared with
+        let (filled_new, filled_reference);
+        let (new, reference) = match (new, reference) {
+            (PageRaster::Image(new), PageRaster::Image(reference)) => (new, reference),
+            (PageRaster::Fill(color), PageRaster::Image(reference)) => {
+                filled_new = solid(*color, reference.width, reference.height);
+                (&filled_new, reference)
+            }
+            (PageRaster::Image(new), PageRaster::Fill(color)) => {
+                filled_reference = solid(*color, new.width, new.height);
+                (new, &filled_reference)
+            }
+            (PageRaster::Fill(new), PageRaster::Fill(reference)) => {
+                filled_new = solid(*new, 1, 1);
+                filled_reference = solid(*reference, 1, 1);
+                (&filled_new, &filled_reference)
+            }
+        };
+        if (new.width, new.height) != (reference.width, reference.height) {
+            println!(
+                "page {page}: size differs: {}x{} vs {}x{}",
+                new.width, new.height, reference.width, reference.height
+            );
+            passed = false;
+            continue;
+        }
+
+        let metrics = diff_metrics(new, reference, args.tolerance);
+        let over = metrics.differing > args.threshold;
+        println!(
+            "page {page}: {:.3}% pixels differ, mean error {:.3}%, max {}{}",
+            metrics.differing * 100.0,
+            metrics.mean_error * 100.0,
+            metrics.max_error,
+            if over { "  FAIL" } else { "" }
+        );
+        if over {
+            passed = false;
+            offending += 1;
+            fs::create_dir_all(&args.diff_dir)
+                .with_context(|| format!("Failed to create {:?}", args.diff_dir))?;
+            let path = args.diff_dir.join(format!("page-{page:04}.png"));
+            heat_map(new, reference, args.tolerance)?
+                .save_png(&path)
+                .with_context(|| format!("Failed to write heat map {:?}", path))?;
+        }
+    }
+
+    if offending > 0 {
+        println!(
+            "{} pages over the {}% threshold, heat maps written to {:?}",
+            offending,
+            args.threshold * 100.0,
+            args.diff_dir
+        );
+    }
+    println!("{}", if passed { "PASSED" } else { "FAILED" });
+    Ok(passed)
+}
+
+#[test]
+fn test_threshold_and_metrics() {
+    assert_eq!(parse_threshold("0.5%"), Ok(0.005));
+    assert_eq!(parse_threshold("0.005"), Ok(0.005));
+    assert!(parse_threshold("150%").is_err());
+    assert!(parse_threshold("half").is_err());
+
+    let image = |rgb_data: Vec<u8>| RenderedImage {
+        width: 2,
+        height: 1,
+        rgb_data,
+        alpha: None,
+    };
+    let reference = image(vec![255, 255, 255, 0, 0, 0]);
+    let new = image(vec![255, 255, 250, 0, 0, 0]);
+    assert_eq!(diff_metrics(&reference, &reference, 0).differing, 0.0);
+    let metrics = diff_metrics(&new, &reference, 0);
+    assert_eq!(metrics.differing, 0.5);
+    assert_eq!(metrics.max_error, 5);
+    assert_eq!(diff_metrics(&new, &reference, 5).differing, 0.0);
+
+    // Pages come back out of our own PDFs unchanged
+    let mut stream = Stream::new(
+        lopdf::Dictionary::from_iter(vec![
+            ("Subtype", Object::Name(b"Image".to_vec())),
+            ("Width", Object::Integer(2)),
+            ("Height", Object::Integer(1)),
+            ("ColorSpace", Object::Name(b"DeviceRGB".to_vec())),
+            ("BitsPerComponent", Object::Integer(8)),
+        ]),
+        new.rgb_data.clone(),
+    );
+    assert_eq!(decode_image(&stream).unwrap().rgb_data, new.rgb_data);
+    stream
+        .dict
+        .set("Filter", Object::Name(b"FlateDecode".to_vec()));
+    stream.set_content({
+        use flate2::write::ZlibEncoder;
+        use std::io::Write;
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&new.rgb_data).unwrap();
+        encoder.finish().unwrap()
+    });
+    assert_eq!(decode_image(&stream).unwrap().rgb_data, new.rgb_data);
+}
+
+#[test]
+fn test_fill_pages_compare_as_solid_images() {
+    use crate::writer::{ContainerWriter, ImageOptions, PdfWriter};
+
+    let mut writer = PdfWriter::new(1.0, ImageOptions::default());
+    let encoder = writer.encoder();
+    let fill = encoder.encode_fill(4, 2, [10, 128, 255]).unwrap();
+    let image = encoder
+        .encode(
+            &RenderedImage {
+                width: 4,
+                height: 2,
+                rgb_data: [10, 128, 255].repeat(8),
+                alpha: None,
+            },
+            "b.svg",
+        )
+        .unwrap();
+    writer.add_page(fill).unwrap();
+    writer.add_page(image).unwrap();
+    let mut pdf = Vec::new();
+    Box::new(writer).finish(&mut pdf).unwrap();
+
+    let doc = Document::load_mem(&pdf).unwrap();
+    let pages = doc.get_pages();
+    let Ok(PageRaster::Fill(color)) = page_image(&doc, pages[&1]) else {
+        panic!("page 1 is not a fill");
+    };
+    let Ok(PageRaster::Image(image)) = page_image(&doc, pages[&2]) else {
+        panic!("page 2 is not an image");
+    };
+    assert_eq!(color, [10, 128, 255]);
+    assert_eq!(solid(color, 4, 2).rgb_data, image.rgb_data);
+}
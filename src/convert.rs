@@ -0,0 +1,3691 @@
+use crate::annotations::{self, Annotations};
+use crate::append::Existing;
+use crate::bookmarks::Bookmarks;
+use crate::budget::Budget;
+use crate::cache::{self, CacheKey, CachedPage, OptionsHashes, PageCache};
+use crate::dedupe::{self, Dedupe, Duplicate};
+use crate::error::Error;
+use crate::export::{self, ImageExport};
+use crate::fonts;
+use crate::inputs::Inputs;
+use crate::layers;
+use crate::metadata::Metadata;
+use crate::names;
+use crate::nup::{self, Grid, Sheet};
+use crate::output::Staged;
+use crate::paths;
+use crate::pixels;
+use crate::pool;
+use crate::print::PrintSetup;
+use crate::progress::{self, Event, Progress};
+use crate::readahead::{self, Dispenser, Prefetched};
+use crate::retry::RetryPolicy;
+use crate::sniff;
+use crate::timings::{FileTimings, Stage};
+use crate::unsupported::{self, Feature};
+use crate::vector::{self, VectorPage};
+use crate::verify;
+use crate::writer::{
+    ContainerWriter, EncodedPage, Encoding, Format, ImageOptions, PageEncoder, TiffCompression,
+    DEFAULT_JPEG_QUALITY,
+};
+use anyhow::{Context, Result};
+use clap::{Args, ValueEnum};
+use resvg::tiny_skia::{Pixmap, Transform};
+use resvg::{self, usvg};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, OnceLock};
+use std::time::Instant;
+use usvg::{fontdb, Options, Tree};
+
+// Options that affect how each page is rendered
+#[derive(Args, Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RenderArgs {
+    /// Points of page per CSS pixel of the drawing (e.g., 1.0 for original size); only sizes the page, see --dpi for its pixels
+    #[arg(short, long, default_value = "0.1")]
+    pub scale: f32,
+
+    /// Pixels rendered per inch of the drawing, taking 96 CSS pixels to the inch, whatever size the page is [default: 96, 48 with --quality draft, 192 with best]
+    #[arg(long, value_parser = parse_dpi)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dpi: Option<f32>,
+
+    /// Rendering quality preset: draft is fast and coarse, best is slow and fine
+    #[arg(long, value_enum, default_value_t = Quality::Normal)]
+    pub quality: Quality,
+
+    /// Fit drawings without their own width and height into WxH pixels, keeping their aspect ratio [default: the viewBox size]
+    #[arg(long, value_name = "WxH", value_parser = parse_size)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_size: Option<DrawingSize>,
+
+    /// Grow pages to fit content that reaches past the drawing's canvas instead of clipping it
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub expand_to_content: bool,
+
+    /// Make every page WxH points, fitting each drawing in centered and keeping its aspect ratio, in place of --scale [default: the size of the drawing]
+    #[arg(long, value_name = "WxH", value_parser = parse_size)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_size: Option<DrawingSize>,
+
+    /// Make every page a sheet of paper: a4, a3, letter, legal or WxHmm such as 200x100mm, fitting each drawing inside the margins, centered, in place of --scale
+    #[arg(long, value_name = "SIZE", value_parser = parse_paper, conflicts_with = "page_size")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paper: Option<DrawingSize>,
+
+    /// Space left clear on each side of --paper, --page-size or --nup pages, in millimeters [default: 0]
+    #[arg(long, value_name = "MM", value_parser = parse_mm)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub margin: Option<f32>,
+
+    /// Grow pages sized by their drawings by MM on each side, for the print shop to trim; the drawing goes on into it where it reaches past its canvas, and PDF pages get a TrimBox and BleedBox [default: 0]
+    #[arg(long, value_name = "MM", value_parser = parse_mm)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bleed: Option<f32>,
+
+    /// Which way --paper pages are turned; auto turns them to landscape for drawings wider than tall, or --nup grids of more columns than rows [default: as --paper gives them, portrait for named sizes]
+    #[arg(long, value_enum, requires = "paper")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<Orientation>,
+
+    /// Put C columns of R drawings on each PDF page, row by row in input order, fitting each into its cell, on --paper or --page-size pages [default: one drawing per page; A4 pages with --nup]
+    #[arg(long, value_name = "CxR", value_parser = nup::parse_grid)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nup: Option<Grid>,
+
+    /// Space between the cells of --nup pages, in millimeters [default: 5]
+    #[arg(long, value_name = "MM", value_parser = parse_mm, requires = "nup")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gutter: Option<f32>,
+
+    /// Write the file name of each drawing under its cell of --nup pages
+    #[arg(long, requires = "nup")]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub nup_labels: bool,
+
+    /// What shows where the drawing leaves the page uncovered: white, transparent, which keeps the alpha channel as a soft mask, or a color such as #1e1e1e
+    #[arg(long, value_name = "COLOR", default_value = "white", value_parser = parse_background)]
+    #[serde(default, skip_serializing_if = "Background::is_white")]
+    pub background: Background,
+}
+
+// CSS pixels per inch, the unit of drawings
+const CSS_DPI: f32 = 96.0;
+
+// Width and height of a drawing in CSS pixels, or of a page in points
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DrawingSize {
+    pub width: f32,
+    pub height: f32,
+}
+
+// How far a drawing reaches past its canvas on each side, in CSS pixels
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Expansion {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+impl Expansion {
+    // Of everything `tree` draws, filters and strokes included, past the
+    // canvas; None when it all fits
+    pub fn of(tree: &Tree) -> Option<Expansion> {
+        let size = tree.size();
+        let bounds = tree.root().abs_layer_bounding_box();
+        let expansion = Expansion {
+            left: (-bounds.left()).max(0.0),
+            top: (-bounds.top()).max(0.0),
+            right: (bounds.right() - size.width()).max(0.0),
+            bottom: (bounds.bottom() - size.height()).max(0.0),
+        };
+        (expansion != Expansion::default()).then_some(expansion)
+    }
+}
+
+impl std::fmt::Display for Expansion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let sides = [
+            (self.left, "left"),
+            (self.top, "top"),
+            (self.right, "right"),
+            (self.bottom, "bottom"),
+        ];
+        let sides: Vec<_> = sides
+            .iter()
+            .filter(|(by, _)| *by > 0.0)
+            .map(|(by, side)| format!("{:.0} px {}", by, side))
+            .collect();
+        write!(f, "{}", sides.join(", "))
+    }
+}
+
+// Parse a size given as WxH, e.g. 800x600
+pub fn parse_size(value: &str) -> Result<DrawingSize, String> {
+    let (width, height) = value
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("invalid size {:?}, expected WxH such as 800x600", value))?;
+    let side = |side: &str| match side.trim().parse::<f32>() {
+        Ok(side) if side.is_finite() && side > 0.0 => Ok(side),
+        _ => Err(format!(
+            "invalid size {:?}, sides must be positive numbers",
+            value
+        )),
+    };
+    Ok(DrawingSize {
+        width: side(width)?,
+        height: side(height)?,
+    })
+}
+
+// Points per millimeter
+pub const MM: f32 = 72.0 / 25.4;
+
+// Millimeters between the cells of --nup pages, unless --gutter says
+const DEFAULT_GUTTER: f32 = 5.0;
+
+// Parse a paper size, a4, a3, letter or legal, or WxHmm such as 200x100mm,
+// into points
+pub fn parse_paper(value: &str) -> Result<DrawingSize, String> {
+    let (width, height) = match value.trim().to_ascii_lowercase().as_str() {
+        "a4" => (210.0 * MM, 297.0 * MM),
+        "a3" => (297.0 * MM, 420.0 * MM),
+        "letter" => (612.0, 792.0),
+        "legal" => (612.0, 1008.0),
+        custom => {
+            let size = custom
+                .strip_suffix("mm")
+                .ok_or(())
+                .and_then(|size| parse_size(size).map_err(|_| ()))
+                .map_err(|_| {
+                    format!(
+                        "invalid paper {:?}, expected a4, a3, letter, legal or WxHmm such as 200x100mm",
+                        value
+                    )
+                })?;
+            (size.width * MM, size.height * MM)
+        }
+    };
+    Ok(DrawingSize { width, height })
+}
+
+// Parse a length in millimeters such as 10 or 12.5
+pub fn parse_mm(value: &str) -> Result<f32, String> {
+    match value.trim().parse::<f32>() {
+        Ok(mm) if mm.is_finite() && mm >= 0.0 => Ok(mm),
+        _ => Err(format!(
+            "invalid length {:?}, expected millimeters such as 10",
+            value
+        )),
+    }
+}
+
+// Which way a sheet of paper is turned
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+    // Landscape for drawings wider than tall, portrait for the others
+    Auto,
+}
+
+// What is behind a drawing on its page
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Background {
+    Color([u8; 3]),
+    // Left to whatever the page is put on, through a soft mask
+    Transparent,
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Color([255; 3])
+    }
+}
+
+impl Background {
+    pub fn is_white(&self) -> bool {
+        *self == Background::default()
+    }
+}
+
+// Parse a background: white, transparent or #RRGGBB
+pub fn parse_background(value: &str) -> Result<Background, String> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("white") {
+        return Ok(Background::default());
+    }
+    if value.eq_ignore_ascii_case("transparent") {
+        return Ok(Background::Transparent);
+    }
+    let color = value
+        .strip_prefix('#')
+        .filter(|hex| hex.len() == 6 && hex.is_ascii())
+        .and_then(|hex| {
+            let channel = |at: usize| u8::from_str_radix(&hex[at..at + 2], 16).ok();
+            Some([channel(0)?, channel(2)?, channel(4)?])
+        })
+        .ok_or_else(|| {
+            format!(
+                "invalid background {:?}, expected white, transparent or a color such as #1e1e1e",
+                value
+            )
+        })?;
+    Ok(Background::Color(color))
+}
+
+// Parse a resolution in dots per inch such as 300 or 150.5
+pub fn parse_dpi(value: &str) -> Result<f32, String> {
+    match value.trim().parse::<f32>() {
+        Ok(dpi) if dpi.is_finite() && dpi > 0.0 => Ok(dpi),
+        _ => Err(format!(
+            "invalid resolution {:?}, expected a positive number of dots per inch such as 300",
+            value
+        )),
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Quality {
+    Draft,
+    #[default]
+    Normal,
+    Best,
+}
+
+// The effective settings a quality preset stands for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QualitySettings {
+    // Rendered pixels per inch of the drawing, unless --dpi says otherwise
+    pub dpi: f32,
+    pub anti_alias: bool,
+    // Smooth (bicubic) rather than nearest-neighbor scaling of embedded images
+    pub smooth_images: bool,
+    pub thumbnails: bool,
+    // For pages stored as JPEG, unless --jpeg-quality says otherwise
+    pub jpeg_quality: u8,
+}
+
+impl Quality {
+    pub fn settings(self) -> QualitySettings {
+        match self {
+            Quality::Draft => QualitySettings {
+                dpi: 48.0,
+                anti_alias: false,
+                smooth_images: false,
+                thumbnails: false,
+                jpeg_quality: 50,
+            },
+            Quality::Normal => QualitySettings {
+                dpi: 96.0,
+                anti_alias: true,
+                smooth_images: true,
+                thumbnails: true,
+                jpeg_quality: DEFAULT_JPEG_QUALITY,
+            },
+            Quality::Best => QualitySettings {
+                dpi: 192.0,
+                anti_alias: true,
+                smooth_images: true,
+                thumbnails: true,
+                jpeg_quality: 95,
+            },
+        }
+    }
+
+    // The usvg options for this preset, on top of `opt`
+    fn options(self, opt: &Arc<Options<'static>>) -> Arc<Options<'static>> {
+        if self == Quality::Normal {
+            return opt.clone();
+        }
+        let settings = self.settings();
+        // Hand text to the font resolver of `opt`, which may load fonts lazily
+        let (base, fallback_base) = (Arc::clone(opt), Arc::clone(opt));
+        Arc::new(Options {
+            font_resolver: usvg::FontResolver {
+                select_font: Box::new(move |font, fontdb| {
+                    (base.font_resolver.select_font)(font, fontdb)
+                }),
+                select_fallback: Box::new(move |c, used, fontdb| {
+                    (fallback_base.font_resolver.select_fallback)(c, used, fontdb)
+                }),
+            },
+            resources_dir: opt.resources_dir.clone(),
+            dpi: opt.dpi,
+            font_family: opt.font_family.clone(),
+            font_size: opt.font_size,
+            languages: opt.languages.clone(),
+            default_size: opt.default_size,
+            fontdb: opt.fontdb.clone(),
+            shape_rendering: if settings.anti_alias {
+                usvg::ShapeRendering::GeometricPrecision
+            } else {
+                usvg::ShapeRendering::CrispEdges
+            },
+            text_rendering: match self {
+                Quality::Draft => usvg::TextRendering::OptimizeSpeed,
+                _ => usvg::TextRendering::GeometricPrecision,
+            },
+            image_rendering: if settings.smooth_images {
+                usvg::ImageRendering::OptimizeQuality
+            } else {
+                usvg::ImageRendering::OptimizeSpeed
+            },
+            ..Options::default()
+        })
+    }
+}
+
+impl std::fmt::Display for QualitySettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} dpi, anti-aliasing {}, {} image scaling, thumbnails {}, JPEG quality {}",
+            self.dpi,
+            if self.anti_alias { "on" } else { "off" },
+            if self.smooth_images {
+                "smooth"
+            } else {
+                "nearest-neighbor"
+            },
+            if self.thumbnails { "on" } else { "off" },
+            self.jpeg_quality
+        )
+    }
+}
+
+impl RenderArgs {
+    // Rendered pixels per inch of the drawing
+    pub fn dpi(&self) -> f32 {
+        self.dpi.unwrap_or(self.quality.settings().dpi)
+    }
+
+    // Rendered pixels per page point along each axis. The pixels only
+    // depend on the drawing and --dpi: a page --scale makes larger gets
+    // fewer pixels per point, and --page-size pages take the place of the
+    // drawing at scale 1, as do --paper pages and --nup cells.
+    pub fn resolution(&self) -> f32 {
+        let scale = match self.fixed_page(false) {
+            Some(_) => 1.0,
+            None => self.scale,
+        };
+        self.dpi() / CSS_DPI / scale
+    }
+
+    // The page every drawing is fit into and the space to leave clear on
+    // each side of it, in points, if --page-size, --paper or --nup fix one.
+    // `wide` tells whether the drawing is wider than tall, for
+    // --orientation auto.
+    fn fixed_page(&self, wide: bool) -> Option<(DrawingSize, f32)> {
+        match self.sheet() {
+            Some(sheet) => Some((sheet.cell(), 0.0)),
+            None => self.paper_page(wide),
+        }
+    }
+
+    // Points of --bleed
+    pub fn bleed(&self) -> f32 {
+        self.bleed.unwrap_or(0.0) * MM
+    }
+
+    // The page of --page-size or --paper and its margin
+    fn paper_page(&self, wide: bool) -> Option<(DrawingSize, f32)> {
+        let margin = self.margin.unwrap_or(0.0) * MM;
+        if let Some(page) = self.page_size {
+            return Some((page, margin));
+        }
+        let paper = self.paper?;
+        let landscape = match self.orientation {
+            None => paper.width > paper.height,
+            Some(Orientation::Portrait) => false,
+            Some(Orientation::Landscape) => true,
+            Some(Orientation::Auto) => wide,
+        };
+        let (short, long) = (paper.width.min(paper.height), paper.width.max(paper.height));
+        let page = match landscape {
+            true => DrawingSize {
+                width: long,
+                height: short,
+            },
+            false => DrawingSize {
+                width: short,
+                height: long,
+            },
+        };
+        Some((page, margin))
+    }
+
+    // The pages of --nup, each --page-size or --paper, or else A4, with
+    // grids of more columns than rows turned by --orientation auto
+    pub fn sheet(&self) -> Option<Sheet> {
+        let grid = self.nup?;
+        let a4 = DrawingSize {
+            width: 210.0 * MM,
+            height: 297.0 * MM,
+        };
+        let (page, margin) = self
+            .paper_page(grid.columns > grid.rows)
+            .unwrap_or((a4, self.margin.unwrap_or(0.0) * MM));
+        Some(Sheet {
+            grid,
+            width: page.width,
+            height: page.height,
+            margin,
+            gutter: self.gutter.unwrap_or(DEFAULT_GUTTER) * MM,
+            labels: self.nup_labels,
+        })
+    }
+
+    // What is wrong with the page options together, such as margins that
+    // leave no room on the page
+    pub fn check(&self) -> Result<(), String> {
+        if self.bleed.is_some() && self.fixed_page(false).is_some() {
+            return Err(
+                "--bleed needs pages sized by their drawings, not --paper, --page-size or --nup"
+                    .to_string(),
+            );
+        }
+        if let Some(margin) = self.margin {
+            let page = self
+                .sheet()
+                .map(|sheet| (sheet.width, sheet.height))
+                .or_else(|| {
+                    self.paper_page(false)
+                        .map(|(page, _)| (page.width, page.height))
+                });
+            let Some((width, height)) = page else {
+                return Err("--margin needs --paper, --page-size or --nup".to_string());
+            };
+            if 2.0 * margin * MM >= width.min(height) {
+                return Err(format!(
+                    "--margin {} mm leaves no room on {:.0}x{:.0} mm pages",
+                    margin,
+                    width / MM,
+                    height / MM
+                ));
+            }
+        }
+        if let Some(sheet) = self.sheet() {
+            let cell = sheet.cell();
+            if cell.width <= 0.0 || cell.height <= 0.0 {
+                return Err(format!(
+                    "--nup {}x{} leaves no room for the drawings on {:.0}x{:.0} mm pages",
+                    sheet.grid.columns,
+                    sheet.grid.rows,
+                    sheet.width / MM,
+                    sheet.height / MM
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Defaults come from the clap definitions so they are declared only once
+impl Default for RenderArgs {
+    fn default() -> Self {
+        #[derive(clap::Parser)]
+        struct Defaults {
+            #[command(flatten)]
+            render: RenderArgs,
+        }
+        <Defaults as clap::Parser>::parse_from(["svg2pdf"]).render
+    }
+}
+
+// An SVG to convert, either a file on disk or bytes already in memory
+#[derive(Clone)]
+pub struct Source {
+    pub path: PathBuf,
+    // Path relative to the input root, see names::page_id
+    pub id: String,
+    pub data: Option<Vec<u8>>,
+    // The only layer rendered, see layers::explode
+    pub layer: Option<usize>,
+}
+
+impl Source {
+    pub fn file(root: &Path, path: PathBuf) -> Self {
+        Source {
+            id: names::page_id(root, &path),
+            path,
+            data: None,
+            layer: None,
+        }
+    }
+
+    pub fn bytes(name: impl Into<PathBuf>, data: Vec<u8>) -> Self {
+        let path = name.into();
+        Source {
+            id: names::page_id("".as_ref(), &path),
+            path,
+            data: Some(data),
+            layer: None,
+        }
+    }
+
+    // Size in bytes, 0 if the file can't be read; reading reports that
+    pub fn size(&self) -> u64 {
+        match &self.data {
+            Some(data) => data.len() as u64,
+            None => fs::metadata(paths::long_path(&self.path)).map_or(0, |metadata| metadata.len()),
+        }
+    }
+
+    pub fn read(&self) -> std::io::Result<Cow<'_, [u8]>> {
+        match &self.data {
+            Some(data) => Ok(Cow::Borrowed(data)),
+            None => fs::read(paths::long_path(&self.path)).map(Cow::Owned),
+        }
+    }
+}
+
+// The sources of a run, produced one at a time as the pipeline takes them
+pub struct Sources<'a> {
+    // Expected number of sources, for progress and sizing
+    pub total: usize,
+    // Whether any of them has to be read from disk
+    pub files: bool,
+    iter: Box<dyn Iterator<Item = Source> + Send + 'a>,
+}
+
+impl<'a> Sources<'a> {
+    pub fn new(total: usize, files: bool, iter: impl Iterator<Item = Source> + Send + 'a) -> Self {
+        Sources {
+            total,
+            files,
+            iter: Box::new(iter),
+        }
+    }
+}
+
+impl From<Vec<Source>> for Sources<'static> {
+    fn from(sources: Vec<Source>) -> Self {
+        let files = sources.iter().any(|source| source.data.is_none());
+        Sources::new(sources.len(), files, sources.into_iter())
+    }
+}
+
+impl Iterator for Sources<'_> {
+    type Item = Source;
+
+    fn next(&mut self) -> Option<Source> {
+        self.iter.next()
+    }
+}
+
+// Pixel data of a rendered page
+pub struct RenderedImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgb_data: Vec<u8>,
+    // Alpha of every pixel of a page with Background::Transparent, whose
+    // colors are then straight; None where the page is opaque throughout
+    pub alpha: Option<Vec<u8>>,
+}
+
+impl RenderedImage {
+    // Memory the pixels take
+    pub fn bytes(&self) -> usize {
+        self.rgb_data.len() + self.alpha.as_ref().map_or(0, Vec::len)
+    }
+}
+
+// Structure to hold rendered page data
+struct PageData {
+    index: usize,
+    image: Arc<RenderedImage>,
+    info: PageInfo,
+    // What goes into the document instead of `image`, with RunOptions::vector
+    vector: Option<Arc<VectorPage>>,
+    // Where the page goes in the cache once it is encoded
+    cache_key: Option<CacheKey>,
+}
+
+impl PageData {
+    fn new(
+        index: usize,
+        source: &Source,
+        image: Arc<RenderedImage>,
+        timings: FileTimings,
+        image_path: Option<PathBuf>,
+        warnings: Vec<String>,
+        run: &RunOptions,
+    ) -> Self {
+        let mut info = PageInfo::new(source, image.width, image.height, timings, run);
+        info.pixel_hash = run.pixel_hashes.then(|| cache::pixel_hash(&image));
+        info.thumbnail = run
+            .thumbnails
+            .then(|| {
+                export::thumbnail(&image, export::THUMBNAIL_SIZE)?
+                    .encode_png()
+                    .ok()
+            })
+            .flatten();
+        info.image_path = image_path;
+        info.warnings = warnings;
+        PageData {
+            index,
+            image,
+            info,
+            vector: None,
+            cache_key: None,
+        }
+    }
+}
+
+// A page of render_page: rendered, or taken from the cache ready to write
+enum Page {
+    Rendered(PageData),
+    Cached(ReadyPage),
+}
+
+// What is known about a page once its pixels have been written
+pub struct PageInfo {
+    pub path: PathBuf,
+    pub id: String,
+    // Size of the rendered pixels
+    pub width: u32,
+    pub height: u32,
+    pub timings: FileTimings,
+    // SHA-256 of the rendered RGB data, before any encoding
+    pub pixel_hash: Option<String>,
+    // PNG written for the page with --export-images
+    pub image_path: Option<PathBuf>,
+    // Small PNG of the page, when requested
+    pub thumbnail: Option<Vec<u8>>,
+    // Problems noticed while rendering; pages reused from the cache have none
+    pub warnings: Vec<String>,
+    // Reused from the page cache instead of rendered
+    pub cached: bool,
+    // Reused from a byte-identical file earlier in the run
+    pub deduped: bool,
+    // Color of a page without any detail, see blank_color
+    pub blank: Option<[u8; 3]>,
+    // How the writer stored the page, and why when it picked automatically
+    pub encoding: Option<Encoding>,
+    pub encoding_reason: Option<String>,
+    // Content past the canvas the page was grown for, with
+    // --expand-to-content; pages reused from the cache have none
+    pub expanded: Option<Expansion>,
+    // Features the page was rendered without; pages reused from the cache
+    // have none
+    pub unsupported: Vec<Feature>,
+    // Times the page is in the document, one after the other
+    pub copies: u32,
+    // Where SVG user units are on the page, in points from its top left;
+    // only known for pages with annotations
+    pub placement: Option<Transform>,
+    // Annotations put on the page, with RunOptions::annotations
+    pub annotations: usize,
+}
+
+impl PageInfo {
+    fn new(
+        source: &Source,
+        width: u32,
+        height: u32,
+        timings: FileTimings,
+        run: &RunOptions,
+    ) -> Self {
+        PageInfo {
+            path: source.path.clone(),
+            id: source.id.clone(),
+            width,
+            height,
+            timings,
+            pixel_hash: None,
+            image_path: None,
+            thumbnail: None,
+            warnings: Vec::new(),
+            cached: false,
+            deduped: false,
+            blank: None,
+            encoding: None,
+            encoding_reason: None,
+            expanded: None,
+            unsupported: Vec::new(),
+            copies: run.copies.of(&source.id),
+            placement: None,
+            annotations: 0,
+        }
+    }
+}
+
+// Per-run settings that don't affect the rendered pixels
+#[derive(Default)]
+pub struct RunOptions<'a> {
+    pub progress: Option<&'a dyn Progress>,
+    pub cache: Option<&'a PageCache>,
+    pub pixel_hashes: bool,
+    pub export: Option<&'a ImageExport>,
+    // Skip writing the document, e.g. when only page images are wanted
+    pub no_pdf: bool,
+    pub format: Format,
+    pub tiff_compression: TiffCompression,
+    pub images: ImageOptions,
+    pub thumbnails: bool,
+    // Longest side of a preview of the first page, if one is wanted
+    pub preview: Option<u32>,
+    // Threads reading files ahead of the render workers; 0 reads in the workers
+    pub io_threads: usize,
+    // Bytes of rendered pages that may wait to be written at once
+    pub max_in_flight: Option<usize>,
+    // Render workers, in a pool of their own rather than rayon's global one
+    pub workers: Option<usize>,
+    // Fail files with pages of more pixels, before rendering them
+    pub max_pixels: Option<u64>,
+    // Render byte-identical files only once per run
+    pub dedupe: bool,
+    // Largest channel difference within a page that still counts as blank
+    pub blank_tolerance: u8,
+    // Leave blank pages out of the document
+    pub drop_blank_pages: bool,
+    // Start files far larger than the rest first, see long_poles_first
+    pub reorder_work: bool,
+    // Render every Inkscape layer as its own page, except those matching
+    // one of `skip_layers`
+    pub explode_layers: bool,
+    pub skip_layers: Vec<String>,
+    // Leave out files byte-identical to an earlier one
+    pub dedupe_inputs: bool,
+    // Fail the run on files using features that can't be rendered
+    pub fail_on_unsupported: bool,
+    // What to write when no page is left, instead of failing
+    pub allow_empty: Option<EmptyOutput>,
+    // SVG for EmptyOutput::Placeholder, a blank page by default
+    pub placeholder: Option<&'a Path>,
+    pub copies: Copies,
+    // How failed reads of the files are retried
+    pub retry: RetryPolicy,
+    // Read the document back once it is written and check it, see
+    // verify::verify_output
+    pub verify: bool,
+    // Comments to put on the pages of a PDF
+    pub annotations: Option<&'a Annotations>,
+    // Draw PDF pages with path operators rather than as one image each
+    pub vector: bool,
+    // Order of the files of an input directory or pattern, last first with
+    // `reverse`
+    pub sort: SortOrder,
+    pub reverse: bool,
+    pub on_error: OnError,
+    // Title and the like for the document, with Producer and dates added
+    pub metadata: Metadata,
+    // Crop marks and viewer preferences of a PDF; its bleed is that of the
+    // RenderArgs
+    pub print: PrintSetup,
+    // The outline of a PDF
+    pub bookmarks: Option<&'a Bookmarks>,
+    // PDF the pages go into instead of a document of their own
+    pub append_to: Option<&'a Existing>,
+    // Set from another thread to fail the run; pages being rendered when it
+    // is set are finished, no more are started
+    pub cancel: Option<&'a AtomicBool>,
+}
+
+// How many times each page goes into the document
+#[derive(Clone, Debug)]
+pub struct Copies {
+    pub all: u32,
+    // Counts for pages whose id matches a pattern, the first match wins
+    pub overrides: Vec<(String, u32)>,
+}
+
+impl Default for Copies {
+    fn default() -> Self {
+        Copies {
+            all: 1,
+            overrides: Vec::new(),
+        }
+    }
+}
+
+impl Copies {
+    pub fn of(&self, id: &str) -> u32 {
+        self.overrides
+            .iter()
+            .find(|(pattern, _)| names::matches(pattern, id))
+            .map_or(self.all, |&(_, copies)| copies)
+    }
+}
+
+// Parse a number of copies, which has to be at least one
+pub fn parse_copies(value: &str) -> Result<u32, String> {
+    let copies: i64 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("expected a number of copies, got {value:?}"))?;
+    if copies < 1 {
+        return Err(format!("a page needs at least 1 copy, got {copies}"));
+    }
+    u32::try_from(copies).map_err(|_| format!("too many copies: {copies}"))
+}
+
+// Parse a per-file count such as `sign*.svg=3`
+pub fn parse_copies_override(value: &str) -> Result<(String, u32), String> {
+    let (pattern, copies) = value.rsplit_once('=').ok_or("expected PATTERN=N")?;
+    Ok((pattern.to_string(), parse_copies(copies)?))
+}
+
+// The order the files of an input directory or pattern become pages in
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    // By name, numbers by their value: slide-9 before slide-10
+    #[default]
+    Natural,
+    // By the bytes of the name
+    Lexical,
+    // Oldest modification first
+    Mtime,
+}
+
+// What a run does with a file that fails to convert
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnError {
+    // Stop the run at the first failure
+    #[default]
+    Abort,
+    // Leave the file out of the document
+    Skip,
+    // Put a white page in its place, so pages still match the inputs
+    Blank,
+}
+
+// A file that failed in a run that went on, see RunOptions::on_error
+pub struct FailedFile {
+    pub path: PathBuf,
+    pub id: String,
+    pub error: anyhow::Error,
+}
+
+// What a run without any page to write produces
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmptyOutput {
+    // A document of a single placeholder page
+    Placeholder,
+    // No document at all
+    #[value(name = "none")]
+    NoFile,
+}
+
+// Rendered as the placeholder page unless another SVG is given
+const BLANK_PAGE: &[u8] = br#"<svg xmlns="http://www.w3.org/2000/svg" width="960" height="720"/>"#;
+
+// Summary of a finished conversion
+pub struct Conversion {
+    pub pages: Vec<PageInfo>,
+    pub cache_hits: usize,
+    // Hash of the options that influenced rendering
+    pub options_hash: String,
+    // Downscaled first page, from RunOptions::preview
+    pub preview: Option<Pixmap>,
+    // Most bytes of rendered pages waiting to be written at once
+    pub peak_in_flight: usize,
+    // How often a worker waited for room under RunOptions::max_in_flight
+    pub budget_waits: usize,
+    // Pages reused from identical files, with RunOptions::dedupe
+    pub dedupe_hits: usize,
+    // Blank pages left out, with RunOptions::drop_blank_pages
+    pub dropped: Vec<PageInfo>,
+    // Files started ahead of their turn, with RunOptions::reorder_work
+    pub started_early: usize,
+    // Files left out as copies, with RunOptions::dedupe_inputs
+    pub skipped: Vec<Duplicate>,
+    // No page was left to write, see RunOptions::allow_empty; `pages`
+    // holds the placeholder, if there is one
+    pub empty: bool,
+    // Entries of RunOptions::annotations for none of the pages
+    pub stray_annotations: Vec<String>,
+    // Files that failed, in input order, with RunOptions::on_error skip or
+    // blank; blank ones are in `pages` too
+    pub failed: Vec<FailedFile>,
+}
+
+impl Conversion {
+    // Pages rendered by this run, rather than reused from the cache or an
+    // identical file; dropped blank pages were rendered too
+    pub fn rendered(&self) -> usize {
+        self.pages
+            .iter()
+            .chain(&self.dropped)
+            .filter(|page| !page.cached && !page.deduped)
+            .count()
+    }
+}
+
+// Whether `path` has an .svg extension (case-insensitive)
+pub fn is_svg(path: &Path) -> bool {
+    path.file_name().is_some_and(is_svg_name)
+}
+
+// is_svg for a bare file name, without allocating
+fn is_svg_name(name: &OsStr) -> bool {
+    let name = name.as_encoded_bytes();
+    // A name of just ".svg" has no extension
+    name.len() > 4 && name[name.len() - 4..].eq_ignore_ascii_case(b".svg")
+}
+
+// A font database built the first time it is needed. Shared by every
+// worker; the first to ask loads it while the others wait.
+pub struct LazyFonts {
+    fonts: OnceLock<Arc<fontdb::Database>>,
+    load: fn() -> fontdb::Database,
+}
+
+impl LazyFonts {
+    pub const fn new(load: fn() -> fontdb::Database) -> Self {
+        LazyFonts {
+            fonts: OnceLock::new(),
+            load,
+        }
+    }
+
+    pub fn get(&self) -> &Arc<fontdb::Database> {
+        self.fonts.get_or_init(|| Arc::new((self.load)()))
+    }
+
+    // The fonts if something needed them yet
+    pub fn loaded(&self) -> Option<&Arc<fontdb::Database>> {
+        self.fonts.get()
+    }
+}
+
+// The system fonts and those of --font-dir. Loading the system fonts can
+// take over a second, so it waits for the first file with text.
+static FONTS: LazyFonts = LazyFonts::new(|| fonts::config().load());
+
+pub fn font_database() -> &'static Arc<fontdb::Database> {
+    FONTS.get()
+}
+
+// The fonts of the run so far, None if no file had text
+pub fn loaded_fonts() -> Option<&'static Arc<fontdb::Database>> {
+    FONTS.loaded()
+}
+
+// Build usvg options backed by the font database
+pub fn load_options() -> Arc<Options<'static>> {
+    lazy_options(&FONTS)
+}
+
+// Options starting with an empty font database. usvg only asks the font
+// resolver once a tree has text, which is when `fonts` get loaded and
+// swapped in.
+fn lazy_options(fonts: &'static LazyFonts) -> Arc<Options<'static>> {
+    let fill = move |fontdb: &mut Arc<fontdb::Database>| {
+        if fontdb.is_empty() {
+            *fontdb = Arc::clone(fonts.get());
+        }
+    };
+    let select_font = usvg::FontResolver::default_font_selector();
+    let select_fallback = usvg::FontResolver::default_fallback_selector();
+    Arc::new(Options {
+        font_resolver: usvg::FontResolver {
+            select_font: Box::new(move |font, fontdb| {
+                fill(fontdb);
+                select_font(font, fontdb)
+            }),
+            select_fallback: Box::new(move |c, used, fontdb| {
+                fill(fontdb);
+                select_fallback(c, used, fontdb)
+            }),
+        },
+        font_family: fonts::config().default_family(),
+        ..Options::default()
+    })
+}
+
+// Whether an SVG may contain text, judged from its bytes. Files without
+// are rendered and cached without the fonts.
+pub fn may_have_text(data: &[u8]) -> bool {
+    data.windows(5)
+        .any(|window| window == b"<text" || window == b":text")
+}
+
+// SVG text of `data`, which may be gzip compressed
+pub fn svg_text(data: &[u8]) -> Result<Cow<'_, str>> {
+    let text = if data.starts_with(&[0x1f, 0x8b]) {
+        Cow::Owned(String::from_utf8(usvg::decompress_svgz(data)?)?)
+    } else {
+        Cow::Borrowed(std::str::from_utf8(data)?)
+    };
+    Ok(text)
+}
+
+// Whether the root element gives an absolute width and height. Drawings
+// without one are sized by usvg: the viewBox in CSS pixels, with
+// percentages of it, or the extent of the drawing without a viewBox.
+pub fn declares_size(data: &[u8]) -> bool {
+    let Ok(text) = svg_text(data) else {
+        return false;
+    };
+    let options = usvg::roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..usvg::roxmltree::ParsingOptions::default()
+    };
+    let Ok(doc) = usvg::roxmltree::Document::parse_with_options(&text, options) else {
+        return false;
+    };
+    let root = doc.root_element();
+    let absolute = |name| {
+        root.attribute(name)
+            .is_some_and(|value: &str| !value.trim().is_empty() && !value.trim_end().ends_with('%'))
+    };
+    absolute("width") && absolute("height")
+}
+
+// Extra scale for a drawing of `size` to fit into --default-size, when it
+// doesn't declare its own size
+fn default_size_scale(data: &[u8], size: usvg::Size, args: &RenderArgs) -> f32 {
+    match args.default_size {
+        Some(fit) if !declares_size(data) => {
+            (fit.width / size.width()).min(fit.height / size.height())
+        }
+        _ => 1.0,
+    }
+}
+
+// Where a drawing goes on its page
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Layout {
+    // Points per CSS pixel
+    scale: f32,
+    // What reaches past the canvas, kept with --expand-to-content
+    expanded: Option<Expansion>,
+    // Top left corner of the drawing on the page, in points
+    left: f32,
+    top: f32,
+    // The page, in points
+    width: f32,
+    height: f32,
+}
+
+impl Layout {
+    // The page of a parsed drawing: its size after scaling, grown by what
+    // is kept past the canvas, or --page-size or --paper with the drawing
+    // fit inside the margins
+    fn of(tree: &Tree, data: &[u8], args: &RenderArgs) -> Layout {
+        let size = tree.size();
+        let expanded = args
+            .expand_to_content
+            .then(|| Expansion::of(tree))
+            .flatten();
+        let margins = expanded.unwrap_or_default();
+        let drawn_width = size.width() + margins.left + margins.right;
+        let drawn_height = size.height() + margins.top + margins.bottom;
+        match args.fixed_page(drawn_width > drawn_height) {
+            Some((page, margin)) => {
+                let scale = ((page.width - 2.0 * margin) / drawn_width)
+                    .min((page.height - 2.0 * margin) / drawn_height)
+                    .max(f32::MIN_POSITIVE);
+                Layout {
+                    scale,
+                    expanded,
+                    left: (page.width - drawn_width * scale) / 2.0,
+                    top: (page.height - drawn_height * scale) / 2.0,
+                    width: page.width,
+                    height: page.height,
+                }
+            }
+            None => {
+                let scale = args.scale * default_size_scale(data, size, args);
+                let bleed = args.bleed();
+                Layout {
+                    scale,
+                    expanded,
+                    left: bleed,
+                    top: bleed,
+                    width: drawn_width * scale + 2.0 * bleed,
+                    height: drawn_height * scale + 2.0 * bleed,
+                }
+            }
+        }
+    }
+
+    // From CSS pixels of the canvas to points from the top left of the
+    // page, moving content left of or above the canvas onto the page
+    fn transform(&self) -> Transform {
+        let margins = self.expanded.unwrap_or_default();
+        Transform::from_translate(self.left, self.top)
+            .pre_scale(self.scale, self.scale)
+            .pre_translate(margins.left, margins.top)
+    }
+
+    // Pixels of the page at `resolution` pixels per point, or what is wrong
+    // with them
+    fn pixels(&self, resolution: f32, max_pixels: Option<u64>) -> Result<(u32, u32), String> {
+        let side = |points: f32| (points as f64 * resolution as f64).round().max(1.0);
+        let (width, height) = (side(self.width), side(self.height));
+        check_pixels(width, height, max_pixels)?;
+        if width * height > MAX_PAGE_PIXELS as f64 {
+            let mb = |pixels: f64| (pixels * BYTES_PER_PIXEL / (1024.0 * 1024.0)).ceil();
+            return Err(format!(
+                "{:.0}x{:.0} pixels would take {} MB of memory, more than the {} MB a page may; lower --dpi",
+                width,
+                height,
+                mb(width * height),
+                mb(MAX_PAGE_PIXELS as f64)
+            ));
+        }
+        Ok((width as u32, height as u32))
+    }
+}
+
+// Whether a page of `width` by `height` pixels is within
+// RunOptions::max_pixels
+fn check_pixels(width: f64, height: f64, max_pixels: Option<u64>) -> Result<(), String> {
+    match max_pixels {
+        Some(max) if width * height > max as f64 => Err(format!(
+            "{:.0}x{:.0} pixels, more than the {} of --max-pixels; lower --dpi or --scale",
+            width, height, max
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Most pixels a page may have, under RunOptions::max_pixels
+fn pixel_limit(max_pixels: Option<u64>) -> u64 {
+    max_pixels.map_or(MAX_PAGE_PIXELS, |max| max.min(MAX_PAGE_PIXELS))
+}
+
+// The drawing of a page rendered as `image`, for RunOptions::vector
+fn vector_page(
+    tree: &Tree,
+    data: &[u8],
+    args: &RenderArgs,
+    image: &RenderedImage,
+    timings: &mut FileTimings,
+    epoch: Instant,
+) -> Result<Arc<VectorPage>> {
+    let resolution = args.resolution();
+    // The page in points, as the writer sizes it
+    let (width, height) = (
+        image.width as f32 / resolution,
+        image.height as f32 / resolution,
+    );
+    let transform = Layout::of(tree, data, args).transform();
+    // White is the paper's own color, so only other colors are painted
+    let background = match args.background {
+        Background::Color(color) if color != [255; 3] => Some(color),
+        _ => None,
+    };
+    let page = timings.measure(epoch, Stage::Encode, || {
+        vector::page(tree, transform, width, height, resolution, background)
+    })?;
+    Ok(Arc::new(page))
+}
+
+// Where the user units of a drawing end up on its page, in points from the
+// top left: the render transform, before it is taken to pixels
+fn placement(tree: &Tree, data: &[u8], args: &RenderArgs) -> Transform {
+    Layout::of(tree, data, args)
+        .transform()
+        .pre_concat(annotations::view_box_transform(data, tree.size()))
+}
+
+// Parse the tree of a page, naming what the file looks like if it isn't SVG
+fn parse_tree(
+    data: &[u8],
+    opt: &Options,
+    path: &Path,
+    timings: &mut FileTimings,
+    epoch: Instant,
+) -> Result<Tree> {
+    timings
+        .measure(epoch, Stage::Parse, || Tree::from_data(data, opt))
+        .map_err(|source| {
+            Error::Parse {
+                path: path.to_path_buf(),
+                looks_like: sniff::detect(data),
+                source,
+            }
+            .into()
+        })
+}
+
+// Render every SVG `inputs` name and write the pages to `output` in
+// `run.format`, or to stdout for an output of `-`.
+// With a cache, files whose contents and render options did not change
+// since a previous run reuse their previously rendered page. Runs left
+// without pages fail unless RunOptions::allow_empty says what to write.
+pub fn convert_dir(
+    opt: &Arc<Options<'static>>,
+    inputs: &Inputs,
+    output: &Path,
+    args: &RenderArgs,
+    run: &RunOptions,
+) -> Result<Conversion> {
+    let sources = match run.allow_empty {
+        Some(_) if inputs.count()? == 0 => Sources::from(Vec::new()),
+        _ => inputs.scan(run.sort, run.reverse)?,
+    };
+    let resolution = args.resolution();
+    let mut writer = run.format.writer(
+        run.tiff_compression,
+        run.images.clone(),
+        resolution,
+        args.sheet(),
+        PrintSetup {
+            bleed: args.bleed(),
+            ..run.print
+        },
+    );
+    writer.describe(&run.metadata);
+    let mut conversion = convert(opt, sources, args, run, writer.as_mut())?;
+
+    // A document without pages is no use, and viewers take it for broken
+    if !run.no_pdf && conversion.pages.is_empty() {
+        conversion.empty = true;
+        match run.allow_empty {
+            None if !conversion.failed.is_empty() => anyhow::bail!(
+                "No pages left to write, {} files failed and any others were dropped as blank; pass --allow-empty to accept that",
+                conversion.failed.len()
+            ),
+            None => anyhow::bail!(
+                "No pages left to write, every page was dropped as blank; pass --allow-empty to accept that"
+            ),
+            Some(EmptyOutput::NoFile) => return Ok(conversion),
+            Some(EmptyOutput::Placeholder) => {
+                // The blank page is as large as a typical one
+                let (source, args) = match run.placeholder {
+                    Some(path) => (
+                        Source::file(path.parent().unwrap_or("".as_ref()), path.into()),
+                        args.clone(),
+                    ),
+                    None => (
+                        Source::bytes("placeholder.svg", BLANK_PAGE.to_vec()),
+                        args.clone(),
+                    ),
+                };
+                // Only the settings the document needs: the placeholder is
+                // blank more often than not, and not an input page
+                let placeholder_run = RunOptions {
+                    pixel_hashes: run.pixel_hashes,
+                    thumbnails: run.thumbnails,
+                    preview: run.preview,
+                    ..RunOptions::default()
+                };
+                let placeholder =
+                    convert(opt, vec![source].into(), &args, &placeholder_run, writer.as_mut())?;
+                conversion.pages = placeholder.pages;
+                conversion.preview = placeholder.preview;
+            }
+        }
+    }
+
+    // Save the document, to stdout for an output of `-`. Pages for
+    // --append-to are written on their own first, then taken into the
+    // existing document.
+    let save = |writer: Box<dyn ContainerWriter>, out: &mut dyn Write| -> Result<()> {
+        match run.append_to {
+            Some(existing) => {
+                let mut pdf = Vec::new();
+                writer.finish(&mut pdf)?;
+                existing.merge(&pdf, out)
+            }
+            None => writer.finish(out),
+        }
+    };
+    if !run.no_pdf && paths::is_stdio(output) {
+        let mut stdout = BufWriter::new(std::io::stdout().lock());
+        save(writer, &mut stdout)?;
+        stdout
+            .flush()
+            .context("Failed to write the document to stdout")?;
+    } else if !run.no_pdf {
+        let staged = Staged::new(output);
+        let failed = |source| Error::Write {
+            path: output.to_path_buf(),
+            source,
+        };
+        let write = || -> Result<()> {
+            let file = fs::File::create(paths::long_path(staged.path())).map_err(failed)?;
+            // Flushed here, as dropping it would throw away a failed last write
+            let mut file = BufWriter::new(file);
+            save(writer, &mut file)?;
+            file.flush().map_err(failed)?;
+            Ok(())
+        };
+        if let Err(err) = write() {
+            staged.abandon();
+            return Err(err);
+        }
+        staged.commit().map_err(failed)?;
+        if run.verify {
+            let pages = conversion
+                .pages
+                .iter()
+                .map(|page| page.copies as usize)
+                .sum();
+            let pages = args.sheet().map_or(pages, |sheet| sheet.pages(pages))
+                + run.append_to.map_or(0, Existing::pages);
+            verify::verify_output(output, run.format, pages)?;
+        }
+    }
+    Ok(conversion)
+}
+
+// A rendered page in the form the writer takes, waiting for its turn
+struct ReadyPage {
+    index: usize,
+    // None when no document is written
+    encoded: Option<EncodedPage>,
+    // Downscaled first page, from RunOptions::preview
+    preview: Option<Pixmap>,
+    info: PageInfo,
+    // Why the file failed, for the stand-ins of RunOptions::on_error
+    failed: Option<anyhow::Error>,
+}
+
+impl ReadyPage {
+    // Memory held until the page is written
+    fn bytes(&self) -> usize {
+        self.encoded.as_ref().map_or(0, |encoded| {
+            encoded.data.len() + encoded.mask.as_ref().map_or(0, Vec::len)
+        })
+    }
+}
+
+// Encode a rendered page for the writer, in the worker that rendered it
+fn finish_page(
+    mut page: PageData,
+    encoder: Option<&dyn PageEncoder>,
+    run: &RunOptions,
+    epoch: Instant,
+) -> Result<ReadyPage> {
+    let image = &page.image;
+    page.info.blank = blank_color(image, run.blank_tolerance);
+    let encoded = encoder
+        .filter(|_| !(run.drop_blank_pages && page.info.blank.is_some()))
+        .map(|encoder| {
+            page.info.timings.measure(epoch, Stage::Encode, || {
+                let fill = page
+                    .info
+                    .blank
+                    .and_then(|color| encoder.encode_fill(image.width, image.height, color));
+                let vector = || {
+                    let vector = page.vector.clone()?;
+                    encoder.encode_vector(image.width, image.height, vector)
+                };
+                fill.or_else(vector)
+                    .map_or_else(|| encoder.encode(image, &page.info.id), Ok)
+            })
+        })
+        .transpose()
+        .with_context(|| format!("Failed to encode page: {:?}", page.info.path))?;
+    if let Some(encoded) = &encoded {
+        page.info.encoding = Some(encoded.encoding);
+        page.info.encoding_reason = encoded.reason.clone();
+    }
+    if let (Some(cache), Some(key)) = (run.cache, page.cache_key) {
+        cache.insert(
+            key,
+            CachedPage {
+                width: image.width,
+                height: image.height,
+                pixel_hash: page
+                    .info
+                    .pixel_hash
+                    .clone()
+                    .unwrap_or_else(|| cache::pixel_hash(image)),
+                blank: page.info.blank,
+                thumbnail: page.info.thumbnail.clone(),
+                encoded: encoded.clone(),
+            },
+        );
+    }
+    // Preview the first page from its rendered pixels
+    let preview = run
+        .preview
+        .filter(|_| page.index == 0)
+        .and_then(|size| export::thumbnail(&page.image, size));
+    Ok(ReadyPage {
+        index: page.index,
+        encoded,
+        preview,
+        info: page.info,
+        failed: None,
+    })
+}
+
+// What takes the place of a file that failed, with RunOptions::on_error:
+// nothing, or a page of the background color as large as --page-size or a
+// typical drawing, white for transparent backgrounds
+fn stand_in(
+    index: usize,
+    source: &Source,
+    error: anyhow::Error,
+    args: &RenderArgs,
+    run: &RunOptions,
+    encoder: Option<&dyn PageEncoder>,
+) -> Result<ReadyPage> {
+    let blank = run.on_error == OnError::Blank;
+    let (width, height) = if blank {
+        page_size_pixels(args)
+    } else {
+        (0, 0)
+    };
+    let color = match args.background {
+        Background::Color(color) => color,
+        Background::Transparent => [255; 3],
+    };
+    let image = Arc::new(RenderedImage {
+        width,
+        height,
+        rgb_data: color.repeat(width as usize * height as usize),
+        alpha: None,
+    });
+    let timings = FileTimings::new(source.path.clone());
+    let mut page = PageData::new(
+        index,
+        source,
+        Arc::clone(&image),
+        timings,
+        None,
+        Vec::new(),
+        run,
+    );
+    let encoded = encoder
+        .filter(|_| blank)
+        .map(|encoder| {
+            encoder
+                .encode_fill(width, height, color)
+                .map_or_else(|| encoder.encode(&image, &source.id), Ok)
+        })
+        .transpose()
+        .with_context(|| format!("Failed to encode page: {:?}", source.path))?;
+    if let Some(encoded) = &encoded {
+        page.info.encoding = Some(encoded.encoding);
+        page.info.blank = Some(color);
+    }
+    Ok(ReadyPage {
+        index,
+        encoded,
+        preview: None,
+        info: page.info,
+        failed: Some(error),
+    })
+}
+
+// The color of a page whose pixels all lie within `tolerance` of each other
+// in every channel, or None if it has any detail. Pages with transparency
+// have no color to fill them with.
+pub fn blank_color(image: &RenderedImage, tolerance: u8) -> Option<[u8; 3]> {
+    if image.alpha.is_some() {
+        return None;
+    }
+    let first = image.rgb_data.get(..3)?;
+    let (mut low, mut high) = (
+        [first[0], first[1], first[2]],
+        [first[0], first[1], first[2]],
+    );
+    for pixel in image.rgb_data.chunks_exact(3) {
+        for channel in 0..3 {
+            low[channel] = low[channel].min(pixel[channel]);
+            high[channel] = high[channel].max(pixel[channel]);
+            if high[channel] - low[channel] > tolerance {
+                return None;
+            }
+        }
+    }
+    Some([0, 1, 2].map(|channel| low[channel].midpoint(high[channel])))
+}
+
+// Pages are sized by their drawings, which are only known once parsed;
+// the memory estimate takes drawings to be this size in CSS pixels
+const TYPICAL_DRAWING: DrawingSize = DrawingSize {
+    width: 960.0,
+    height: 720.0,
+};
+
+// Largest page rendered, 16384x16384: its pixmap and RGB data take 1.75 GiB
+const MAX_PAGE_PIXELS: u64 = 1 << 28;
+
+// Bytes a rendered pixel takes, in the pixmap and its RGB copy
+const BYTES_PER_PIXEL: f64 = 7.0;
+
+// Pixels rendered for each page, exactly with --page-size and with --paper
+// unless --orientation auto turns it
+pub fn page_pixels(args: &RenderArgs) -> u64 {
+    let (width, height) = page_size_pixels(args);
+    width as u64 * height as u64
+}
+
+// Width and height of the pages in pixels, as far as they are known before
+// the drawings are: those of --page-size or --paper, or of a typical drawing
+fn page_size_pixels(args: &RenderArgs) -> (u32, u32) {
+    let (size, resolution) = match args.fixed_page(false) {
+        Some((page, _)) => (page, args.resolution()),
+        None => (TYPICAL_DRAWING, args.dpi() / CSS_DPI),
+    };
+    let side = |size: f32| (size * resolution).round().max(1.0) as u32;
+    (side(size.width), side(size.height))
+}
+
+// A source to render and where its page image goes
+struct Job<'a> {
+    index: usize,
+    source: &'a Source,
+    export_path: Option<&'a Path>,
+    // Contents already read by the IO threads
+    prefetched: Option<Prefetched>,
+}
+
+// Read, render and flatten one source, or reuse its page from the cache
+fn render_page(
+    opt: &Arc<Options<'static>>,
+    job: Job,
+    args: &RenderArgs,
+    run: &RunOptions,
+    dedupe: Option<&Dedupe>,
+    hashes: &OptionsHashes,
+    epoch: Instant,
+) -> Result<Page> {
+    let (index, source) = (job.index, job.source);
+    let cache = run.cache;
+    let path = &source.path;
+    let mut timings = FileTimings::new(path.clone());
+
+    // Read and parse SVG, unless an IO thread already did
+    let svg_data = match job.prefetched {
+        Some(prefetched) => {
+            timings.spans.push(prefetched.read);
+            timings.queue_depth = Some(prefetched.queue_depth);
+            timings.retries = prefetched.retries;
+            prefetched.data.map(Cow::Owned)
+        }
+        None => {
+            let (data, retries) =
+                timings.measure(epoch, Stage::Read, || run.retry.run(|| source.read()));
+            timings.retries = retries;
+            data
+        }
+    }
+    .map_err(|source| Error::Read {
+        path: path.clone(),
+        source,
+    })?;
+    let svg_data = layers::page_data(source, svg_data)?;
+
+    // Strict runs check before the cache, so pages cached by runs without
+    // the check don't get through
+    let checked = run
+        .fail_on_unsupported
+        .then(|| unsupported::scan(&svg_data));
+    if let Some(features) = checked.as_ref().filter(|features| !features.is_empty()) {
+        return Err(Error::Unsupported {
+            path: path.clone(),
+            features: features.iter().map(Feature::to_string).collect(),
+        }
+        .into());
+    }
+    let options_hash = hashes.for_page(&svg_data);
+
+    // Annotations are placed by where the drawing went, and vector pages
+    // drawn from the tree, which pages reused from elsewhere only have once
+    // it is parsed
+    let annotated = run
+        .annotations
+        .is_some_and(|annotations| annotations.has(&source.id, path));
+    let reparse = |timings: &mut FileTimings,
+                   image: &RenderedImage,
+                   vector: bool|
+     -> Result<(Option<Transform>, Option<Arc<VectorPage>>)> {
+        if !annotated && !vector {
+            return Ok((None, None));
+        }
+        let tree = parse_tree(&svg_data, opt, path, timings, epoch)?;
+        let placement = annotated.then(|| placement(&tree, &svg_data, args));
+        let vector = vector
+            .then(|| vector_page(&tree, &svg_data, args, image, timings, epoch))
+            .transpose()?;
+        Ok((placement, vector))
+    };
+
+    // Another copy of a file rendered earlier in this run
+    let content_key = dedupe.map(|dedupe| (dedupe, dedupe::key(&svg_data)));
+    if let Some(seen) = content_key
+        .as_ref()
+        .and_then(|(dedupe, key)| dedupe.get(key))
+    {
+        let image_path = match (seen.image_path, job.export_path) {
+            (Some(from), Some(to)) => {
+                timings
+                    .measure(epoch, Stage::Export, || fs::copy(&from, to))
+                    .with_context(|| format!("Failed to copy page image to {:?}", to))?;
+                Some(to.to_path_buf())
+            }
+            _ => None,
+        };
+        let (placement, _) = reparse(&mut timings, &seen.image, false)?;
+        let mut page = PageData::new(
+            index,
+            source,
+            seen.image,
+            timings,
+            image_path,
+            seen.warnings,
+            run,
+        );
+        page.info.deduped = true;
+        page.info.placement = placement;
+        page.vector = seen.vector;
+        page.info.expanded = seen.expanded;
+        page.info.unsupported = seen.unsupported;
+        return Ok(Page::Rendered(page));
+    }
+
+    // Reuse the page from a previous run when nothing changed. Pages are
+    // cached as they were encoded, so pages whose pixels are wanted are
+    // rendered: to be exported, drawn as vector pages or previewed. Their
+    // duplicates find them in the cache too.
+    let key = cache
+        .filter(|_| run.export.is_none() && !run.vector)
+        .filter(|_| run.preview.is_none() || index > 0)
+        .map(|cache| {
+            let key = cache.key(&svg_data, options_hash, hashes.encoding());
+            (cache, key)
+        });
+    if let Some(cached) = key.and_then(|(cache, key)| cache.get(&key, pixel_limit(run.max_pixels)))
+    {
+        let placement = annotated
+            .then(|| parse_tree(&svg_data, opt, path, &mut timings, epoch))
+            .transpose()?
+            .map(|tree| placement(&tree, &svg_data, args));
+        let mut info = PageInfo::new(source, cached.width, cached.height, timings, run);
+        info.cached = true;
+        info.placement = placement;
+        info.pixel_hash = run.pixel_hashes.then(|| cached.pixel_hash.clone());
+        info.thumbnail = cached.thumbnail.clone();
+        info.blank = cached.blank;
+        let encoded = cached.encoded.clone();
+        if let Some(encoded) = &encoded {
+            info.encoding = Some(encoded.encoding);
+            info.encoding_reason = encoded.reason.clone();
+        }
+        return Ok(Page::Cached(ReadyPage {
+            index,
+            encoded,
+            preview: None,
+            info,
+            failed: None,
+        }));
+    }
+
+    // Parse SVG tree
+    let tree = parse_tree(&svg_data, opt, path, &mut timings, epoch)?;
+    // Text would silently be left out
+    if tree.fontdb().is_empty() && may_have_text(&svg_data) {
+        return Err(Error::Render {
+            path: path.clone(),
+            reason: "it has text, but no fonts were found; add some with --font-dir".to_string(),
+        }
+        .into());
+    }
+
+    // Size the page by the drawing, or fit the drawing into --page-size
+    let layout = Layout::of(&tree, &svg_data, args);
+    let resolution = args.resolution();
+    let (width, height) =
+        layout
+            .pixels(resolution, run.max_pixels)
+            .map_err(|size| Error::Render {
+                path: path.clone(),
+                reason: format!("too large, {}", size),
+            })?;
+    let mut warnings = Vec::new();
+    let unsupported = checked.unwrap_or_else(|| unsupported::scan(&svg_data));
+    if !unsupported.is_empty() {
+        let features: Vec<_> = unsupported.iter().map(Feature::to_string).collect();
+        warnings.push(format!(
+            "Rendered without unsupported SVG features: {}",
+            features.join(", ")
+        ));
+    }
+
+    // Create transform with scaling, in pixels rather than page points
+    let transform = Transform::from_scale(resolution, resolution).pre_concat(layout.transform());
+
+    // Render into this worker's reused pixel buffer, cleared to transparent
+    let (rgb_data, alpha) = pool::with_pixmap(width, height, |pixmap| -> Result<_> {
+        // Render SVG
+        timings.measure(epoch, Stage::Render, || {
+            resvg::render(&tree, transform, &mut pixmap.as_mut())
+        });
+
+        // Write the page image before its transparency is flattened
+        if let (Some(export), Some(export_path)) = (run.export, job.export_path) {
+            timings.measure(epoch, Stage::Export, || export.write(export_path, pixmap))?;
+        }
+
+        // Convert pixmap to RGB data over the background, or keep its
+        // alpha unless the drawing covers the whole page
+        Ok(
+            timings.measure(epoch, Stage::Convert, || match args.background {
+                Background::Color(color) => (pixels::flatten_rgb_over(pixmap.data(), color), None),
+                Background::Transparent => {
+                    let (rgb, alpha) = pixels::split_alpha(pixmap.data());
+                    let opaque = alpha.iter().all(|&alpha| alpha == 255);
+                    (rgb, (!opaque).then_some(alpha))
+                }
+            }),
+        )
+    })
+    .ok_or_else(|| Error::Render {
+        path: path.clone(),
+        reason: format!("a {}x{} pixel buffer can't be allocated", width, height),
+    })??;
+    let image_path = job.export_path.map(Path::to_path_buf);
+
+    let image = Arc::new(RenderedImage {
+        width,
+        height,
+        rgb_data,
+        alpha,
+    });
+    let vector = run
+        .vector
+        .then(|| vector_page(&tree, &svg_data, args, &image, &mut timings, epoch))
+        .transpose()?;
+    if let Some(rasterized) = vector.as_ref().filter(|page| !page.rasterized.is_empty()) {
+        let parts: Vec<_> = rasterized.rasterized.iter().copied().collect();
+        warnings.push(format!(
+            "Drawn as images in the vector page: {}",
+            parts.join(", ")
+        ));
+    }
+    if let Some((dedupe, key)) = content_key {
+        let seen = dedupe::Rendered {
+            image: Arc::clone(&image),
+            warnings: warnings.clone(),
+            expanded: layout.expanded,
+            unsupported: unsupported.clone(),
+            image_path: image_path.clone(),
+            vector: vector.clone(),
+        };
+        dedupe.insert(key, seen);
+    }
+
+    let mut page = PageData::new(index, source, image, timings, image_path, warnings, run);
+    page.vector = vector;
+    page.cache_key = key.map(|(_, key)| key);
+    page.info.expanded = layout.expanded;
+    page.info.unsupported = unsupported;
+    page.info.placement = annotated.then(|| placement(&tree, &svg_data, args));
+    Ok(Page::Rendered(page))
+}
+
+// Render `sources` in parallel and add them to `writer` in order
+pub fn convert(
+    opt: &Arc<Options<'static>>,
+    sources: Sources,
+    args: &RenderArgs,
+    run: &RunOptions,
+    writer: &mut dyn ContainerWriter,
+) -> Result<Conversion> {
+    let cache = run.cache;
+    let (sources, skipped) = if run.dedupe_inputs {
+        dedupe::skip_duplicates(sources, &run.retry)
+    } else {
+        (sources, Vec::new())
+    };
+    let sources = if run.explode_layers {
+        layers::explode(sources, &run.skip_layers, &run.retry)?
+    } else {
+        sources
+    };
+    // Image names depend on every page id, so exporting needs them up front
+    let (sources, export_paths) = match run.export {
+        Some(export) => {
+            let files = sources.files;
+            let listed: Vec<Source> = sources.collect();
+            let paths = export.prepare(&listed)?;
+            let sources = Sources {
+                files,
+                ..Sources::from(listed)
+            };
+            (sources, Some(paths))
+        }
+        None => (sources, None),
+    };
+    let total = sources.total;
+
+    // Process SVGs in parallel
+    let opt = &args.quality.options(opt);
+    // Pages are encoded by the workers, so writing them is cheap
+    let encoder = (!run.no_pdf).then(|| writer.encoder());
+    // Pages are cached as they are encoded, with their blank color and
+    // thumbnail, so only runs doing all of that alike share them
+    let encoding = format!(
+        "{} blank {} {} thumbnails {}",
+        encoder
+            .as_ref()
+            .map_or_else(|| "none".to_string(), |encoder| encoder.options()),
+        run.blank_tolerance,
+        run.drop_blank_pages,
+        run.thumbnails
+    );
+    let options_hash = OptionsHashes::new(args, encoding);
+    let epoch = Instant::now();
+    let progress = run.progress;
+    if let Some(progress) = progress {
+        progress.event(&Event::Started { total });
+    }
+    let dedupe = run.dedupe.then(|| Dedupe::new(dedupe::MEMORY_LIMIT));
+    let resolution = args.resolution();
+    let render = |index: usize, source: &Source, prefetched: Option<Prefetched>| {
+        let path = &source.path;
+        let worker = progress::current_worker();
+        if let Some(progress) = progress {
+            progress.event(&Event::FileStarted { path, worker });
+        }
+        let job = Job {
+            index,
+            source,
+            export_path: export_paths.as_ref().map(|paths| paths[index].as_path()),
+            prefetched,
+        };
+        let page = render_page(opt, job, args, run, dedupe.as_ref(), &options_hash, epoch)
+            .and_then(|page| match page {
+                Page::Rendered(page) => finish_page(page, encoder.as_deref(), run, epoch),
+                Page::Cached(page) => Ok(page),
+            });
+        if let Some(progress) = progress {
+            progress.event(&match &page {
+                Ok(page) => Event::FileFinished {
+                    path,
+                    worker,
+                    cached: page.info.cached,
+                    bytes: page.bytes(),
+                },
+                Err(error) => Event::FileFailed {
+                    path,
+                    worker,
+                    error,
+                },
+            });
+        }
+        page.or_else(|error| match run.on_error {
+            OnError::Abort => Err(error),
+            _ => stand_in(index, source, error, args, run, encoder.as_deref()),
+        })
+    };
+
+    // Files in memory need no reading, so only files use the IO threads
+    let read_ahead = run.io_threads > 0 && sources.files;
+    let pool = run
+        .workers
+        .map(|workers| rayon::ThreadPoolBuilder::new().num_threads(workers).build())
+        .transpose()?;
+    let workers = pool
+        .as_ref()
+        .map_or_else(rayon::current_num_threads, |pool| {
+            pool.current_num_threads()
+        });
+
+    // Long poles need every size up front, so they are only worth it when
+    // there are more files than workers
+    let (sources, order, early) = if run.reorder_work && total > workers {
+        let files = sources.files;
+        let listed: Vec<Source> = sources.collect();
+        let sizes: Vec<u64> = listed.iter().map(Source::size).collect();
+        let (order, poles) = readahead::long_poles_first(&sizes);
+        let mut listed: Vec<Option<Source>> = listed.into_iter().map(Some).collect();
+        let reordered: Vec<Source> = order
+            .iter()
+            .filter_map(|&index| listed[index].take())
+            .collect();
+        let sources = Sources {
+            files,
+            ..Sources::from(reordered)
+        };
+        let mut early = vec![false; order.len()];
+        for &index in &order[..poles] {
+            early[index] = true;
+        }
+        (sources, Some(order), early)
+    } else {
+        (sources, None, Vec::new())
+    };
+    let dispenser = Dispenser::new(sources, read_ahead, workers * 2, order);
+    let budget = Budget::new(run.max_in_flight);
+    let stop = || {
+        dispenser.stop();
+        budget.stop();
+    };
+
+    // Workers render in input order and wait for room in the budget. Pages
+    // are written here as soon as all earlier ones are, so only pages that
+    // finished out of order are held.
+    let mut pages = Vec::with_capacity(total);
+    let mut written = 0;
+    let mut dropped = Vec::new();
+    let mut preview = None;
+    let mut failure = None;
+    let mut failed = Vec::new();
+    let (sender, receiver) = mpsc::channel::<Result<ReadyPage>>();
+    std::thread::scope(|scope| {
+        if read_ahead {
+            for _ in 0..run.io_threads {
+                scope.spawn(|| dispenser.read_loop(epoch, &run.retry));
+            }
+        }
+        scope.spawn(|| {
+            let run_workers = || {
+                rayon::scope(|workers_scope| {
+                    for _ in 0..workers {
+                        let sender = sender.clone();
+                        workers_scope.spawn(|_| {
+                            let sender = sender;
+                            while let Some((index, source, prefetched)) = dispenser.next() {
+                                if run
+                                    .cancel
+                                    .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+                                {
+                                    stop();
+                                    let _ =
+                                        sender.send(Err(anyhow::anyhow!("Conversion cancelled")));
+                                    break;
+                                }
+                                let page = render(index, &source, prefetched);
+                                match &page {
+                                    Ok(page) if early.get(index) == Some(&true) => {
+                                        budget.reserve(page.bytes())
+                                    }
+                                    Ok(page) => budget.acquire(index, page.bytes()),
+                                    Err(_) => stop(),
+                                }
+                                if sender.send(page).is_err() {
+                                    break;
+                                }
+                            }
+                        });
+                    }
+                })
+            };
+            match &pool {
+                Some(pool) => pool.install(run_workers),
+                None => run_workers(),
+            }
+            drop(sender);
+        });
+
+        let mut pending: BTreeMap<usize, ReadyPage> = BTreeMap::new();
+        for page in &receiver {
+            match page {
+                Ok(page) => {
+                    pending.insert(page.index, page);
+                }
+                Err(err) => {
+                    failure.get_or_insert(err);
+                }
+            }
+            while let Some(mut page) = pending.remove(&written) {
+                let bytes = page.bytes();
+                if let Some(encoded) = page.encoded.take().filter(|_| failure.is_none()) {
+                    // The page in points, as the writer sizes it
+                    let width = encoded.width as f32 / resolution;
+                    let height = encoded.height as f32 / resolution;
+                    if let Err(err) = writer.add_copies(encoded, page.info.copies) {
+                        failure = Some(err);
+                        stop();
+                    } else {
+                        if let Some(bookmarks) = run.bookmarks {
+                            for (title, level) in bookmarks.of(&page.info.id, &page.info.path) {
+                                writer.bookmark(title, level);
+                            }
+                        }
+                        if args.nup_labels {
+                            // The file name, of the page id without its directories
+                            let name = page.info.id.rsplit('/').next().unwrap_or_default();
+                            writer.label(name.to_string());
+                        }
+                        if let (Some(annotations), Some(placement)) =
+                            (run.annotations, page.info.placement)
+                        {
+                            let (placed, warnings) = annotations.place(
+                                &page.info.id,
+                                &page.info.path,
+                                placement,
+                                width,
+                                height,
+                            );
+                            page.info.annotations = placed.len();
+                            page.info.warnings.extend(warnings);
+                            writer.annotate(placed);
+                        }
+                    }
+                }
+                if page.preview.is_some() {
+                    preview = page.preview;
+                }
+                budget.written(bytes);
+                if let Some(progress) = progress {
+                    progress.event(&Event::PageWritten { bytes });
+                }
+                written += 1;
+                if let Some(error) = page.failed.take() {
+                    failed.push(FailedFile {
+                        path: page.info.path.clone(),
+                        id: page.info.id.clone(),
+                        error,
+                    });
+                    // A blank stand-in is kept even among dropped blank pages
+                    if run.on_error == OnError::Blank {
+                        pages.push(page.info);
+                    }
+                } else if run.drop_blank_pages && page.info.blank.is_some() {
+                    dropped.push(page.info);
+                } else {
+                    pages.push(page.info);
+                }
+            }
+        }
+    });
+    if let Some(err) = failure {
+        return Err(err);
+    }
+    let cache_hits = cache.map_or(0, |cache| cache.finish_run());
+
+    if let Some(progress) = progress {
+        progress.event(&Event::Assembling);
+    }
+
+    let (peak_in_flight, budget_waits) = budget.peak();
+    let stray_annotations = run.annotations.map_or_else(Vec::new, |annotations| {
+        let pages: Vec<_> = pages
+            .iter()
+            .map(|page| (page.id.as_str(), page.path.as_path()))
+            .collect();
+        annotations.unmatched(&pages)
+    });
+    let conversion = Conversion {
+        pages,
+        cache_hits,
+        options_hash: options_hash.run(),
+        preview,
+        peak_in_flight,
+        budget_waits,
+        dedupe_hits: dedupe.map_or(0, |dedupe| dedupe.hits()),
+        dropped,
+        started_early: early.iter().filter(|&&early| early).count(),
+        skipped,
+        empty: false,
+        stray_annotations,
+        failed,
+    };
+    Ok(conversion)
+}
+
+#[test]
+fn test_drawings_without_a_size() {
+    let opt = load_options();
+    let fixtures = [
+        // viewBox only: its size in CSS pixels
+        (r#"viewBox="0 0 400 300""#, (400.0, 300.0), false),
+        // Percentages are of the viewBox
+        (
+            r#"width="50%" height="100%" viewBox="0 0 400 300""#,
+            (200.0, 300.0),
+            false,
+        ),
+        (
+            r#"width="800" viewBox="0 0 400 300""#,
+            (800.0, 300.0),
+            false,
+        ),
+        // Nothing to go by: usvg takes the extent of the drawing
+        (r#"width="100%" height="100%""#, (60.0, 50.0), false),
+        ("", (60.0, 50.0), false),
+        (
+            r#"width="2in" height="1in" viewBox="0 0 10 10""#,
+            (192.0, 96.0),
+            true,
+        ),
+    ];
+    let fit = RenderArgs {
+        default_size: Some(parse_size("80x60").unwrap()),
+        ..RenderArgs::default()
+    };
+    let mut sources = Vec::new();
+    for (attributes, (width, height), declared) in fixtures {
+        let svg = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" {attributes}><rect x="10" y="10" width="50" height="40"/></svg>"#
+        );
+        let size = Tree::from_str(&svg, &opt).unwrap().size();
+        assert_eq!(
+            (size.width(), size.height()),
+            (width, height),
+            "{attributes}"
+        );
+        assert_eq!(declares_size(svg.as_bytes()), declared, "{attributes}");
+
+        let scale = default_size_scale(svg.as_bytes(), size, &fit);
+        let expected = if declared {
+            1.0
+        } else {
+            (80.0 / width).min(60.0 / height)
+        };
+        assert_eq!(scale, expected, "{attributes}");
+        sources.push(Source::bytes(
+            format!("{}.svg", sources.len()),
+            svg.into_bytes(),
+        ));
+    }
+
+    // Every one renders, with or without --default-size
+    for args in [RenderArgs::default(), fit] {
+        let mut writer = crate::writer::PdfWriter::new(1.0, ImageOptions::default());
+        let run = RunOptions::default();
+        let conversion = convert(&opt, sources.clone().into(), &args, &run, &mut writer).unwrap();
+        assert_eq!(conversion.pages.len(), sources.len());
+    }
+    assert!(parse_size("800").is_err());
+    assert!(parse_size("0x600").is_err());
+    assert_eq!(
+        parse_size("1024X768"),
+        Ok(DrawingSize {
+            width: 1024.0,
+            height: 768.0
+        })
+    );
+}
+
+#[test]
+fn test_expand_to_content() {
+    use crate::writer::{EncodedPage, ImageFormat, PageEncoder};
+
+    // Keeps the raw pages it is given
+    struct Pages(crate::writer::PdfWriter, Vec<EncodedPage>);
+    impl ContainerWriter for Pages {
+        fn encoder(&self) -> Box<dyn PageEncoder> {
+            self.0.encoder()
+        }
+        fn add_page(&mut self, page: EncodedPage) -> Result<()> {
+            self.1.push(page);
+            Ok(())
+        }
+        fn finish(self: Box<Self>, _out: &mut dyn std::io::Write) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    // A callout left of the canvas and a legend past its bottom right
+    let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 100 75">
+<rect x="-10" width="20" height="10" fill="red"/>
+<rect x="90" y="70" width="30" height="10" fill="blue"/>
+</svg>"#;
+    let opt = load_options();
+    let tree = Tree::from_data(svg, &opt).unwrap();
+    let expansion = Expansion::of(&tree).unwrap();
+    assert_eq!(
+        expansion,
+        Expansion {
+            left: 10.0,
+            top: 0.0,
+            right: 20.0,
+            bottom: 5.0
+        }
+    );
+    assert_eq!(
+        expansion.to_string(),
+        "10 px left, 20 px right, 5 px bottom"
+    );
+    let inside = br#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 100 75"><rect width="100" height="75"/></svg>"#;
+    assert_eq!(Expansion::of(&Tree::from_data(inside, &opt).unwrap()), None);
+
+    let sources = vec![
+        Source::bytes("a.svg", svg.to_vec()),
+        Source::bytes("inside.svg", inside.to_vec()),
+    ];
+    let render = |expand_to_content| {
+        let args = RenderArgs {
+            scale: 8.0,
+            quality: Quality::Draft,
+            dpi: Some(384.0),
+            expand_to_content,
+            ..RenderArgs::default()
+        };
+        let images = ImageOptions {
+            format: ImageFormat::Raw,
+            ..ImageOptions::default()
+        };
+        let resolution = args.resolution();
+        let mut writer = Pages(
+            crate::writer::PdfWriter::new(resolution, images),
+            Vec::new(),
+        );
+        let conversion = convert(
+            &opt,
+            sources.clone().into(),
+            &args,
+            &RunOptions::default(),
+            &mut writer,
+        )
+        .unwrap();
+        (conversion, writer.1)
+    };
+
+    // Clipped at the left and the bottom of the 100x75 drawing, at four
+    // pixels per CSS pixel
+    let (conversion, pages) = render(false);
+    assert_eq!(conversion.pages[0].expanded, None);
+    assert!(conversion.pages[0].warnings.is_empty());
+    assert_eq!((pages[0].width, pages[0].height), (400, 300));
+
+    // 130x80 at 4x is 520x320
+    let (conversion, pages) = render(true);
+    assert_eq!(conversion.pages[0].expanded, Some(expansion));
+    assert_eq!(conversion.pages[1].expanded, None);
+    assert_eq!((pages[0].width, pages[0].height), (520, 320));
+    assert_eq!((pages[1].width, pages[1].height), (400, 300));
+    let pixel = |x: u32, y: u32| {
+        let at = ((y * pages[0].width + x) * 3) as usize;
+        <[u8; 3]>::try_from(&pages[0].data[at..at + 3]).unwrap()
+    };
+    // The callout now starts at the left edge, the legend ends in the page
+    assert_eq!(pixel(2, 2), [255, 0, 0]);
+    assert_eq!(pixel(515, 318), [0, 0, 255]);
+    assert_eq!(pixel(2, 100), [255, 255, 255]);
+}
+
+// An SVG of `width` by `height` CSS pixels, filled black
+#[cfg(test)]
+fn filled_drawing(width: u32, height: u32) -> Vec<u8> {
+    format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}"><rect width="100%" height="100%"/></svg>"#
+    )
+    .into_bytes()
+}
+
+// A page's size in points, and the width and RGB data of its image unless
+// it is a fill
+#[cfg(test)]
+type RenderedPage = (Vec<f32>, Option<(i64, Vec<u8>)>);
+
+// The pages `sources` become as raw images
+#[cfg(test)]
+fn rendered_pages(sources: &[Source], args: &RenderArgs) -> Vec<RenderedPage> {
+    use crate::writer::ImageFormat;
+
+    let images = ImageOptions {
+        format: ImageFormat::Raw,
+        ..ImageOptions::default()
+    };
+    let mut writer = Box::new(crate::writer::PdfWriter::new(args.resolution(), images));
+    let run = RunOptions::default();
+    convert(
+        &load_options(),
+        sources.to_vec().into(),
+        args,
+        &run,
+        writer.as_mut(),
+    )
+    .unwrap();
+    let mut pdf = Vec::new();
+    writer.finish(&mut pdf).unwrap();
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    doc.get_pages()
+        .values()
+        .map(|&page| {
+            let media_box = doc.get_dictionary(page).unwrap().get(b"MediaBox").unwrap();
+            let size: Vec<_> = media_box.as_array().unwrap()[2..]
+                .iter()
+                .map(|value| value.as_float().unwrap())
+                .collect();
+            let image = doc.get_page_images(page).ok();
+            let image = image
+                .as_ref()
+                .map(|images| (images[0].width, images[0].content));
+            (size, image.map(|(width, data)| (width, data.to_vec())))
+        })
+        .collect()
+}
+
+// A portrait drawing and a wide one
+#[cfg(test)]
+fn portrait_and_banner() -> Vec<Source> {
+    vec![
+        Source::bytes("portrait.svg", filled_drawing(210, 297)),
+        Source::bytes("banner.svg", filled_drawing(600, 100)),
+    ]
+}
+
+#[test]
+fn test_pages_take_the_drawing_size() {
+    // Half the size of each drawing, with a pixel per CSS pixel all the same
+    let args = RenderArgs {
+        scale: 0.5,
+        ..RenderArgs::default()
+    };
+    let sizes: Vec<_> = rendered_pages(&portrait_and_banner(), &args)
+        .into_iter()
+        .map(|page| page.0)
+        .collect();
+    assert_eq!(sizes, [[105.0, 148.5], [300.0, 50.0]]);
+
+    // Both fit into the same square, centered
+    let args = RenderArgs {
+        page_size: Some(parse_size("100x100").unwrap()),
+        ..args
+    };
+    let pages = rendered_pages(&portrait_and_banner(), &args);
+    for (page, (inside, outside)) in pages
+        .iter()
+        .zip([((50, 50), (5, 50)), ((50, 50), (50, 10))])
+    {
+        assert_eq!(page.0, [100.0, 100.0]);
+        let (width, data) = page.1.as_ref().unwrap();
+        let pixel = |(x, y): (i64, i64)| data[((y * width + x) * 3) as usize];
+        assert_eq!((pixel(inside), pixel(outside)), (0, 255));
+    }
+    assert_eq!(page_pixels(&args), 100 * 100);
+}
+
+#[test]
+fn test_too_large_pages_name_their_file() {
+    let sources = vec![Source::bytes("huge.svg", filled_drawing(100_000, 100_000))];
+    let mut writer = crate::writer::PdfWriter::new(1.0, ImageOptions::default());
+    let Err(err) = convert(
+        &load_options(),
+        sources.into(),
+        &RenderArgs::default(),
+        &RunOptions::default(),
+        &mut writer,
+    ) else {
+        panic!("a 100000x100000 page renders");
+    };
+    let err = format!("{err:#}");
+    assert!(
+        err.contains("Failed to render \"huge.svg\": too large"),
+        "{err}"
+    );
+    assert!(err.contains("MB of memory"), "{err}");
+}
+
+#[test]
+fn test_dpi_sets_the_pixels_not_the_page() {
+    let args = RenderArgs {
+        scale: 0.5,
+        ..RenderArgs::default()
+    };
+    let dense = RenderArgs {
+        dpi: Some(192.0),
+        ..args.clone()
+    };
+    let sizes = |args: &RenderArgs| -> Vec<_> {
+        rendered_pages(&portrait_and_banner(), args)
+            .into_iter()
+            .map(|page| page.0)
+            .collect()
+    };
+    assert_eq!(sizes(&args), sizes(&dense));
+
+    let data = filled_drawing(210, 297);
+    let tree = Tree::from_data(&data, &load_options()).unwrap();
+    let pixels = |args: &RenderArgs| Layout::of(&tree, &data, args).pixels(args.resolution(), None);
+    assert_eq!(pixels(&args), Ok((210, 297)));
+    assert_eq!(pixels(&dense), Ok((420, 594)));
+
+    assert_eq!(parse_dpi("150.5"), Ok(150.5));
+    for value in ["0", "-96", "inf", "high"] {
+        assert!(parse_dpi(value).is_err(), "{value}");
+    }
+}
+
+// An SVG of `width` by `height` CSS pixels, its left half filled, so its
+// page is no plain fill
+#[cfg(test)]
+fn half_filled_drawing(width: u32, height: u32) -> Vec<u8> {
+    format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}"><rect width="50%" height="100%"/></svg>"#
+    )
+    .into_bytes()
+}
+
+#[test]
+fn test_fractional_dpi() {
+    // 100 CSS pixels at 150.5 dpi are 156.77 pixels, rounded to 157, on a
+    // page of 100 points give or take the rounding
+    let sources = vec![Source::bytes("square.svg", half_filled_drawing(100, 100))];
+    let args = RenderArgs {
+        scale: 1.0,
+        dpi: Some(150.5),
+        ..RenderArgs::default()
+    };
+    let pages = rendered_pages(&sources, &args);
+    let (width, data) = pages[0].1.as_ref().unwrap();
+    assert_eq!(*width, 157);
+    assert_eq!(data.len(), 157 * 157 * 3);
+    for side in &pages[0].0 {
+        assert!((side - 100.0).abs() < 96.0 / 150.5, "{side}");
+    }
+}
+
+#[test]
+fn test_high_dpi() {
+    // 1200 dpi renders a small page
+    let sources = vec![Source::bytes("stamp.svg", half_filled_drawing(20, 20))];
+    let args = RenderArgs {
+        dpi: Some(1200.0),
+        ..RenderArgs::default()
+    };
+    let pages = rendered_pages(&sources, &args);
+    assert_eq!(pages[0].1.as_ref().unwrap().0, 250);
+
+    // and fails a large one, by the memory it would take, before any of it
+    // is allocated
+    let sources = vec![Source::bytes("poster.svg", filled_drawing(2000, 2000))];
+    let mut writer = crate::writer::PdfWriter::new(args.resolution(), ImageOptions::default());
+    let Err(err) = convert(
+        &load_options(),
+        sources.into(),
+        &args,
+        &RunOptions::default(),
+        &mut writer,
+    ) else {
+        panic!("a 25000x25000 page renders");
+    };
+    let err = format!("{err:#}");
+    assert!(
+        err.contains("\"poster.svg\": too large, 25000x25000 pixels would take"),
+        "{err}"
+    );
+    assert!(
+        err.contains("MB of memory") && err.contains("lower --dpi"),
+        "{err}"
+    );
+}
+
+// Black on the left fading to white on the right, the fixture of the
+// dithering tests
+#[cfg(test)]
+fn gradient_drawing(width: u32, height: u32) -> Vec<u8> {
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}"><linearGradient id="fade"><stop offset="0"/><stop offset="1" stop-color="#fff"/></linearGradient><rect width="100%" height="100%" fill="url(#fade)"/></svg>"##
+    )
+    .into_bytes()
+}
+
+#[test]
+fn test_dithered_gradients() {
+    use crate::bilevel::{self, Dither};
+    use crate::writer::ColorMode;
+
+    let (width, height) = (256, 64);
+    let args = RenderArgs {
+        scale: 1.0,
+        ..RenderArgs::default()
+    };
+    // The share of black pixels in 8 bands of 32 columns, and the size of
+    // the page in Group 4
+    let bands = |dither| {
+        let images = ImageOptions {
+            color_mode: ColorMode::Bilevel,
+            dither,
+            ..ImageOptions::default()
+        };
+        let mut writer = crate::writer::PdfWriter::new(1.0, images);
+        let sources = vec![Source::bytes("fade.svg", gradient_drawing(width, height))];
+        let run = RunOptions::default();
+        convert(&load_options(), sources.into(), &args, &run, &mut writer).unwrap();
+        let mut pdf = Vec::new();
+        Box::new(writer).finish(&mut pdf).unwrap();
+        let doc = lopdf::Document::load_mem(&pdf).unwrap();
+        let page = *doc.get_pages().values().next().unwrap();
+        let coded = doc.get_page_images(page).unwrap()[0].content.to_vec();
+        let bitmap = bilevel::decode_g4(&coded, width, height).unwrap();
+        let shares: Vec<f64> = (0..8)
+            .map(|band| {
+                let black = (0..height)
+                    .flat_map(|y| (band * 32..band * 32 + 32).map(move |x| (x, y)))
+                    .filter(|&(x, y)| bitmap.is_black(x, y))
+                    .count();
+                black as f64 / (32 * height) as f64
+            })
+            .collect();
+        (shares, coded.len())
+    };
+
+    // Thresholding leaves a black half and a white one
+    let (threshold, banded_size) = bands(Dither::None);
+    assert_eq!(threshold, [1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+    // Dithering keeps the gray of every band
+    for dither in [Dither::FloydSteinberg, Dither::Ordered] {
+        let (shares, size) = bands(dither);
+        for (band, share) in shares.into_iter().enumerate() {
+            let expected = 1.0 - (band as f64 * 32.0 + 15.5) / 255.0;
+            assert!(
+                (share - expected).abs() < 0.05,
+                "{dither:?} band {band}: {share} black, expected {expected}"
+            );
+        }
+        // Dots take more bytes than bands
+        assert!(size > banded_size, "{dither:?}");
+    }
+}
+
+#[test]
+fn test_paper_sizes() {
+    // A4 sheets, turned for the banner only, with the drawings inside 10 mm
+    // of margin
+    let args = RenderArgs {
+        scale: 0.5,
+        paper: Some(parse_paper("A4").unwrap()),
+        margin: Some(10.0),
+        orientation: Some(Orientation::Auto),
+        ..RenderArgs::default()
+    };
+    let pages = rendered_pages(&portrait_and_banner(), &args);
+    for (page, (size, inside, outside)) in pages.iter().zip([
+        ([595, 842], (297, 420), (10, 420)),
+        ([842, 595], (420, 297), (420, 200)),
+    ]) {
+        let rounded: Vec<_> = page.0.iter().map(|side| side.round() as i64).collect();
+        assert_eq!(rounded, size);
+        let (width, data) = page.1.as_ref().unwrap();
+        let pixel = |(x, y): (i64, i64)| data[((y * width + x) * 3) as usize];
+        assert_eq!((pixel(inside), pixel(outside)), (0, 255));
+    }
+    let portrait = RenderArgs {
+        orientation: None,
+        ..args.clone()
+    };
+    let sizes: Vec<_> = rendered_pages(&portrait_and_banner(), &portrait)
+        .into_iter()
+        .map(|page| page.0)
+        .collect();
+    assert_eq!(sizes[0], sizes[1]);
+
+    let custom = parse_paper("200x100mm").unwrap();
+    assert_eq!(
+        (custom.width.round(), custom.height.round()),
+        (567.0, 283.0)
+    );
+    for value in ["a5", "200x100", "mm"] {
+        assert!(parse_paper(value).is_err(), "{value}");
+    }
+    let cramped = RenderArgs {
+        margin: Some(105.0),
+        ..args
+    };
+    assert!(cramped.check().is_err());
+}
+
+#[test]
+fn test_nup_sheets() {
+    // Each drawing of --nup is fit into its cell, on pages of --page-size
+    let nup = RenderArgs {
+        page_size: Some(parse_size("200x100").unwrap()),
+        nup: Some(nup::parse_grid("2x1").unwrap()),
+        gutter: Some(0.0),
+        ..RenderArgs::default()
+    };
+    assert_eq!(page_pixels(&nup), 100 * 100);
+    assert_eq!(nup.sheet().map(|sheet| sheet.width), Some(200.0));
+    let crowded = RenderArgs {
+        nup: Some(nup::parse_grid("300x1").unwrap()),
+        gutter: None,
+        ..nup
+    };
+    assert!(crowded.check().is_err());
+}
+
+#[test]
+fn test_max_pixels() {
+    // Files with larger pages fail before rendering, and can be skipped
+    let sources = vec![
+        Source::bytes("small.svg", filled_drawing(50, 20)),
+        Source::bytes("wide.svg", filled_drawing(200, 100)),
+    ];
+    let run = RunOptions {
+        max_pixels: Some(10_000),
+        on_error: OnError::Skip,
+        ..RunOptions::default()
+    };
+    let mut writer = crate::writer::PdfWriter::new(1.0, ImageOptions::default());
+    let conversion = convert(
+        &load_options(),
+        sources.into(),
+        &RenderArgs::default(),
+        &run,
+        &mut writer,
+    )
+    .unwrap();
+    assert_eq!(conversion.pages.len(), 1);
+    let err = format!("{:#}", conversion.failed[0].error);
+    assert!(
+        err.contains("Failed to render \"wide.svg\": too large, 200x100 pixels, more than the 10000 of --max-pixels"),
+        "{err}"
+    );
+
+    // Never more than the largest page rendered at all
+    assert_eq!(pixel_limit(None), MAX_PAGE_PIXELS);
+    assert_eq!(pixel_limit(Some(u64::MAX)), MAX_PAGE_PIXELS);
+    assert_eq!(pixel_limit(Some(10_000)), 10_000);
+}
+
+#[test]
+fn test_cancelled_runs_fail() {
+    let sources = vec![
+        Source::bytes("a.svg", filled_drawing(50, 20)),
+        Source::bytes("b.svg", filled_drawing(50, 20)),
+    ];
+    let cancel = AtomicBool::new(true);
+    // Even where failed files would be skipped
+    let run = RunOptions {
+        cancel: Some(&cancel),
+        on_error: OnError::Skip,
+        ..RunOptions::default()
+    };
+    let mut writer = crate::writer::PdfWriter::new(1.0, ImageOptions::default());
+    let Err(err) = convert(
+        &load_options(),
+        sources.into(),
+        &RenderArgs::default(),
+        &run,
+        &mut writer,
+    ) else {
+        panic!("a cancelled run finished");
+    };
+    assert_eq!(err.to_string(), "Conversion cancelled");
+}
+
+#[test]
+fn test_jobs_size_the_pool() {
+    let sources: Vec<Source> = (0..16)
+        .map(|index| Source::bytes(format!("{index}.svg"), filled_drawing(40, 30)))
+        .collect();
+    for workers in [1, 2] {
+        let run = RunOptions {
+            workers: Some(workers),
+            ..RunOptions::default()
+        };
+        let mut writer = crate::writer::PdfWriter::new(1.0, ImageOptions::default());
+        let conversion = convert(
+            &load_options(),
+            sources.clone().into(),
+            &RenderArgs::default(),
+            &run,
+            &mut writer,
+        )
+        .unwrap();
+        // Threads of the pool count from 1
+        let mut threads: Vec<_> = conversion
+            .pages
+            .iter()
+            .map(|page| page.timings.thread)
+            .collect();
+        threads.sort();
+        threads.dedup();
+        assert!(
+            threads
+                .iter()
+                .all(|&thread| (1..=workers).contains(&thread)),
+            "{threads:?}"
+        );
+    }
+}
+
+#[test]
+fn test_pipeline_keeps_page_order() {
+    let dir = std::env::temp_dir().join(format!("svg2pdf-io-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let sources: Vec<Source> = (0..12)
+        .map(|i| {
+            let path = dir.join(format!("{i}.svg"));
+            let svg = format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="100"><rect width="100%" height="50%"/></svg>"#,
+                (i + 1) * 10
+            );
+            fs::write(&path, svg).unwrap();
+            Source::file(&dir, path)
+        })
+        .collect();
+
+    let opt = load_options();
+    let args = RenderArgs::default();
+    let ids = |io_threads, max_in_flight| {
+        let run = RunOptions {
+            io_threads,
+            max_in_flight,
+            ..RunOptions::default()
+        };
+        // Raw pages with some detail, so every page takes exactly its pixels
+        // in the budget
+        let mut writer = crate::writer::PdfWriter::new(
+            args.resolution(),
+            ImageOptions {
+                format: crate::writer::ImageFormat::Raw,
+                ..ImageOptions::default()
+            },
+        );
+        let conversion = convert(&opt, sources.clone().into(), &args, &run, &mut writer).unwrap();
+        let depths: Vec<_> = conversion
+            .pages
+            .iter()
+            .map(|page| page.timings.queue_depth)
+            .collect();
+        assert_eq!(depths.iter().all(Option::is_some), io_threads > 0);
+        // A one-byte budget only lets the page written next through, at
+        // most the last and widest one
+        if max_in_flight == Some(1) {
+            assert_eq!(conversion.peak_in_flight, 120 * 100 * 3);
+        }
+        conversion
+            .pages
+            .into_iter()
+            .map(|page| page.id)
+            .collect::<Vec<_>>()
+    };
+    let expected = ids(0, None);
+    assert_eq!(expected[11], "11.svg");
+    assert_eq!(ids(3, None), expected);
+    assert_eq!(ids(1, Some(1)), expected);
+    assert_eq!(ids(0, Some(1)), expected);
+
+    // Unreadable files still fail the run
+    fs::remove_file(&sources[5].path).unwrap();
+    let run = RunOptions {
+        io_threads: 2,
+        ..RunOptions::default()
+    };
+    let mut writer = crate::writer::PdfWriter::new(1.0, ImageOptions::default());
+    assert!(convert(&opt, sources.into(), &args, &run, &mut writer).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_long_poles_keep_page_order() {
+    let dir = std::env::temp_dir().join(format!("svg2pdf-poles-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let sources: Vec<Source> = (0..10)
+        .map(|i| {
+            let path = dir.join(format!("{i}.svg"));
+            // The last three files are far larger than the others
+            let padding = if i >= 7 { "x".repeat(5000) } else { String::new() };
+            let svg = format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="10"><rect width="100%" height="100%"/><!--{padding}--></svg>"#,
+                i + 1
+            );
+            fs::write(&path, svg).unwrap();
+            Source::file(&dir, path)
+        })
+        .collect();
+
+    let opt = load_options();
+    for io_threads in [0, 2] {
+        // A budget that only lets the next page through, and as many long
+        // poles as workers: they must not keep the page written next waiting
+        let run = RunOptions {
+            io_threads,
+            max_in_flight: Some(1),
+            workers: Some(3),
+            reorder_work: true,
+            ..RunOptions::default()
+        };
+        let mut writer = crate::writer::PdfWriter::new(1.0, ImageOptions::default());
+        let conversion = convert(
+            &opt,
+            sources.clone().into(),
+            &RenderArgs::default(),
+            &run,
+            &mut writer,
+        )
+        .unwrap();
+        assert_eq!(conversion.started_early, 3);
+        let ids: Vec<_> = conversion
+            .pages
+            .iter()
+            .map(|page| page.id.as_str())
+            .collect();
+        assert_eq!(ids, (0..10).map(|i| format!("{i}.svg")).collect::<Vec<_>>());
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_scan_dir_streams_svgs() {
+    let dir = std::env::temp_dir().join(format!("svg2pdf-scan-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for name in ["a.svg", "B.SVG", "notes.txt", ".svg", "c.svgz"] {
+        fs::write(
+            dir.join(name),
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="4"/>"#,
+        )
+        .unwrap();
+    }
+    assert_eq!(Inputs::dir(&dir).count().unwrap(), 2);
+
+    // Streamed through the IO threads and into the writer
+    let sources = Inputs::dir(&dir).scan(SortOrder::Natural, false).unwrap();
+    assert_eq!((sources.total, sources.files), (2, true));
+    let run = RunOptions {
+        io_threads: 2,
+        ..RunOptions::default()
+    };
+    let mut writer = crate::writer::PdfWriter::new(1.0, ImageOptions::default());
+    let conversion = convert(
+        &load_options(),
+        sources,
+        &RenderArgs::default(),
+        &run,
+        &mut writer,
+    )
+    .unwrap();
+    let ids: Vec<_> = conversion.pages.into_iter().map(|page| page.id).collect();
+    assert_eq!(ids, ["a.svg", "B.SVG"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+    let empty = std::env::temp_dir().join(format!("svg2pdf-scan-empty-{}", std::process::id()));
+    fs::create_dir_all(&empty).unwrap();
+    assert!(Inputs::dir(&empty).scan(SortOrder::Natural, false).is_err());
+    fs::remove_dir_all(&empty).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_utf8_file_names() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = std::env::temp_dir().join(format!("svg2pdf-names-test-{}", std::process::id()));
+    let names = [&b"a\xff.svg"[..], b"a\xfe.svg", b"new\nline.svg"];
+    fs::create_dir_all(&dir).unwrap();
+    for name in names {
+        fs::write(
+            dir.join(OsStr::from_bytes(name)),
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="4"><rect width="2" height="2"/></svg>"#,
+        )
+        .unwrap();
+    }
+
+    let export = ImageExport::new(dir.join("png"), "{name}.png").unwrap();
+    let run = RunOptions {
+        export: Some(&export),
+        pixel_hashes: true,
+        ..RunOptions::default()
+    };
+    let mut writer = crate::writer::PdfWriter::new(1.0, ImageOptions::default());
+    let args = RenderArgs::default();
+    let conversion = convert(
+        &load_options(),
+        Inputs::dir(&dir).scan(SortOrder::Natural, false).unwrap(),
+        &args,
+        &run,
+        &mut writer,
+    )
+    .unwrap();
+    let mut ids: Vec<_> = conversion
+        .pages
+        .iter()
+        .map(|page| page.id.as_str())
+        .collect();
+    ids.sort();
+    assert_eq!(ids, ["a%FE.svg", "a%FF.svg", "new%0Aline.svg"]);
+
+    // Every page gets its own image, and the reports keep one line per page
+    for page in &conversion.pages {
+        let image = page.image_path.as_ref().unwrap();
+        assert_eq!(image, &dir.join("png").join(format!("{}.png", page.id)));
+        assert!(image.is_file());
+    }
+    assert_eq!(crate::hashes::manifest(&conversion).lines().count(), 3);
+    let html = crate::html::index(&conversion, &dir, None);
+    assert!(html.contains("src=\"png/a%25FF.svg.png\""));
+    assert!(html.contains(">new%0Aline.svg</div>"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_copies() {
+    assert_eq!(parse_copies("3"), Ok(3));
+    for value in ["0", "-2", "two", ""] {
+        assert!(parse_copies(value).is_err(), "{value}");
+    }
+    assert_eq!(
+        parse_copies_override("sign=off*.svg=3"),
+        Ok(("sign=off*.svg".to_string(), 3))
+    );
+    assert!(parse_copies_override("sign.svg=0").is_err());
+    assert!(parse_copies_override("sign.svg").is_err());
+
+    let copies = Copies {
+        all: 2,
+        overrides: vec![("sign*".to_string(), 3), ("*".to_string(), 1)],
+    };
+    assert_eq!(copies.of("signature.svg"), 3);
+    assert_eq!(copies.of("cover.svg"), 1);
+    assert_eq!(Copies::default().of("cover.svg"), 1);
+
+    // Each copy is a page of the document, the report has each page once
+    let svg = |color: &str| {
+        format!(r#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="4"><rect width="2" height="2" fill="{color}"/></svg>"#)
+            .into_bytes()
+    };
+    let sources = vec![
+        Source::bytes("cover.svg", svg("red")),
+        Source::bytes("sign.svg", svg("blue")),
+        Source::bytes("back.svg", svg("green")),
+    ];
+    let run = RunOptions {
+        copies: Copies {
+            all: 2,
+            overrides: vec![("sign*".to_string(), 3)],
+        },
+        ..RunOptions::default()
+    };
+    let mut writer = Box::new(crate::writer::PdfWriter::new(1.0, ImageOptions::default()));
+    let conversion = convert(
+        &load_options(),
+        sources.into(),
+        &RenderArgs::default(),
+        &run,
+        writer.as_mut(),
+    )
+    .unwrap();
+    let copies: Vec<_> = conversion.pages.iter().map(|page| page.copies).collect();
+    assert_eq!(copies, [2, 3, 2]);
+    let mut pdf = Vec::new();
+    writer.finish(&mut pdf).unwrap();
+    assert_eq!(
+        lopdf::Document::load_mem(&pdf).unwrap().get_pages().len(),
+        7
+    );
+}
+
+#[test]
+fn test_annotations_on_pdf_pages() {
+    let annotations = Annotations::parse(
+        r#"[
+            {"page": "a.svg", "point": [25, 25], "author": "Zoë", "text": "Größer"},
+            {"page": "b.svg", "rect": [0, 0, 50, 10], "kind": "highlight", "modified": "2026-03-01T14:30:00Z"},
+            {"page": "gone.svg", "point": [0, 0]}
+        ]"#
+        .as_bytes(),
+    )
+    .unwrap();
+    // 100x75 user units drawn on a 400x300 page; at best
+    // quality a point is two pixels
+    let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="400" height="300" viewBox="0 0 100 75"><rect width="10" height="10"/></svg>"#;
+    let sources = vec![
+        Source::bytes("a.svg", svg.to_vec()),
+        Source::bytes("b.svg", svg.to_vec()),
+        Source::bytes("c.svg", svg.to_vec()),
+    ];
+    let args = RenderArgs {
+        scale: 1.0,
+        quality: Quality::Best,
+        ..RenderArgs::default()
+    };
+    let run = RunOptions {
+        annotations: Some(&annotations),
+        copies: Copies {
+            all: 1,
+            overrides: vec![("b.svg".to_string(), 2)],
+        },
+        // b.svg reuses the page of a.svg
+        dedupe: true,
+        ..RunOptions::default()
+    };
+    let resolution = args.resolution();
+    let mut writer = Box::new(crate::writer::PdfWriter::new(
+        resolution,
+        ImageOptions::default(),
+    ));
+    let conversion = convert(
+        &load_options(),
+        sources.into(),
+        &args,
+        &run,
+        writer.as_mut(),
+    )
+    .unwrap();
+    let placed: Vec<_> = conversion
+        .pages
+        .iter()
+        .map(|page| page.annotations)
+        .collect();
+    assert_eq!(placed, [1, 1, 0]);
+    assert!(conversion.pages[1].deduped);
+    assert_eq!(
+        conversion.stray_annotations,
+        ["Annotation for \"gone.svg\" matches no page"]
+    );
+    let mut pdf = Vec::new();
+    writer.finish(&mut pdf).unwrap();
+    assert_eq!(verify::check(Format::Pdf, &pdf, 4), Vec::<String>::new());
+
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let annots = |page: u32| -> Vec<&lopdf::Dictionary> {
+        let page = doc.get_pages()[&page];
+        let Ok(annots) = doc.get_dictionary(page).unwrap().get(b"Annots") else {
+            return Vec::new();
+        };
+        annots
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|id| doc.get_dictionary(id.as_reference().unwrap()).unwrap())
+            .collect()
+    };
+    let numbers = |dict: &lopdf::Dictionary, key: &[u8]| -> Vec<f32> {
+        let array = dict.get(key).unwrap().as_array().unwrap();
+        array.iter().map(|v| v.as_float().unwrap()).collect()
+    };
+    let note = annots(1);
+    assert_eq!(note.len(), 1);
+    assert_eq!(note[0].get(b"Subtype").unwrap().as_name().unwrap(), b"Text");
+    // The icon hangs down from the point, y counted up from the bottom
+    assert_eq!(numbers(note[0], b"Rect"), [100.0, 176.0, 124.0, 200.0]);
+    let utf16 = |text: &str| {
+        let mut bytes = vec![0xfe, 0xff];
+        bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+        bytes
+    };
+    assert_eq!(note[0].get(b"T").unwrap().as_str().unwrap(), utf16("Zoë"));
+    assert_eq!(
+        note[0].get(b"Contents").unwrap().as_str().unwrap(),
+        utf16("Größer")
+    );
+
+    // Every copy has its own annotation pointing back at it
+    for page in [2, 3] {
+        let highlight = annots(page);
+        assert_eq!(highlight.len(), 1);
+        assert_eq!(numbers(highlight[0], b"Rect"), [0.0, 260.0, 200.0, 300.0]);
+        assert_eq!(
+            highlight[0].get(b"P").unwrap().as_reference().unwrap(),
+            doc.get_pages()[&page]
+        );
+        assert_eq!(
+            highlight[0].get(b"M").unwrap().as_str().unwrap(),
+            b"D:20260301143000Z"
+        );
+    }
+    assert!(annots(4).is_empty());
+}
+
+#[test]
+fn test_empty_runs() {
+    let dir = std::env::temp_dir().join(format!("svg2pdf-empty-test-{}", std::process::id()));
+    let input = dir.join("in");
+    fs::create_dir_all(&input).unwrap();
+    let output = dir.join("out.pdf");
+    let opt = load_options();
+    let convert = |allow_empty, placeholder: Option<&Path>, drop_blank_pages| {
+        let run = RunOptions {
+            allow_empty,
+            placeholder,
+            drop_blank_pages,
+            ..RunOptions::default()
+        };
+        let _ = fs::remove_file(&output);
+        convert_dir(
+            &opt,
+            &Inputs::dir(&input),
+            &output,
+            &RenderArgs::default(),
+            &run,
+        )
+    };
+    let pages = || lopdf::Document::load(&output).unwrap().get_pages().len();
+
+    let Err(err) = convert(None, None, false) else {
+        panic!("an empty directory fails without --allow-empty");
+    };
+    assert!(err.to_string().contains("--allow-empty"), "{err}");
+    let conversion = convert(Some(EmptyOutput::NoFile), None, false).unwrap();
+    assert!(conversion.empty && conversion.pages.is_empty());
+    assert!(!output.exists());
+    let conversion = convert(Some(EmptyOutput::Placeholder), None, false).unwrap();
+    assert!(conversion.empty);
+    assert_eq!(conversion.pages[0].id, "placeholder.svg");
+    assert_eq!(pages(), 1);
+
+    // Every page dropped as blank leaves nothing to write either
+    fs::write(input.join("blank.svg"), BLANK_PAGE).unwrap();
+    let Err(err) = convert(None, None, true) else {
+        panic!("a document without pages is never written");
+    };
+    assert!(err.to_string().contains("dropped as blank"), "{err}");
+    assert!(!output.exists());
+    let placeholder = dir.join("todo.svg");
+    fs::write(&placeholder, r#"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="40"><rect width="20" height="20"/></svg>"#).unwrap();
+    let conversion = convert(Some(EmptyOutput::Placeholder), Some(&placeholder), true).unwrap();
+    assert_eq!(conversion.dropped.len(), 1);
+    assert_eq!(conversion.pages[0].id, "todo.svg");
+    assert_eq!(conversion.pages[0].blank, None);
+    assert_eq!(pages(), 1);
+    // Runs with pages are not affected
+    let conversion = convert(Some(EmptyOutput::NoFile), None, false).unwrap();
+    assert!(!conversion.empty);
+    assert_eq!(pages(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_duplicates_render_once() {
+    let svg = |fill: &str| {
+        format!(r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100"><rect width="100" height="100" fill="{fill}"/></svg>"#)
+            .into_bytes()
+    };
+    let sources = vec![
+        Source::bytes("a.svg", svg("red")),
+        Source::bytes("b.svg", svg("blue")),
+        Source::bytes("copy of a.svg", svg("red")),
+        Source::bytes("another a.svg", svg("red")),
+    ];
+    let opt = load_options();
+    let run = |dedupe| {
+        let run = RunOptions {
+            dedupe,
+            pixel_hashes: true,
+            ..RunOptions::default()
+        };
+        let mut writer = crate::writer::PdfWriter::new(1.0, ImageOptions::default());
+        convert(
+            &opt,
+            sources.clone().into(),
+            &RenderArgs::default(),
+            &run,
+            &mut writer,
+        )
+        .unwrap()
+    };
+
+    let conversion = run(true);
+    assert_eq!(conversion.dedupe_hits, 2);
+    let deduped: Vec<_> = conversion.pages.iter().map(|page| page.deduped).collect();
+    assert_eq!(deduped, [false, false, true, true]);
+    assert_eq!(conversion.pages[2].id, "copy of a.svg");
+    assert_eq!(
+        conversion.pages[0].pixel_hash,
+        conversion.pages[3].pixel_hash
+    );
+    assert_ne!(
+        conversion.pages[0].pixel_hash,
+        conversion.pages[1].pixel_hash
+    );
+
+    assert_eq!(run(false).dedupe_hits, 0);
+}
+
+#[test]
+fn test_blank_pages() {
+    let page = |rgb_data: Vec<u8>| RenderedImage {
+        width: 2,
+        height: 2,
+        rgb_data,
+        alpha: None,
+    };
+    assert_eq!(blank_color(&page(vec![255; 12]), 0), Some([255; 3]));
+    // A faint artifact only counts as blank within the tolerance
+    let faint = page([[250, 255, 255], [255; 3], [255; 3], [255; 3]].concat());
+    assert_eq!(blank_color(&faint, 0), None);
+    assert_eq!(blank_color(&faint, 5), Some([252, 255, 255]));
+
+    let svg = |body: &str| {
+        format!(r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">{body}</svg>"#)
+            .into_bytes()
+    };
+    let sources = vec![
+        Source::bytes("a.svg", svg(r#"<rect width="50" height="50"/>"#)),
+        Source::bytes("empty.svg", svg("")),
+        Source::bytes("c.svg", svg(r#"<circle r="30"/>"#)),
+    ];
+    let run = |drop_blank_pages| {
+        let run = RunOptions {
+            drop_blank_pages,
+            ..RunOptions::default()
+        };
+        let mut writer = crate::writer::PdfWriter::new(1.0, ImageOptions::default());
+        convert(
+            &load_options(),
+            sources.clone().into(),
+            &RenderArgs::default(),
+            &run,
+            &mut writer,
+        )
+        .unwrap()
+    };
+
+    let conversion = run(false);
+    assert_eq!(conversion.pages[1].blank, Some([255; 3]));
+    assert_eq!(conversion.pages[1].encoding, Some(Encoding::Fill));
+    assert_eq!(conversion.pages[0].blank, None);
+
+    let conversion = run(true);
+    let ids: Vec<_> = conversion
+        .pages
+        .iter()
+        .map(|page| page.id.as_str())
+        .collect();
+    assert_eq!(ids, ["a.svg", "c.svg"]);
+    assert_eq!(conversion.dropped.len(), 1);
+    assert_eq!(conversion.dropped[0].id, "empty.svg");
+}
+
+#[test]
+fn test_cached_pages_are_encoded_once() {
+    use crate::cache::PageCache;
+    use crate::writer::{ImageFormat, PdfWriter};
+
+    let sources = vec![Source::bytes("a.svg", gradient_drawing(40, 30))];
+    let cache = PageCache::new(true, None);
+    let run = RunOptions {
+        cache: Some(&cache),
+        ..RunOptions::default()
+    };
+    let convert = |format| {
+        let images = ImageOptions {
+            format,
+            ..ImageOptions::default()
+        };
+        let mut writer = PdfWriter::new(1.0, images);
+        let conversion = convert(
+            &load_options(),
+            sources.clone().into(),
+            &RenderArgs::default(),
+            &run,
+            &mut writer,
+        )
+        .unwrap();
+        let mut pdf = Vec::new();
+        Box::new(writer).finish(&mut pdf).unwrap();
+        (conversion, pdf)
+    };
+
+    let (first, pdf) = convert(ImageFormat::Jpeg);
+    assert_eq!(first.cache_hits, 0);
+    // The hit is written as the encoded page it was stored as
+    let (second, cached_pdf) = convert(ImageFormat::Jpeg);
+    assert_eq!(second.cache_hits, 1);
+    let page = &second.pages[0];
+    assert_eq!(page.encoding, Some(Encoding::Jpeg));
+    assert!(page.timings.stage(Stage::Encode).is_zero());
+    let images = |pdf: &[u8]| {
+        let doc = lopdf::Document::load_mem(pdf).unwrap();
+        let streams = doc.objects.into_values().filter_map(|object| {
+            let stream = object.as_stream().ok()?;
+            let image = stream.dict.get(b"Subtype").ok()?.as_name().ok()? == b"Image";
+            image.then(|| stream.content.clone())
+        });
+        streams.collect::<Vec<_>>()
+    };
+    assert_eq!(images(&cached_pdf), images(&pdf));
+    // Another image format is another page
+    let (third, _) = convert(ImageFormat::Flate);
+    assert_eq!(third.cache_hits, 0);
+    assert_eq!(third.pages[0].encoding, Some(Encoding::Flate));
+}
+
+#[test]
+fn test_dropped_pages_from_the_cache() {
+    use crate::cache::PageCache;
+
+    let svg = |body: &str| {
+        format!(r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">{body}</svg>"#)
+            .into_bytes()
+    };
+    // The blank pages are dropped, the second as a copy of the first
+    let sources = vec![
+        Source::bytes("a.svg", svg(r#"<rect width="50" height="50"/>"#)),
+        Source::bytes("empty.svg", svg("")),
+        Source::bytes("again.svg", svg("")),
+    ];
+    let cache = PageCache::new(true, None);
+    let run = RunOptions {
+        cache: Some(&cache),
+        drop_blank_pages: true,
+        dedupe: true,
+        ..RunOptions::default()
+    };
+    let convert = || {
+        let mut writer = crate::writer::PdfWriter::new(1.0, ImageOptions::default());
+        convert(
+            &load_options(),
+            sources.clone().into(),
+            &RenderArgs::default(),
+            &run,
+            &mut writer,
+        )
+        .unwrap()
+    };
+
+    let first = convert();
+    assert_eq!((first.pages.len(), first.dropped.len()), (1, 2));
+    assert_eq!(first.dedupe_hits, 1);
+    assert_eq!(first.rendered(), 2);
+    // Every hit of the second run is more than it has pages
+    let second = convert();
+    assert!(second.cache_hits + second.dedupe_hits > second.pages.len());
+    assert_eq!(second.rendered(), 0);
+}
+
+#[test]
+fn test_fonts_load_only_for_text() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static LOADS: AtomicUsize = AtomicUsize::new(0);
+    static FONTS: LazyFonts = LazyFonts::new(|| {
+        LOADS.fetch_add(1, Ordering::SeqCst);
+        fontdb::Database::new()
+    });
+    let opt = lazy_options(&FONTS);
+    let convert_svg = |svg: &str, quality| {
+        let sources = vec![Source::bytes("page.svg", svg.as_bytes().to_vec())];
+        let args = RenderArgs {
+            quality,
+            ..RenderArgs::default()
+        };
+        let mut writer = crate::writer::PdfWriter::new(1.0, ImageOptions::default());
+        convert(
+            &opt,
+            sources.into(),
+            &args,
+            &RunOptions::default(),
+            &mut writer,
+        )
+        .map(|_| ())
+    };
+
+    let shapes = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><rect width="5" height="5"/></svg>"#;
+    let text = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><text y="8">Hi</text></svg>"#;
+    assert!(!may_have_text(shapes.as_bytes()));
+    assert!(may_have_text(text.as_bytes()));
+    convert_svg(shapes, Quality::Normal).unwrap();
+    convert_svg(shapes, Quality::Draft).unwrap();
+    assert_eq!(LOADS.load(Ordering::SeqCst), 0);
+
+    // Presets other than normal still go through the lazy resolver. There
+    // are no fonts to load, so the text can't be drawn.
+    let err = convert_svg(text, Quality::Draft).unwrap_err();
+    assert!(
+        format!("{err:#}").contains("no fonts were found"),
+        "{err:#}"
+    );
+    assert_eq!(LOADS.load(Ordering::SeqCst), 1);
+    assert!(convert_svg(text, Quality::Normal).is_err());
+    assert_eq!(LOADS.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_vector_pages_verify() {
+    let svg = |body: &str| {
+        format!(r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">{body}</svg>"#)
+            .into_bytes()
+    };
+    let sources = vec![
+        Source::bytes("shapes.svg", svg(r#"<rect width="50" height="50"/>"#)),
+        Source::bytes(
+            "blur.svg",
+            svg(
+                r#"<filter id="f"><feGaussianBlur stdDeviation="2"/></filter><circle cx="50" cy="50" r="30" filter="url(#f)"/>"#,
+            ),
+        ),
+    ];
+    let run = RunOptions {
+        vector: true,
+        ..RunOptions::default()
+    };
+    let mut writer = Box::new(crate::writer::PdfWriter::new(1.0, ImageOptions::default()));
+    let conversion = convert(
+        &load_options(),
+        sources.into(),
+        &RenderArgs::default(),
+        &run,
+        writer.as_mut(),
+    )
+    .unwrap();
+    let mut pdf = Vec::new();
+    writer.finish(&mut pdf).unwrap();
+
+    let encodings: Vec<_> = conversion.pages.iter().map(|page| page.encoding).collect();
+    assert_eq!(encodings, [Some(Encoding::Vector); 2]);
+    assert!(conversion.pages[0].warnings.is_empty());
+    assert_eq!(
+        conversion.pages[1].warnings,
+        ["Drawn as images in the vector page: filters"]
+    );
+    assert_eq!(
+        verify::check(crate::writer::Format::Pdf, &pdf, 2),
+        Vec::<String>::new()
+    );
+    // Only the blurred circle is an image, with its soft mask
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let images = doc
+        .objects
+        .values()
+        .filter_map(|object| object.as_stream().ok())
+        .filter(|stream| {
+            stream.dict.get(b"Subtype").and_then(|s| s.as_name()).ok() == Some(b"Image")
+        })
+        .count();
+    assert_eq!(images, 2);
+}
+
+#[test]
+fn test_failed_files_skipped_or_blank() {
+    let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50"><rect width="50" height="50"/></svg>"#;
+    let sources = || -> Vec<Source> {
+        vec![
+            Source::bytes("a.svg", svg.to_vec()),
+            Source::bytes("truncated.svg", svg[..40].to_vec()),
+            Source::bytes("c.svg", svg.to_vec()),
+        ]
+    };
+    let opt = load_options();
+    let args = RenderArgs::default();
+    let convert_with = |on_error, drop_blank_pages| {
+        let run = RunOptions {
+            on_error,
+            drop_blank_pages,
+            ..RunOptions::default()
+        };
+        let mut writer = Box::new(crate::writer::PdfWriter::new(
+            args.resolution(),
+            ImageOptions::default(),
+        ));
+        let conversion = convert(&opt, sources().into(), &args, &run, writer.as_mut())?;
+        let mut pdf = Vec::new();
+        writer.finish(&mut pdf)?;
+        let pages = lopdf::Document::load_mem(&pdf)?.get_pages().len();
+        anyhow::Ok((conversion, pages))
+    };
+
+    let Err(err) = convert_with(OnError::Abort, false) else {
+        panic!("truncated.svg fails the run");
+    };
+    assert!(format!("{err:#}").contains("truncated.svg"), "{err:#}");
+
+    let (conversion, pages) = convert_with(OnError::Skip, false).unwrap();
+    let ids: Vec<_> = conversion
+        .pages
+        .iter()
+        .map(|page| page.id.as_str())
+        .collect();
+    assert_eq!((ids, pages), (vec!["a.svg", "c.svg"], 2));
+    let failed: Vec<_> = conversion
+        .failed
+        .iter()
+        .map(|file| file.id.as_str())
+        .collect();
+    assert_eq!(failed, ["truncated.svg"]);
+    assert!(matches!(
+        conversion.failed[0].error.downcast_ref::<Error>(),
+        Some(Error::Parse { .. })
+    ));
+
+    // The stand-in is a typical page, kept when blank pages are dropped
+    let (conversion, pages) = convert_with(OnError::Blank, true).unwrap();
+    let ids: Vec<_> = conversion
+        .pages
+        .iter()
+        .map(|page| page.id.as_str())
+        .collect();
+    assert_eq!((ids, pages), (vec!["a.svg", "truncated.svg", "c.svg"], 3));
+    assert_eq!(conversion.pages[1].blank, Some([255; 3]));
+    assert_eq!(conversion.failed.len(), 1);
+}
+
+#[test]
+fn test_backgrounds() {
+    assert_eq!(parse_background("white"), Ok(Background::default()));
+    assert_eq!(parse_background("Transparent"), Ok(Background::Transparent));
+    assert_eq!(
+        parse_background("#1e1E00"),
+        Ok(Background::Color([0x1e, 0x1e, 0]))
+    );
+    for invalid in ["#fff", "1e1e1e", "#1e1e1g", "#ééé", "black"] {
+        assert!(parse_background(invalid).is_err(), "{invalid}");
+    }
+
+    let svg = |body: &str| {
+        format!(r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50">{body}</svg>"#)
+            .into_bytes()
+    };
+    let convert_on = |background, sources: Vec<Source>| {
+        let args = RenderArgs {
+            background,
+            ..RenderArgs::default()
+        };
+        let mut writer = Box::new(crate::writer::PdfWriter::new(
+            args.resolution(),
+            ImageOptions::default(),
+        ));
+        let conversion = convert(
+            &load_options(),
+            sources.into(),
+            &args,
+            &RunOptions::default(),
+            writer.as_mut(),
+        )
+        .unwrap();
+        let mut pdf = Vec::new();
+        writer.finish(&mut pdf).unwrap();
+        let problems = verify::check(crate::writer::Format::Pdf, &pdf, conversion.pages.len());
+        assert!(problems.is_empty(), "{problems:?}");
+        (conversion, lopdf::Document::load_mem(&pdf).unwrap())
+    };
+
+    // Where the drawing leaves the page bare, the soft mask is clear
+    let (_, doc) = convert_on(
+        Background::Transparent,
+        vec![
+            Source::bytes(
+                "half.svg",
+                svg(r#"<rect width="50" height="50" fill="red" fill-opacity="0.5"/>"#),
+            ),
+            Source::bytes(
+                "full.svg",
+                svg(r#"<rect width="100" height="50"/><rect width="50" height="50" fill="red"/>"#),
+            ),
+        ],
+    );
+    let image = |page: lopdf::ObjectId| {
+        let page = doc.get_dictionary(page).unwrap();
+        let resources = page.get(b"Resources").unwrap().as_reference().unwrap();
+        let resources = doc.get_dictionary(resources).unwrap();
+        let xobjects = resources.get(b"XObject").unwrap().as_dict().unwrap();
+        let id = xobjects.get(b"Im1").unwrap().as_reference().unwrap();
+        doc.get_object(id).unwrap().as_stream().unwrap()
+    };
+    let pages = doc.get_pages();
+    let half = image(pages[&1]);
+    let mask = half.dict.get(b"SMask").unwrap().as_reference().unwrap();
+    let mask = doc.get_object(mask).unwrap().as_stream().unwrap();
+    assert_eq!(
+        mask.dict.get(b"ColorSpace").unwrap().as_name().unwrap(),
+        b"DeviceGray"
+    );
+    let mut alpha = Vec::new();
+    std::io::Read::read_to_end(
+        &mut flate2::read::ZlibDecoder::new(&mask.content[..]),
+        &mut alpha,
+    )
+    .unwrap();
+    let width = mask.dict.get(b"Width").unwrap().as_i64().unwrap() as usize;
+    assert_eq!(alpha.len() % width, 0);
+    assert!(alpha[1].abs_diff(128) <= 1, "{}", alpha[1]);
+    assert_eq!(alpha[width - 2], 0);
+    // The drawing covers the whole of the second page, which has no mask
+    assert!(image(pages[&2]).dict.get(b"SMask").is_err());
+
+    // A color takes white's place
+    let (conversion, _) = convert_on(
+        Background::Color([0, 0, 255]),
+        vec![Source::bytes("empty.svg", svg(""))],
+    );
+    assert_eq!(conversion.pages[0].blank, Some([0, 0, 255]));
+}
+
+#[test]
+fn test_deterministic_output() {
+    let dir =
+        std::env::temp_dir().join(format!("svg2pdf-deterministic-test-{}", std::process::id()));
+    let input = dir.join("in");
+    fs::create_dir_all(&input).unwrap();
+    // Sizes that keep the workers finishing in another order than they start
+    let svg = |size: u32, fill: &str| {
+        format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}"><circle cx="{r}" cy="{r}" r="{r}" fill="{fill}"/></svg>"#,
+            r = size / 2
+        )
+    };
+    for index in 0..12 {
+        let size = 40 + (index * 137) % 400;
+        fs::write(input.join(format!("page-{index}.svg")), svg(size, "teal")).unwrap();
+    }
+    let run = RunOptions {
+        metadata: Metadata {
+            title: Some("Reference".to_string()),
+            undated: true,
+            ..Metadata::default()
+        },
+        ..RunOptions::default()
+    };
+    let convert = |name: &str| {
+        let output = dir.join(name);
+        convert_dir(
+            &load_options(),
+            &Inputs::dir(&input),
+            &output,
+            &RenderArgs::default(),
+            &run,
+        )
+        .unwrap();
+        fs::read(&output).unwrap()
+    };
+    let id = |pdf: &[u8]| {
+        let doc = lopdf::Document::load_mem(pdf).unwrap();
+        let info = doc.trailer.get(b"Info").unwrap().as_reference().unwrap();
+        assert!(doc
+            .get_dictionary(info)
+            .unwrap()
+            .get(b"CreationDate")
+            .is_err());
+        doc.trailer.get(b"ID").unwrap().clone()
+    };
+
+    let first = convert("first.pdf");
+    let second = convert("second.pdf");
+    assert!(first == second, "two runs wrote different bytes");
+    // The ID comes from the contents, so other pages get another one
+    fs::write(input.join("page-3.svg"), svg(100, "orange")).unwrap();
+    assert_ne!(id(&convert("third.pdf")), id(&first));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,197 @@
+use crate::bookmarks::Bookmarks;
+use crate::convert::{
+    self, FailedFile, OnError, PageInfo, RenderArgs, RunOptions, SortOrder, Source, Sources,
+};
+use crate::error::Error;
+use crate::inputs::Inputs;
+use crate::metadata::Metadata;
+use crate::progress::Progress;
+use crate::writer::{ContainerWriter, ImageOptions, PdfWriter};
+use resvg::usvg::{self, fontdb};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// What a Converter makes of the drawings it is given
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    pub render: RenderArgs,
+    pub images: ImageOptions,
+    // Draw pages as paths where possible, with images only for what paths
+    // can't express
+    pub vector: bool,
+    // Order of the files of a directory or pattern, last first with `reverse`
+    pub sort: SortOrder,
+    pub reverse: bool,
+    // Directories bring the SVGs of their subdirectories too
+    pub recursive: bool,
+    // Whether a file failing fails the conversion; the files skipped or
+    // replaced otherwise are listed by PdfDocument::failed
+    pub on_error: OnError,
+    // Title, author, subject and keywords of the documents
+    pub metadata: Metadata,
+    // The outline of the documents, none by default
+    pub bookmarks: Option<Bookmarks>,
+    // Fonts for text, instead of the system fonts loaded on first use
+    pub fonts: Option<Arc<fontdb::Database>>,
+}
+
+// Converts SVG drawings into PDF documents, one page per drawing. Meant to
+// be kept and shared: the fonts are loaded once, for every conversion.
+pub struct Converter {
+    options: Options,
+    usvg: Arc<usvg::Options<'static>>,
+    progress: Option<Box<dyn Progress + Send>>,
+}
+
+// A converted document, held in memory until it is written
+pub struct PdfDocument {
+    writer: PdfWriter,
+    pages: Vec<PageInfo>,
+    failed: Vec<FailedFile>,
+}
+
+impl Converter {
+    pub fn new(options: Options) -> Self {
+        let usvg = match &options.fonts {
+            Some(fonts) => Arc::new(usvg::Options {
+                fontdb: Arc::clone(fonts),
+                ..usvg::Options::default()
+            }),
+            None => convert::load_options(),
+        };
+        Converter {
+            options,
+            usvg,
+            progress: None,
+        }
+    }
+
+    // Report the events of every conversion to `progress`, called from the
+    // render workers
+    pub fn with_progress(mut self, progress: impl Progress + Send + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    // The SVG files of `dir`
+    pub fn convert_dir(&self, dir: &Path) -> Result<PdfDocument, Error> {
+        self.convert_files(&[dir.to_path_buf()])
+    }
+
+    // SVG files, directories of them and glob patterns, in the order given
+    pub fn convert_files(&self, files: &[PathBuf]) -> Result<PdfDocument, Error> {
+        let inputs = Inputs {
+            paths: files.to_vec(),
+            recursive: self.options.recursive,
+        };
+        let sources = inputs.scan(self.options.sort, self.options.reverse)?;
+        self.convert(sources)
+    }
+
+    // Drawings already in memory, named page-1.svg and on in messages
+    pub fn convert_svg_bytes(&self, svgs: &[&[u8]]) -> Result<PdfDocument, Error> {
+        let sources: Vec<_> = svgs
+            .iter()
+            .enumerate()
+            .map(|(index, svg)| Source::bytes(format!("page-{}.svg", index + 1), svg.to_vec()))
+            .collect();
+        self.convert(sources.into())
+    }
+
+    fn convert(&self, sources: Sources) -> Result<PdfDocument, Error> {
+        let render = &self.options.render;
+        let mut writer = PdfWriter::new(render.resolution(), self.options.images.clone())
+            .with_sheet(render.sheet());
+        writer.describe(&self.options.metadata);
+        let run = RunOptions {
+            progress: self
+                .progress
+                .as_deref()
+                .map(|progress| progress as &dyn Progress),
+            images: self.options.images.clone(),
+            dedupe: true,
+            vector: self.options.vector,
+            on_error: self.options.on_error,
+            bookmarks: self.options.bookmarks.as_ref(),
+            ..RunOptions::default()
+        };
+        let conversion = convert::convert(&self.usvg, sources, render, &run, &mut writer)?;
+        if conversion.pages.is_empty() {
+            return Err(Error::Other(anyhow::anyhow!("No pages to write")));
+        }
+        Ok(PdfDocument {
+            writer,
+            pages: conversion.pages,
+            failed: conversion.failed,
+        })
+    }
+}
+
+impl PdfDocument {
+    // What is known about each page, in document order
+    pub fn pages(&self) -> &[PageInfo] {
+        &self.pages
+    }
+
+    // Files that failed and were skipped or replaced by a blank page, with
+    // Options::on_error
+    pub fn failed(&self) -> &[FailedFile] {
+        &self.failed
+    }
+
+    // Write the document to `out`, such as a file or a response body
+    pub fn write_to<W: Write>(self, mut out: W) -> Result<(), Error> {
+        Box::new(self.writer).finish(&mut out)?;
+        Ok(out.flush().map_err(anyhow::Error::from)?)
+    }
+}
+
+#[test]
+fn test_converter_api() {
+    use std::sync::Mutex;
+
+    fn shareable<T: Send + Sync>(_: &T) {}
+
+    // Forwards the finished files, as a service would
+    struct Finished(Arc<Mutex<Vec<PathBuf>>>);
+    impl Progress for Finished {
+        fn event(&self, event: &crate::progress::Event) {
+            if let crate::progress::Event::FileFinished { path, .. } = event {
+                self.0.lock().unwrap().push(path.to_path_buf());
+            }
+        }
+    }
+    let finished = Arc::new(Mutex::new(Vec::new()));
+    let converter = Converter::new(Options::default()).with_progress(Finished(finished.clone()));
+    shareable(&converter);
+
+    let rect = br#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50"><rect width="50" height="50"/></svg>"#;
+    let document = converter.convert_svg_bytes(&[rect, rect]).unwrap();
+    assert_eq!(document.pages().len(), 2);
+    let mut pdf = Vec::new();
+    document.write_to(&mut pdf).unwrap();
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    assert_eq!(doc.get_pages().len(), 2);
+    let mut finished = finished.lock().unwrap().clone();
+    finished.sort();
+    assert_eq!(finished, [Path::new("page-1.svg"), Path::new("page-2.svg")]);
+
+    // Failures are typed and name their file
+    let Err(err) = converter.convert_svg_bytes(&[rect, b"\x89PNG\r\n\x1a\n"]) else {
+        panic!("a PNG is no SVG");
+    };
+    match &err {
+        Error::Parse {
+            path, looks_like, ..
+        } => assert_eq!(
+            (path.as_path(), *looks_like),
+            (Path::new("page-2.svg"), Some("a PNG image"))
+        ),
+        err => panic!("expected a parse error, got {err:?}"),
+    }
+    let Err(err) = converter.convert_files(&["missing.svg".into()]) else {
+        panic!("missing.svg doesn't exist");
+    };
+    assert!(err.to_string().contains("does not exist"), "{err}");
+}
@@ -0,0 +1,186 @@
+use crate::convert::{Expansion, RenderedImage, Source, Sources};
+use crate::retry::RetryPolicy;
+use crate::unsupported::Feature;
+use crate::vector::VectorPage;
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+// Memory the rendered pages kept for duplicates may take
+pub const MEMORY_LIMIT: usize = 256 * 1024 * 1024;
+
+// SHA-256 of a file's raw bytes
+pub type ContentKey = [u8; 32];
+
+pub fn key(data: &[u8]) -> ContentKey {
+    Sha256::digest(data).into()
+}
+
+// A file left out for being byte-identical to an earlier one
+pub struct Duplicate {
+    pub path: PathBuf,
+    pub id: String,
+    // Id of the file it is a copy of
+    pub original: String,
+}
+
+// The sources without files byte-identical to an earlier one, and those
+// left out. Every file is read to hash it; ones that can't be read are
+// kept, so rendering reports them.
+pub fn skip_duplicates(
+    sources: Sources,
+    retry: &RetryPolicy,
+) -> (Sources<'static>, Vec<Duplicate>) {
+    let mut seen: HashMap<ContentKey, String> = HashMap::new();
+    let mut kept: Vec<Source> = Vec::with_capacity(sources.total);
+    let mut skipped = Vec::new();
+    for source in sources {
+        let content = match retry.run(|| source.read()).0 {
+            Ok(data) => key(&data),
+            Err(_) => {
+                kept.push(source);
+                continue;
+            }
+        };
+        match seen.entry(content) {
+            Entry::Occupied(original) => skipped.push(Duplicate {
+                path: source.path,
+                id: source.id,
+                original: original.get().clone(),
+            }),
+            Entry::Vacant(slot) => {
+                slot.insert(source.id.clone());
+                kept.push(source);
+            }
+        }
+    }
+    (kept.into(), skipped)
+}
+
+// A page rendered earlier in the run. Render options are the same for the
+// whole run, so byte-identical files always render to the same pixels.
+#[derive(Clone)]
+pub struct Rendered {
+    pub image: Arc<RenderedImage>,
+    pub warnings: Vec<String>,
+    pub expanded: Option<Expansion>,
+    pub unsupported: Vec<Feature>,
+    // PNG exported for it, copied for every duplicate
+    pub image_path: Option<PathBuf>,
+    pub vector: Option<Arc<VectorPage>>,
+}
+
+#[derive(Default)]
+struct State {
+    pages: HashMap<ContentKey, Rendered>,
+    // Oldest first, to forget pages once over the limit
+    order: VecDeque<ContentKey>,
+    bytes: usize,
+}
+
+// Pages of the files rendered so far in one run, by content, so that
+// duplicates are neither parsed nor rendered again. Keeps up to `limit`
+// bytes of pages and forgets the oldest beyond that.
+pub struct Dedupe {
+    limit: usize,
+    state: Mutex<State>,
+    hits: AtomicUsize,
+}
+
+impl Dedupe {
+    pub fn new(limit: usize) -> Self {
+        Dedupe {
+            limit,
+            state: Mutex::default(),
+            hits: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn get(&self, key: &ContentKey) -> Option<Rendered> {
+        let page = self.state.lock().unwrap().pages.get(key).cloned();
+        if page.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        page
+    }
+
+    pub fn insert(&self, key: ContentKey, page: Rendered) {
+        let bytes = page.image.bytes();
+        if bytes > self.limit {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        // A duplicate rendered by two workers at once
+        if state.pages.contains_key(&key) {
+            return;
+        }
+        while state.bytes + bytes > self.limit {
+            let Some(oldest) = state.order.pop_front() else {
+                break;
+            };
+            if let Some(old) = state.pages.remove(&oldest) {
+                state.bytes -= old.image.bytes();
+            }
+        }
+        state.pages.insert(key, page);
+        state.order.push_back(key);
+        state.bytes += bytes;
+    }
+
+    // Pages reused so far
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+#[test]
+fn test_dedupe_forgets_oldest_pages() {
+    let page = |value| Rendered {
+        image: Arc::new(RenderedImage {
+            width: 10,
+            height: 1,
+            rgb_data: vec![value; 30],
+            alpha: None,
+        }),
+        warnings: Vec::new(),
+        expanded: None,
+        unsupported: Vec::new(),
+        image_path: None,
+        vector: None,
+    };
+    let dedupe = Dedupe::new(70);
+    let (a, b, c) = (key(b"a"), key(b"b"), key(b"c"));
+    dedupe.insert(a, page(1));
+    dedupe.insert(b, page(2));
+    assert_eq!(dedupe.get(&a).unwrap().image.rgb_data[0], 1);
+
+    // A third page only fits without the first
+    dedupe.insert(c, page(3));
+    assert!(dedupe.get(&a).is_none());
+    assert_eq!(dedupe.get(&b).unwrap().image.rgb_data[0], 2);
+    assert_eq!(dedupe.get(&c).unwrap().image.rgb_data[0], 3);
+    assert_eq!(dedupe.hits(), 3);
+}
+
+#[test]
+fn test_skip_duplicate_inputs() {
+    let sources = vec![
+        Source::bytes("a.svg", b"<svg/>".to_vec()),
+        Source::bytes("b.svg", b"<svg></svg>".to_vec()),
+        Source::bytes("figures/a.svg", b"<svg/>".to_vec()),
+        Source::file("".as_ref(), "missing.svg".into()),
+        Source::bytes("c.svg", b"<svg/>".to_vec()),
+    ];
+    let (kept, skipped) = skip_duplicates(sources.into(), &RetryPolicy::default());
+    // The first copy stays, and unreadable files are left to rendering
+    let ids: Vec<_> = kept.map(|source| source.id).collect();
+    assert_eq!(ids, ["a.svg", "b.svg", "missing.svg"]);
+    let skipped: Vec<_> = skipped
+        .iter()
+        .map(|duplicate| (duplicate.id.as_str(), duplicate.original.as_str()))
+        .collect();
+    assert_eq!(skipped, [("figures/a.svg", "a.svg"), ("c.svg", "a.svg")]);
+}
@@ -0,0 +1,338 @@
+use crate::budget;
+use crate::convert::{self, RenderArgs, RunOptions, Source};
+use crate::dedupe;
+use crate::fonts;
+use crate::pixels;
+use crate::writer::{ImageOptions, PdfWriter};
+use anyhow::Result;
+use clap::Args;
+use resvg::tiny_skia::Pixmap;
+use resvg::usvg::{fontdb, Options, Tree};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Test page: shapes plus text, so missing fonts show up as missing ink
+const SAMPLE_SVG: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" width="400" height="120">
+    <rect x="10" y="10" width="100" height="100" fill="#3366cc"/>
+    <text x="130" y="75" font-family="sans-serif" font-size="40">svg2pdf</text>
+</svg>"##;
+
+// Pages and page size the memory estimate is made for
+const SAMPLE_PAGES: u64 = 100;
+const SAMPLE_PAGE_PIXELS: u64 = 960 * 720;
+
+#[derive(Args)]
+pub struct DoctorArgs {
+    /// Directory the output would be written to
+    #[arg(long, default_value = ".")]
+    output_dir: PathBuf,
+
+    /// Print the results as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Serialize)]
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Check {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+// Run every check, print them and return whether none failed
+pub fn run(args: &DoctorArgs) -> Result<bool> {
+    let opt = convert::load_options();
+    let checks = vec![
+        check_fonts(convert::font_database()),
+        check_families(convert::font_database()),
+        check_locale(),
+        check_writable("output directory", &args.output_dir),
+        check_writable("temp directory", &std::env::temp_dir()),
+        check_cpus(),
+        check_memory(fs::read_to_string("/proc/meminfo").ok().as_deref()),
+        check_render(&opt),
+        check_pipeline(&opt),
+    ];
+
+    let passed = checks.iter().all(|check| check.status != Status::Fail);
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "passed": passed,
+                "checks": checks,
+            }))?
+        );
+    } else {
+        for check in &checks {
+            let mark = match check.status {
+                Status::Pass => "[ok]  ",
+                Status::Warn => "[warn]",
+                Status::Fail => "[FAIL]",
+            };
+            println!("{} {}: {}", mark, check.name, check.detail);
+        }
+        println!(
+            "{}",
+            if passed {
+                "All checks passed."
+            } else {
+                "Some checks failed."
+            }
+        );
+    }
+    Ok(passed)
+}
+
+fn check_fonts(fontdb: &fontdb::Database) -> Check {
+    let sources = fonts::config().sources();
+    match fontdb.len() {
+        0 => Check::new(
+            "fonts",
+            Status::Fail,
+            format!("no fonts found in {sources}, text will not render"),
+        ),
+        count => Check::new(
+            "fonts",
+            Status::Pass,
+            format!("{count} font faces from {sources}"),
+        ),
+    }
+}
+
+fn check_families(fontdb: &fontdb::Database) -> Check {
+    // The default is usvg's when an SVG names no font, unless --font-family
+    // says otherwise
+    let default = fonts::config().default_family();
+    let families = [
+        ("sans-serif", fontdb::Family::SansSerif),
+        ("serif", fontdb::Family::Serif),
+        ("monospace", fontdb::Family::Monospace),
+        (default.as_str(), fontdb::Family::Name(&default)),
+    ];
+    let mut missing = Vec::new();
+    let mut found = Vec::new();
+    for (name, family) in families {
+        let query = fontdb::Query {
+            families: &[family],
+            ..fontdb::Query::default()
+        };
+        match fontdb.query(&query).and_then(|id| fontdb.face(id)) {
+            Some(face) => found.push(format!("{name} -> {}", face.post_script_name)),
+            None => missing.push(name),
+        }
+    }
+    if missing.is_empty() {
+        return Check::new("font families", Status::Pass, found.join(", "));
+    }
+
+    // Name a few installed families to point at a working alternative
+    let mut installed: Vec<&str> = fontdb
+        .faces()
+        .filter_map(|face| face.families.first().map(|(name, _)| name.as_str()))
+        .collect();
+    installed.sort_unstable();
+    installed.dedup();
+    let mut detail = format!("not resolved: {}", missing.join(", "));
+    if !installed.is_empty() {
+        let shown = installed.len().min(5);
+        detail.push_str(&format!("; installed: {}", installed[..shown].join(", ")));
+        if installed.len() > shown {
+            detail.push_str(&format!(" and {} more", installed.len() - shown));
+        }
+    }
+    Check::new("font families", Status::Warn, detail)
+}
+
+fn check_locale() -> Check {
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()));
+    match locale {
+        Some(locale)
+            if locale
+                .to_ascii_lowercase()
+                .replace('-', "")
+                .contains("utf8") =>
+        {
+            Check::new("locale", Status::Pass, locale)
+        }
+        Some(locale) => Check::new(
+            "locale",
+            Status::Warn,
+            format!("{locale} is not UTF-8, non-ASCII file names may be shown garbled"),
+        ),
+        None => Check::new("locale", Status::Warn, "no locale set"),
+    }
+}
+
+fn check_writable(name: &'static str, dir: &Path) -> Check {
+    let probe = dir.join(format!(".svg2pdf-doctor-{}", std::process::id()));
+    match fs::write(&probe, b"probe") {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe);
+            Check::new(name, Status::Pass, format!("{:?} is writable", dir))
+        }
+        Err(err) => Check::new(
+            name,
+            Status::Fail,
+            format!("cannot write to {:?}: {}", dir, err),
+        ),
+    }
+}
+
+fn check_cpus() -> Check {
+    let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+    Check::new(
+        "cpu",
+        Status::Pass,
+        format!(
+            "{cores} cores available, {} rayon threads, {} pixel conversion",
+            rayon::current_num_threads(),
+            pixels::Path::detect().name()
+        ),
+    )
+}
+
+// Peak memory of a sample run at the default settings
+fn sample_run_estimate(threads: u64) -> u64 {
+    budget::Estimate {
+        workers: threads,
+        page_pixels: SAMPLE_PAGE_PIXELS,
+        pages: SAMPLE_PAGES,
+        in_flight_limit: SAMPLE_PAGES * SAMPLE_PAGE_PIXELS * 3,
+        document: false,
+        dedupe_limit: dedupe::MEMORY_LIMIT as u64,
+    }
+    .total()
+}
+
+fn check_memory(meminfo: Option<&str>) -> Check {
+    let estimate = sample_run_estimate(rayon::current_num_threads() as u64);
+    let mib = |bytes: u64| bytes / (1024 * 1024);
+    match meminfo.and_then(budget::available_memory) {
+        Some(available) if available >= estimate => Check::new(
+            "memory",
+            Status::Pass,
+            format!(
+                "{} MiB available, a {SAMPLE_PAGES}-page run needs about {} MiB",
+                mib(available),
+                mib(estimate)
+            ),
+        ),
+        Some(available) => Check::new(
+            "memory",
+            Status::Warn,
+            format!(
+                "only {} MiB available, a {SAMPLE_PAGES}-page run needs about {} MiB",
+                mib(available),
+                mib(estimate)
+            ),
+        ),
+        None => Check::new(
+            "memory",
+            Status::Warn,
+            format!(
+                "available memory unknown, a {SAMPLE_PAGES}-page run needs about {} MiB",
+                mib(estimate)
+            ),
+        ),
+    }
+}
+
+// Render the sample page and make sure its text left ink behind
+fn check_render(opt: &Options<'static>) -> Check {
+    let tree = match Tree::from_data(SAMPLE_SVG.as_bytes(), opt) {
+        Ok(tree) => tree,
+        Err(err) => return Check::new("test render", Status::Fail, format!("parse failed: {err}")),
+    };
+    let size = tree.size().to_int_size();
+    let Some(mut pixmap) = Pixmap::new(size.width(), size.height()) else {
+        return Check::new("test render", Status::Fail, "cannot allocate a pixmap");
+    };
+    resvg::render(&tree, Default::default(), &mut pixmap.as_mut());
+
+    // Everything right of the square belongs to the text
+    let text_ink = pixmap
+        .pixels()
+        .chunks(size.width() as usize)
+        .flat_map(|row| &row[120..])
+        .filter(|pixel| pixel.alpha() > 0)
+        .count();
+    if text_ink == 0 {
+        Check::new(
+            "test render",
+            Status::Fail,
+            "text rendered blank, no usable font",
+        )
+    } else {
+        Check::new("test render", Status::Pass, "shapes and text rendered")
+    }
+}
+
+// Push the sample through the real pipeline into an in-memory PDF
+fn check_pipeline(opt: &std::sync::Arc<Options<'static>>) -> Check {
+    let sources = vec![Source::bytes("doctor.svg", SAMPLE_SVG.as_bytes().to_vec())];
+    let args = RenderArgs::default();
+    let settings = args.quality.settings();
+    let mut writer = Box::new(PdfWriter::new(
+        args.resolution(),
+        ImageOptions {
+            jpeg_quality: settings.jpeg_quality,
+            ..ImageOptions::default()
+        },
+    ));
+    let result = convert::convert(
+        opt,
+        sources.into(),
+        &args,
+        &RunOptions::default(),
+        writer.as_mut(),
+    )
+    .and_then(|_| {
+        let mut pdf = Vec::new();
+        crate::writer::ContainerWriter::finish(writer, &mut pdf)?;
+        Ok(pdf.len())
+    });
+    match result {
+        Ok(bytes) => Check::new(
+            "test conversion",
+            Status::Pass,
+            format!("sample PDF of {bytes} bytes"),
+        ),
+        Err(err) => Check::new("test conversion", Status::Fail, format!("{err:#}")),
+    }
+}
+
+#[test]
+fn test_memory_check() {
+    let meminfo = "MemTotal:       16000000 kB\nMemAvailable:    8000000 kB\n";
+    assert_eq!(budget::available_memory(meminfo), Some(8_000_000 * 1024));
+    assert_eq!(budget::available_memory("MemTotal: 1 kB"), None);
+
+    assert_eq!(check_memory(Some(meminfo)).status, Status::Pass);
+    assert_eq!(
+        check_memory(Some("MemAvailable: 1024 kB")).status,
+        Status::Warn
+    );
+    assert_eq!(check_memory(None).status, Status::Warn);
+}
@@ -0,0 +1,99 @@
+use resvg::usvg;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+// What stopped a conversion, with the file it happened to. The pipeline
+// passes these on inside anyhow errors, with more context around them;
+// Error::from gets them back out.
+#[derive(Debug)]
+pub enum Error {
+    // An input file couldn't be read
+    Read {
+        path: PathBuf,
+        source: io::Error,
+    },
+    // An input isn't SVG the parser understands; `looks_like` names what
+    // the file seems to be instead, if anything
+    Parse {
+        path: PathBuf,
+        looks_like: Option<&'static str>,
+        source: usvg::Error,
+    },
+    // A drawing parsed but couldn't be rendered, usually for its size
+    Render {
+        path: PathBuf,
+        reason: String,
+    },
+    // A drawing uses features that can't be rendered, in a run that fails
+    // on those
+    Unsupported {
+        path: PathBuf,
+        features: Vec<String>,
+    },
+    // The document couldn't be written
+    Write {
+        path: PathBuf,
+        source: io::Error,
+    },
+    // Anything else, such as inputs naming no SVG or invalid options
+    Other(anyhow::Error),
+}
+
+impl Error {
+    // The file the error is about, if it is about one
+    pub fn path(&self) -> Option<&std::path::Path> {
+        match self {
+            Error::Read { path, .. }
+            | Error::Parse { path, .. }
+            | Error::Render { path, .. }
+            | Error::Unsupported { path, .. }
+            | Error::Write { path, .. } => Some(path),
+            Error::Other(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Read { path, .. } => write!(f, "Failed to read SVG file: {:?}", path),
+            Error::Parse {
+                path,
+                looks_like: Some(kind),
+                ..
+            } => write!(
+                f,
+                "Failed to parse SVG file: {:?} looks like {}",
+                path, kind
+            ),
+            Error::Parse { path, .. } => write!(f, "Failed to parse SVG file: {:?}", path),
+            Error::Render { path, reason } => write!(f, "Failed to render {:?}: {}", path, reason),
+            Error::Unsupported { path, features } => write!(
+                f,
+                "{:?} uses SVG features that can't be rendered: {}",
+                path,
+                features.join(", ")
+            ),
+            Error::Write { path, .. } => write!(f, "Failed to write output file: {:?}", path),
+            Error::Other(err) => write!(f, "{:#}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Read { source, .. } | Error::Write { source, .. } => Some(source),
+            Error::Parse { source, .. } => Some(source),
+            Error::Render { .. } | Error::Unsupported { .. } | Error::Other(_) => None,
+        }
+    }
+}
+
+// The Error inside `err`, under whatever context was added to it
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        err.downcast().unwrap_or_else(Error::Other)
+    }
+}
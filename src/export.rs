@@ -0,0 +1,274 @@
+use crate::convert::{RenderedImage, Source};
+use crate::names;
+use crate::paths;
+use anyhow::{bail, Context, Result};
+use resvg::tiny_skia::{FilterQuality, Pixmap, PixmapPaint, Transform};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// One piece of a parsed --image-template
+#[derive(Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    // Page number starting at 1, zero-padded to the given width
+    Index(usize),
+    Stem,
+    Name,
+}
+
+// Where the PNG of every rendered page is written
+pub struct ImageExport {
+    dir: PathBuf,
+    template: Vec<Part>,
+}
+
+impl ImageExport {
+    // Placeholders: {index} (page number, {index:04} zero-pads it),
+    // {stem} (file name without extension) and {name} (full file name)
+    pub fn new(dir: PathBuf, template: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..].find('}').with_context(|| {
+                format!("Unclosed placeholder in image template {:?}", template)
+            })? + start;
+            parts.push(match &rest[start + 1..end] {
+                "index" => Part::Index(0),
+                "stem" => Part::Stem,
+                "name" => Part::Name,
+                placeholder => match placeholder.strip_prefix("index:") {
+                    Some(width) if width.starts_with('0') => {
+                        Part::Index(width.parse().with_context(|| {
+                            format!("Invalid width in placeholder {{{}}}", placeholder)
+                        })?)
+                    }
+                    _ => bail!(
+                        "Unknown placeholder {{{}}} in image template (expected {{index}}, {{index:04}}, {{stem}} or {{name}})",
+                        placeholder
+                    ),
+                },
+            });
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        Ok(ImageExport {
+            dir,
+            template: parts,
+        })
+    }
+
+    // File for page `index` whose flat name (see names::flat_names) is `stem`
+    pub fn path(&self, index: usize, stem: &str, source: &Path) -> PathBuf {
+        let mut file_name = String::new();
+        for part in &self.template {
+            match part {
+                Part::Literal(text) => file_name.push_str(text),
+                Part::Index(width) => file_name.push_str(&format!("{:0width$}", index + 1)),
+                Part::Stem => file_name.push_str(stem),
+                Part::Name => {
+                    file_name.push_str(stem);
+                    if let Some(extension) = source.extension() {
+                        file_name.push('.');
+                        file_name.push_str(&names::path_text(extension));
+                    }
+                }
+            }
+        }
+        self.dir.join(file_name)
+    }
+
+    // Plan the file of every page. Files sharing a name get their directory
+    // appended, and names Windows reserves for devices a `_`, on every
+    // platform so the names don't depend on where the run happened. If
+    // pages would still overwrite each other, fail before anything is
+    // rendered.
+    pub fn prepare(&self, sources: &[Source]) -> Result<Vec<PathBuf>> {
+        let ids: Vec<&str> = sources.iter().map(|source| source.id.as_str()).collect();
+        let (stems, collisions) = names::flat_names(&ids);
+        if self
+            .template
+            .iter()
+            .any(|part| matches!(part, Part::Stem | Part::Name))
+        {
+            for group in &collisions {
+                eprintln!(
+                    "Warning: {} share a file name, their images are told apart by directory",
+                    group.join(", ")
+                );
+            }
+        }
+
+        let mut seen = HashMap::new();
+        let mut paths = Vec::with_capacity(sources.len());
+        for (index, (source, stem)) in sources.iter().zip(&stems).enumerate() {
+            let mut path = self.path(index, stem, &source.path);
+            let portable = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(paths::portable_name);
+            if let Some(portable) = portable {
+                eprintln!(
+                    "Warning: {} would be written to {:?}, a reserved name on Windows; using {:?}",
+                    source.id,
+                    path.file_name().unwrap_or_default(),
+                    portable
+                );
+                path.set_file_name(portable);
+            }
+            // Case-insensitive file systems would merge names differing in case
+            let folded = path.to_string_lossy().to_lowercase();
+            if let Some(other) = seen.insert(folded, index) {
+                bail!(
+                    "Pages {} and {} would both be written to {:?}; use {{index}} or {{stem}} in --image-template to tell them apart",
+                    other + 1,
+                    index + 1,
+                    path
+                );
+            }
+            paths.push(path);
+        }
+        fs::create_dir_all(paths::long_path(&self.dir))
+            .with_context(|| format!("Failed to create image directory: {:?}", self.dir))?;
+        Ok(paths)
+    }
+
+    // Write the page as a PNG, keeping its transparency
+    pub fn write(&self, path: &Path, pixmap: &Pixmap) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(paths::long_path(parent))?;
+        }
+        pixmap
+            .save_png(paths::long_path(path))
+            .with_context(|| format!("Failed to write page image: {:?}", path))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+// Longest side of thumbnails, in pixels
+pub const THUMBNAIL_SIZE: u32 = 240;
+
+// Pixmap holding a rendered page, transparent where the page is
+pub fn to_pixmap(image: &RenderedImage) -> Option<Pixmap> {
+    let mut pixmap = Pixmap::new(image.width, image.height)?;
+    for (rgba, rgb) in pixmap
+        .data_mut()
+        .chunks_exact_mut(4)
+        .zip(image.rgb_data.chunks_exact(3))
+    {
+        rgba[..3].copy_from_slice(rgb);
+        rgba[3] = 255;
+    }
+    if let Some(alpha) = &image.alpha {
+        // Pixmaps are premultiplied
+        for (rgba, &alpha) in pixmap.data_mut().chunks_exact_mut(4).zip(alpha) {
+            for channel in &mut rgba[..3] {
+                *channel = ((*channel as u16 * alpha as u16 + 127) / 255) as u8;
+            }
+            rgba[3] = alpha;
+        }
+    }
+    Some(pixmap)
+}
+
+// Downscaled copy of a page whose longest side is at most `max_size`
+pub fn thumbnail(image: &RenderedImage, max_size: u32) -> Option<Pixmap> {
+    let page = to_pixmap(image)?;
+
+    let scale = (max_size as f32 / image.width.max(image.height) as f32).min(1.0);
+    let width = ((image.width as f32 * scale).round() as u32).max(1);
+    let height = ((image.height as f32 * scale).round() as u32).max(1);
+    let mut thumbnail = Pixmap::new(width, height)?;
+    let paint = PixmapPaint {
+        quality: FilterQuality::Bicubic,
+        ..PixmapPaint::default()
+    };
+    thumbnail.draw_pixmap(
+        0,
+        0,
+        page.as_ref(),
+        &paint,
+        Transform::from_scale(scale, scale),
+        None,
+    );
+    Some(thumbnail)
+}
+
+#[test]
+fn test_image_template() {
+    let export = ImageExport::new("out".into(), "{index:04}-{stem}.png").unwrap();
+    assert_eq!(
+        export.path(6, "intro", Path::new("slides/intro.svg")),
+        Path::new("out/0007-intro.png")
+    );
+    let export = ImageExport::new("out".into(), "{name}.png").unwrap();
+    assert_eq!(
+        export.path(0, "a", Path::new("a.svg")),
+        Path::new("out/a.svg.png")
+    );
+
+    assert!(ImageExport::new("out".into(), "{page}.png").is_err());
+    assert!(ImageExport::new("out".into(), "{index.png").is_err());
+
+    // Same-named files from different directories must not overwrite
+    // each other
+    let dir = std::env::temp_dir().join(format!("svg2pdf-export-test-{}", std::process::id()));
+    let root = Path::new("in");
+    let sources = [
+        Source::file(root, "in/chapter1/figure1.svg".into()),
+        Source::file(root, "in/chapter2/figure1.svg".into()),
+    ];
+    let export = ImageExport::new(dir.clone(), "{stem}.png").unwrap();
+    let paths = export.prepare(&sources).unwrap();
+    assert_eq!(paths[0], dir.join("figure1 (chapter1).png"));
+    assert_eq!(paths[1], dir.join("figure1 (chapter2).png"));
+
+    // Device names are avoided, the template's own text included
+    let sources = [
+        Source::file(root, "in/con.svg".into()),
+        Source::file(root, "in/console.svg".into()),
+    ];
+    let paths = export.prepare(&sources).unwrap();
+    assert_eq!(paths[0], dir.join("con_.png"));
+    assert_eq!(paths[1], dir.join("console.png"));
+    let export = ImageExport::new(dir.clone(), "aux.{index}.png").unwrap();
+    assert_eq!(
+        export.prepare(&sources[..1]).unwrap()[0],
+        dir.join("aux_.1.png")
+    );
+    let sources = [
+        Source::file(root, "in/chapter1/figure1.svg".into()),
+        Source::file(root, "in/chapter2/figure1.svg".into()),
+    ];
+
+    // A template without any per-page part can't be disambiguated
+    let export = ImageExport::new(dir.clone(), "page.png").unwrap();
+    let err = export.prepare(&sources).unwrap_err().to_string();
+    assert!(err.contains("Pages 1 and 2"), "{err}");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_thumbnail_keeps_aspect_ratio() {
+    let image = RenderedImage {
+        width: 960,
+        height: 720,
+        rgb_data: vec![40; 960 * 720 * 3],
+        alpha: None,
+    };
+    let preview = thumbnail(&image, 512).unwrap();
+    assert_eq!((preview.width(), preview.height()), (512, 384));
+    assert_eq!(&preview.data()[..4], &[40, 40, 40, 255]);
+
+    // Small pages are never scaled up
+    let preview = thumbnail(&image, 2048).unwrap();
+    assert_eq!((preview.width(), preview.height()), (960, 720));
+}
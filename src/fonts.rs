@@ -0,0 +1,150 @@
+use anyhow::Result;
+use clap::Args;
+use resvg::usvg::{self, fontdb};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+// Where the fonts for text come from
+#[derive(Args, Clone, Debug, Default)]
+pub struct FontArgs {
+    /// Also load the fonts in DIR and its subdirectories; repeatable
+    #[arg(long = "font-dir", value_name = "DIR", global = true)]
+    pub font_dirs: Vec<PathBuf>,
+
+    /// Load only the fonts of --font-dir, not the system's, so output doesn't depend on the machine
+    #[arg(long, global = true)]
+    pub skip_system_fonts: bool,
+
+    /// Font family of text that names none [default: Times New Roman]
+    #[arg(long, value_name = "FAMILY", global = true)]
+    pub font_family: Option<String>,
+
+    /// Font family that the generic serif family stands for
+    #[arg(long, value_name = "FAMILY", global = true)]
+    pub serif_family: Option<String>,
+
+    /// Font family that the generic sans-serif family stands for
+    #[arg(long, value_name = "FAMILY", global = true)]
+    pub sans_serif_family: Option<String>,
+
+    /// Font family that the generic monospace family stands for
+    #[arg(long, value_name = "FAMILY", global = true)]
+    pub monospace_family: Option<String>,
+}
+
+// The fonts of this process, set once by the command line
+static CONFIG: OnceLock<FontArgs> = OnceLock::new();
+
+// Use `fonts` for every conversion of this process. Only takes effect
+// before the fonts are first loaded.
+pub fn configure(fonts: FontArgs) -> Result<()> {
+    if let Some(dir) = fonts.font_dirs.iter().find(|dir| !dir.is_dir()) {
+        anyhow::bail!("Font directory {:?} does not exist", dir);
+    }
+    if fonts.skip_system_fonts && fonts.font_dirs.is_empty() {
+        anyhow::bail!("--skip-system-fonts leaves no fonts without --font-dir");
+    }
+    let _ = CONFIG.set(fonts);
+    Ok(())
+}
+
+pub fn config() -> &'static FontArgs {
+    CONFIG.get_or_init(FontArgs::default)
+}
+
+impl FontArgs {
+    // Whether anything differs from the system fonts with usvg's defaults
+    pub fn is_custom(&self) -> bool {
+        !self.font_dirs.is_empty()
+            || self.skip_system_fonts
+            || self.font_family.is_some()
+            || self.serif_family.is_some()
+            || self.sans_serif_family.is_some()
+            || self.monospace_family.is_some()
+    }
+
+    // The font database these options stand for. Can take over a second
+    // with the system fonts.
+    pub fn load(&self) -> fontdb::Database {
+        let mut fontdb = fontdb::Database::new();
+        if !self.skip_system_fonts {
+            fontdb.load_system_fonts();
+        }
+        for dir in &self.font_dirs {
+            let before = fontdb.len();
+            fontdb.load_fonts_dir(dir);
+            if fontdb.len() == before {
+                eprintln!("Warning: no fonts found in {:?}", dir);
+            }
+        }
+        if let Some(family) = &self.serif_family {
+            fontdb.set_serif_family(family);
+        }
+        if let Some(family) = &self.sans_serif_family {
+            fontdb.set_sans_serif_family(family);
+        }
+        if let Some(family) = &self.monospace_family {
+            fontdb.set_monospace_family(family);
+        }
+        fontdb
+    }
+
+    // The family of text that names none
+    pub fn default_family(&self) -> String {
+        self.font_family
+            .clone()
+            .unwrap_or_else(|| usvg::Options::default().font_family)
+    }
+
+    // Where the fonts came from, for messages such as "12 font faces from ..."
+    pub fn sources(&self) -> String {
+        let dirs = match self.font_dirs.len() {
+            0 => None,
+            1 => Some(format!("{:?}", self.font_dirs[0])),
+            count => Some(format!("{} directories", count)),
+        };
+        match (dirs, self.skip_system_fonts) {
+            (Some(dirs), true) => dirs,
+            (Some(dirs), false) => format!("{} and the system", dirs),
+            (None, _) => "the system".to_string(),
+        }
+    }
+}
+
+#[test]
+fn test_font_options() {
+    let dir = std::env::temp_dir().join(format!("svg2pdf-fonts-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("not-a-font.ttf"), b"nothing").unwrap();
+    let fonts = FontArgs {
+        font_dirs: vec![dir.clone()],
+        skip_system_fonts: true,
+        font_family: Some("Corporate Sans".to_string()),
+        serif_family: Some("Corporate Serif".to_string()),
+        ..FontArgs::default()
+    };
+    assert!(fonts.is_custom() && !FontArgs::default().is_custom());
+    // Only the directory is consulted, and it has no fonts
+    let fontdb = fonts.load();
+    assert_eq!(fontdb.len(), 0);
+    assert_eq!(
+        fontdb.family_name(&fontdb::Family::Serif),
+        "Corporate Serif"
+    );
+    assert_eq!(fonts.default_family(), "Corporate Sans");
+    assert_eq!(FontArgs::default().default_family(), "Times New Roman");
+    assert_eq!(fonts.sources(), format!("{:?}", dir));
+
+    // Entered before anything loads, so mistakes fail the run right away
+    let missing = FontArgs {
+        font_dirs: vec![dir.join("missing")],
+        ..FontArgs::default()
+    };
+    assert!(configure(missing).is_err());
+    let nothing = FontArgs {
+        skip_system_fonts: true,
+        ..FontArgs::default()
+    };
+    assert!(configure(nothing).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
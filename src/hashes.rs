@@ -0,0 +1,73 @@
+use crate::convert::Conversion;
+use crate::paths;
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+// Format the manifest: one line per page with the pixel hash, the hash of
+// the options that influenced rendering and the page id (the source path
+// relative to the directory or pattern it was found by)
+pub fn manifest(conversion: &Conversion) -> String {
+    let mut manifest = String::new();
+    for page in &conversion.pages {
+        let _ = writeln!(
+            manifest,
+            "{}  {}  {}",
+            page.pixel_hash.as_deref().unwrap_or("-"),
+            conversion.options_hash,
+            page.id
+        );
+    }
+    manifest
+}
+
+pub fn write_manifest(path: &Path, conversion: &Conversion) -> Result<()> {
+    fs::write(paths::long_path(path), manifest(conversion))
+        .with_context(|| format!("Failed to write hash manifest: {:?}", path))
+}
+
+#[test]
+fn test_manifest_lines() {
+    use crate::convert::PageInfo;
+    use crate::timings::FileTimings;
+    use std::path::PathBuf;
+
+    let page = |path: &str, pixel_hash: &str| PageInfo {
+        path: PathBuf::from(path),
+        id: path.trim_start_matches("in/").to_string(),
+        width: 1,
+        height: 1,
+        timings: FileTimings::new(PathBuf::from(path)),
+        pixel_hash: Some(pixel_hash.to_string()),
+        image_path: None,
+        thumbnail: None,
+        warnings: Vec::new(),
+        cached: false,
+        deduped: false,
+        blank: None,
+        encoding: None,
+        encoding_reason: None,
+        expanded: None,
+        unsupported: Vec::new(),
+        copies: 1,
+        placement: None,
+        annotations: 0,
+    };
+    let conversion = Conversion {
+        pages: vec![page("in/b.svg", "bb"), page("in/a.svg", "aa")],
+        cache_hits: 0,
+        options_hash: "0f".to_string(),
+        preview: None,
+        peak_in_flight: 0,
+        budget_waits: 0,
+        dedupe_hits: 0,
+        dropped: Vec::new(),
+        started_early: 0,
+        skipped: Vec::new(),
+        empty: false,
+        stray_annotations: Vec::new(),
+        failed: Vec::new(),
+    };
+    assert_eq!(manifest(&conversion), "bb  0f  b.svg\naa  0f  a.svg\n");
+}
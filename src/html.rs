@@ -0,0 +1,220 @@
+use crate::convert::Conversion;
+use crate::names;
+use crate::paths;
+use anyhow::{Context, Result};
+use base64::Engine;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; background: #f4f4f4; color: #222; }
+h1 { font-size: 1.4em; }
+.grid { display: grid; grid-template-columns: repeat(auto-fill, minmax(260px, 1fr)); gap: 1em; }
+.page { background: #fff; border: 1px solid #ddd; border-radius: 4px; padding: 10px; }
+.page img { display: block; max-width: 100%; margin: 0 auto 8px; border: 1px solid #eee; }
+.page a { color: inherit; text-decoration: none; }
+.name { font-size: 0.9em; word-break: break-all; }
+.number { color: #888; font-size: 0.8em; }
+.warning { color: #a15c00; font-size: 0.8em; margin: 4px 0 0; }
+h2 { font-size: 1.1em; margin-top: 2em; }
+.skipped { font-size: 0.9em; word-break: break-all; }
+";
+
+// Escape text for use in element content and quoted attribute values
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Percent-encode a relative path for use in a URL, keeping the separators.
+// Works on the bytes of the path, so names that aren't UTF-8 still link.
+fn escape_url(path: &Path) -> String {
+    let path = path.as_os_str().as_encoded_bytes();
+    let mut escaped = String::with_capacity(path.len());
+    for &byte in path {
+        match byte {
+            b'\\' => escaped.push('/'),
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                escaped.push(byte as char)
+            }
+            byte => {
+                let _ = write!(escaped, "%{byte:02X}");
+            }
+        }
+    }
+    escaped
+}
+
+// Build a self-contained gallery of the pages. `document` is the written
+// output, linked per page, relative to `dir`, the directory the index goes
+// to. Exported PNGs below `dir` are referenced, other pages use their
+// embedded thumbnail. Pages put in more than once are shown once, with the
+// numbers of all their copies.
+pub fn index(conversion: &Conversion, dir: &Path, document: Option<&Path>) -> String {
+    let title = match document.and_then(|document| document.file_name()) {
+        Some(name) => escape_html(&names::path_text(name)),
+        None => "Pages".to_string(),
+    };
+    let mut html = String::new();
+    let _ = writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n<h1>{title} &middot; {} pages</h1>\n<div class=\"grid\">",
+        conversion.pages.iter().map(|page| page.copies).sum::<u32>()
+    );
+
+    let mut next = 1;
+    for page in &conversion.pages {
+        let number = next;
+        next += page.copies;
+        let numbers = match page.copies {
+            1 => format!("Page {number}"),
+            copies => format!(
+                "Pages {number}&ndash;{} ({copies} copies)",
+                number + copies - 1
+            ),
+        };
+        let exported = page
+            .image_path
+            .as_ref()
+            .and_then(|path| path.strip_prefix(dir).ok());
+        let image = match (exported, &page.thumbnail) {
+            (Some(path), _) => Some(escape_url(path)),
+            (None, Some(png)) => Some(format!(
+                "data:image/png;base64,{}",
+                base64::engine::general_purpose::STANDARD.encode(png)
+            )),
+            _ => None,
+        };
+        let name = escape_html(&page.id);
+
+        html.push_str("<div class=\"page\">\n");
+        if let Some(document) = document {
+            let _ = writeln!(
+                html,
+                "<a href=\"{}#page={number}\">",
+                escape_url(document.strip_prefix(dir).unwrap_or(document))
+            );
+        }
+        if let Some(image) = image {
+            let _ = writeln!(
+                html,
+                "<img src=\"{image}\" alt=\"Page {number}\" loading=\"lazy\">"
+            );
+        }
+        let _ = writeln!(
+            html,
+            "<div class=\"number\">{numbers}</div>\n<div class=\"name\" title=\"{}\">{name}</div>",
+            escape_html(&names::path_text(page.path.as_os_str()))
+        );
+        if document.is_some() {
+            html.push_str("</a>\n");
+        }
+        for warning in &page.warnings {
+            let _ = writeln!(html, "<p class=\"warning\">{}</p>", escape_html(warning));
+        }
+        html.push_str("</div>\n");
+    }
+
+    html.push_str("</div>\n");
+
+    // Files left out for repeating another one
+    if !conversion.skipped.is_empty() {
+        html.push_str("<h2>Skipped duplicates</h2>\n<ul class=\"skipped\">\n");
+        for duplicate in &conversion.skipped {
+            let _ = writeln!(
+                html,
+                "<li>{} &middot; same as {}</li>",
+                escape_html(&duplicate.id),
+                escape_html(&duplicate.original)
+            );
+        }
+        html.push_str("</ul>\n");
+    }
+
+    // Annotations without a page to go on
+    if !conversion.stray_annotations.is_empty() {
+        html.push_str("<h2>Annotations not placed</h2>\n<ul class=\"skipped\">\n");
+        for stray in &conversion.stray_annotations {
+            let _ = writeln!(html, "<li>{}</li>", escape_html(stray));
+        }
+        html.push_str("</ul>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+pub fn write_index(path: &Path, conversion: &Conversion, document: Option<&Path>) -> Result<()> {
+    let dir = path.parent().unwrap_or("".as_ref());
+    fs::write(paths::long_path(path), index(conversion, dir, document))
+        .with_context(|| format!("Failed to write HTML index: {:?}", path))
+}
+
+#[test]
+fn test_index_escapes_names() {
+    use crate::convert::PageInfo;
+    use crate::timings::FileTimings;
+    use std::path::PathBuf;
+
+    let path = PathBuf::from("in/<b>&\"x\".svg");
+    let conversion = Conversion {
+        pages: vec![PageInfo {
+            path: path.clone(),
+            id: "<b>&\"x\".svg".to_string(),
+            width: 1,
+            height: 1,
+            timings: FileTimings::new(path),
+            pixel_hash: None,
+            image_path: Some(PathBuf::from("out/png/0001 <b>.png")),
+            thumbnail: None,
+            warnings: vec!["<script>".to_string()],
+            cached: false,
+            deduped: false,
+            blank: None,
+            encoding: None,
+            encoding_reason: None,
+            expanded: None,
+            unsupported: Vec::new(),
+            copies: 1,
+            placement: None,
+            annotations: 0,
+        }],
+        cache_hits: 0,
+        options_hash: String::new(),
+        preview: None,
+        peak_in_flight: 0,
+        budget_waits: 0,
+        dedupe_hits: 0,
+        dropped: Vec::new(),
+        started_early: 0,
+        skipped: vec![crate::dedupe::Duplicate {
+            path: "in/copy/<b>.svg".into(),
+            id: "copy/<b>.svg".to_string(),
+            original: "<b>&\"x\".svg".to_string(),
+        }],
+        empty: false,
+        stray_annotations: vec!["Annotation for \"<i>.svg\" matches no page".to_string()],
+        failed: Vec::new(),
+    };
+    let html = index(&conversion, "out".as_ref(), Some("out/a b#1.pdf".as_ref()));
+
+    assert!(html.contains("&lt;b&gt;&amp;&quot;x&quot;.svg"));
+    assert!(!html.contains("<b>"));
+    assert!(html.contains("&lt;script&gt;"));
+    assert!(html.contains("href=\"a%20b%231.pdf#page=1\""));
+    assert!(html.contains("src=\"png/0001%20%3Cb%3E.png\""));
+    assert!(html
+        .contains("<li>copy/&lt;b&gt;.svg &middot; same as &lt;b&gt;&amp;&quot;x&quot;.svg</li>"));
+    assert!(html.contains("<li>Annotation for &quot;&lt;i&gt;.svg&quot; matches no page</li>"));
+    assert!(!html.contains("http"));
+}
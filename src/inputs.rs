@@ -0,0 +1,434 @@
+use crate::convert::{self, SortOrder, Source, Sources};
+use crate::names;
+use crate::paths;
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// What a run converts: SVG files, directories of them and glob patterns
+// such as `diagrams/**/*.svg`, taken in the order given
+#[derive(Clone, Debug, Default)]
+pub struct Inputs {
+    pub paths: Vec<PathBuf>,
+    // Whether directories bring the SVGs of their subdirectories too
+    pub recursive: bool,
+}
+
+// A file found by one input, and its path relative to the root its page id
+// is taken from
+struct Found {
+    path: PathBuf,
+    relative: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl Inputs {
+    // The SVG files of a single directory
+    pub fn dir(dir: &Path) -> Self {
+        Inputs {
+            paths: vec![dir.to_path_buf()],
+            recursive: false,
+        }
+    }
+
+    // The files named by every input in turn, those of a directory or pattern
+    // in `sort` order, and the inputs that named none. A file named twice is
+    // only taken the first time. Only the listing is read; the files are read
+    // as the pipeline takes them.
+    pub fn list(&self, sort: SortOrder, reverse: bool) -> Result<(Vec<Source>, Vec<&Path>)> {
+        let mut sources = Vec::new();
+        let mut unmatched = Vec::new();
+        let mut seen = HashSet::new();
+        for input in &self.paths {
+            let (root, mut found) = self.expand(input, sort)?;
+            if found.is_empty() {
+                unmatched.push(input.as_path());
+                continue;
+            }
+            sort_found(&mut found, sort);
+            if reverse {
+                found.reverse();
+            }
+            for file in found {
+                let canonical = fs::canonicalize(paths::long_path(&file.path))
+                    .unwrap_or_else(|_| file.path.clone());
+                if seen.insert(canonical) {
+                    sources.push(Source::file(&root, file.path));
+                }
+            }
+        }
+        Ok((sources, unmatched))
+    }
+
+    // Number of files the inputs name
+    pub fn count(&self) -> Result<usize> {
+        Ok(self.list(SortOrder::Lexical, false)?.0.len())
+    }
+
+    // The files to convert, see list; fails when there are none
+    pub fn scan(&self, sort: SortOrder, reverse: bool) -> Result<Sources<'static>> {
+        let (sources, unmatched) = self.list(sort, reverse)?;
+        if sources.is_empty() {
+            let unmatched: Vec<_> = unmatched.iter().map(|input| format!("{input:?}")).collect();
+            anyhow::bail!(
+                "No SVG files found in {}; pass --allow-empty to accept that",
+                unmatched.join(", ")
+            );
+        }
+        Ok(sources.into())
+    }
+
+    // Where to watch for changes to the files, and whether below it too
+    pub fn watched(&self) -> Vec<(PathBuf, bool)> {
+        self.paths
+            .iter()
+            .map(|input| {
+                let (dir, recursive) = if input.is_dir() {
+                    (input.clone(), self.recursive)
+                } else if is_pattern(input) {
+                    (pattern_root(input), true)
+                } else {
+                    // Editors often replace a file on saving it, which a
+                    // watch on the file itself wouldn't survive
+                    (input.parent().unwrap_or("".as_ref()).to_path_buf(), false)
+                };
+                match dir.as_os_str().is_empty() {
+                    true => (PathBuf::from("."), recursive),
+                    false => (dir, recursive),
+                }
+            })
+            .collect()
+    }
+
+    // The root of `input` and the SVG files below it
+    fn expand(&self, input: &Path, sort: SortOrder) -> Result<(PathBuf, Vec<Found>)> {
+        let modified = |path: &Path| match sort {
+            SortOrder::Mtime => fs::metadata(paths::long_path(path))
+                .and_then(|meta| meta.modified())
+                .ok(),
+            _ => None,
+        };
+        let metadata = fs::metadata(paths::long_path(input));
+        if metadata.as_ref().is_ok_and(|meta| meta.is_dir()) {
+            let mut found = Vec::new();
+            self.walk(input, "".as_ref(), &mut found, &modified)?;
+            return Ok((input.to_path_buf(), found));
+        }
+        if metadata.is_ok() {
+            anyhow::ensure!(convert::is_svg(input), "{:?} is not an SVG file", input);
+            let root = input.parent().unwrap_or("".as_ref()).to_path_buf();
+            let found = Found {
+                path: input.to_path_buf(),
+                relative: input.file_name().unwrap_or_default().into(),
+                modified: modified(input),
+            };
+            return Ok((root, vec![found]));
+        }
+        if !is_pattern(input) {
+            anyhow::bail!("Input {:?} does not exist", input);
+        }
+
+        let root = pattern_root(input);
+        // is_pattern only takes patterns that are text
+        let pattern = input.to_str().unwrap_or_default();
+        let mut found = Vec::new();
+        for path in glob::glob(pattern).with_context(|| format!("Invalid pattern {:?}", pattern))? {
+            let path = path.with_context(|| format!("Failed to expand {:?}", pattern))?;
+            if convert::is_svg(&path) && path.is_file() {
+                let relative = path.strip_prefix(&root).unwrap_or(&path).to_path_buf();
+                let modified = modified(&path);
+                found.push(Found {
+                    path,
+                    relative,
+                    modified,
+                });
+            }
+        }
+        Ok((root, found))
+    }
+
+    // Collect the SVG files of `dir`, at `relative` below the input
+    fn walk(
+        &self,
+        dir: &Path,
+        relative: &Path,
+        found: &mut Vec<Found>,
+        modified: &dyn Fn(&Path) -> Option<SystemTime>,
+    ) -> Result<()> {
+        let entries = fs::read_dir(paths::long_path(dir))
+            .with_context(|| format!("Failed to read directory {:?}", dir))?;
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let name = entry.file_name();
+            // Joined to the directory as given, which long_path may have changed
+            let path = dir.join(&name);
+            if self.recursive && entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                self.walk(&path, &relative.join(&name), found, modified)?;
+            } else if convert::is_svg(name.as_ref()) {
+                found.push(Found {
+                    modified: modified(&path),
+                    relative: relative.join(&name),
+                    path,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// The inputs listed in `list`, or on stdin for `-`, one per line in the
+// order given and relative to the working directory. Blank lines and lines
+// starting with # are left out.
+pub fn read_list(list: &Path) -> Result<Vec<PathBuf>> {
+    if paths::is_stdio(list) {
+        let mut bytes = Vec::new();
+        std::io::stdin()
+            .lock()
+            .read_to_end(&mut bytes)
+            .context("Failed to read the file list from stdin")?;
+        return parse_list("stdin", &bytes);
+    }
+    let bytes = fs::read(paths::long_path(list))
+        .with_context(|| format!("Failed to read file list {:?}", list))?;
+    parse_list(&format!("{:?}", list), &bytes)
+}
+
+// The inputs of the file list `name`, which must all exist, failing with
+// the line of the first that doesn't
+fn parse_list(name: &str, bytes: &[u8]) -> Result<Vec<PathBuf>> {
+    let mut inputs = Vec::new();
+    for (index, line) in bytes.split(|&byte| byte == b'\n').enumerate() {
+        let line = line.trim_ascii();
+        if line.is_empty() || line.starts_with(b"#") {
+            continue;
+        }
+        let input = path_of(line);
+        let metadata = fs::metadata(paths::long_path(&input));
+        match metadata {
+            Err(_) => anyhow::bail!("{}, line {}: {:?} does not exist", name, index + 1, input),
+            Ok(meta) if meta.is_file() && !convert::is_svg(&input) => {
+                anyhow::bail!(
+                    "{}, line {}: {:?} is not an SVG file",
+                    name,
+                    index + 1,
+                    input
+                )
+            }
+            Ok(_) => inputs.push(input),
+        }
+    }
+    Ok(inputs)
+}
+
+// A path of a file list, which on Unix may be any bytes
+#[cfg(unix)]
+fn path_of(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::OsStr::from_bytes(bytes).into()
+}
+
+#[cfg(not(unix))]
+fn path_of(bytes: &[u8]) -> PathBuf {
+    String::from_utf8_lossy(bytes).into_owned().into()
+}
+
+// Whether `input` is a glob pattern rather than a path
+fn is_pattern(input: &Path) -> bool {
+    input
+        .to_str()
+        .is_some_and(|text| text.contains(['*', '?', '[']))
+}
+
+// The directories a pattern starts with before its first wildcard, which
+// the page ids of its files are relative to
+fn pattern_root(pattern: &Path) -> PathBuf {
+    pattern
+        .components()
+        .take_while(|component| !is_pattern(component.as_os_str().as_ref()))
+        .collect()
+}
+
+// Order the files of one input, whatever order the file system lists them
+// in: by their relative path, directory by directory
+fn sort_found(found: &mut [Found], sort: SortOrder) {
+    let natural = |a: &Path, b: &Path| {
+        let text = |path: &Path| -> Vec<String> {
+            path.components()
+                .map(|component| names::path_text(component.as_os_str()).into_owned())
+                .collect()
+        };
+        let (a, b) = (text(a), text(b));
+        a.iter()
+            .zip(&b)
+            .map(|(a, b)| names::natural_cmp(a, b))
+            .find(|order| order.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len()))
+    };
+    match sort {
+        SortOrder::Natural => found.sort_by(|a, b| natural(&a.relative, &b.relative)),
+        SortOrder::Lexical => found.sort_by(|a, b| a.relative.cmp(&b.relative)),
+        // Files of the same time by name, and those without one first
+        SortOrder::Mtime => found.sort_by(|a, b| {
+            a.modified
+                .cmp(&b.modified)
+                .then_with(|| natural(&a.relative, &b.relative))
+        }),
+    }
+}
+
+#[test]
+fn test_inputs_sort() {
+    use std::time::{Duration, SystemTime};
+
+    let dir = std::env::temp_dir().join(format!("svg2pdf-sort-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // Written newest first
+    let names = ["slide-10.svg", "img010.svg", "slide-9.svg", "img2.svg"];
+    for (age, name) in names.iter().enumerate() {
+        let file = fs::File::create(dir.join(name)).unwrap();
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000 - age as u64);
+        file.set_modified(modified).unwrap();
+    }
+    let ids = |sort, reverse| -> Vec<String> {
+        Inputs::dir(&dir)
+            .scan(sort, reverse)
+            .unwrap()
+            .map(|source| source.id)
+            .collect()
+    };
+    let natural = ["img2.svg", "img010.svg", "slide-9.svg", "slide-10.svg"];
+    assert_eq!(ids(SortOrder::Natural, false), natural);
+    assert_eq!(
+        ids(SortOrder::Lexical, false),
+        ["img010.svg", "img2.svg", "slide-10.svg", "slide-9.svg"]
+    );
+    assert_eq!(
+        ids(SortOrder::Mtime, false),
+        ["img2.svg", "slide-9.svg", "img010.svg", "slide-10.svg"]
+    );
+    let mut reversed = natural;
+    reversed.reverse();
+    assert_eq!(ids(SortOrder::Natural, true), reversed);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_inputs_expand_in_order() {
+    let dir = std::env::temp_dir().join(format!("svg2pdf-inputs-test-{}", std::process::id()));
+    for name in [
+        "a/2.svg",
+        "a/10.svg",
+        "a/notes.txt",
+        "a/sub/1.svg",
+        "b/x.svg",
+        "b/deep/y.svg",
+        "b/deep/z.txt",
+    ] {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "<svg/>").unwrap();
+    }
+    fs::create_dir_all(dir.join("empty")).unwrap();
+    let ids = |inputs: &[PathBuf], recursive| -> Vec<String> {
+        let inputs = Inputs {
+            paths: inputs.to_vec(),
+            recursive,
+        };
+        let (sources, _) = inputs.list(SortOrder::Natural, false).unwrap();
+        sources.into_iter().map(|source| source.id).collect()
+    };
+
+    // Files as given, directories and patterns in sort order each
+    let inputs = [
+        dir.join("b/x.svg"),
+        dir.join("a"),
+        dir.join("b/**/*.svg"),
+        dir.join("a/10.svg"),
+    ];
+    assert_eq!(
+        ids(&inputs, false),
+        ["x.svg", "2.svg", "10.svg", "deep/y.svg"]
+    );
+    assert_eq!(
+        ids(&inputs, true),
+        ["x.svg", "2.svg", "10.svg", "sub/1.svg", "deep/y.svg"]
+    );
+
+    // Named on purpose, a file that isn't SVG is an error
+    let inputs = Inputs {
+        paths: vec![dir.join("a/notes.txt")],
+        recursive: false,
+    };
+    let Err(err) = inputs.list(SortOrder::Natural, false) else {
+        panic!("notes.txt is no SVG");
+    };
+    assert!(err.to_string().contains("is not an SVG file"), "{err}");
+    let inputs = Inputs {
+        paths: vec![dir.join("missing.svg")],
+        recursive: false,
+    };
+    assert!(inputs.list(SortOrder::Natural, false).is_err());
+
+    // Only the inputs that named nothing are listed
+    let inputs = Inputs {
+        paths: vec![dir.join("empty"), dir.join("b/*.txt")],
+        recursive: false,
+    };
+    let Err(err) = inputs.scan(SortOrder::Natural, false) else {
+        panic!("no files to convert");
+    };
+    let err = err.to_string();
+    assert!(err.contains("empty") && err.contains("b/*.txt"), "{err}");
+    let inputs = Inputs {
+        paths: vec![dir.join("empty"), dir.join("a")],
+        recursive: false,
+    };
+    assert_eq!(
+        inputs.list(SortOrder::Natural, false).unwrap().1,
+        [dir.join("empty")]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_file_lists() {
+    let dir = std::env::temp_dir().join(format!("svg2pdf-list-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for name in ["b.svg", "a.svg", "notes.txt"] {
+        fs::write(dir.join(name), "<svg/>").unwrap();
+    }
+    let list = |lines: &[&str]| {
+        let lines: Vec<String> = lines
+            .iter()
+            .map(|line| line.replace("DIR", dir.to_str().unwrap()))
+            .collect();
+        parse_list("list.txt", lines.join("\r\n").as_bytes())
+    };
+
+    // In the order given, without blank lines and comments
+    let listed = list(&["# slides", "DIR/b.svg", "", "  DIR/a.svg  ", "DIR"]).unwrap();
+    assert_eq!(listed, [dir.join("b.svg"), dir.join("a.svg"), dir.clone()]);
+    let err = list(&["DIR/a.svg", "# gone", "DIR/missing.svg"]).unwrap_err();
+    assert!(
+        err.to_string().starts_with("list.txt, line 3: ")
+            && err.to_string().contains("missing.svg"),
+        "{err}"
+    );
+    let err = list(&["DIR/notes.txt"]).unwrap_err();
+    assert!(
+        err.to_string().contains("line 1") && err.to_string().contains("not an SVG"),
+        "{err}"
+    );
+
+    // Listed files keep their order through Inputs
+    let inputs = Inputs {
+        paths: list(&["DIR/b.svg", "DIR/a.svg"]).unwrap(),
+        recursive: false,
+    };
+    let (sources, _) = inputs.list(SortOrder::Natural, false).unwrap();
+    let ids: Vec<_> = sources.into_iter().map(|source| source.id).collect();
+    assert_eq!(ids, ["b.svg", "a.svg"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
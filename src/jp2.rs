@@ -0,0 +1,203 @@
+use crate::convert::RenderedImage;
+use anyhow::Result;
+use openjp2::openjpeg::*;
+use openjp2::{opj_image, opj_image_comptparm};
+use std::ffi::c_void;
+use std::io::{Cursor, Seek, SeekFrom, Write};
+
+// Compression ratio of lossy pages unless --jp2-rate says otherwise
+pub const DEFAULT_RATE: f32 = 20.0;
+
+// Wavelet levels of pages, fewer for images too small for them
+const RESOLUTIONS: u32 = 6;
+
+// How JPEG 2000 pages are compressed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Jp2Compression {
+    // Reversible wavelet, the samples come back exactly
+    Lossless,
+    // Irreversible wavelet at this compression ratio, 20 for 20:1
+    Rate(f32),
+}
+
+impl Default for Jp2Compression {
+    fn default() -> Self {
+        Jp2Compression::Rate(DEFAULT_RATE)
+    }
+}
+
+// Parse a compression ratio such as 20 or 12.5, which has to be above 1
+pub fn parse_rate(value: &str) -> Result<f32, String> {
+    let rate: f32 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("expected a compression ratio such as 20, got {value:?}"))?;
+    if !(rate > 1.0 && rate.is_finite()) {
+        return Err(format!("a compression ratio has to be above 1, got {rate}"));
+    }
+    Ok(rate)
+}
+
+// OpenJPEG writes through callbacks; the codestream goes into a Cursor
+// passed as their user data
+unsafe extern "C" fn write(buffer: *mut c_void, len: usize, data: *mut c_void) -> usize {
+    let out = &mut *(data as *mut Cursor<Vec<u8>>);
+    let buffer = std::slice::from_raw_parts(buffer as *const u8, len);
+    match out.write_all(buffer) {
+        Ok(()) => len,
+        Err(_) => usize::MAX,
+    }
+}
+
+unsafe extern "C" fn skip(offset: i64, data: *mut c_void) -> i64 {
+    let out = &mut *(data as *mut Cursor<Vec<u8>>);
+    match out.seek(SeekFrom::Current(offset)) {
+        Ok(_) => offset,
+        Err(_) => -1,
+    }
+}
+
+unsafe extern "C" fn seek(offset: i64, data: *mut c_void) -> i32 {
+    let out = &mut *(data as *mut Cursor<Vec<u8>>);
+    out.seek(SeekFrom::Start(offset as u64)).is_ok() as i32
+}
+
+// A raw JPEG 2000 codestream of `image`, as PDF's JPXDecode takes it.
+// Lossless pages transform their colors reversibly, so nothing is lost on
+// the way to YCbCr either.
+pub fn encode(image: &RenderedImage, compression: Jp2Compression) -> Result<Vec<u8>> {
+    let (width, height) = (image.width, image.height);
+    anyhow::ensure!(width > 0 && height > 0, "An empty page can't be JPEG 2000");
+    let component = opj_image_comptparm {
+        dx: 1,
+        dy: 1,
+        w: width,
+        h: height,
+        prec: 8,
+        bpp: 8,
+        ..Default::default()
+    };
+    let Some(mut j2k) = opj_image::create(&[component; 3], OPJ_CLRSPC_SRGB) else {
+        anyhow::bail!("Failed to allocate a {width}x{height} JPEG 2000 image");
+    };
+    (j2k.x1, j2k.y1) = (width, height);
+    for (channel, samples) in j2k.comps_data_mut_iter().into_iter().flatten().enumerate() {
+        for (sample, pixel) in samples.iter_mut().zip(image.rgb_data.chunks_exact(3)) {
+            *sample = pixel[channel] as i32;
+        }
+    }
+
+    // Every level halves the image, down to a single pixel at most
+    let levels = u32::BITS - width.min(height).leading_zeros();
+    let mut parameters = opj_cparameters_t {
+        tcp_numlayers: 1,
+        cp_disto_alloc: 1,
+        tcp_mct: 1,
+        numresolution: RESOLUTIONS.min(levels) as i32,
+        ..Default::default()
+    };
+    match compression {
+        Jp2Compression::Lossless => {
+            parameters.irreversible = 0;
+            parameters.tcp_rates[0] = 0.0;
+        }
+        Jp2Compression::Rate(rate) => {
+            parameters.irreversible = 1;
+            parameters.tcp_rates[0] = rate;
+        }
+    }
+
+    let mut out = Box::new(Cursor::new(Vec::new()));
+    // SAFETY: the codec and stream are destroyed before `out` and `j2k`,
+    // which their pointers refer to, go out of scope
+    let encoded = unsafe {
+        let codec = opj_create_compress(OPJ_CODEC_J2K);
+        let stream = opj_stream_default_create(0);
+        opj_stream_set_write_function(stream, Some(write));
+        opj_stream_set_skip_function(stream, Some(skip));
+        opj_stream_set_seek_function(stream, Some(seek));
+        opj_stream_set_user_data(
+            stream,
+            &mut *out as *mut Cursor<Vec<u8>> as *mut c_void,
+            None,
+        );
+        let image: *mut opj_image = &mut *j2k;
+        let encoded = !codec.is_null()
+            && opj_setup_encoder(codec, &mut parameters, image) != 0
+            && opj_start_compress(codec, image, stream) != 0
+            && opj_encode(codec, stream) != 0
+            && opj_end_compress(codec, stream) != 0;
+        // Flushes what the stream still buffers
+        opj_stream_destroy(stream);
+        opj_destroy_codec(codec);
+        encoded
+    };
+    anyhow::ensure!(
+        encoded,
+        "Failed to encode a {width}x{height} page as JPEG 2000"
+    );
+    Ok(out.into_inner())
+}
+
+#[test]
+fn test_jp2_pages_decode() {
+    use hayro_jpeg2000::{DecodeSettings, DecoderContext, Image};
+
+    // Read back by another decoder than OpenJPEG's
+    let decode = |data: &[u8]| {
+        let image = Image::new(data, &DecodeSettings::default()).unwrap();
+        let mut context = DecoderContext::default();
+        let size = (image.width(), image.height());
+        (size, image.decode(&mut context).unwrap().data_u8())
+    };
+    let gradient = |width: u32, height: u32| RenderedImage {
+        width,
+        height,
+        rgb_data: (0..width * height)
+            .flat_map(|i| {
+                let (x, y) = (i % width, i / width);
+                [
+                    (x * 255 / width) as u8,
+                    (y * 255 / height) as u8,
+                    ((x + y) * 255 / (width + height)) as u8,
+                ]
+            })
+            .collect(),
+        alpha: None,
+    };
+    for (width, height) in [(64, 48), (5, 3), (1, 1)] {
+        let image = gradient(width, height);
+        let data = encode(&image, Jp2Compression::Lossless).unwrap();
+        assert_eq!(
+            decode(&data),
+            ((width, height), image.rgb_data),
+            "{width}x{height}"
+        );
+    }
+
+    // Lossy pages come out close and about as small as asked for
+    let image = gradient(256, 192);
+    let data = encode(&image, Jp2Compression::Rate(20.0)).unwrap();
+    assert!(
+        data.len() < image.rgb_data.len() / 15,
+        "{} bytes",
+        data.len()
+    );
+    let (size, samples) = decode(&data);
+    assert_eq!(size, (256, 192));
+    let error: u64 = samples
+        .iter()
+        .zip(&image.rgb_data)
+        .map(|(&a, &b)| a.abs_diff(b) as u64)
+        .sum();
+    assert!(
+        error < samples.len() as u64 * 4,
+        "mean error {}",
+        error as f64 / samples.len() as f64
+    );
+
+    assert_eq!(parse_rate("12.5"), Ok(12.5));
+    for value in ["1", "0", "-5", "inf", "fast"] {
+        assert!(parse_rate(value).is_err(), "{value}");
+    }
+}
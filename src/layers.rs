@@ -0,0 +1,168 @@
+use crate::convert::{svg_text, Source, Sources};
+use crate::names;
+use crate::retry::RetryPolicy;
+use anyhow::{Context, Result};
+use resvg::usvg::roxmltree;
+use std::borrow::Cow;
+use std::ops::Range;
+
+const INKSCAPE_NS: &str = "http://www.inkscape.org/namespaces/inkscape";
+
+// A top-level Inkscape layer and where its element is in the document
+pub struct Layer {
+    pub name: String,
+    range: Range<usize>,
+}
+
+// The layers of a document in document order: groups directly below the
+// root that Inkscape marks as layers. Named by their label, else their id.
+pub fn find(text: &str) -> Result<Vec<Layer>> {
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..roxmltree::ParsingOptions::default()
+    };
+    let doc = roxmltree::Document::parse_with_options(text, options)?;
+    let layers = doc
+        .root_element()
+        .children()
+        .filter(|node| {
+            node.tag_name().name() == "g"
+                && node.attribute((INKSCAPE_NS, "groupmode")) == Some("layer")
+        })
+        .enumerate()
+        .map(|(index, node)| Layer {
+            name: node
+                .attribute((INKSCAPE_NS, "label"))
+                .or(node.attribute("id"))
+                .map_or_else(|| format!("layer {}", index + 1), str::to_string),
+            range: node.range(),
+        })
+        .collect();
+    Ok(layers)
+}
+
+// The document with every layer but the `keep`th hidden. Hidden layers
+// are wrapped rather than restyled, since Inkscape sets display:inline on
+// the layers themselves; anything they define can still be referenced.
+pub fn isolate(data: &[u8], keep: usize) -> Result<Vec<u8>> {
+    let text = svg_text(data)?;
+    let layers = find(&text)?;
+    let mut isolated = String::with_capacity(text.len() + layers.len() * 32);
+    let mut copied = 0;
+    for (index, layer) in layers.iter().enumerate() {
+        if index == keep {
+            continue;
+        }
+        isolated.push_str(&text[copied..layer.range.start]);
+        isolated.push_str("<g style=\"display:none\">");
+        isolated.push_str(&text[layer.range.clone()]);
+        isolated.push_str("</g>");
+        copied = layer.range.end;
+    }
+    isolated.push_str(&text[copied..]);
+    Ok(isolated.into_bytes())
+}
+
+// One source per layer of every file that has layers, named `id#layer`,
+// leaving out layers whose name matches one of `skip`. Other files, and
+// files that can't be read or parsed, are kept as they are; rendering
+// reports their errors.
+pub fn explode(sources: Sources, skip: &[String], retry: &RetryPolicy) -> Result<Sources<'static>> {
+    let mut exploded = Vec::with_capacity(sources.total);
+    for source in sources {
+        let layers = retry
+            .run(|| source.read())
+            .0
+            .ok()
+            .and_then(|data| find(&svg_text(&data).ok()?).ok());
+        let layers = match layers {
+            Some(layers) if !layers.is_empty() => layers,
+            _ => {
+                exploded.push(source);
+                continue;
+            }
+        };
+        for (index, layer) in layers.into_iter().enumerate() {
+            if skip
+                .iter()
+                .any(|pattern| names::matches(pattern, &layer.name))
+            {
+                continue;
+            }
+            exploded.push(Source {
+                id: format!("{}#{}", source.id, layer.name),
+                layer: Some(index),
+                ..source.clone()
+            });
+        }
+    }
+    if exploded.is_empty() {
+        anyhow::bail!("Every layer was left out by --layer-filter");
+    }
+    Ok(exploded.into())
+}
+
+// Contents of `source` as rendered: only its layer, if it is one
+pub fn page_data<'a>(source: &Source, data: Cow<'a, [u8]>) -> Result<Cow<'a, [u8]>> {
+    match source.layer {
+        Some(layer) => isolate(&data, layer)
+            .map(Cow::Owned)
+            .with_context(|| format!("Failed to read the layers of {:?}", source.path)),
+        None => Ok(data),
+    }
+}
+
+#[test]
+fn test_layers_render_one_at_a_time() {
+    use resvg::usvg;
+
+    let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:inkscape="http://www.inkscape.org/namespaces/inkscape" width="10" height="10">
+<defs><linearGradient id="g"><stop stop-color="red"/></linearGradient></defs>
+<g inkscape:groupmode="layer" inkscape:label="Title" style="display:inline"><rect width="5" height="5" fill="url(#g)"/></g>
+<g inkscape:groupmode="layer" id="layer2"><circle r="3"/></g>
+<g inkscape:groupmode="layer" inkscape:label="guides"><path d="M0 0"/></g>
+<g><rect width="1" height="1"/></g>
+</svg>"##;
+    let names: Vec<_> = find(svg)
+        .unwrap()
+        .into_iter()
+        .map(|layer| layer.name)
+        .collect();
+    assert_eq!(names, ["Title", "layer2", "guides"]);
+
+    let isolated = String::from_utf8(isolate(svg.as_bytes(), 1).unwrap()).unwrap();
+    assert_eq!(isolated.matches("<g style=\"display:none\">").count(), 2);
+    assert!(isolated.contains("\n<g inkscape:groupmode=\"layer\" id=\"layer2\">"));
+    // Still one document, with only the kept layer directly below the root
+    assert_eq!(find(&isolated).unwrap()[0].name, "layer2");
+    // The circle and the rectangle outside the layers are left to render
+    fn paths(group: &usvg::Group) -> usize {
+        group
+            .children()
+            .iter()
+            .map(|node| match node {
+                usvg::Node::Group(group) => paths(group),
+                usvg::Node::Path(_) => 1,
+                _ => 0,
+            })
+            .sum()
+    }
+    let tree = usvg::Tree::from_str(&isolated, &usvg::Options::default()).unwrap();
+    assert_eq!(paths(tree.root()), 2);
+
+    let sources = explode(
+        vec![
+            Source::bytes("a.svg", svg.as_bytes().to_vec()),
+            Source::bytes(
+                "plain.svg",
+                b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>".to_vec(),
+            ),
+        ]
+        .into(),
+        &["guide*".to_string()],
+        &RetryPolicy::default(),
+    )
+    .unwrap();
+    let ids: Vec<_> = sources.map(|source| source.id).collect();
+    assert_eq!(ids, ["a.svg#Title", "a.svg#layer2", "plain.svg"]);
+}
@@ -0,0 +1,60 @@
+// svg2pdf converts SVG drawings to PDF documents, one page per drawing.
+// Converter is the way in for programs; cli is the command line tool on top.
+mod annotations;
+mod append;
+mod bench;
+mod bilevel;
+mod bookmarks;
+mod budget;
+mod cache;
+pub mod cli;
+mod compare;
+mod convert;
+mod converter;
+mod dedupe;
+mod doctor;
+mod error;
+mod export;
+mod fonts;
+mod hashes;
+mod html;
+mod inputs;
+#[cfg(feature = "jp2")]
+mod jp2;
+mod layers;
+mod metadata;
+mod names;
+mod nup;
+mod output;
+mod paths;
+mod pixels;
+mod pool;
+mod predictor;
+mod presets;
+mod print;
+mod progress;
+mod readahead;
+mod report;
+mod retry;
+#[cfg(feature = "serve")]
+mod serve;
+mod sniff;
+mod spool;
+mod timings;
+mod unsupported;
+mod vector;
+mod verify;
+mod watch;
+mod writer;
+
+pub use bookmarks::{Bookmarks, Entry as Bookmark};
+pub use convert::{
+    Background, DrawingSize, FailedFile, OnError, Orientation, PageInfo, Quality, RenderArgs,
+    SortOrder,
+};
+pub use converter::{Converter, Options, PdfDocument};
+pub use error::Error;
+pub use metadata::Metadata;
+pub use nup::Grid;
+pub use progress::{Event, Progress};
+pub use writer::{ImageFormat, ImageOptions};
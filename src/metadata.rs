@@ -0,0 +1,187 @@
+use crate::html;
+use chrono::{DateTime, FixedOffset, Local};
+use std::fmt::Write;
+
+// The program every document says produced it
+pub const PRODUCER: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+
+// What a document says about itself, in its Info dictionary and again as
+// XMP for readers that only look there
+#[derive(Clone, Debug, Default)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+    // Say nothing of when the document was written, so the same pages
+    // always make the same bytes; see Metadata::time
+    pub undated: bool,
+}
+
+// The time a document is written, in the local time zone
+pub fn now() -> DateTime<FixedOffset> {
+    Local::now().fixed_offset()
+}
+
+// The time reproducible builds pin dates to, in seconds since 1970
+pub const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+// A SOURCE_DATE_EPOCH value as a time in UTC
+pub fn parse_epoch(value: &str) -> Result<DateTime<FixedOffset>, String> {
+    value
+        .trim()
+        .parse::<i64>()
+        .ok()
+        .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+        .map(|time| time.fixed_offset())
+        .ok_or_else(|| {
+            format!(
+                "invalid {} {:?}, expected seconds since 1970 such as 1700000000",
+                SOURCE_DATE_EPOCH, value
+            )
+        })
+}
+
+// SOURCE_DATE_EPOCH of the environment; None when it is unset or invalid
+fn source_date() -> Option<DateTime<FixedOffset>> {
+    parse_epoch(&std::env::var(SOURCE_DATE_EPOCH).ok()?).ok()
+}
+
+// `time` as a PDF date, D:YYYYMMDDHHmmSS with its offset
+pub fn pdf_date(time: &DateTime<FixedOffset>) -> String {
+    let offset = time.offset().local_minus_utc() / 60;
+    let zone = match offset {
+        0 => "Z".to_string(),
+        _ => format!(
+            "{}{:02}'{:02}'",
+            if offset < 0 { '-' } else { '+' },
+            offset.abs() / 60,
+            offset.abs() % 60
+        ),
+    };
+    format!("{}{}", time.format("D:%Y%m%d%H%M%S"), zone)
+}
+
+impl Metadata {
+    // When the document says it was written: SOURCE_DATE_EPOCH when that is
+    // set, otherwise now, or never when `undated`
+    pub fn time(&self) -> Option<DateTime<FixedOffset>> {
+        match source_date() {
+            Some(time) => Some(time),
+            None if self.undated => None,
+            None => Some(now()),
+        }
+    }
+
+    // Entries of the Info dictionary of a document written at `time`, as
+    // text the writer encodes
+    pub fn info(&self, time: Option<&DateTime<FixedOffset>>) -> Vec<(&'static str, String)> {
+        let mut entries: Vec<_> = [
+            ("Title", &self.title),
+            ("Author", &self.author),
+            ("Subject", &self.subject),
+            ("Keywords", &self.keywords),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value.clone()?)))
+        .collect();
+        entries.push(("Producer", PRODUCER.to_string()));
+        if let Some(time) = time {
+            let date = pdf_date(time);
+            entries.push(("CreationDate", date.clone()));
+            entries.push(("ModDate", date));
+        }
+        entries
+    }
+
+    // The same as an XMP packet, for the catalog's Metadata stream
+    pub fn xmp(&self, time: Option<&DateTime<FixedOffset>>) -> String {
+        let date = time.map(|time| time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+        let mut properties = String::from("   <dc:format>application/pdf</dc:format>\n");
+        let mut property = |name: &str, wrap: Option<&str>, value: &Option<String>| {
+            let Some(value) = value else {
+                return;
+            };
+            let value = html::escape_html(value);
+            let _ = match wrap {
+                // Language alternatives, of which there is only the default
+                Some("Alt") => writeln!(
+                    properties,
+                    "   <{name}><rdf:Alt><rdf:li xml:lang=\"x-default\">{value}</rdf:li></rdf:Alt></{name}>"
+                ),
+                Some(list) => writeln!(
+                    properties,
+                    "   <{name}><rdf:{list}><rdf:li>{value}</rdf:li></rdf:{list}></{name}>"
+                ),
+                None => writeln!(properties, "   <{name}>{value}</{name}>"),
+            };
+        };
+        property("dc:title", Some("Alt"), &self.title);
+        property("dc:creator", Some("Seq"), &self.author);
+        property("dc:description", Some("Alt"), &self.subject);
+        property("pdf:Keywords", None, &self.keywords);
+        property("pdf:Producer", None, &Some(PRODUCER.to_string()));
+        for name in ["xmp:CreateDate", "xmp:ModifyDate", "xmp:MetadataDate"] {
+            property(name, None, &date);
+        }
+        format!(
+            concat!(
+                "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n",
+                "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n",
+                " <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n",
+                "  <rdf:Description rdf:about=\"\"\n",
+                "    xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n",
+                "    xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\"\n",
+                "    xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\">\n",
+                "{}",
+                "  </rdf:Description>\n",
+                " </rdf:RDF>\n",
+                "</x:xmpmeta>\n",
+                "<?xpacket end=\"w\"?>"
+            ),
+            properties
+        )
+    }
+}
+
+#[test]
+fn test_metadata_info_and_xmp() {
+    let time = DateTime::parse_from_rfc3339("2026-03-01T14:30:05+01:00").unwrap();
+    assert_eq!(pdf_date(&time), "D:20260301143005+01'00'");
+    let utc = DateTime::parse_from_rfc3339("2026-03-01T14:30:05Z").unwrap();
+    assert_eq!(pdf_date(&utc), "D:20260301143005Z");
+    let west = DateTime::parse_from_rfc3339("2026-03-01T14:30:05-03:30").unwrap();
+    assert_eq!(pdf_date(&west), "D:20260301143005-03'30'");
+
+    let metadata = Metadata {
+        title: Some("Größenübersicht <Q1>".to_string()),
+        author: Some("Jörg & Ann".to_string()),
+        ..Metadata::default()
+    };
+    let info = metadata.info(Some(&time));
+    let keys: Vec<_> = info.iter().map(|(key, _)| *key).collect();
+    assert_eq!(
+        keys,
+        ["Title", "Author", "Producer", "CreationDate", "ModDate"]
+    );
+    assert_eq!(info[2].1, PRODUCER);
+    assert!(PRODUCER.starts_with("svg2pdf "));
+
+    let xmp = metadata.xmp(Some(&time));
+    assert!(xmp.contains(
+        "<dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">Größenübersicht &lt;Q1&gt;</rdf:li></rdf:Alt></dc:title>"
+    ));
+    assert!(xmp.contains("<rdf:Seq><rdf:li>Jörg &amp; Ann</rdf:li></rdf:Seq>"));
+    assert!(xmp.contains("<xmp:CreateDate>2026-03-01T14:30:05+01:00</xmp:CreateDate>"));
+    assert!(!xmp.contains("dc:description"));
+
+    // Undated documents only name their producer
+    let info = metadata.info(None);
+    assert_eq!(info.last().unwrap().0, "Producer");
+    assert!(!metadata.xmp(None).contains("Date"));
+    assert_eq!(
+        parse_epoch("1700000000").unwrap().to_rfc3339(),
+        "2023-11-14T22:13:20+00:00"
+    );
+    assert!(parse_epoch("yesterday").is_err());
+}
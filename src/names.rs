@@ -0,0 +1,322 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+use std::path::Path;
+
+// A file name or path as text that tells every name apart. Bytes that
+// aren't UTF-8 (unpaired surrogates on Windows) and control characters,
+// which would break line-based output, are written as %XX; other names
+// come out unchanged. The one lossy case is a name that spells out such an
+// escape itself.
+pub fn path_text(name: &OsStr) -> Cow<'_, str> {
+    let bytes = name.as_encoded_bytes();
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        if !text.chars().any(char::is_control) {
+            return Cow::Borrowed(text);
+        }
+    }
+    let mut text = String::with_capacity(bytes.len() + 8);
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c.is_control() {
+                for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                    let _ = write!(text, "%{byte:02X}");
+                }
+            } else {
+                text.push(c);
+            }
+        }
+        for byte in chunk.invalid() {
+            let _ = write!(text, "%{byte:02X}");
+        }
+    }
+    Cow::Owned(text)
+}
+
+// Canonical identifier of a page: its path relative to the input root,
+// with forward slashes on every platform, see path_text
+pub fn page_id(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative
+        .components()
+        .map(|component| path_text(component.as_os_str()))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Directories and stem of a page id. Layer pages, `file.svg#layer`, keep
+// their layer in the stem: `file#layer`.
+fn split(id: &str) -> (Vec<&str>, Cow<'_, str>) {
+    let (path, layer) = match id.to_ascii_lowercase().find(".svg#") {
+        Some(at) => (&id[..at + 4], Some(&id[at + 5..])),
+        None => (id, None),
+    };
+    let mut parts: Vec<&str> = path.split('/').collect();
+    let file = parts.pop().unwrap_or_default();
+    let stem = Path::new(file)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(file);
+    match layer {
+        // Layer names may contain separators, but the stem is a file name
+        Some(layer) => {
+            let layer = layer.replace(['/', '\\'], "-");
+            (parts, Cow::Owned(format!("{stem}#{layer}")))
+        }
+        None => (parts, Cow::Borrowed(stem)),
+    }
+}
+
+// The name of a page without its directories and extension, `file#layer`
+// for layer pages
+pub fn stem(id: &str) -> Cow<'_, str> {
+    split(id).1
+}
+
+// Unique names without directories for the pages, e.g. output file names.
+// Files sharing a stem get the shortest tail of their directory that tells
+// them apart appended: `figure1 (chapter2)`. Comparison ignores case since
+// the names may end up on a case-insensitive file system. Also returns the
+// ids of every group that had to be renamed.
+pub fn flat_names(ids: &[&str]) -> (Vec<String>, Vec<Vec<String>>) {
+    let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, id) in ids.iter().enumerate() {
+        groups
+            .entry(split(id).1.to_lowercase())
+            .or_default()
+            .push(index);
+    }
+
+    let mut names: Vec<String> = ids.iter().map(|id| split(id).1.to_string()).collect();
+    let mut collisions = Vec::new();
+    for members in groups.values().filter(|members| members.len() > 1) {
+        let dirs: Vec<Vec<&str>> = members.iter().map(|&index| split(ids[index]).0).collect();
+        let depth = dirs.iter().map(Vec::len).max().unwrap_or(0);
+        let suffix = |dir: &[&str], k: usize| dir[dir.len().saturating_sub(k)..].join("-");
+        let k = (1..=depth)
+            .find(|&k| {
+                let mut suffixes: Vec<_> = dirs
+                    .iter()
+                    .map(|dir| suffix(dir, k).to_lowercase())
+                    .collect();
+                suffixes.sort();
+                suffixes.windows(2).all(|pair| pair[0] != pair[1])
+            })
+            .unwrap_or(depth);
+        for (&index, dir) in members.iter().zip(&dirs) {
+            if !dir.is_empty() {
+                names[index] = format!("{} ({})", names[index], suffix(dir, k));
+            }
+        }
+        let mut group: Vec<String> = members
+            .iter()
+            .map(|&index| ids[index].to_string())
+            .collect();
+        group.sort();
+        collisions.push(group);
+    }
+    collisions.sort();
+
+    // Whatever still clashes (same directory, names differing in case)
+    // gets numbered in input order
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for name in &mut names {
+        let count = seen.entry(name.to_lowercase()).or_insert(0);
+        *count += 1;
+        if *count > 1 {
+            *name = format!("{} {}", name, count);
+        }
+    }
+    (names, collisions)
+}
+
+// Whether a page id matches a pattern where `*` stands for any run of
+// characters, slashes included, and `?` for any single character
+pub fn matches(pattern: &str, id: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let id: Vec<char> = id.chars().collect();
+    // Where to resume after the last `*`: pattern after it, id position
+    let mut star = None;
+    let (mut p, mut i) = (0, 0);
+    while i < id.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, i));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == id[i] => {
+                p += 1;
+                i += 1;
+            }
+            _ => match star {
+                // Let the `*` take one more character
+                Some((after, taken)) => {
+                    star = Some((after, taken + 1));
+                    p = after;
+                    i = taken + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Order of names the way people count: runs of digits compare by their
+// value, so `slide-9` comes before `slide-10`, and letters ignore case.
+// Names equal that way, `img2` and `img02`, are told apart by their text.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut left, mut right) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        let order = match (left.peek(), right.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                        digits.push(digit);
+                    }
+                    digits.trim_start_matches('0').to_string()
+                };
+                let (x, y) = (number(&mut left), number(&mut right));
+                x.len().cmp(&y.len()).then_with(|| x.cmp(&y))
+            }
+            (Some(&x), Some(&y)) => {
+                left.next();
+                right.next();
+                x.to_lowercase().cmp(y.to_lowercase())
+            }
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+}
+
+#[test]
+fn test_flat_names_are_unique() {
+    assert_eq!(
+        page_id(Path::new("in"), Path::new("in/chapter2/figure1.svg")),
+        "chapter2/figure1.svg"
+    );
+
+    let ids = [
+        "chapter1/figure1.svg",
+        "chapter2/figure1.svg",
+        "intro.svg",
+        "figure1.svg",
+        "a/x/plot.svg",
+        "b/x/plot.svg",
+        "Cover.svg",
+        "cover.SVG",
+        "deck.svg#Intro",
+        "deck.svg#Details/2",
+    ];
+    let (names, collisions) = flat_names(&ids);
+    assert_eq!(
+        names,
+        [
+            "figure1 (chapter1)",
+            "figure1 (chapter2)",
+            "intro",
+            "figure1",
+            "plot (a-x)",
+            "plot (b-x)",
+            "Cover",
+            "cover 2",
+            "deck#Intro",
+            "deck#Details-2",
+        ]
+    );
+    assert_eq!(collisions.len(), 3);
+    assert_eq!(
+        collisions[2],
+        [
+            "chapter1/figure1.svg",
+            "chapter2/figure1.svg",
+            "figure1.svg"
+        ]
+    );
+}
+
+#[test]
+fn test_matches() {
+    assert!(matches("*.svg", "slides/a.svg"));
+    assert!(matches("photos/*", "photos/2024/b.svg"));
+    assert!(matches("a?c.svg", "abc.svg"));
+    assert!(matches("*", ""));
+    assert!(!matches("photos/*", "slides/photos/b.svg"));
+    assert!(!matches("a?c.svg", "ac.svg"));
+    assert!(!matches("*.svg", "a.svgz"));
+}
+
+#[test]
+fn test_natural_order() {
+    let mut names = [
+        "slide-10.svg",
+        "img010.svg",
+        "slide-9.svg",
+        "Slide-1.svg",
+        "img2.svg",
+        "img02.svg",
+        "img.svg",
+        "slide-1.svg",
+    ];
+    names.sort_by(|a, b| natural_cmp(a, b));
+    assert_eq!(
+        names,
+        [
+            "img.svg",
+            "img02.svg",
+            "img2.svg",
+            "img010.svg",
+            "Slide-1.svg",
+            "slide-1.svg",
+            "slide-9.svg",
+            "slide-10.svg",
+        ]
+    );
+    // Longer than any integer type
+    assert_eq!(
+        natural_cmp("a99999999999999999999999", "a100000000000000000000000"),
+        Ordering::Less
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_utf8_names() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let name = |bytes: &[u8]| OsStr::from_bytes(bytes).to_owned();
+    assert!(matches!(
+        path_text(&name("café.svg".as_bytes())),
+        Cow::Borrowed("café.svg")
+    ));
+    assert_eq!(path_text(&name(b"caf\xe9.svg")), "caf%E9.svg");
+    assert_eq!(path_text(&name(b"\xff\xfe.svg")), "%FF%FE.svg");
+    assert_eq!(
+        path_text(&name(b"two\nlines\x85.svg")),
+        "two%0Alines%85.svg"
+    );
+    assert_eq!(
+        path_text(&name("bell\u{7}\u{85}.svg".as_bytes())),
+        "bell%07%C2%85.svg"
+    );
+
+    // Names that used to come out the same stay apart in ids and file names
+    let root = Path::new("in");
+    let ids: Vec<String> = [&b"in/d\xe9/a\xff.svg"[..], b"in/d\xe9/a\xfe.svg"]
+        .iter()
+        .map(|bytes| page_id(root, Path::new(OsStr::from_bytes(bytes))))
+        .collect();
+    assert_eq!(ids, ["d%E9/a%FF.svg", "d%E9/a%FE.svg"]);
+    let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+    let (names, collisions) = flat_names(&ids);
+    assert_eq!(names, ["a%FF", "a%FE"]);
+    assert!(collisions.is_empty());
+}
@@ -0,0 +1,126 @@
+use crate::convert::DrawingSize;
+use serde::{Deserialize, Serialize};
+
+// Columns and rows of drawings on each page, from --nup
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Grid {
+    pub columns: u32,
+    pub rows: u32,
+}
+
+// Parse a grid given as CxR, e.g. 2x3 for two columns of three rows
+pub fn parse_grid(value: &str) -> Result<Grid, String> {
+    let invalid = || {
+        format!(
+            "invalid grid {:?}, expected columns x rows such as 2x3",
+            value
+        )
+    };
+    let (columns, rows) = value.split_once(['x', 'X']).ok_or_else(invalid)?;
+    let side = |side: &str| match side.trim().parse::<u32>() {
+        Ok(side) if side > 0 => Ok(side),
+        _ => Err(invalid()),
+    };
+    Ok(Grid {
+        columns: side(columns)?,
+        rows: side(rows)?,
+    })
+}
+
+// Height of the strip under each cell that --nup-labels writes the file
+// name in, and the size of its text, in points
+pub const LABEL_HEIGHT: f32 = 12.0;
+pub const LABEL_SIZE: f32 = 8.0;
+
+// A page of --nup and where its drawings go, in points
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sheet {
+    pub grid: Grid,
+    pub width: f32,
+    pub height: f32,
+    // Space left clear around the grid, and between its cells
+    pub margin: f32,
+    pub gutter: f32,
+    pub labels: bool,
+}
+
+impl Sheet {
+    // Drawings on each page
+    pub fn slots(&self) -> usize {
+        (self.grid.columns * self.grid.rows) as usize
+    }
+
+    // Pages that `cells` drawings take, the last one maybe partly filled
+    pub fn pages(&self, cells: usize) -> usize {
+        cells.div_ceil(self.slots())
+    }
+
+    fn label_height(&self) -> f32 {
+        match self.labels {
+            true => LABEL_HEIGHT,
+            false => 0.0,
+        }
+    }
+
+    // What each drawing is fit into, without its label; not positive when
+    // the margins and gutters leave no room
+    pub fn cell(&self) -> DrawingSize {
+        let Grid { columns, rows } = self.grid;
+        let across = |page: f32, cells: u32| {
+            (page - 2.0 * self.margin - (cells - 1) as f32 * self.gutter) / cells as f32
+        };
+        DrawingSize {
+            width: across(self.width, columns),
+            height: across(self.height, rows) - self.label_height(),
+        }
+    }
+
+    // Top left corner of the cell in `slot`, filled row by row, from the
+    // top left of the page
+    pub fn origin(&self, slot: usize) -> (f32, f32) {
+        let cell = self.cell();
+        let column = (slot % self.grid.columns as usize) as f32;
+        let row = (slot / self.grid.columns as usize) as f32;
+        (
+            self.margin + column * (cell.width + self.gutter),
+            self.margin + row * (cell.height + self.label_height() + self.gutter),
+        )
+    }
+}
+
+#[test]
+fn test_sheet_cells() {
+    assert_eq!(
+        parse_grid("2x3"),
+        Ok(Grid {
+            columns: 2,
+            rows: 3
+        })
+    );
+    for value in ["0x3", "2", "2x-1", "axb"] {
+        assert!(parse_grid(value).is_err(), "{value}");
+    }
+
+    let sheet = Sheet {
+        grid: parse_grid("2x3").unwrap(),
+        width: 210.0,
+        height: 330.0,
+        margin: 10.0,
+        gutter: 10.0,
+        labels: false,
+    };
+    let cell = sheet.cell();
+    assert_eq!(cell.width, 90.0);
+    assert!((cell.height - 290.0 / 3.0).abs() < 1e-4);
+    let (left, top) = sheet.origin(3);
+    assert_eq!((left, top.round()), (110.0, 117.0));
+    assert_eq!((sheet.slots(), sheet.pages(7)), (6, 2));
+
+    // Labels take their strip out of every row
+    let labeled = Sheet {
+        labels: true,
+        ..sheet
+    };
+    assert_eq!(labeled.cell().height, sheet.cell().height - LABEL_HEIGHT);
+    assert_eq!(labeled.origin(2), (10.0, 10.0 + sheet.cell().height + 10.0));
+}
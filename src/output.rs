@@ -0,0 +1,172 @@
+use crate::paths;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Make sure a file can be written to `path` before a run spends its time
+// rendering: its directory must exist, or is created with `create_dirs`,
+// and must take new files, and an existing file must be writable.
+pub fn check_writable(path: &Path, create_dirs: bool) -> Result<()> {
+    // Messages show the paths as given, see paths::long_path
+    let opened = paths::long_path(path);
+    if opened.is_dir() {
+        bail!("Output {:?} is a directory, expected a file name", path);
+    }
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let opened_dir = paths::long_path(dir);
+    if !opened_dir.exists() {
+        if !create_dirs {
+            bail!(
+                "Output directory {:?} does not exist; create it or pass --create-dirs",
+                dir
+            );
+        }
+        fs::create_dir_all(&opened_dir)
+            .with_context(|| format!("Failed to create output directory {:?}", dir))?;
+    } else if !opened_dir.is_dir() {
+        bail!("Output directory {:?} is not a directory", dir);
+    }
+
+    // Try what the run will do at the end, without touching the output:
+    // create the temp file a document is written to, see Staged
+    if let Some(probe) = Staged::new(path).temp {
+        let probe = paths::long_path(&probe);
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&probe)
+            .with_context(|| format!("Cannot write to output directory {:?}", dir))?;
+        let _ = fs::remove_file(&probe);
+    }
+    if opened.exists() {
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&opened)
+            .with_context(|| format!("Cannot overwrite output file {:?}", path))?;
+    }
+    Ok(())
+}
+
+// A symlinked output is replaced where it points
+fn target(path: &Path) -> PathBuf {
+    let opened = paths::long_path(path);
+    match fs::symlink_metadata(&opened) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            fs::canonicalize(&opened).unwrap_or_else(|_| path.to_path_buf())
+        }
+        _ => path.to_path_buf(),
+    }
+}
+
+// Next to the file, so renaming it over the file stays on one file system
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.svg2pdf-{}.tmp", name, std::process::id()))
+}
+
+// A document being written to `path`. It goes to a temp file that only
+// replaces the file at `path` once complete, so a failed or interrupted run
+// leaves any earlier file as it was. Devices and pipes such as /dev/null
+// are written in place.
+pub struct Staged {
+    target: PathBuf,
+    temp: Option<PathBuf>,
+}
+
+impl Staged {
+    pub fn new(path: &Path) -> Staged {
+        let target = target(path);
+        let in_place =
+            fs::metadata(paths::long_path(&target)).is_ok_and(|metadata| !metadata.is_file());
+        Staged {
+            temp: (!in_place).then(|| temp_path(&target)),
+            target,
+        }
+    }
+
+    // The file to write the document to
+    pub fn path(&self) -> &Path {
+        self.temp.as_deref().unwrap_or(&self.target)
+    }
+
+    // Put the written document in place
+    pub fn commit(self) -> std::io::Result<()> {
+        match &self.temp {
+            Some(temp) => fs::rename(paths::long_path(temp), paths::long_path(&self.target)),
+            None => Ok(()),
+        }
+    }
+
+    // Remove what was written of a document that failed
+    pub fn abandon(self) {
+        if let Some(temp) = &self.temp {
+            let _ = fs::remove_file(paths::long_path(temp));
+        }
+    }
+}
+
+#[test]
+fn test_check_writable() {
+    let dir = std::env::temp_dir().join(format!("svg2pdf-output-test-{}", std::process::id()));
+    let output = dir.join("nested/out.pdf");
+    let err = check_writable(&output, false).unwrap_err();
+    assert!(err.to_string().contains("--create-dirs"), "{err}");
+    assert!(!dir.exists());
+
+    check_writable(&output, true).unwrap();
+    assert!(output.parent().unwrap().is_dir());
+    // Nothing is left behind, and an existing file is kept as it is
+    assert_eq!(fs::read_dir(output.parent().unwrap()).unwrap().count(), 0);
+    fs::write(&output, b"old").unwrap();
+    check_writable(&output, false).unwrap();
+    assert_eq!(fs::read(&output).unwrap(), b"old");
+
+    assert!(check_writable(&dir, false).is_err());
+    assert!(check_writable(&output.join("page.pdf"), true).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_staged_output() {
+    let dir = std::env::temp_dir().join(format!("svg2pdf-staged-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let output = dir.join("out.pdf");
+    fs::write(&output, b"old").unwrap();
+
+    // The earlier file stays until the new one is complete, and when it
+    // fails
+    let staged = Staged::new(&output);
+    assert_ne!(staged.path(), output);
+    fs::write(staged.path(), b"partial").unwrap();
+    staged.abandon();
+    assert_eq!(fs::read(&output).unwrap(), b"old");
+    let staged = Staged::new(&output);
+    fs::write(staged.path(), b"new").unwrap();
+    assert_eq!(fs::read(&output).unwrap(), b"old");
+    staged.commit().unwrap();
+    assert_eq!(fs::read(&output).unwrap(), b"new");
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+    // Links are followed, and devices written in place
+    #[cfg(unix)]
+    {
+        let link = dir.join("link.pdf");
+        std::os::unix::fs::symlink(&output, &link).unwrap();
+        let staged = Staged::new(&link);
+        fs::write(staged.path(), b"linked").unwrap();
+        staged.commit().unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read(&output).unwrap(), b"linked");
+        assert_eq!(
+            Staged::new(Path::new("/dev/null")).path(),
+            Path::new("/dev/null")
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
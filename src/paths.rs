@@ -0,0 +1,187 @@
+// Paths as Windows wants them. The rules are plain functions on text so
+// every platform tests them; only long_path differs per platform.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+// Longest path Windows takes without the extended-length prefix. Creating a
+// directory leaves room for an 8.3 file name, hence the 12.
+#[cfg(windows)]
+const MAX_PATH: usize = 260 - 12;
+
+// Device names, which Windows opens instead of a file of that name, with
+// any extension: CON.svg is the console
+const RESERVED: [&str; 30] = [
+    "CON", "PRN", "AUX", "NUL", "COM0", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7",
+    "COM8", "COM9", "COM¹", "COM²", "COM³", "LPT0", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6",
+    "LPT7", "LPT8", "LPT9", "LPT¹", "LPT²", "LPT³",
+];
+
+// Whether Windows reserves a file name for a device. What counts is the
+// part before the first dot, without trailing spaces, in any case.
+pub fn is_reserved_name(name: &str) -> bool {
+    let device = name.split('.').next().unwrap_or(name).trim_end_matches(' ');
+    RESERVED
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(device))
+}
+
+// A file name Windows can create: reserved names get a `_` after the
+// device part, CON.png becomes CON_.png. None when the name is fine.
+pub fn portable_name(name: &str) -> Option<String> {
+    if !is_reserved_name(name) {
+        return None;
+    }
+    let at = name.find('.').unwrap_or(name.len());
+    Some(format!("{}_{}", &name[..at], &name[at..]))
+}
+
+// Whether a path given on the command line, `-`, stands for stdin or stdout
+pub fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+// The extended-length form of an absolute Windows path, `\\?\C:\...` or
+// `\\?\UNC\server\share\...` for shares. Such paths are passed on as they
+// are, so separators are made backslashes and `.` and `..` resolved here;
+// `..` never climbs above the drive or share. None for relative paths.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn verbatim(path: &str) -> Option<String> {
+    if ["\\\\?\\", "\\\\.\\", "\\??\\"]
+        .iter()
+        .any(|prefix| path.starts_with(prefix))
+    {
+        return Some(path.to_string());
+    }
+    let is_separator = |c: char| c == '\\' || c == '/';
+    let (mut verbatim, rest, root_parts) =
+        if path.starts_with(is_separator) && path[1..].starts_with(is_separator) {
+            // \\server\share\... keeps server and share as its root
+            (String::from("\\\\?\\UNC"), &path[2..], 2)
+        } else {
+            let bytes = path.as_bytes();
+            if bytes.len() < 3 || !bytes[0].is_ascii_alphabetic() || bytes[1] != b':' {
+                return None;
+            }
+            if !is_separator(bytes[2] as char) {
+                // C:file is relative to the drive's current directory
+                return None;
+            }
+            (format!("\\\\?\\{}", &path[..2]), &path[2..], 0)
+        };
+    let mut parts: Vec<&str> = Vec::new();
+    for part in rest.split(is_separator) {
+        match part {
+            "" | "." => {}
+            ".." if parts.len() > root_parts => {
+                parts.pop();
+            }
+            ".." => {}
+            part => parts.push(part),
+        }
+    }
+    if parts.len() < root_parts {
+        return None;
+    }
+    for part in &parts {
+        verbatim.push('\\');
+        verbatim.push_str(part);
+    }
+    if parts.is_empty() {
+        verbatim.push('\\');
+    }
+    Some(verbatim)
+}
+
+// `path` as it should be opened: on Windows in its extended-length form
+// when it is too long for the usual APIs or names a device. Elsewhere, and
+// for paths that are fine, unchanged, so messages keep showing the path
+// as given.
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    let reserved = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(is_reserved_name);
+    if path.as_os_str().len() < MAX_PATH && !reserved {
+        return Cow::Borrowed(path);
+    }
+    let Ok(absolute) = std::path::absolute(path) else {
+        return Cow::Borrowed(path);
+    };
+    match absolute.to_str().and_then(verbatim) {
+        Some(verbatim) => Cow::Owned(verbatim.into()),
+        None => Cow::Borrowed(path),
+    }
+}
+
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+#[test]
+fn test_reserved_names() {
+    for name in [
+        "CON",
+        "con.svg",
+        "Nul.tar.gz",
+        "AUX .png",
+        "com1.svg",
+        "LPT9",
+        "COM²",
+    ] {
+        assert!(is_reserved_name(name), "{name}");
+    }
+    for name in ["CONSOLE.svg", "con_.svg", "COM10", "xNUL", ".con", "LPT"] {
+        assert!(!is_reserved_name(name), "{name}");
+    }
+    assert_eq!(portable_name("CON.png").as_deref(), Some("CON_.png"));
+    assert_eq!(
+        portable_name("nul.svg.png").as_deref(),
+        Some("nul_.svg.png")
+    );
+    assert_eq!(portable_name("prn").as_deref(), Some("prn_"));
+    assert_eq!(portable_name("0001-con.png"), None);
+}
+
+#[test]
+fn test_verbatim_paths() {
+    assert_eq!(
+        verbatim(r"C:\slides\deck\a.svg").as_deref(),
+        Some(r"\\?\C:\slides\deck\a.svg")
+    );
+    assert_eq!(
+        verbatim("d:/slides//./old/../a.svg").as_deref(),
+        Some(r"\\?\d:\slides\a.svg")
+    );
+    assert_eq!(verbatim(r"C:\..\..").as_deref(), Some(r"\\?\C:\"));
+    // Shares keep their server and share
+    assert_eq!(
+        verbatim(r"\\server\share\in\a.svg").as_deref(),
+        Some(r"\\?\UNC\server\share\in\a.svg")
+    );
+    assert_eq!(
+        verbatim("//server/share/in/../../out").as_deref(),
+        Some(r"\\?\UNC\server\share\out")
+    );
+    assert_eq!(verbatim(r"\\server"), None);
+    // Already verbatim or a device path
+    for path in [r"\\?\C:\a", r"\\?\UNC\server\share", r"\\.\pipe\x"] {
+        assert_eq!(verbatim(path).as_deref(), Some(path));
+    }
+    for path in ["slides/a.svg", r"C:a.svg", r"\slides", ""] {
+        assert_eq!(verbatim(path), None, "{path}");
+    }
+
+    // Short paths are opened as given
+    let long = "x".repeat(300);
+    assert_eq!(long_path(Path::new("in/a.svg")), Path::new("in/a.svg"));
+    #[cfg(not(windows))]
+    assert_eq!(long_path(Path::new(&long)), Path::new(&long));
+    #[cfg(windows)]
+    assert!(long_path(Path::new(&long))
+        .to_str()
+        .unwrap()
+        .starts_with(r"\\?\"));
+}
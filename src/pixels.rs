@@ -0,0 +1,293 @@
+// Conversions over the pixels of whole pages. The scalar loops are the
+// reference; vector versions are picked at runtime where the CPU has them
+// and must give the same bytes.
+
+// How pixels are converted on this CPU
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Path {
+    Scalar,
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+    #[cfg(target_arch = "aarch64")]
+    Neon,
+}
+
+impl Path {
+    // The fastest path this CPU supports
+    pub fn detect() -> Path {
+        #[cfg(target_arch = "x86_64")]
+        if std::arch::is_x86_feature_detected!("avx2") {
+            return Path::Avx2;
+        }
+        #[cfg(target_arch = "aarch64")]
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            return Path::Neon;
+        }
+        Path::Scalar
+    }
+
+    // Every path this CPU supports, scalar first
+    #[cfg(test)]
+    fn available() -> Vec<Path> {
+        let mut paths = vec![Path::Scalar];
+        if Path::detect() != Path::Scalar {
+            paths.push(Path::detect());
+        }
+        paths
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Path::Scalar => "scalar",
+            #[cfg(target_arch = "x86_64")]
+            Path::Avx2 => "avx2",
+            #[cfg(target_arch = "aarch64")]
+            Path::Neon => "neon",
+        }
+    }
+}
+
+// RGB bytes of premultiplied RGBA pixels composited over white
+pub fn flatten_rgb(rgba: &[u8]) -> Vec<u8> {
+    flatten_rgb_with(Path::detect(), rgba)
+}
+
+// flatten_rgb on a given path; paths the CPU lacks fall back to scalar
+fn flatten_rgb_with(path: Path, rgba: &[u8]) -> Vec<u8> {
+    let mut rgb = vec![0; rgba.len() / 4 * 3];
+    // The vector loops do whole blocks of pixels and leave the rest
+    let done = match path {
+        Path::Scalar => 0,
+        // SAFETY: AVX2 was detected
+        #[cfg(target_arch = "x86_64")]
+        Path::Avx2 if std::arch::is_x86_feature_detected!("avx2") => unsafe {
+            avx2::flatten_rgb(rgba, &mut rgb)
+        },
+        // SAFETY: NEON was detected
+        #[cfg(target_arch = "aarch64")]
+        Path::Neon if std::arch::is_aarch64_feature_detected!("neon") => unsafe {
+            neon::flatten_rgb(rgba, &mut rgb)
+        },
+        #[allow(unreachable_patterns)]
+        _ => 0,
+    };
+    flatten_rgb_scalar(&rgba[done * 4..], &mut rgb[done * 3..]);
+    rgb
+}
+
+fn flatten_rgb_scalar(rgba: &[u8], rgb: &mut [u8]) {
+    for (out, pixel) in rgb.chunks_exact_mut(3).zip(rgba.chunks_exact(4)) {
+        // Premultiplied, so white shows through by 255 - alpha
+        let white = 255 - pixel[3];
+        out[0] = pixel[0] + white;
+        out[1] = pixel[1] + white;
+        out[2] = pixel[2] + white;
+    }
+}
+
+// RGB bytes of premultiplied RGBA pixels composited over `color`. White,
+// the common case, takes the fast paths of flatten_rgb.
+pub fn flatten_rgb_over(rgba: &[u8], color: [u8; 3]) -> Vec<u8> {
+    if color == [255; 3] {
+        return flatten_rgb(rgba);
+    }
+    let mut rgb = vec![0; rgba.len() / 4 * 3];
+    for (out, pixel) in rgb.chunks_exact_mut(3).zip(rgba.chunks_exact(4)) {
+        // The background shows through by 255 - alpha; a premultiplied
+        // channel is at most alpha, so the sum stays within a byte
+        let through = 255 - pixel[3] as u16;
+        for channel in 0..3 {
+            let background = (color[channel] as u16 * through + 127) / 255;
+            out[channel] = pixel[channel] + background as u8;
+        }
+    }
+    rgb
+}
+
+// Straight RGB bytes and alpha of premultiplied RGBA pixels, for images
+// with a soft mask. Colors are divided by their alpha again, so they don't
+// come out darker where the mask makes them transparent; fully transparent
+// pixels are black.
+pub fn split_alpha(rgba: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let pixels = rgba.len() / 4;
+    let (mut rgb, mut alpha) = (Vec::with_capacity(pixels * 3), Vec::with_capacity(pixels));
+    for pixel in rgba.chunks_exact(4) {
+        let a = pixel[3] as u16;
+        for &channel in &pixel[..3] {
+            rgb.push(match a {
+                0 => 0,
+                255 => channel,
+                _ => ((channel as u16 * 255 + a / 2) / a).min(255) as u8,
+            });
+        }
+        alpha.push(pixel[3]);
+    }
+    (rgb, alpha)
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    // Flatten 8 pixels at a time and return how many were done. `rgb`
+    // holds 3 bytes for every 4 of `rgba`.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn flatten_rgb(rgba: &[u8], rgb: &mut [u8]) -> usize {
+        let pixels = rgba.len() / 32 * 8;
+        debug_assert!(rgb.len() >= pixels * 3);
+        // Shuffles work within each 16-byte lane of four pixels: copy the
+        // alpha into every byte of its pixel, then pack the RGB bytes into
+        // the lane's first 12
+        #[rustfmt::skip]
+        let alpha = _mm256_setr_epi8(
+            3, 3, 3, 3, 7, 7, 7, 7, 11, 11, 11, 11, 15, 15, 15, 15,
+            3, 3, 3, 3, 7, 7, 7, 7, 11, 11, 11, 11, 15, 15, 15, 15,
+        );
+        #[rustfmt::skip]
+        let pack = _mm256_setr_epi8(
+            0, 1, 2, 4, 5, 6, 8, 9, 10, 12, 13, 14, -1, -1, -1, -1,
+            0, 1, 2, 4, 5, 6, 8, 9, 10, 12, 13, 14, -1, -1, -1, -1,
+        );
+        // Moves both lanes' 12 bytes next to each other
+        let lanes = _mm256_setr_epi32(0, 1, 2, 4, 5, 6, 3, 7);
+        let ones = _mm256_set1_epi8(-1);
+        for i in (0..pixels).step_by(8) {
+            let src = _mm256_loadu_si256(rgba.as_ptr().add(i * 4).cast());
+            // 255 - alpha is !alpha
+            let white = _mm256_xor_si256(_mm256_shuffle_epi8(src, alpha), ones);
+            let sum = _mm256_add_epi8(src, white);
+            let packed = _mm256_permutevar8x32_epi32(_mm256_shuffle_epi8(sum, pack), lanes);
+            let out = rgb.as_mut_ptr().add(i * 3);
+            _mm_storeu_si128(out.cast(), _mm256_castsi256_si128(packed));
+            _mm_storel_epi64(out.add(16).cast(), _mm256_extracti128_si256::<1>(packed));
+        }
+        pixels
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    // Flatten 16 pixels at a time and return how many were done. `rgb`
+    // holds 3 bytes for every 4 of `rgba`.
+    #[target_feature(enable = "neon")]
+    pub unsafe fn flatten_rgb(rgba: &[u8], rgb: &mut [u8]) -> usize {
+        let pixels = rgba.len() / 64 * 16;
+        debug_assert!(rgb.len() >= pixels * 3);
+        for i in (0..pixels).step_by(16) {
+            // Loads split the channels apart and stores interleave them again
+            let src = vld4q_u8(rgba.as_ptr().add(i * 4));
+            let white = vmvnq_u8(src.3);
+            let out = uint8x16x3_t(
+                vaddq_u8(src.0, white),
+                vaddq_u8(src.1, white),
+                vaddq_u8(src.2, white),
+            );
+            vst3q_u8(rgb.as_mut_ptr().add(i * 3), out);
+        }
+        pixels
+    }
+}
+
+// The conversion as it was written before flatten_rgb, kept as a reference
+#[cfg(test)]
+fn flatten_rgb_reference(rgba: &[u8]) -> Vec<u8> {
+    rgba.chunks(4)
+        .flat_map(|chunk| {
+            let white = 255 - chunk[3];
+            [chunk[0] + white, chunk[1] + white, chunk[2] + white]
+        })
+        .collect()
+}
+
+// Premultiplied test pixels covering every alpha with varied colors
+#[cfg(test)]
+fn reference_pixels(count: usize) -> Vec<u8> {
+    (0..count)
+        .flat_map(|i| {
+            let alpha = (i % 256) as u8;
+            let channel = |seed: usize| ((i * seed / 7) % (alpha as usize + 1)) as u8;
+            [channel(3), channel(5), channel(11), alpha]
+        })
+        .collect()
+}
+
+#[test]
+fn test_flatten_matches_reference() {
+    let rgba = reference_pixels(960 * 720);
+    let reference = flatten_rgb_reference(&rgba);
+    for path in Path::available() {
+        assert_eq!(flatten_rgb_with(path, &rgba), reference, "{}", path.name());
+        // Lengths that leave a tail for the scalar loop
+        for pixels in [0, 1, 7, 8, 15, 17, 33, 100] {
+            assert_eq!(
+                flatten_rgb_with(path, &rgba[..pixels * 4]),
+                reference[..pixels * 3],
+                "{} with {pixels} pixels",
+                path.name()
+            );
+        }
+    }
+    assert_eq!(
+        flatten_rgb(&[0, 0, 0, 0, 10, 20, 30, 255]),
+        [255, 255, 255, 10, 20, 30]
+    );
+    assert!(flatten_rgb(&[]).is_empty());
+}
+
+#[test]
+fn test_backgrounds() {
+    // Transparent, opaque and half covered red, premultiplied
+    let rgba = [0, 0, 0, 0, 10, 20, 30, 255, 128, 0, 0, 128];
+    assert_eq!(
+        flatten_rgb_over(&rgba, [0, 0, 255]),
+        [0, 0, 255, 10, 20, 30, 128, 0, 127]
+    );
+    assert_eq!(flatten_rgb_over(&rgba, [255; 3]), flatten_rgb(&rgba));
+    let (rgb, alpha) = split_alpha(&rgba);
+    assert_eq!(rgb, [0, 0, 0, 10, 20, 30, 255, 0, 0]);
+    assert_eq!(alpha, [0, 255, 128]);
+    // Never past a byte, over every alpha
+    let rgba = reference_pixels(4096);
+    let (rgb, _) = split_alpha(&rgba);
+    assert_eq!(rgb.len(), 4096 * 3);
+    for (pixel, color) in rgba.chunks_exact(4).zip(rgb.chunks_exact(3)) {
+        let alpha = pixel[3] as u32;
+        for channel in 0..3 {
+            // Premultiplying again gives the pixel back, give or take rounding
+            let again = (color[channel] as u32 * alpha + 127) / 255;
+            assert!(again.abs_diff(pixel[channel] as u32) <= 1, "{pixel:?}");
+        }
+    }
+}
+
+// cargo test --release bench_flatten -- --ignored --nocapture
+#[test]
+#[ignore]
+fn bench_flatten() {
+    use std::time::{Duration, Instant};
+
+    let rgba = reference_pixels(1920 * 1080);
+    let time = |convert: &dyn Fn(&[u8]) -> Vec<u8>| {
+        let start = Instant::now();
+        for _ in 0..20 {
+            std::hint::black_box(convert(std::hint::black_box(&rgba)));
+        }
+        start.elapsed() / 20
+    };
+    let reference = time(&flatten_rgb_reference);
+    println!("1080p page: flat_map {reference:?}");
+    let mut scalar = Duration::ZERO;
+    for path in Path::available() {
+        let elapsed = time(&|rgba| flatten_rgb_with(path, rgba));
+        if path == Path::Scalar {
+            scalar = elapsed;
+        }
+        println!(
+            "1080p page: {} {elapsed:?}, {:.1}x scalar",
+            path.name(),
+            scalar.as_secs_f64() / elapsed.as_secs_f64()
+        );
+    }
+}
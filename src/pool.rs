@@ -0,0 +1,92 @@
+use resvg::tiny_skia::{Color, Pixmap};
+use std::cell::RefCell;
+
+thread_local! {
+    // Pixmap of the last page rendered on this thread, kept for the next one
+    static PIXMAP: RefCell<Option<Pixmap>> = const { RefCell::new(None) };
+}
+
+// Run `f` on a transparent pixmap of the given size. Each worker thread
+// keeps its last pixmap, so runs of same-sized pages clear and reuse one
+// buffer instead of allocating a new one per file. Returns None if the
+// size is invalid.
+pub fn with_pixmap<T>(width: u32, height: u32, f: impl FnOnce(&mut Pixmap) -> T) -> Option<T> {
+    let cached = PIXMAP.with(|slot| slot.borrow_mut().take());
+    let mut pixmap = match cached {
+        Some(mut pixmap) if pixmap.width() == width && pixmap.height() == height => {
+            pixmap.fill(Color::TRANSPARENT);
+            pixmap
+        }
+        // A different size: let the old buffer go and allocate
+        _ => Pixmap::new(width, height)?,
+    };
+
+    // Taken out of the slot while in use, so a nested call can't see it
+    let result = f(&mut pixmap);
+    PIXMAP.with(|slot| *slot.borrow_mut() = Some(pixmap));
+    Some(result)
+}
+
+#[cfg(test)]
+mod counting {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    thread_local! {
+        pub static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    // Counts the bytes each thread allocates, so tests can measure their own
+    pub struct CountingAllocator;
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() + layout.size()));
+            System.alloc(layout)
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() + layout.size()));
+            System.alloc_zeroed(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+}
+
+#[test]
+fn test_pixmap_reuse_avoids_allocations() {
+    let allocated = || counting::ALLOCATED.with(|allocated| allocated.get());
+    let page_bytes = 960 * 720 * 4;
+
+    // Fresh pixmaps cost a full page each
+    let before = allocated();
+    for _ in 0..20 {
+        let pixmap = Pixmap::new(960, 720).unwrap();
+        std::hint::black_box(&pixmap);
+    }
+    let fresh = allocated() - before;
+    assert!(fresh >= 20 * page_bytes);
+
+    // Reusing pays for the first page only, and hands out cleared buffers
+    let before = allocated();
+    for _ in 0..20 {
+        with_pixmap(960, 720, |pixmap| {
+            assert!(pixmap.data().iter().all(|&byte| byte == 0));
+            pixmap.fill(Color::WHITE);
+        })
+        .unwrap();
+    }
+    let reused = allocated() - before;
+    assert!(reused < 2 * page_bytes, "{reused} bytes allocated");
+
+    // A different size falls back to allocating
+    let before = allocated();
+    with_pixmap(100, 100, |pixmap| assert_eq!(pixmap.width(), 100)).unwrap();
+    assert!(allocated() - before >= 100 * 100 * 4);
+}
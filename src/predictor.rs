@@ -0,0 +1,128 @@
+use anyhow::{bail, Result};
+
+// PNG row filters, as used by PDF's Flate predictor 15 on 8-bit RGB
+const BYTES_PER_PIXEL: usize = 3;
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let distance = |value: u8| (estimate - value as i16).abs();
+    if distance(left) <= distance(up) && distance(left) <= distance(up_left) {
+        left
+    } else if distance(up) <= distance(up_left) {
+        up
+    } else {
+        up_left
+    }
+}
+
+// Filter every row with the PNG filter that leaves the smallest residuals,
+// each row prefixed with its filter type
+pub fn filter_rows(rgb: &[u8], width: u32) -> Vec<u8> {
+    let stride = width as usize * BYTES_PER_PIXEL;
+    if stride == 0 {
+        return Vec::new();
+    }
+    let zero = vec![0; stride];
+    let mut out = Vec::with_capacity(rgb.len() + rgb.len() / stride);
+    let mut candidate = vec![0; stride];
+    let mut best = vec![0; stride];
+    for (index, row) in rgb.chunks_exact(stride).enumerate() {
+        let prior = match index {
+            0 => &zero[..],
+            _ => &rgb[(index - 1) * stride..index * stride],
+        };
+        let mut best_filter = 0;
+        let mut best_cost = u64::MAX;
+        for filter in 0..5u8 {
+            for i in 0..stride {
+                let left = if i >= BYTES_PER_PIXEL {
+                    row[i - BYTES_PER_PIXEL]
+                } else {
+                    0
+                };
+                let up_left = if i >= BYTES_PER_PIXEL {
+                    prior[i - BYTES_PER_PIXEL]
+                } else {
+                    0
+                };
+                let predicted = match filter {
+                    0 => 0,
+                    1 => left,
+                    2 => prior[i],
+                    3 => ((left as u16 + prior[i] as u16) / 2) as u8,
+                    _ => paeth(left, prior[i], up_left),
+                };
+                candidate[i] = row[i].wrapping_sub(predicted);
+            }
+            // Residuals closest to zero compress best
+            let cost = candidate
+                .iter()
+                .map(|&byte| (byte as i8).unsigned_abs() as u64)
+                .sum();
+            if cost < best_cost {
+                best_cost = cost;
+                best_filter = filter;
+                std::mem::swap(&mut best, &mut candidate);
+            }
+        }
+        out.push(best_filter);
+        out.extend_from_slice(&best);
+    }
+    out
+}
+
+// Undo filter_rows
+pub fn unfilter_rows(data: &[u8], width: u32) -> Result<Vec<u8>> {
+    let stride = width as usize * BYTES_PER_PIXEL;
+    if !data.len().is_multiple_of(stride + 1) {
+        bail!("Predicted image data is not a whole number of rows");
+    }
+    let mut rgb: Vec<u8> = Vec::with_capacity(data.len() / (stride + 1) * stride);
+    for row in data.chunks_exact(stride + 1) {
+        let start = rgb.len();
+        for i in 0..stride {
+            let left = if i >= BYTES_PER_PIXEL {
+                rgb[start + i - BYTES_PER_PIXEL]
+            } else {
+                0
+            };
+            let up = if start > 0 {
+                rgb[start - stride + i]
+            } else {
+                0
+            };
+            let up_left = if start > 0 && i >= BYTES_PER_PIXEL {
+                rgb[start - stride + i - BYTES_PER_PIXEL]
+            } else {
+                0
+            };
+            let predicted = match row[0] {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                filter => bail!("Unknown PNG filter type {}", filter),
+            };
+            rgb.push(row[i + 1].wrapping_add(predicted));
+        }
+    }
+    Ok(rgb)
+}
+
+#[test]
+fn test_filters_round_trip() {
+    let width = 7;
+    let rgb: Vec<u8> = (0..width * 5 * 3)
+        .map(|i| (i * 37 % 251) as u8 ^ (i / 21) as u8)
+        .collect();
+    let filtered = filter_rows(&rgb, width as u32);
+    assert_eq!(filtered.len(), rgb.len() + 5);
+    assert_eq!(unfilter_rows(&filtered, width as u32).unwrap(), rgb);
+
+    // Below the first row, a flat image is all zeros apart from the filter types
+    let flat = vec![200; 4 * 4 * 3];
+    let filtered = filter_rows(&flat, 4);
+    assert_eq!(filtered[13..].iter().filter(|&&byte| byte != 0).count(), 3);
+    assert!(unfilter_rows(&filtered[1..], 4).is_err());
+}
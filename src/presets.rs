@@ -0,0 +1,169 @@
+// --print-preset: the print settings of a kind of job in one flag. A preset
+// only stands in for flags, whatever the command line gives wins. Print
+// shops would also want CMYK, which svg2pdf doesn't write.
+
+use crate::convert::{Orientation, Quality};
+use crate::print::Duplex;
+use crate::writer::ImageFormat;
+use clap::ValueEnum;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrintPreset {
+    // Artwork for a print shop, on pages the size of the drawing
+    Offset,
+    // Drawings fitted onto sheets for a desk printer
+    Office,
+}
+
+// The options a preset stands for, None where it leaves them alone
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PresetOptions {
+    pub dpi: f32,
+    pub quality: Quality,
+    pub image_format: ImageFormat,
+    // As --paper takes it
+    pub paper: Option<&'static str>,
+    pub orientation: Option<Orientation>,
+    // Millimeters
+    pub margin: Option<f32>,
+    // Millimeters
+    pub bleed: Option<f32>,
+    pub crop_marks: bool,
+    pub no_print_scaling: bool,
+    pub duplex: Option<Duplex>,
+}
+
+impl PrintPreset {
+    pub fn options(self) -> PresetOptions {
+        match self {
+            // Fine and lossless, as plates are made from it, with the 3mm
+            // of bleed and the crop marks the shop trims the sheets by
+            PrintPreset::Offset => PresetOptions {
+                dpi: 300.0,
+                quality: Quality::Best,
+                image_format: ImageFormat::Flate,
+                paper: None,
+                orientation: None,
+                margin: None,
+                bleed: Some(3.0),
+                crop_marks: true,
+                no_print_scaling: true,
+                duplex: None,
+            },
+            // Enough for office printers, JPEG where it is smaller,
+            // clear of the edge they can't print to, and on both sides
+            PrintPreset::Office => PresetOptions {
+                dpi: 150.0,
+                quality: Quality::Normal,
+                image_format: ImageFormat::Auto,
+                paper: Some("a4"),
+                orientation: Some(Orientation::Auto),
+                margin: Some(10.0),
+                bleed: None,
+                crop_marks: false,
+                no_print_scaling: true,
+                duplex: Some(Duplex::LongEdge),
+            },
+        }
+    }
+
+    pub fn about(self) -> &'static str {
+        match self {
+            PrintPreset::Offset => "artwork for a print shop, on pages the size of the drawing",
+            PrintPreset::Office => "drawings fitted onto A4 sheets for a desk printer",
+        }
+    }
+
+    // The flags the preset stands for
+    pub fn flags(self) -> Vec<String> {
+        let options = self.options();
+        let mut flags = vec![
+            format!("--dpi {}", options.dpi),
+            format!("--quality {}", name(options.quality)),
+            format!("--image-format {}", name(options.image_format)),
+        ];
+        if let Some(paper) = options.paper {
+            flags.push(format!("--paper {paper}"));
+        }
+        if let Some(orientation) = options.orientation {
+            flags.push(format!("--orientation {}", name(orientation)));
+        }
+        if let Some(margin) = options.margin {
+            flags.push(format!("--margin {margin}"));
+        }
+        if let Some(bleed) = options.bleed {
+            flags.push(format!("--bleed {bleed}"));
+        }
+        if options.crop_marks {
+            flags.push("--crop-marks".to_string());
+        }
+        if options.no_print_scaling {
+            flags.push("--no-print-scaling".to_string());
+        }
+        if let Some(duplex) = options.duplex {
+            flags.push(format!("--duplex {}", name(duplex)));
+        }
+        flags
+    }
+}
+
+// The name a flag takes `value` by
+fn name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map_or(String::new(), |value| value.get_name().to_string())
+}
+
+// What --list-print-presets prints
+pub fn list() -> String {
+    PrintPreset::value_variants()
+        .iter()
+        .map(|&preset| {
+            format!(
+                "{:<8}{}\n{:<8}{}\n",
+                name(preset),
+                preset.about(),
+                "",
+                preset.flags().join(" ")
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_presets_expand_to_flags() {
+    assert_eq!(
+        PrintPreset::Offset.flags(),
+        [
+            "--dpi 300",
+            "--quality best",
+            "--image-format flate",
+            "--bleed 3",
+            "--crop-marks",
+            "--no-print-scaling"
+        ]
+    );
+    assert_eq!(
+        PrintPreset::Office.flags(),
+        [
+            "--dpi 150",
+            "--quality normal",
+            "--image-format auto",
+            "--paper a4",
+            "--orientation auto",
+            "--margin 10",
+            "--no-print-scaling",
+            "--duplex long-edge"
+        ]
+    );
+    let list = list();
+    assert!(
+        list.starts_with("offset  artwork for a print shop"),
+        "{list}"
+    );
+    assert!(
+        list.contains("\n        --dpi 150 --quality normal"),
+        "{list}"
+    );
+    assert_eq!(list.lines().count(), 4);
+}
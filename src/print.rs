@@ -0,0 +1,173 @@
+// What a PDF tells the print shop and the printer: the TrimBox and BleedBox
+// of pages with --bleed, --crop-marks around them, and the viewer
+// preferences of --no-print-scaling and --duplex.
+
+use crate::convert::MM;
+use clap::ValueEnum;
+use lopdf::content::Operation;
+use lopdf::{Dictionary, Object};
+
+// Points of crop marks, and of the lines they are drawn with
+const MARK_LENGTH: f32 = 5.0 * MM;
+const MARK_WIDTH: f32 = 0.25;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Duplex {
+    Simplex,
+    // Pages turned like those of a book
+    LongEdge,
+    // Pages turned like those of a notepad
+    ShortEdge,
+}
+
+impl Duplex {
+    fn name(self) -> &'static [u8] {
+        match self {
+            Duplex::Simplex => b"Simplex",
+            Duplex::LongEdge => b"DuplexFlipLongEdge",
+            Duplex::ShortEdge => b"DuplexFlipShortEdge",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PrintSetup {
+    // Points the pages reach past their trim on each side, see
+    // RenderArgs::bleed
+    pub bleed: f32,
+    pub crop_marks: bool,
+    // Ask viewers to print at the actual size rather than fitted
+    pub no_print_scaling: bool,
+    pub duplex: Option<Duplex>,
+}
+
+impl PrintSetup {
+    // Whether pages get a TrimBox and BleedBox
+    pub fn trimmed(&self) -> bool {
+        self.bleed > 0.0 || self.crop_marks
+    }
+
+    // The boxes of a page of `width` by `height` points, bleed included:
+    // the bleed is the page as it was drawn, with the trim inside it and
+    // the crop marks, if any, outside it
+    pub fn boxes(&self, width: f32, height: f32) -> Vec<(&'static str, [f32; 4])> {
+        let page = [0.0, 0.0, width, height];
+        if !self.trimmed() {
+            return vec![("MediaBox", page)];
+        }
+        let room = if self.crop_marks { MARK_LENGTH } else { 0.0 };
+        let bleed = self.bleed;
+        vec![
+            ("MediaBox", [-room, -room, width + room, height + room]),
+            ("BleedBox", page),
+            ("TrimBox", [bleed, bleed, width - bleed, height - bleed]),
+        ]
+    }
+
+    // Operators drawing the crop marks of such a page: at every corner, a
+    // line on from each edge of the trim, from the bleed out to the edge of
+    // the page
+    pub fn crop_marks(&self, width: f32, height: f32) -> Vec<Operation> {
+        if !self.crop_marks {
+            return Vec::new();
+        }
+        let bleed = self.bleed;
+        let line = |from: [f32; 2], to: [f32; 2]| {
+            [
+                Operation::new("m", from.map(Object::Real).to_vec()),
+                Operation::new("l", to.map(Object::Real).to_vec()),
+            ]
+        };
+        let mut operations = vec![
+            Operation::new("q", vec![]),
+            Operation::new("G", vec![Object::Real(0.0)]),
+            Operation::new("w", vec![Object::Real(MARK_WIDTH)]),
+        ];
+        for x in [bleed, width - bleed] {
+            operations.extend(line([x, -MARK_LENGTH], [x, 0.0]));
+            operations.extend(line([x, height], [x, height + MARK_LENGTH]));
+        }
+        for y in [bleed, height - bleed] {
+            operations.extend(line([-MARK_LENGTH, y], [0.0, y]));
+            operations.extend(line([width, y], [width + MARK_LENGTH, y]));
+        }
+        operations.push(Operation::new("S", vec![]));
+        operations.push(Operation::new("Q", vec![]));
+        operations
+    }
+
+    // The ViewerPreferences of the catalog, if there are any
+    pub fn viewer_preferences(&self) -> Option<Dictionary> {
+        let mut preferences = Dictionary::new();
+        if self.no_print_scaling {
+            preferences.set("PrintScaling", Object::Name(b"None".to_vec()));
+        }
+        if let Some(duplex) = self.duplex {
+            preferences.set("Duplex", Object::Name(duplex.name().to_vec()));
+        }
+        (!preferences.is_empty()).then_some(preferences)
+    }
+}
+
+#[test]
+fn test_trimmed_page_boxes() {
+    let setup = PrintSetup {
+        bleed: 3.0 * MM,
+        crop_marks: true,
+        ..PrintSetup::default()
+    };
+    let boxes = setup.boxes(100.0, 50.0);
+    let bleed = 3.0 * MM;
+    assert_eq!(
+        boxes,
+        [
+            (
+                "MediaBox",
+                [
+                    -MARK_LENGTH,
+                    -MARK_LENGTH,
+                    100.0 + MARK_LENGTH,
+                    50.0 + MARK_LENGTH
+                ]
+            ),
+            ("BleedBox", [0.0, 0.0, 100.0, 50.0]),
+            ("TrimBox", [bleed, bleed, 100.0 - bleed, 50.0 - bleed])
+        ]
+    );
+    // Two lines at each corner, all of them off the bleed
+    let marks = setup.crop_marks(100.0, 50.0);
+    let ends: Vec<_> = marks
+        .iter()
+        .filter(|operation| ["m", "l"].contains(&operation.operator.as_str()))
+        .map(|operation| {
+            let number = |index: usize| operation.operands[index].as_float().unwrap();
+            (number(0), number(1))
+        })
+        .collect();
+    assert_eq!(ends.len(), 16);
+    assert!(ends
+        .iter()
+        .all(|&(x, y)| x <= 0.0 || x >= 100.0 || y <= 0.0 || y >= 50.0));
+
+    let plain = PrintSetup::default();
+    assert_eq!(
+        plain.boxes(100.0, 50.0),
+        [("MediaBox", [0.0, 0.0, 100.0, 50.0])]
+    );
+    assert!(plain.crop_marks(100.0, 50.0).is_empty());
+    assert!(plain.viewer_preferences().is_none());
+    let office = PrintSetup {
+        no_print_scaling: true,
+        duplex: Some(Duplex::LongEdge),
+        ..PrintSetup::default()
+    };
+    let preferences = office.viewer_preferences().unwrap();
+    assert_eq!(
+        preferences.get(b"PrintScaling").unwrap().as_name().unwrap(),
+        b"None"
+    );
+    assert_eq!(
+        preferences.get(b"Duplex").unwrap().as_name().unwrap(),
+        b"DuplexFlipLongEdge"
+    );
+}
@@ -0,0 +1,507 @@
+use crate::names;
+use clap::ValueEnum;
+use console::Term;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde_json::json;
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Something that happened during a conversion, reported from the workers
+pub enum Event<'a> {
+    Started {
+        total: usize,
+    },
+    FileStarted {
+        path: &'a Path,
+        worker: usize,
+    },
+    FileFinished {
+        path: &'a Path,
+        worker: usize,
+        cached: bool,
+        // Size of the page kept in memory until the output is written
+        bytes: usize,
+    },
+    FileFailed {
+        path: &'a Path,
+        worker: usize,
+        error: &'a anyhow::Error,
+    },
+    // A page of FileFinished went to the writer, which frees its `bytes`
+    PageWritten {
+        bytes: usize,
+    },
+    // All pages are rendered and the output is being assembled
+    Assembling,
+}
+
+// Receives the events of a run; called concurrently from the workers
+pub trait Progress: Sync {
+    fn event(&self, event: &Event);
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProgressMode {
+    // A bar on terminals, plain lines otherwise
+    #[default]
+    Auto,
+    Bar,
+    // One line per file without ANSI escapes, for CI logs
+    Plain,
+    // Live dashboard with per-worker status
+    Tui,
+    // One JSON object per line, for programs supervising the run
+    Json,
+    None,
+}
+
+impl ProgressMode {
+    pub fn reporter(self) -> Option<Box<dyn Progress>> {
+        let is_term = Term::stderr().is_term();
+        match self {
+            ProgressMode::Auto if is_term => Some(Box::new(BarProgress::new())),
+            ProgressMode::Auto | ProgressMode::Plain => Some(Box::new(PlainProgress::default())),
+            ProgressMode::Bar => Some(Box::new(BarProgress::new())),
+            // Without a terminal to redraw on, fall back to plain lines
+            ProgressMode::Tui if is_term => Some(Box::new(TuiProgress::new())),
+            ProgressMode::Tui => Some(Box::new(PlainProgress::default())),
+            ProgressMode::Json => Some(Box::new(JsonProgress::default())),
+            ProgressMode::None => None,
+        }
+    }
+}
+
+// Worker running the current rayon task, 0 outside the pool
+pub fn current_worker() -> usize {
+    rayon::current_thread_index().map_or(0, |index| index + 1)
+}
+
+fn file_name(path: &Path) -> String {
+    names::path_text(path.file_name().unwrap_or(path.as_os_str())).into_owned()
+}
+
+// The single indicatif bar
+pub struct BarProgress {
+    bar: ProgressBar,
+}
+
+impl BarProgress {
+    fn new() -> Self {
+        let bar = ProgressBar::new(0);
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} {msg}")
+                .unwrap(),
+        );
+        BarProgress { bar }
+    }
+}
+
+impl Progress for BarProgress {
+    fn event(&self, event: &Event) {
+        match event {
+            Event::Started { total } => self.bar.set_length(*total as u64),
+            Event::FileFinished { path, .. } => {
+                self.bar.inc(1);
+                self.bar
+                    .set_message(format!("Processed {:?}", file_name(path)));
+            }
+            Event::FileFailed { path, error, .. } => {
+                self.bar.inc(1);
+                self.bar
+                    .println(format!("Failed {:?}: {:#}", file_name(path), error));
+            }
+            Event::Assembling => self
+                .bar
+                .finish_with_message("Rendering complete. Writing output..."),
+            Event::FileStarted { .. } | Event::PageWritten { .. } => {}
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counts {
+    total: usize,
+    done: usize,
+}
+
+impl Counts {
+    fn percent(&self) -> usize {
+        (self.done * 100).checked_div(self.total).unwrap_or(100)
+    }
+}
+
+// One line per finished file, no escapes, for logs
+#[derive(Default)]
+pub struct PlainProgress {
+    counts: Mutex<Counts>,
+}
+
+impl PlainProgress {
+    fn line(&self, event: &Event) -> Option<String> {
+        let mut counts = self.counts.lock().unwrap();
+        match event {
+            Event::Started { total } => {
+                counts.total = *total;
+                Some(format!("Converting {} files", total))
+            }
+            Event::FileFinished { path, cached, .. } => {
+                counts.done += 1;
+                Some(format!(
+                    "[{:>3}%] {}/{} {}{}",
+                    counts.percent(),
+                    counts.done,
+                    counts.total,
+                    names::path_text(path.as_os_str()),
+                    if *cached { " (cached)" } else { "" }
+                ))
+            }
+            Event::FileFailed { path, error, .. } => {
+                counts.done += 1;
+                Some(format!(
+                    "[{:>3}%] failed {}: {:#}",
+                    counts.percent(),
+                    names::path_text(path.as_os_str()),
+                    error
+                ))
+            }
+            Event::Assembling => Some("[100%] rendering complete, writing output".to_string()),
+            Event::FileStarted { .. } | Event::PageWritten { .. } => None,
+        }
+    }
+}
+
+impl Progress for PlainProgress {
+    fn event(&self, event: &Event) {
+        if let Some(line) = self.line(event) {
+            eprintln!("{line}");
+        }
+    }
+}
+
+// A JSON object per line: started with the total, one per finished or
+// failed file, and assembling once everything is rendered
+#[derive(Default)]
+pub struct JsonProgress {
+    counts: Mutex<Counts>,
+}
+
+impl JsonProgress {
+    fn line(&self, event: &Event) -> Option<serde_json::Value> {
+        let mut counts = self.counts.lock().unwrap();
+        match event {
+            Event::Started { total } => {
+                counts.total = *total;
+                Some(json!({ "event": "started", "total": total }))
+            }
+            Event::FileFinished { path, cached, .. } => {
+                counts.done += 1;
+                Some(json!({
+                    "event": "file",
+                    "path": names::path_text(path.as_os_str()),
+                    "status": "ok",
+                    "cached": cached,
+                    "done": counts.done,
+                    "total": counts.total,
+                }))
+            }
+            Event::FileFailed { path, error, .. } => {
+                counts.done += 1;
+                Some(json!({
+                    "event": "file",
+                    "path": names::path_text(path.as_os_str()),
+                    "status": "failed",
+                    "error": format!("{:#}", error),
+                    "done": counts.done,
+                    "total": counts.total,
+                }))
+            }
+            Event::Assembling => Some(json!({ "event": "assembling" })),
+            Event::FileStarted { .. } | Event::PageWritten { .. } => None,
+        }
+    }
+}
+
+impl Progress for JsonProgress {
+    fn event(&self, event: &Event) {
+        if let Some(line) = self.line(event) {
+            eprintln!("{line}");
+        }
+    }
+}
+
+// How often the dashboard is redrawn at most
+const REDRAW: Duration = Duration::from_millis(100);
+
+// Failures kept on screen
+const RECENT_FAILURES: usize = 3;
+
+struct Dashboard {
+    counts: Counts,
+    cached: usize,
+    failed: usize,
+    recent_failures: VecDeque<String>,
+    // File each worker is on, by worker number
+    workers: BTreeMap<usize, String>,
+    buffered_bytes: u64,
+    largest_page: u64,
+    started: Instant,
+    last_draw: Option<Instant>,
+    lines_drawn: usize,
+}
+
+// Live multi-line status, redrawn in place
+pub struct TuiProgress {
+    term: Term,
+    state: Mutex<Dashboard>,
+}
+
+impl TuiProgress {
+    fn new() -> Self {
+        TuiProgress {
+            term: Term::stderr(),
+            state: Mutex::new(Dashboard::new()),
+        }
+    }
+}
+
+impl Dashboard {
+    fn new() -> Self {
+        Dashboard {
+            counts: Counts::default(),
+            cached: 0,
+            failed: 0,
+            recent_failures: VecDeque::new(),
+            workers: BTreeMap::new(),
+            buffered_bytes: 0,
+            largest_page: 0,
+            started: Instant::now(),
+            last_draw: None,
+            lines_drawn: 0,
+        }
+    }
+
+    // Take in `event`, true when it should be shown right away
+    fn update(&mut self, event: &Event) -> bool {
+        match event {
+            Event::Started { total } => {
+                self.counts.total = *total;
+                self.started = Instant::now();
+                return true;
+            }
+            Event::FileStarted { path, worker } => {
+                self.workers.insert(*worker, file_name(path));
+            }
+            Event::FileFinished {
+                worker,
+                cached,
+                bytes,
+                ..
+            } => {
+                self.workers.remove(worker);
+                self.counts.done += 1;
+                self.cached += *cached as usize;
+                self.buffered_bytes += *bytes as u64;
+                self.largest_page = self.largest_page.max(*bytes as u64);
+            }
+            Event::FileFailed {
+                path,
+                worker,
+                error,
+            } => {
+                self.workers.remove(worker);
+                self.counts.done += 1;
+                self.failed += 1;
+                self.recent_failures
+                    .push_back(format!("{}: {:#}", file_name(path), error));
+                if self.recent_failures.len() > RECENT_FAILURES {
+                    self.recent_failures.pop_front();
+                }
+                return true;
+            }
+            Event::PageWritten { bytes } => {
+                self.buffered_bytes = self.buffered_bytes.saturating_sub(*bytes as u64);
+            }
+            Event::Assembling => {
+                self.workers.clear();
+                return true;
+            }
+        }
+        false
+    }
+
+    // Finished pages wait in memory until they are written, plus a page
+    // being rendered per worker
+    fn memory(&self) -> u64 {
+        self.buffered_bytes + self.workers.len() as u64 * self.largest_page * 4 / 3
+    }
+
+    fn lines(&self, width: usize, height: usize) -> Vec<String> {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rendered = self.counts.done - self.cached;
+        let memory = self.memory();
+        let summary = format!(
+            "{}/{} ({}%)  {:.1} pages/s  {} cached  {} failed  ~{} MiB  {:.1}s",
+            self.counts.done,
+            self.counts.total,
+            self.counts.percent(),
+            if elapsed > 0.0 {
+                rendered as f64 / elapsed
+            } else {
+                0.0
+            },
+            self.cached,
+            self.failed,
+            memory / (1024 * 1024),
+            elapsed
+        );
+
+        let bar_width = width.saturating_sub(2).min(60);
+        let filled = bar_width * self.counts.percent() / 100;
+        let mut lines = vec![
+            format!("[{}{}]", "#".repeat(filled), "-".repeat(bar_width - filled)),
+            summary,
+        ];
+        for (worker, file) in &self.workers {
+            lines.push(format!("  worker {worker:>2}: {file}"));
+        }
+        for failure in &self.recent_failures {
+            lines.push(format!("  failed: {failure}"));
+        }
+
+        // Too small for the full dashboard: keep just the summary
+        if lines.len() > height || width < 40 {
+            lines = vec![lines.swap_remove(1)];
+        }
+        lines
+            .into_iter()
+            .map(|line| console::truncate_str(&line, width, "…").into_owned())
+            .collect()
+    }
+}
+
+impl TuiProgress {
+    fn draw(&self, state: &mut Dashboard) {
+        // Assume a classic terminal when the size can't be queried
+        let (height, width) = self.term.size_checked().unwrap_or((24, 80));
+        let width = width.max(2);
+        let lines = state.lines(width as usize, height.saturating_sub(1) as usize);
+        let _ = self.term.clear_last_lines(state.lines_drawn);
+        for line in &lines {
+            let _ = self.term.write_line(line);
+        }
+        state.lines_drawn = lines.len();
+        state.last_draw = Some(Instant::now());
+    }
+}
+
+impl Progress for TuiProgress {
+    fn event(&self, event: &Event) {
+        let mut state = self.state.lock().unwrap();
+        let urgent = state.update(event);
+        if urgent || state.last_draw.is_none_or(|last| last.elapsed() >= REDRAW) {
+            self.draw(&mut state);
+        }
+    }
+}
+
+#[test]
+fn test_plain_progress_lines() {
+    let progress = PlainProgress::default();
+    let path = Path::new("in/a.svg");
+    assert_eq!(
+        progress.line(&Event::Started { total: 4 }).unwrap(),
+        "Converting 4 files"
+    );
+    assert!(progress
+        .line(&Event::FileStarted { path, worker: 1 })
+        .is_none());
+    let line = progress
+        .line(&Event::FileFinished {
+            path,
+            worker: 1,
+            cached: true,
+            bytes: 0,
+        })
+        .unwrap();
+    assert_eq!(line, "[ 25%] 1/4 in/a.svg (cached)");
+    assert!(!line.contains('\x1b'));
+
+    // Failed files count as done, so runs that go on still reach 100%
+    let error = anyhow::anyhow!("bad");
+    let line = progress.line(&Event::FileFailed {
+        path: Path::new("in/b.svg"),
+        worker: 2,
+        error: &error,
+    });
+    assert_eq!(line.unwrap(), "[ 50%] failed in/b.svg: bad");
+    let line = progress.line(&Event::FileFinished {
+        path,
+        worker: 1,
+        cached: false,
+        bytes: 0,
+    });
+    assert_eq!(line.unwrap(), "[ 75%] 3/4 in/a.svg");
+}
+
+#[test]
+fn test_json_progress_lines() {
+    let progress = JsonProgress::default();
+    let path = Path::new("in/a \"quoted\".svg");
+    progress.line(&Event::Started { total: 2 });
+    let line = progress
+        .line(&Event::FileFinished {
+            path,
+            worker: 1,
+            cached: false,
+            bytes: 0,
+        })
+        .unwrap();
+    assert_eq!(
+        line.to_string(),
+        r#"{"cached":false,"done":1,"event":"file","path":"in/a \"quoted\".svg","status":"ok","total":2}"#
+    );
+    let error = anyhow::anyhow!("bad");
+    let line = progress
+        .line(&Event::FileFailed {
+            path,
+            worker: 2,
+            error: &error,
+        })
+        .unwrap();
+    assert_eq!(
+        (line["status"].as_str(), line["done"].as_u64()),
+        (Some("failed"), Some(2))
+    );
+    assert_eq!(line["error"], "bad");
+}
+
+#[test]
+fn test_written_pages_leave_the_memory_estimate() {
+    let mut state = Dashboard::new();
+    let path = Path::new("in/a.svg");
+    state.update(&Event::Started { total: 2 });
+    state.update(&Event::FileStarted { path, worker: 1 });
+    state.update(&Event::FileFinished {
+        path,
+        worker: 1,
+        cached: false,
+        bytes: 3 << 20,
+    });
+    assert_eq!(state.memory(), 3 << 20);
+    assert!(state.lines(80, 24)[1].contains("~3 MiB"));
+    // The next page counts as one being rendered, until it too is written
+    state.update(&Event::FileStarted { path, worker: 1 });
+    state.update(&Event::PageWritten { bytes: 3 << 20 });
+    assert_eq!(state.memory(), 4 << 20);
+    state.update(&Event::FileFinished {
+        path,
+        worker: 1,
+        cached: false,
+        bytes: 3 << 20,
+    });
+    state.update(&Event::PageWritten { bytes: 3 << 20 });
+    assert_eq!(state.memory(), 0);
+}
@@ -0,0 +1,211 @@
+use crate::convert::{Source, Sources};
+use crate::retry::RetryPolicy;
+use crate::timings::{Span, Stage};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Condvar, Mutex};
+use std::time::Instant;
+
+// A file read ahead of rendering by an IO thread
+pub struct Prefetched {
+    pub data: io::Result<Vec<u8>>,
+    pub read: Span,
+    // Files still waiting to be taken when this one was
+    pub queue_depth: usize,
+    // Times the read was tried again
+    pub retries: u32,
+}
+
+struct State<'a> {
+    sources: Sources<'a>,
+    // Read sources by position, their queue depth still to be set
+    ready: HashMap<usize, (Source, Prefetched)>,
+    // Sources taken by the IO threads so far
+    taken: usize,
+    // Number of sources, once the IO threads reached the end
+    end: Option<usize>,
+    // Sources handed out to render workers so far
+    claimed: usize,
+    stopped: bool,
+}
+
+// Multiple of the median file size from which a file is a long pole
+const LONG_POLE_FACTOR: u64 = 4;
+
+// Order to hand out files of `sizes` in, and how many long poles lead it:
+// files far larger than the median go first, largest first, so they don't
+// start last and leave the other workers idle at the end of the run. The
+// rest keep their input order, so most pages still finish in the order
+// they are written in.
+pub fn long_poles_first(sizes: &[u64]) -> (Vec<usize>, usize) {
+    let mut sorted = sizes.to_vec();
+    sorted.sort_unstable();
+    let Some(&median) = sorted.get(sorted.len() / 2) else {
+        return (Vec::new(), 0);
+    };
+    let threshold = median.saturating_mul(LONG_POLE_FACTOR).max(1);
+    let (mut order, rest): (Vec<usize>, Vec<usize>) =
+        (0..sizes.len()).partition(|&index| sizes[index] >= threshold);
+    order.sort_by_key(|&index| std::cmp::Reverse(sizes[index]));
+    let poles = order.len();
+    order.extend(rest);
+    (order, poles)
+}
+
+// Hands the sources to the render workers strictly in the order they come
+// from `Sources`, so the page written next is always being worked on.
+// Sources reordered by `long_poles_first` come with that `order`, which
+// maps them back to their pages; only the long poles are out of order.
+// Sources are only taken from `Sources` as they are needed, so a directory
+// scan stays just ahead of the pipeline. With read-ahead, IO threads
+// running `read_loop` read up to `capacity` files past the last one handed
+// out, and workers get the contents along with the source.
+pub struct Dispenser<'a> {
+    read_ahead: bool,
+    capacity: usize,
+    order: Option<Vec<usize>>,
+    state: Mutex<State<'a>>,
+    changed: Condvar,
+}
+
+impl<'a> Dispenser<'a> {
+    pub fn new(
+        sources: Sources<'a>,
+        read_ahead: bool,
+        capacity: usize,
+        order: Option<Vec<usize>>,
+    ) -> Self {
+        Dispenser {
+            read_ahead,
+            capacity,
+            order,
+            state: Mutex::new(State {
+                sources,
+                ready: HashMap::new(),
+                taken: 0,
+                end: None,
+                claimed: 0,
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    // Body of an IO thread; returns once every file was read
+    pub fn read_loop(&self, epoch: Instant, retry: &RetryPolicy) {
+        loop {
+            // Don't run too far ahead of the workers
+            let state = self.state.lock().unwrap();
+            let mut state = self
+                .changed
+                .wait_while(state, |state| {
+                    !state.stopped && state.taken >= state.claimed + self.capacity
+                })
+                .unwrap();
+            if state.stopped {
+                break;
+            }
+            let index = state.taken;
+            let Some(source) = state.sources.next() else {
+                state.end = Some(index);
+                drop(state);
+                self.changed.notify_all();
+                break;
+            };
+            state.taken += 1;
+            drop(state);
+
+            let started = Instant::now();
+            let (data, retries) = retry.run(|| source.read().map(Cow::into_owned));
+            let prefetched = Prefetched {
+                data,
+                read: Span {
+                    stage: Stage::Read,
+                    start: started.duration_since(epoch),
+                    duration: started.elapsed(),
+                },
+                queue_depth: 0,
+                retries,
+            };
+            self.state
+                .lock()
+                .unwrap()
+                .ready
+                .insert(index, (source, prefetched));
+            self.changed.notify_all();
+        }
+    }
+
+    // Page index of the source handed out at `position`
+    fn page(&self, position: usize) -> usize {
+        self.order
+            .as_ref()
+            .map_or(position, |order| order[position])
+    }
+
+    // The next source to render with its page index, and its contents when
+    // reading ahead. None once all were handed out or after `stop`.
+    pub fn next(&self) -> Option<(usize, Source, Option<Prefetched>)> {
+        let mut state = self.state.lock().unwrap();
+        if state.stopped {
+            return None;
+        }
+        if !self.read_ahead {
+            let source = state.sources.next()?;
+            state.claimed += 1;
+            return Some((self.page(state.claimed - 1), source, None));
+        }
+        if state.end.is_some_and(|end| state.claimed >= end) {
+            return None;
+        }
+        let index = state.claimed;
+        state.claimed += 1;
+
+        // Let the readers move on, then wait for this file
+        self.changed.notify_all();
+        let mut state = self
+            .changed
+            .wait_while(state, |state| {
+                !state.stopped
+                    && !state.ready.contains_key(&index)
+                    && state.end.is_none_or(|end| index < end)
+            })
+            .unwrap();
+        let (source, mut prefetched) = state.ready.remove(&index)?;
+        prefetched.queue_depth = state.ready.len();
+        Some((self.page(index), source, Some(prefetched)))
+    }
+
+    // Hand out nothing more and release the IO threads
+    pub fn stop(&self) {
+        self.state.lock().unwrap().stopped = true;
+        self.changed.notify_all();
+    }
+}
+
+#[test]
+fn test_long_poles_start_first() {
+    let sizes = [10, 12, 400, 9, 11, 10, 90, 10];
+    assert_eq!(long_poles_first(&sizes), (vec![2, 6, 0, 1, 3, 4, 5, 7], 2));
+    assert_eq!(long_poles_first(&[5; 4]), (vec![0, 1, 2, 3], 0));
+    assert_eq!(long_poles_first(&[]), (Vec::new(), 0));
+
+    // Time until the last file is done when each worker takes the next
+    // file as soon as it's free, with the time to render a file its size
+    let makespan = |order: &[usize], sizes: &[u64], workers: usize| {
+        let mut free = vec![0; workers];
+        for &index in order {
+            let worker = (0..workers).min_by_key(|&worker| free[worker]).unwrap();
+            free[worker] += sizes[index];
+        }
+        free.into_iter().max().unwrap()
+    };
+    // A few giant files at the end of the list
+    let sizes: Vec<u64> = (0..40).map(|_| 1).chain([20, 20, 20]).collect();
+    let in_order: Vec<usize> = (0..sizes.len()).collect();
+    let (order, poles) = long_poles_first(&sizes);
+    assert_eq!(poles, 3);
+    assert_eq!(makespan(&in_order, &sizes, 4), 30);
+    assert_eq!(makespan(&order, &sizes, 4), 25);
+}
@@ -0,0 +1,525 @@
+use crate::convert::{Conversion, Expansion, PageInfo};
+use crate::names;
+use crate::nup::Sheet;
+use crate::paths;
+use crate::timings::Stage;
+use crate::unsupported::Feature;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+// What --report writes once the run is done
+#[derive(Serialize)]
+pub struct Report {
+    pub files: Vec<FileEntry>,
+    pub totals: Totals,
+    // None without a document, e.g. with --no-pdf
+    pub output: Option<OutputEntry>,
+    // No page was left to write, see --allow-empty
+    pub empty: bool,
+    // Hash of the options that influenced rendering, as in --hashes
+    pub options_hash: String,
+    // Entries of --annotations for none of the pages
+    pub stray_annotations: Vec<String>,
+}
+
+// An input file, or a page of one for Inkscape layers of their own
+#[derive(Serialize)]
+pub struct FileEntry {
+    pub path: String,
+    pub id: String,
+    pub status: Status,
+    // First page of the document showing the file; None when it isn't in
+    // the document
+    pub page_number: Option<usize>,
+    pub width_px: Option<u32>,
+    pub height_px: Option<u32>,
+    pub render_ms: Option<f64>,
+    pub cached: bool,
+    pub error: Option<String>,
+    pub warnings: Vec<String>,
+    // Id of the earlier file a skipped one is byte-identical to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<String>,
+    // Milliseconds of each stage measured for the file
+    pub timings: BTreeMap<&'static str, f64>,
+    // Files read ahead and waiting when this one was taken, with IO threads
+    pub queue_depth: Option<usize>,
+    // Times reading the file was tried again, see --retries
+    pub retries: u32,
+    // PNG written with --export-images
+    pub image_path: Option<String>,
+    // How the page image is stored, and why with --image-format auto
+    pub encoding: Option<String>,
+    pub encoding_reason: Option<String>,
+    // CSS pixels the page grew by on each side, with --expand-to-content
+    pub expanded: Option<Expansion>,
+    pub unsupported: Vec<Feature>,
+    // SHA-256 of the rendered pixels
+    pub pixel_hash: Option<String>,
+    // Reused from a byte-identical file earlier in the run
+    pub deduped: bool,
+    // Color of a page without any detail
+    pub blank: Option<[u8; 3]>,
+    pub copies: u32,
+    // Annotations put on the page, with --annotations
+    pub annotations: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Failed,
+    // Left out as blank, see RunOptions::drop_blank_pages
+    Dropped,
+    // Left out as a copy, see RunOptions::dedupe_inputs
+    Skipped,
+}
+
+#[derive(Serialize)]
+pub struct Totals {
+    pub files: usize,
+    // Pages of the document, counting any it was appended to
+    pub pages: usize,
+    pub failed: usize,
+    pub dropped: usize,
+    pub skipped: usize,
+    pub cached: usize,
+    pub warnings: usize,
+    pub retries: u32,
+    // Pages reused from identical files
+    pub dedupe_hits: usize,
+    // Files started ahead of their turn for being large
+    pub started_early: usize,
+    // Most bytes of rendered pages waiting to be written at once, and how
+    // often workers waited for room, see --max-in-flight-mb
+    pub peak_in_flight_bytes: usize,
+    pub budget_waits: usize,
+    pub render_ms: f64,
+    pub elapsed_ms: f64,
+}
+
+#[derive(Serialize)]
+pub struct OutputEntry {
+    pub path: String,
+    pub format: &'static str,
+    // None for a document written to stdout
+    pub bytes: Option<u64>,
+}
+
+// How the pages of a run end up in the document
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Numbering {
+    // Pages of an existing document before the new ones, with --append-to
+    pub before: usize,
+    // Pages of an existing document in all
+    pub existing: usize,
+    pub sheet: Option<Sheet>,
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+// The entry of a file without a page
+fn bare_entry(path: String, id: String, status: Status) -> FileEntry {
+    FileEntry {
+        path,
+        id,
+        status,
+        page_number: None,
+        width_px: None,
+        height_px: None,
+        render_ms: None,
+        cached: false,
+        error: None,
+        warnings: Vec::new(),
+        duplicate_of: None,
+        timings: BTreeMap::new(),
+        queue_depth: None,
+        retries: 0,
+        image_path: None,
+        encoding: None,
+        encoding_reason: None,
+        expanded: None,
+        unsupported: Vec::new(),
+        pixel_hash: None,
+        deduped: false,
+        blank: None,
+        copies: 0,
+        annotations: 0,
+    }
+}
+
+fn page_entry(page: &PageInfo, status: Status, page_number: Option<usize>) -> FileEntry {
+    let timings = Stage::ALL
+        .into_iter()
+        .filter(|&stage| page.timings.spans.iter().any(|span| span.stage == stage))
+        .map(|stage| (stage.name(), millis(page.timings.stage(stage))))
+        .collect();
+    FileEntry {
+        page_number,
+        width_px: Some(page.width),
+        height_px: Some(page.height),
+        render_ms: Some(millis(page.timings.stage(Stage::Render))),
+        cached: page.cached,
+        warnings: page.warnings.clone(),
+        timings,
+        queue_depth: page.timings.queue_depth,
+        retries: page.timings.retries,
+        image_path: page
+            .image_path
+            .as_ref()
+            .map(|path| names::path_text(path.as_os_str()).into_owned()),
+        encoding: page
+            .encoding
+            .map(|encoding| format!("{:?}", encoding).to_lowercase()),
+        encoding_reason: page.encoding_reason.clone(),
+        expanded: page.expanded,
+        unsupported: page.unsupported.clone(),
+        pixel_hash: page.pixel_hash.clone(),
+        deduped: page.deduped,
+        blank: page.blank,
+        copies: page.copies,
+        annotations: page.annotations,
+        ..bare_entry(
+            names::path_text(page.path.as_os_str()).into_owned(),
+            page.id.clone(),
+            status,
+        )
+    }
+}
+
+// The report of `conversion`, which took `elapsed` and wrote `output`
+pub fn build(
+    conversion: &Conversion,
+    numbering: Numbering,
+    elapsed: Duration,
+    output: Option<OutputEntry>,
+) -> Report {
+    let errors: HashMap<&str, String> = conversion
+        .failed
+        .iter()
+        .map(|file| (file.id.as_str(), format!("{:#}", file.error)))
+        .collect();
+    let mut files = Vec::new();
+
+    // Pages in document order, the stand-ins of failed files among them
+    let mut drawings = 0;
+    for page in &conversion.pages {
+        let page_number = match numbering.sheet {
+            Some(sheet) => drawings / sheet.slots(),
+            None => drawings,
+        } + numbering.before
+            + 1;
+        drawings += page.copies as usize;
+        let mut entry = page_entry(page, Status::Ok, Some(page_number));
+        if let Some(error) = errors.get(page.id.as_str()) {
+            entry.status = Status::Failed;
+            entry.error = Some(error.clone());
+        }
+        files.push(entry);
+    }
+    for page in &conversion.dropped {
+        files.push(page_entry(page, Status::Dropped, None));
+    }
+    let placed: Vec<&str> = conversion
+        .pages
+        .iter()
+        .map(|page| page.id.as_str())
+        .collect();
+    for file in &conversion.failed {
+        if placed.contains(&file.id.as_str()) {
+            continue;
+        }
+        files.push(FileEntry {
+            error: Some(format!("{:#}", file.error)),
+            ..bare_entry(
+                names::path_text(file.path.as_os_str()).into_owned(),
+                file.id.clone(),
+                Status::Failed,
+            )
+        });
+    }
+    for duplicate in &conversion.skipped {
+        files.push(FileEntry {
+            duplicate_of: Some(duplicate.original.clone()),
+            ..bare_entry(
+                names::path_text(duplicate.path.as_os_str()).into_owned(),
+                duplicate.id.clone(),
+                Status::Skipped,
+            )
+        });
+    }
+
+    let count = |status| files.iter().filter(|file| file.status == status).count();
+    let pages = match (&output, numbering.sheet) {
+        (None, _) => 0,
+        (Some(_), Some(sheet)) => sheet.pages(drawings),
+        (Some(_), None) => drawings,
+    };
+    let totals = Totals {
+        files: files.len(),
+        pages: pages + output.as_ref().map_or(0, |_| numbering.existing),
+        failed: count(Status::Failed),
+        dropped: count(Status::Dropped),
+        skipped: count(Status::Skipped),
+        cached: files.iter().filter(|file| file.cached).count(),
+        warnings: files.iter().map(|file| file.warnings.len()).sum(),
+        retries: files.iter().map(|file| file.retries).sum(),
+        dedupe_hits: conversion.dedupe_hits,
+        started_early: conversion.started_early,
+        peak_in_flight_bytes: conversion.peak_in_flight,
+        budget_waits: conversion.budget_waits,
+        render_ms: files.iter().filter_map(|file| file.render_ms).sum(),
+        elapsed_ms: millis(elapsed),
+    };
+    Report {
+        files,
+        totals,
+        output,
+        empty: conversion.empty,
+        options_hash: conversion.options_hash.clone(),
+        stray_annotations: conversion.stray_annotations.clone(),
+    }
+}
+
+// The entry of the document written to `path`, with its size once it is
+// on disk
+pub fn output_entry(path: &Path, format: &'static str) -> OutputEntry {
+    let bytes = match paths::is_stdio(path) {
+        true => None,
+        false => fs::metadata(paths::long_path(path))
+            .ok()
+            .map(|metadata| metadata.len()),
+    };
+    OutputEntry {
+        path: names::path_text(path.as_os_str()).into_owned(),
+        format,
+        bytes,
+    }
+}
+
+pub fn write_report(path: &Path, report: &Report) -> Result<()> {
+    let json = serde_json::to_string_pretty(report).context("Failed to serialize report")?;
+    fs::write(paths::long_path(path), json + "\n")
+        .with_context(|| format!("Failed to write report: {:?}", path))
+}
+
+#[test]
+fn test_report_entries() {
+    use crate::convert::FailedFile;
+    use crate::dedupe::Duplicate;
+    use crate::nup::Grid;
+    use crate::timings::FileTimings;
+    use std::path::PathBuf;
+
+    let page = |id: &str, copies: u32| PageInfo {
+        path: PathBuf::from("in").join(id),
+        id: id.to_string(),
+        width: 800,
+        height: 600,
+        timings: FileTimings::new(PathBuf::from("in").join(id)),
+        pixel_hash: None,
+        image_path: None,
+        thumbnail: None,
+        warnings: vec!["font Arial not found".to_string()],
+        cached: id == "b.svg",
+        deduped: false,
+        blank: None,
+        encoding: None,
+        encoding_reason: None,
+        expanded: None,
+        unsupported: Vec::new(),
+        copies,
+        placement: None,
+        annotations: 0,
+    };
+    let failed = |id: &str| FailedFile {
+        path: PathBuf::from("in").join(id),
+        id: id.to_string(),
+        error: anyhow::anyhow!("bad"),
+    };
+    let conversion = Conversion {
+        // c.svg failed and has a blank page in its place
+        pages: vec![page("a.svg", 2), page("b.svg", 1), page("c.svg", 1)],
+        cache_hits: 1,
+        options_hash: String::new(),
+        preview: None,
+        peak_in_flight: 0,
+        budget_waits: 0,
+        dedupe_hits: 0,
+        dropped: vec![page("blank.svg", 1)],
+        started_early: 0,
+        skipped: vec![Duplicate {
+            path: "in/copy.svg".into(),
+            id: "copy.svg".to_string(),
+            original: "a.svg".to_string(),
+        }],
+        empty: false,
+        stray_annotations: Vec::new(),
+        failed: vec![failed("c.svg"), failed("d.svg")],
+    };
+    let output = OutputEntry {
+        path: "out.pdf".to_string(),
+        format: "PDF",
+        bytes: Some(1234),
+    };
+    let report = build(
+        &conversion,
+        Numbering::default(),
+        Duration::from_millis(1500),
+        Some(output),
+    );
+    let statuses: Vec<_> = report
+        .files
+        .iter()
+        .map(|file| (file.id.as_str(), file.status, file.page_number))
+        .collect();
+    assert_eq!(
+        statuses,
+        [
+            ("a.svg", Status::Ok, Some(1)),
+            ("b.svg", Status::Ok, Some(3)),
+            ("c.svg", Status::Failed, Some(4)),
+            ("blank.svg", Status::Dropped, None),
+            ("d.svg", Status::Failed, None),
+            ("copy.svg", Status::Skipped, None),
+        ]
+    );
+    assert_eq!((report.totals.pages, report.totals.failed), (4, 2));
+    assert_eq!((report.totals.cached, report.totals.warnings), (1, 4));
+
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["files"][0]["width_px"], 800);
+    assert_eq!(json["files"][2]["error"], "bad");
+    assert_eq!(json["files"][5]["path"], "in/copy.svg");
+    assert_eq!(json["files"][5]["duplicate_of"], "a.svg");
+    assert_eq!(json["totals"]["elapsed_ms"], 1500.0);
+    assert_eq!(json["output"]["bytes"], 1234);
+
+    // Drawings share pages with --nup, after those of an appended-to PDF
+    let numbering = Numbering {
+        before: 10,
+        existing: 10,
+        sheet: Some(Sheet {
+            grid: Grid {
+                columns: 2,
+                rows: 1,
+            },
+            width: 100.0,
+            height: 100.0,
+            margin: 0.0,
+            gutter: 0.0,
+            labels: false,
+        }),
+    };
+    let output = output_entry(Path::new("-"), "PDF");
+    let report = build(&conversion, numbering, Duration::ZERO, Some(output));
+    let numbers: Vec<_> = report.files.iter().map(|file| file.page_number).collect();
+    assert_eq!(numbers[..3], [Some(11), Some(12), Some(12)]);
+    assert_eq!(report.totals.pages, 12);
+    assert_eq!(report.output.unwrap().bytes, None);
+}
+
+#[test]
+fn test_report_page_details() {
+    use crate::timings::{FileTimings, Span};
+    use crate::writer::Encoding;
+    use std::path::PathBuf;
+
+    let mut timings = FileTimings::new(PathBuf::from("in/a.svg"));
+    timings.retries = 2;
+    for (stage, millis) in [(Stage::Read, 5), (Stage::Render, 20), (Stage::Render, 10)] {
+        timings.spans.push(Span {
+            stage,
+            start: Duration::ZERO,
+            duration: Duration::from_millis(millis),
+        });
+    }
+    let page = PageInfo {
+        path: PathBuf::from("in/a.svg"),
+        id: "a.svg".to_string(),
+        width: 800,
+        height: 600,
+        timings,
+        pixel_hash: Some("abc".to_string()),
+        image_path: Some(PathBuf::from("images/0001-a.png")),
+        thumbnail: None,
+        warnings: Vec::new(),
+        cached: false,
+        deduped: true,
+        blank: Some([255, 255, 255]),
+        encoding: Some(Encoding::Jpeg),
+        encoding_reason: Some("many colors".to_string()),
+        expanded: Some(Expansion {
+            left: 10.0,
+            ..Expansion::default()
+        }),
+        unsupported: vec![Feature {
+            name: "script",
+            count: 1,
+        }],
+        copies: 2,
+        placement: None,
+        annotations: 3,
+    };
+    let conversion = Conversion {
+        pages: vec![page],
+        cache_hits: 0,
+        options_hash: "options".to_string(),
+        preview: None,
+        peak_in_flight: 4096,
+        budget_waits: 1,
+        dedupe_hits: 1,
+        dropped: Vec::new(),
+        started_early: 0,
+        skipped: Vec::new(),
+        empty: false,
+        stray_annotations: vec!["notes.json: no page for b.svg".to_string()],
+        failed: Vec::new(),
+    };
+    let report = build(&conversion, Numbering::default(), Duration::ZERO, None);
+    let json = serde_json::to_value(&report).unwrap();
+    let file = &json["files"][0];
+    assert_eq!(
+        file["timings"],
+        serde_json::json!({"read": 5.0, "render": 30.0})
+    );
+    assert_eq!(file["render_ms"], 30.0);
+    assert_eq!(file["retries"], 2);
+    assert_eq!(file["image_path"], "images/0001-a.png");
+    assert_eq!(
+        (&file["encoding"], &file["encoding_reason"]),
+        (&"jpeg".into(), &"many colors".into())
+    );
+    assert_eq!(file["expanded"]["left"], 10.0);
+    assert_eq!(file["unsupported"][0]["name"], "script");
+    assert_eq!(
+        (&file["pixel_hash"], &file["deduped"]),
+        (&"abc".into(), &true.into())
+    );
+    assert_eq!(file["blank"], serde_json::json!([255, 255, 255]));
+    assert_eq!(
+        (&file["copies"], &file["annotations"]),
+        (&2.into(), &3.into())
+    );
+    assert_eq!(
+        json["stray_annotations"][0],
+        "notes.json: no page for b.svg"
+    );
+    assert_eq!(
+        (&json["empty"], &json["options_hash"]),
+        (&false.into(), &"options".into())
+    );
+    let totals = &json["totals"];
+    assert_eq!(
+        (&totals["retries"], &totals["dedupe_hits"]),
+        (&2.into(), &1.into())
+    );
+    assert_eq!(totals["peak_in_flight_bytes"], 4096);
+}
@@ -0,0 +1,131 @@
+use std::io;
+use std::time::Duration;
+
+// How reads that fail for a reason that may go away are tried again
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    // Tries after the first one; 0 never retries
+    pub retries: u32,
+    // Wait before the first retry, doubled before each one after it
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 0,
+            delay: Duration::from_millis(500),
+        }
+    }
+}
+
+// Longest wait between two tries, however many came before
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+// Whether a read that failed with `err` may work when tried again:
+// interrupted or timed out reads and stale handles of network mounts.
+// Everything else, a missing file or a permission, stays as it is.
+pub fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::StaleNetworkFileHandle
+    )
+}
+
+impl RetryPolicy {
+    // Wait before retry number `retry`, counting from 0
+    fn delay(&self, retry: u32) -> Duration {
+        self.delay
+            .saturating_mul(1 << retry.min(16))
+            .min(MAX_DELAY.max(self.delay))
+    }
+
+    // Run `read` until it succeeds, fails for good or runs out of retries.
+    // Returns its last result and how often it was retried.
+    pub fn run<T>(&self, mut read: impl FnMut() -> io::Result<T>) -> (io::Result<T>, u32) {
+        let mut retries = 0;
+        loop {
+            match read() {
+                Err(err) if retries < self.retries && is_transient(&err) => {
+                    std::thread::sleep(self.delay(retries));
+                    retries += 1;
+                }
+                result => return (result, retries),
+            }
+        }
+    }
+}
+
+// Parse a delay such as `500ms`, `2s` or `1.5s`; a bare number is in
+// milliseconds
+pub fn parse_delay(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = match value.strip_suffix("ms") {
+        Some(number) => (number, 0.001),
+        None => match value.strip_suffix('s') {
+            Some(number) => (number, 1.0),
+            None => (value, 0.001),
+        },
+    };
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("expected a delay such as 500ms or 2s, got {value:?}"))?;
+    Duration::try_from_secs_f64(number * unit).map_err(|_| format!("invalid delay {value:?}"))
+}
+
+#[test]
+fn test_retries_transient_errors_only() {
+    use std::time::Instant;
+
+    // Fails `failures` times with `kind`, then reads
+    let flaky = |failures: u32, kind: io::ErrorKind| {
+        let mut calls = 0;
+        move || {
+            calls += 1;
+            if calls <= failures {
+                Err(io::Error::from(kind))
+            } else {
+                Ok(calls)
+            }
+        }
+    };
+    let policy = RetryPolicy {
+        retries: 3,
+        delay: Duration::from_millis(1),
+    };
+    let started = Instant::now();
+    let (read, retries) = policy.run(flaky(3, io::ErrorKind::StaleNetworkFileHandle));
+    assert_eq!((read.unwrap(), retries), (4, 3));
+    // Waited 1, 2 and 4 ms
+    assert!(started.elapsed() >= Duration::from_millis(7));
+
+    let (read, retries) = policy.run(flaky(4, io::ErrorKind::TimedOut));
+    assert_eq!(read.unwrap_err().kind(), io::ErrorKind::TimedOut);
+    assert_eq!(retries, 3);
+    let (read, retries) = policy.run(flaky(1, io::ErrorKind::NotFound));
+    assert_eq!(read.unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(retries, 0);
+    let (read, retries) = RetryPolicy::default().run(flaky(1, io::ErrorKind::Interrupted));
+    assert!(read.is_err());
+    assert_eq!(retries, 0);
+
+    // Backoff doubles up to its limit
+    let policy = RetryPolicy {
+        retries: 20,
+        delay: Duration::from_millis(500),
+    };
+    assert_eq!(policy.delay(0), Duration::from_millis(500));
+    assert_eq!(policy.delay(2), Duration::from_secs(2));
+    assert_eq!(policy.delay(19), MAX_DELAY);
+
+    assert_eq!(parse_delay("500ms"), Ok(Duration::from_millis(500)));
+    assert_eq!(parse_delay("1.5s"), Ok(Duration::from_millis(1500)));
+    assert_eq!(parse_delay("250"), Ok(Duration::from_millis(250)));
+    assert_eq!(parse_delay("0"), Ok(Duration::ZERO));
+    for value in ["", "fast", "-1s", "2m"] {
+        assert!(parse_delay(value).is_err(), "{value}");
+    }
+}
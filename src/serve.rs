@@ -0,0 +1,424 @@
+use crate::convert::{self, RenderArgs, RunOptions, Source};
+use crate::writer::{ContainerWriter, ImageOptions, PdfWriter};
+use anyhow::{anyhow, Context, Result};
+use clap::Args;
+use resvg::usvg::Options;
+use std::io::{Cursor, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub listen: String,
+
+    /// Number of requests converted at the same time
+    #[arg(long, default_value = "2")]
+    pub concurrency: usize,
+
+    /// Maximum number of SVG files per request
+    #[arg(long, default_value = "1000")]
+    pub max_files: usize,
+
+    /// Maximum request size in bytes (also caps unpacked zip contents)
+    #[arg(long, default_value = "104857600")]
+    pub max_bytes: u64,
+
+    /// Per-request conversion timeout in seconds
+    #[arg(long, default_value = "120")]
+    pub timeout: u64,
+
+    /// Fail requests with pages of more than N pixels, before rendering them
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_pixels: Option<u64>,
+
+    /// Defaults for requests that don't override them in their options
+    #[command(flatten)]
+    pub render: RenderArgs,
+}
+
+// Files and options extracted from an upload
+#[derive(Default)]
+struct Upload {
+    files: Vec<(String, Vec<u8>)>,
+    options: Option<serde_json::Value>,
+}
+
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+    files: usize,
+}
+
+impl Reply {
+    fn text(status: u16, message: impl Into<String>) -> Self {
+        let mut body = message.into().into_bytes();
+        body.push(b'\n');
+        Reply {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body,
+            files: 0,
+        }
+    }
+}
+
+pub fn run(args: &ServeArgs) -> Result<()> {
+    if args.concurrency == 0 {
+        anyhow::bail!("--concurrency must be at least 1");
+    }
+
+    let server = Arc::new(
+        Server::http(&args.listen)
+            .map_err(|err| anyhow!("Failed to listen on {}: {}", args.listen, err))?,
+    );
+    let opt = convert::load_options();
+    eprintln!(
+        "Listening on http://{} ({} concurrent conversions)",
+        args.listen, args.concurrency
+    );
+
+    // Each handler thread serves one request at a time, which bounds how
+    // many conversions run concurrently; the rest wait in the accept queue
+    thread::scope(|scope| {
+        for _ in 0..args.concurrency {
+            let server = Arc::clone(&server);
+            let opt = Arc::clone(&opt);
+            scope.spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(request, args, &opt);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn handle(mut request: Request, args: &ServeArgs, opt: &Arc<Options<'static>>) {
+    let started = Instant::now();
+    let method = request.method().clone();
+    let url = request.url().to_string();
+
+    let reply = match (&method, url.split('?').next().unwrap_or_default()) {
+        (Method::Get, "/health") => Reply {
+            status: 200,
+            content_type: "application/json",
+            body: br#"{"status":"ok"}"#.to_vec(),
+            files: 0,
+        },
+        (Method::Post, "/convert") => convert_request(&mut request, args, opt),
+        (_, "/health" | "/convert") => Reply::text(405, "Method not allowed"),
+        _ => Reply::text(404, "Not found"),
+    };
+
+    eprintln!(
+        "{} {} -> {} ({} files, {} bytes, {} ms)",
+        method,
+        url,
+        reply.status,
+        reply.files,
+        reply.body.len(),
+        started.elapsed().as_millis()
+    );
+
+    let header = Header::from_bytes("Content-Type", reply.content_type).unwrap();
+    let response = Response::from_data(reply.body)
+        .with_status_code(reply.status)
+        .with_header(header);
+    if let Err(err) = request.respond(response) {
+        eprintln!("Failed to send response: {}", err);
+    }
+}
+
+fn convert_request(request: &mut Request, args: &ServeArgs, opt: &Arc<Options<'static>>) -> Reply {
+    if request
+        .body_length()
+        .is_some_and(|length| length as u64 > args.max_bytes)
+    {
+        return Reply::text(413, format!("Request exceeds {} bytes", args.max_bytes));
+    }
+
+    // Read the body, refusing to buffer more than the limit
+    let mut body = Vec::new();
+    if let Err(err) = request
+        .as_reader()
+        .take(args.max_bytes + 1)
+        .read_to_end(&mut body)
+    {
+        return Reply::text(400, format!("Failed to read request body: {}", err));
+    }
+    if body.len() as u64 > args.max_bytes {
+        return Reply::text(413, format!("Request exceeds {} bytes", args.max_bytes));
+    }
+
+    let content_type = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Content-Type"))
+        .map(|header| header.value.as_str().to_string())
+        .unwrap_or_default();
+    let upload = if content_type.starts_with("multipart/form-data") {
+        match boundary(&content_type) {
+            Some(boundary) => parse_multipart(&body, &boundary),
+            None => Err(anyhow!("Missing multipart boundary")),
+        }
+    } else if content_type.starts_with("application/zip") {
+        parse_zip(&body, args.max_bytes)
+    } else {
+        return Reply::text(
+            415,
+            "Expected multipart/form-data or application/zip upload",
+        );
+    };
+    let upload = match upload {
+        Ok(upload) => upload,
+        Err(err) => return Reply::text(400, format!("{:#}", err)),
+    };
+
+    let files = upload.files.len();
+    if files == 0 {
+        return Reply::text(400, "No SVG files in upload");
+    }
+    if files > args.max_files {
+        return Reply::text(
+            413,
+            format!("Upload has {} files, limit is {}", files, args.max_files),
+        );
+    }
+    let render_args = match request_options(&args.render, upload.options) {
+        Ok(render_args) => render_args,
+        Err(err) => return Reply::text(400, format!("Invalid options: {:#}", err)),
+    };
+
+    // Convert on a separate thread so a slow request can be timed out. A
+    // timed out conversion is cancelled and waited for, so the handler stays
+    // busy until it no longer renders and --concurrency holds.
+    let sources: Vec<Source> = upload
+        .files
+        .into_iter()
+        .map(|(name, data)| Source::bytes(name, data))
+        .collect();
+    let cancel = AtomicBool::new(false);
+    let run = RunOptions {
+        max_pixels: args.max_pixels,
+        cancel: Some(&cancel),
+        ..RunOptions::default()
+    };
+    let (tx, rx) = mpsc::channel();
+    let result = thread::scope(|scope| {
+        scope.spawn(|| {
+            let _ = tx.send(convert_to_pdf(opt, sources, &render_args, &run));
+        });
+        let result = rx.recv_timeout(Duration::from_secs(args.timeout));
+        if result.is_err() {
+            cancel.store(true, Ordering::Relaxed);
+        }
+        result
+    });
+
+    let mut reply = match result {
+        Ok(Ok(pdf)) => Reply {
+            status: 200,
+            content_type: "application/pdf",
+            body: pdf,
+            files: 0,
+        },
+        Ok(Err(err)) => Reply::text(422, format!("{:#}", err)),
+        Err(_) => Reply::text(
+            504,
+            format!("Conversion did not finish within {} s", args.timeout),
+        ),
+    };
+    reply.files = files;
+    reply
+}
+
+fn convert_to_pdf(
+    opt: &Arc<Options<'static>>,
+    sources: Vec<Source>,
+    args: &RenderArgs,
+    run: &RunOptions,
+) -> Result<Vec<u8>> {
+    let settings = args.quality.settings();
+    let mut writer = Box::new(
+        PdfWriter::new(
+            args.resolution(),
+            ImageOptions {
+                jpeg_quality: settings.jpeg_quality,
+                ..ImageOptions::default()
+            },
+        )
+        .with_sheet(args.sheet()),
+    );
+    convert::convert(opt, sources.into(), args, run, writer.as_mut())?;
+    let mut pdf = Vec::new();
+    writer.finish(&mut pdf)?;
+    Ok(pdf)
+}
+
+// Layer the request's options JSON over the server defaults
+fn request_options(
+    defaults: &RenderArgs,
+    options: Option<serde_json::Value>,
+) -> Result<RenderArgs> {
+    let mut merged = serde_json::to_value(defaults)?;
+    match options {
+        None => {}
+        Some(serde_json::Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                merged[key] = value;
+            }
+        }
+        Some(_) => anyhow::bail!("options must be a JSON object"),
+    }
+    let args: RenderArgs = serde_json::from_value(merged)?;
+    args.check().map_err(anyhow::Error::msg)?;
+    Ok(args)
+}
+
+fn boundary(content_type: &str) -> Option<String> {
+    content_type.split(';').find_map(|param| {
+        let value = param.trim().strip_prefix("boundary=")?;
+        Some(value.trim_matches('"').to_string())
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| position + from)
+}
+
+// Extract a quoted parameter such as name="..." from a header value
+fn header_param(header: &str, param: &str) -> Option<String> {
+    let start = header.find(&format!("{param}=\""))? + param.len() + 2;
+    let end = header[start..].find('"')? + start;
+    Some(header[start..end].to_string())
+}
+
+// Parse a multipart/form-data body: parts with an .svg filename become
+// files, a part named "options" holds the options JSON
+fn parse_multipart(body: &[u8], boundary: &str) -> Result<Upload> {
+    let delimiter = format!("--{boundary}").into_bytes();
+    let mut upload = Upload::default();
+    let mut position =
+        find(body, &delimiter, 0).context("Multipart body has no boundary")? + delimiter.len();
+
+    loop {
+        // The closing delimiter is followed by "--"
+        if body[position..].starts_with(b"--") {
+            break;
+        }
+        let headers_start = position + 2;
+        let headers_end =
+            find(body, b"\r\n\r\n", headers_start).context("Truncated multipart headers")?;
+        let headers = String::from_utf8_lossy(&body[headers_start..headers_end]);
+        let data_start = headers_end + 4;
+
+        let mut next_delimiter = b"\r\n".to_vec();
+        next_delimiter.extend_from_slice(&delimiter);
+        let data_end =
+            find(body, &next_delimiter, data_start).context("Truncated multipart body")?;
+        let data = &body[data_start..data_end];
+
+        let disposition = headers
+            .lines()
+            .find(|line| {
+                line.to_ascii_lowercase()
+                    .starts_with("content-disposition:")
+            })
+            .unwrap_or_default();
+        let name = header_param(disposition, "name");
+        match header_param(disposition, "filename") {
+            Some(filename) if convert::is_svg(filename.as_ref()) => {
+                upload.files.push((filename, data.to_vec()));
+            }
+            Some(filename) => anyhow::bail!("Not an SVG file: {}", filename),
+            None if name.as_deref() == Some("options") => {
+                upload.options =
+                    Some(serde_json::from_slice(data).context("options part is not valid JSON")?);
+            }
+            None => {}
+        }
+
+        position = data_end + next_delimiter.len();
+    }
+    Ok(upload)
+}
+
+// Parse a zip upload: every .svg entry becomes a file (in name order) and
+// an optional options.json entry holds the options
+fn parse_zip(body: &[u8], max_bytes: u64) -> Result<Upload> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(body)).context("Invalid zip archive")?;
+    let mut upload = Upload::default();
+    let mut unpacked = 0;
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        if !entry.is_file() {
+            continue;
+        }
+        let name = entry.name().to_string();
+        let wanted = convert::is_svg(name.as_ref()) || name == "options.json";
+        if !wanted {
+            continue;
+        }
+
+        // Guard against zip bombs by capping the unpacked size
+        let mut data = Vec::new();
+        entry
+            .by_ref()
+            .take(max_bytes - unpacked + 1)
+            .read_to_end(&mut data)?;
+        unpacked += data.len() as u64;
+        if unpacked > max_bytes {
+            anyhow::bail!("Unpacked upload exceeds {} bytes", max_bytes);
+        }
+
+        if name == "options.json" {
+            upload.options =
+                Some(serde_json::from_slice(&data).context("options.json is not valid JSON")?);
+        } else {
+            upload.files.push((name, data));
+        }
+    }
+
+    upload.files.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(upload)
+}
+
+#[test]
+fn test_parse_multipart() {
+    let body = b"--XyZ\r\n\
+Content-Disposition: form-data; name=\"options\"\r\n\r\n\
+{\"scale\": 0.5}\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"file\"; filename=\"a.svg\"\r\n\
+Content-Type: image/svg+xml\r\n\r\n\
+<svg/>\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"file\"; filename=\"b.SVG\"\r\n\r\n\
+<svg>\r\n</svg>\r\n\
+--XyZ--\r\n";
+
+    assert_eq!(
+        boundary("multipart/form-data; boundary=\"XyZ\"").as_deref(),
+        Some("XyZ")
+    );
+    let upload = parse_multipart(body, "XyZ").unwrap();
+    assert_eq!(upload.files.len(), 2);
+    assert_eq!(upload.files[0], ("a.svg".to_string(), b"<svg/>".to_vec()));
+    assert_eq!(upload.files[1].1, b"<svg>\r\n</svg>".to_vec());
+
+    let defaults = RenderArgs::default();
+    let merged = request_options(&defaults, upload.options).unwrap();
+    assert_eq!(merged.scale, 0.5);
+    assert!(request_options(&defaults, Some(serde_json::json!({ "bogus": 1 }))).is_err());
+}
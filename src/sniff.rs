@@ -0,0 +1,129 @@
+use resvg::usvg;
+
+// Leading bytes of formats that turn up under an .svg name, mostly from
+// export scripts that save whatever they were sent
+const MAGIC: [(&[u8], &str); 9] = [
+    (b"\x89PNG\r\n\x1a\n", "a PNG image"),
+    (b"\xff\xd8\xff", "a JPEG image"),
+    (b"GIF87a", "a GIF image"),
+    (b"GIF89a", "a GIF image"),
+    (b"%PDF-", "a PDF document"),
+    (b"PK\x03\x04", "a ZIP archive"),
+    (b"II*\0", "a TIFF image"),
+    (b"MM\0*", "a TIFF image"),
+    (b"BM", "a BMP image"),
+];
+
+// What `data` looks like when it isn't SVG, for the error of a file that
+// failed to parse. None when it may well be SVG, so the parse error says
+// more. Compressed files are judged by what they hold.
+pub fn detect(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0x1f, 0x8b]) {
+        return match usvg::decompress_svgz(data) {
+            Ok(inner) => detect(&inner),
+            Err(_) => Some("a damaged gzip file"),
+        };
+    }
+    if data.iter().all(u8::is_ascii_whitespace) {
+        return Some("an empty file");
+    }
+    if let Some((_, kind)) = MAGIC.iter().find(|(magic, _)| data.starts_with(magic)) {
+        return Some(kind);
+    }
+    if data.len() > 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("a WebP image");
+    }
+
+    // Text formats, after a byte order mark and whitespace
+    let text = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+    let start = text.iter().position(|byte| !byte.is_ascii_whitespace())?;
+    let text = &text[start..text.len().min(start + 256)];
+    let starts_with = |prefix: &str| {
+        text.len() >= prefix.len() && text[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+    };
+    if ["<!doctype html", "<html", "<head", "<body"]
+        .iter()
+        .any(|prefix| starts_with(prefix))
+    {
+        return Some("HTML (possibly a failed download)");
+    }
+    if text[0] == b'{' || text[0] == b'[' {
+        return Some("JSON (possibly an error response)");
+    }
+    if text[0] != b'<' {
+        return Some("plain text");
+    }
+    None
+}
+
+#[test]
+fn test_detects_misnamed_files() {
+    assert_eq!(
+        detect(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+        Some("a PNG image")
+    );
+    assert_eq!(detect(b"%PDF-1.7\n%\xe2\xe3"), Some("a PDF document"));
+    assert_eq!(detect(b"\xff\xd8\xff\xe0\0\x10JFIF"), Some("a JPEG image"));
+    assert_eq!(detect(b"RIFF\x10\0\0\0WEBPVP8 "), Some("a WebP image"));
+    assert_eq!(
+        detect(b"\xef\xbb\xbf\n  <!DOCTYPE HTML>\n<html><body>404 Not Found</body></html>"),
+        Some("HTML (possibly a failed download)")
+    );
+    assert_eq!(
+        detect(br#"{"error": "rate limited"}"#),
+        Some("JSON (possibly an error response)")
+    );
+    assert_eq!(detect(b"Access denied"), Some("plain text"));
+    assert_eq!(detect(b" \n"), Some("an empty file"));
+
+    // Broken SVG is left to the parse error
+    for svg in [
+        &b"<svg xmlns=\"http://www.w3.org/2000/svg\"><rect"[..],
+        b"<?xml version=\"1.0\"?>\n<svg>",
+        b"\n<!-- made by hand -->\n<svg/>",
+    ] {
+        assert_eq!(detect(svg), None);
+    }
+
+    // Compressed files are looked into
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+    let gzip = |data: &[u8]| {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    };
+    assert_eq!(
+        detect(&gzip(b"<html>oops</html>")),
+        Some("HTML (possibly a failed download)")
+    );
+    assert_eq!(detect(&gzip(b"<svg/>")), None);
+    assert_eq!(
+        detect(b"\x1f\x8b\x08\0garbage"),
+        Some("a damaged gzip file")
+    );
+
+    // Named in the error of the file, while compressed SVG renders
+    use crate::convert::{self, RenderArgs, RunOptions, Source};
+    let render = |data: Vec<u8>| {
+        let mut writer = crate::writer::PdfWriter::new(1.0, Default::default());
+        let opt = std::sync::Arc::new(resvg::usvg::Options::default());
+        convert::convert(
+            &opt,
+            vec![Source::bytes("chart.svg", data)].into(),
+            &RenderArgs::default(),
+            &RunOptions::default(),
+            &mut writer,
+        )
+    };
+    let Err(err) = render(b"<!DOCTYPE html><title>502 Bad Gateway</title>".to_vec()) else {
+        panic!("HTML should not render");
+    };
+    let err = format!("{err:#}");
+    assert!(
+        err.contains("\"chart.svg\" looks like HTML (possibly a failed download)"),
+        "{err}"
+    );
+    let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="4"/>"#;
+    assert_eq!(render(gzip(svg)).unwrap().pages.len(), 1);
+}
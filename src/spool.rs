@@ -0,0 +1,136 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Bytes a spool keeps in memory before it moves to a temporary file; most
+// documents never get there
+pub const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+// Spools of this process so far, to name their files apart
+static SPOOLS: AtomicUsize = AtomicUsize::new(0);
+
+// A document written front to back before it has anywhere to go, e.g.
+// because the output is only opened once every page is in. Kept in memory
+// up to a limit, then in a temporary file removed again on drop.
+pub struct Spool {
+    limit: usize,
+    memory: Vec<u8>,
+    file: Option<(PathBuf, BufWriter<File>)>,
+    len: u64,
+}
+
+impl Spool {
+    pub fn new(limit: usize) -> Self {
+        Spool {
+            limit,
+            memory: Vec::new(),
+            file: None,
+            len: 0,
+        }
+    }
+
+    // Bytes written so far, which is where the next write starts
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Copy everything written to `out`
+    pub fn copy_to(mut self, out: &mut dyn Write) -> io::Result<()> {
+        match self.file.take() {
+            None => out.write_all(&self.memory),
+            Some((path, file)) => {
+                let copied = (|| {
+                    let mut file = file.into_inner().map_err(|err| err.into_error())?;
+                    file.seek(SeekFrom::Start(0))?;
+                    io::copy(&mut file, out).map(|_| ())
+                })();
+                let _ = fs::remove_file(path);
+                copied
+            }
+        }
+    }
+
+    // Move what is in memory to a new temporary file
+    fn spill(&mut self) -> io::Result<()> {
+        let spool = SPOOLS.fetch_add(1, Ordering::Relaxed);
+        let name = format!("svg2pdf-spool-{}-{}", std::process::id(), spool);
+        let path = std::env::temp_dir().join(name);
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let mut file = BufWriter::new(file);
+        if let Err(err) = file.write_all(&self.memory) {
+            drop(file);
+            let _ = fs::remove_file(&path);
+            return Err(err);
+        }
+        self.memory = Vec::new();
+        self.file = Some((path, file));
+        Ok(())
+    }
+}
+
+impl Write for Spool {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.file.is_none() && self.memory.len() + buf.len() > self.limit {
+            self.spill()?;
+        }
+        let written = match &mut self.file {
+            Some((_, file)) => file.write(buf)?,
+            None => {
+                self.memory.extend_from_slice(buf);
+                buf.len()
+            }
+        };
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some((_, file)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        if let Some((path, file)) = self.file.take() {
+            drop(file);
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[test]
+fn test_spool_moves_to_a_file() {
+    let mut spool = Spool::new(10);
+    spool.write_all(b"12345678").unwrap();
+    assert!(spool.file.is_none());
+    spool.write_all(b"9abc").unwrap();
+    assert!(spool.file.is_some());
+    let (path, _) = spool.file.as_ref().unwrap();
+    let path = path.clone();
+    assert!(path.exists());
+    spool.write_all(b"def").unwrap();
+    assert_eq!(spool.len(), 15);
+    let mut out = Vec::new();
+    spool.copy_to(&mut out).unwrap();
+    assert_eq!(out, b"123456789abcdef");
+    assert!(!path.exists());
+
+    // Dropped without being copied, the file goes too
+    let mut spool = Spool::new(0);
+    spool.write_all(b"x").unwrap();
+    let path = spool.file.as_ref().unwrap().0.clone();
+    drop(spool);
+    assert!(!path.exists());
+}
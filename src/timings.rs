@@ -0,0 +1,252 @@
+use crate::names;
+use crate::paths;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// Pipeline stages measured for every file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Read,
+    Parse,
+    Render,
+    Export,
+    Convert,
+    Encode,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 6] = [
+        Stage::Read,
+        Stage::Parse,
+        Stage::Render,
+        Stage::Export,
+        Stage::Convert,
+        Stage::Encode,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Read => "read",
+            Stage::Parse => "parse",
+            Stage::Render => "render",
+            Stage::Export => "export",
+            Stage::Convert => "convert",
+            Stage::Encode => "encode",
+        }
+    }
+}
+
+// A single measured stage, relative to the start of the run
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Span {
+    pub stage: Stage,
+    #[serde(serialize_with = "as_micros")]
+    pub start: Duration,
+    #[serde(serialize_with = "as_micros")]
+    pub duration: Duration,
+}
+
+// Stage timings recorded by a worker for one file
+#[derive(Clone, Debug, Serialize)]
+pub struct FileTimings {
+    #[serde(serialize_with = "as_text")]
+    pub path: PathBuf,
+    pub thread: usize,
+    pub spans: Vec<Span>,
+    // Files read ahead and waiting when this one was taken, with IO threads
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_depth: Option<usize>,
+    // Times reading the file was tried again, see RetryPolicy
+    #[serde(skip_serializing_if = "is_zero")]
+    pub retries: u32,
+}
+
+fn is_zero(count: &u32) -> bool {
+    *count == 0
+}
+
+impl FileTimings {
+    pub fn new(path: PathBuf) -> Self {
+        FileTimings {
+            path,
+            thread: rayon::current_thread_index().map_or(0, |index| index + 1),
+            spans: Vec::with_capacity(Stage::ALL.len()),
+            queue_depth: None,
+            retries: 0,
+        }
+    }
+
+    // Run `f` and record how long it took under `stage`
+    pub fn measure<T>(&mut self, epoch: Instant, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let value = f();
+        self.spans.push(Span {
+            stage,
+            start: started.duration_since(epoch),
+            duration: started.elapsed(),
+        });
+        value
+    }
+
+    pub fn stage(&self, stage: Stage) -> Duration {
+        self.spans
+            .iter()
+            .filter(|span| span.stage == stage)
+            .map(|span| span.duration)
+            .sum()
+    }
+}
+
+fn as_micros<S: serde::Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_micros() as u64)
+}
+
+// Paths that aren't UTF-8 would fail to serialize as they are
+fn as_text<S: serde::Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&names::path_text(path.as_os_str()))
+}
+
+// Print the `top` slowest files for every stage to `out`
+pub fn print_summary(out: &mut dyn Write, timings: &[FileTimings], top: usize) -> io::Result<()> {
+    writeln!(out, "Slowest files per stage:")?;
+    for stage in Stage::ALL {
+        // Optional stages only show up when some file went through them
+        if matches!(stage, Stage::Export | Stage::Encode)
+            && !timings
+                .iter()
+                .any(|t| t.spans.iter().any(|span| span.stage == stage))
+        {
+            continue;
+        }
+        let mut ranked: Vec<_> = timings.iter().map(|t| (t.stage(stage), &t.path)).collect();
+        ranked.sort_by_key(|(duration, _)| std::cmp::Reverse(*duration));
+
+        let total: Duration = ranked.iter().map(|(duration, _)| *duration).sum();
+        writeln!(
+            out,
+            "  {} (total {:.1} ms)",
+            stage.name(),
+            total.as_secs_f64() * 1000.0
+        )?;
+        for (duration, path) in ranked.iter().take(top) {
+            writeln!(
+                out,
+                "    {:>10.2} ms  {}",
+                duration.as_secs_f64() * 1000.0,
+                names::path_text(path.as_os_str())
+            )?;
+        }
+    }
+    if let Some(queue) = queue_summary(timings) {
+        writeln!(out, "{queue}")?;
+    }
+    Ok(())
+}
+
+// How full the read-ahead queue was when files were taken from it. A
+// mostly empty queue means rendering waits on IO and more --io-threads help.
+fn queue_summary(timings: &[FileTimings]) -> Option<String> {
+    let depths: Vec<usize> = timings.iter().filter_map(|t| t.queue_depth).collect();
+    if depths.is_empty() {
+        return None;
+    }
+    let mean = depths.iter().sum::<usize>() as f64 / depths.len() as f64;
+    let empty = depths.iter().filter(|&&depth| depth == 0).count();
+    Some(format!(
+        "IO queue: {:.1} files waiting on average, empty for {} of {} files",
+        mean,
+        empty,
+        depths.len()
+    ))
+}
+
+#[derive(Serialize)]
+struct TraceEvent<'a> {
+    name: &'a str,
+    cat: &'a str,
+    ph: &'a str,
+    ts: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<u64>,
+    pid: u32,
+    tid: usize,
+    args: serde_json::Value,
+}
+
+// Write a chrome://tracing compatible trace of every measured span
+pub fn write_trace(path: &Path, timings: &[FileTimings]) -> Result<()> {
+    let mut events = Vec::new();
+
+    // Name the threads so the viewer shows one row per worker
+    let mut threads: Vec<usize> = timings.iter().map(|t| t.thread).collect();
+    threads.sort_unstable();
+    threads.dedup();
+    for tid in threads {
+        events.push(TraceEvent {
+            name: "thread_name",
+            cat: "__metadata",
+            ph: "M",
+            ts: 0,
+            dur: None,
+            pid: 1,
+            tid,
+            args: serde_json::json!({ "name": format!("worker {tid}") }),
+        });
+    }
+
+    for file in timings {
+        let file_name = names::path_text(file.path.as_os_str());
+        for span in &file.spans {
+            events.push(TraceEvent {
+                name: span.stage.name(),
+                cat: "file",
+                ph: "X",
+                ts: span.start.as_micros() as u64,
+                dur: Some(span.duration.as_micros() as u64),
+                pid: 1,
+                tid: file.thread,
+                args: serde_json::json!({ "file": file_name }),
+            });
+        }
+    }
+
+    let trace = serde_json::json!({
+        "traceEvents": events,
+        "displayTimeUnit": "ms",
+    });
+    fs::write(paths::long_path(path), serde_json::to_vec(&trace)?)
+        .with_context(|| format!("Failed to write trace file: {:?}", path))
+}
+
+#[test]
+fn test_stage_totals() {
+    let epoch = Instant::now();
+    let mut timings = FileTimings::new(PathBuf::from("a.svg"));
+    timings.measure(epoch, Stage::Read, || {
+        std::thread::sleep(Duration::from_millis(2))
+    });
+    timings.measure(epoch, Stage::Render, || ());
+
+    assert!(timings.stage(Stage::Read) >= Duration::from_millis(2));
+    assert!(timings.stage(Stage::Read) > timings.stage(Stage::Render));
+    assert_eq!(timings.stage(Stage::Parse), Duration::ZERO);
+    assert_eq!(timings.spans[0].stage, Stage::Read);
+}
+
+#[test]
+fn test_queue_summary() {
+    let file = |queue_depth| FileTimings {
+        queue_depth,
+        ..FileTimings::new(PathBuf::from("a.svg"))
+    };
+    assert_eq!(queue_summary(&[file(None)]), None);
+    assert_eq!(
+        queue_summary(&[file(Some(0)), file(Some(3)), file(Some(0))]).unwrap(),
+        "IO queue: 1.0 files waiting on average, empty for 2 of 3 files"
+    );
+}
@@ -0,0 +1,161 @@
+use crate::convert::svg_text;
+use resvg::usvg::roxmltree;
+use serde::Serialize;
+
+// Elements resvg doesn't render, by what they are for. usvg drops them
+// while parsing, so they are looked for in the source.
+const ELEMENTS: [(&str, &[&str]); 7] = [
+    ("foreignObject", &["foreignObject"]),
+    ("script", &["script"]),
+    (
+        "animation",
+        &[
+            "animate",
+            "animateColor",
+            "animateMotion",
+            "animateTransform",
+            "set",
+        ],
+    ),
+    ("mesh gradient", &["meshgradient", "meshGradient", "mesh"]),
+    ("hatch", &["hatch"]),
+    ("SVG font", &["font"]),
+    ("media", &["video", "audio", "iframe", "canvas"]),
+];
+
+// Filter inputs usvg replaces with the source graphic
+const FILTER_INPUTS: [&str; 4] = [
+    "BackgroundImage",
+    "BackgroundAlpha",
+    "FillPaint",
+    "StrokePaint",
+];
+
+// A feature a file uses that its page is rendered without
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Feature {
+    pub name: &'static str,
+    // Elements using it
+    pub count: usize,
+}
+
+impl std::fmt::Display for Feature {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({}x)", self.name, self.count)
+    }
+}
+
+// The features of `data` that can't be rendered, in the order of
+// ELEMENTS with filter inputs last. Files that don't parse have none;
+// parsing them reports the error.
+pub fn scan(data: &[u8]) -> Vec<Feature> {
+    let Ok(text) = svg_text(data) else {
+        return Vec::new();
+    };
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..roxmltree::ParsingOptions::default()
+    };
+    let Ok(doc) = roxmltree::Document::parse_with_options(&text, options) else {
+        return Vec::new();
+    };
+
+    let mut counts = [0; ELEMENTS.len() + 1];
+    let mut nodes = doc.root_element().descendants();
+    while let Some(node) = nodes.next() {
+        if !node.is_element() {
+            continue;
+        }
+        let name = node.tag_name().name();
+        if let Some(kind) = ELEMENTS.iter().position(|(_, names)| names.contains(&name)) {
+            counts[kind] += 1;
+            // Whatever a foreignObject holds, HTML included, goes with it
+            if name == "foreignObject" {
+                let end = node.range().end;
+                while nodes
+                    .clone()
+                    .next()
+                    .is_some_and(|next| next.range().start < end)
+                {
+                    nodes.next();
+                }
+            }
+        } else if name.starts_with("fe")
+            && ["in", "in2"].iter().any(|input| {
+                node.attribute(*input)
+                    .is_some_and(|value| FILTER_INPUTS.contains(&value))
+            })
+        {
+            counts[ELEMENTS.len()] += 1;
+        }
+    }
+
+    let names = ELEMENTS
+        .iter()
+        .map(|(name, _)| *name)
+        .chain(["filter input"]);
+    names
+        .zip(counts)
+        .filter(|(_, count)| *count > 0)
+        .map(|(name, count)| Feature { name, count })
+        .collect()
+}
+
+#[test]
+fn test_scan_finds_unsupported_features() {
+    let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+<script>alert(1)</script>
+<foreignObject width="5" height="5"><div xmlns="http://www.w3.org/1999/xhtml"><video/><p>Hi</p></div></foreignObject>
+<rect width="5" height="5"><animate attributeName="x" to="5"/><set attributeName="fill" to="red"/></rect>
+<filter id="f"><feOffset in="BackgroundImage"/><feBlend in="SourceGraphic" in2="FillPaint"/><feGaussianBlur in="SourceGraphic"/></filter>
+<circle r="2" filter="url(#f)"/>
+</svg>"#;
+    let features: Vec<String> = scan(svg).iter().map(Feature::to_string).collect();
+    assert_eq!(
+        features,
+        [
+            "foreignObject (1x)",
+            "script (1x)",
+            "animation (2x)",
+            "filter input (2x)"
+        ]
+    );
+
+    let plain = br#"<svg xmlns="http://www.w3.org/2000/svg"><rect width="5" height="5"/></svg>"#;
+    assert!(scan(plain).is_empty());
+    assert!(scan(b"not svg").is_empty());
+
+    // Reported with the page, or failing the run when asked to
+    use crate::convert::{self, RenderArgs, RunOptions, Source};
+    let sources = vec![
+        Source::bytes("plain.svg", plain.to_vec()),
+        Source::bytes("page.svg", svg.to_vec()),
+    ];
+    let render = |fail_on_unsupported| {
+        let run = RunOptions {
+            fail_on_unsupported,
+            ..RunOptions::default()
+        };
+        let mut writer = crate::writer::PdfWriter::new(1.0, Default::default());
+        let opt = std::sync::Arc::new(resvg::usvg::Options::default());
+        convert::convert(
+            &opt,
+            sources.clone().into(),
+            &RenderArgs::default(),
+            &run,
+            &mut writer,
+        )
+    };
+    let conversion = render(false).unwrap();
+    assert!(conversion.pages[0].warnings.is_empty());
+    assert_eq!(conversion.pages[1].unsupported.len(), 4);
+    assert_eq!(
+        conversion.pages[1].warnings,
+        ["Rendered without unsupported SVG features: foreignObject (1x), script (1x), animation (2x), filter input (2x)"]
+    );
+    let Err(err) = render(true) else {
+        panic!("the run should fail");
+    };
+    let err = err.to_string();
+    assert!(err.contains("\"page.svg\" uses SVG features"), "{err}");
+}
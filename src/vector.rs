@@ -0,0 +1,513 @@
+use anyhow::Result;
+use flate2::write::ZlibEncoder;
+use lopdf::content::{Content, Operation};
+use lopdf::Object;
+use resvg::tiny_skia::{Pixmap, Rect, Transform};
+use resvg::usvg::tiny_skia_path::PathSegment;
+use resvg::usvg::{
+    self, ClipPath, FillRule, Group, LineCap, LineJoin, Node, Paint, PaintOrder, Tree,
+};
+use std::collections::BTreeSet;
+use std::io::Write;
+
+// A part of a drawing PDF paths can't express, rendered on its own
+pub struct FallbackImage {
+    pub width: u32,
+    pub height: u32,
+    // Deflated RGB samples, and the deflated alpha of the image's soft mask
+    pub rgb: Vec<u8>,
+    pub alpha: Vec<u8>,
+}
+
+// A page drawn with PDF path operators instead of one image
+pub struct VectorPage {
+    // Content stream, in points from the bottom left of the page
+    pub content: Vec<u8>,
+    // Fill and stroke opacity of the graphics states /GS0, /GS1, ...
+    pub opacities: Vec<(f32, f32)>,
+    // Drawn as /Im0, /Im1, ...
+    pub images: Vec<FallbackImage>,
+    // What made parts of the drawing images
+    pub rasterized: BTreeSet<&'static str>,
+}
+
+// The PDF page of `tree`, whose canvas `transform` puts on a page of
+// `width` by `height` points, in points from the top left. Parts drawn as
+// images get `resolution` pixels per point. The page is filled with
+// `background` first, if given; otherwise it is left as the paper is.
+pub fn page(
+    tree: &Tree,
+    transform: Transform,
+    width: f32,
+    height: f32,
+    resolution: f32,
+    background: Option<[u8; 3]>,
+) -> Result<VectorPage> {
+    let operations = match background {
+        Some(color) => vec![
+            Operation::new(
+                "rg",
+                color
+                    .iter()
+                    .map(|&channel| Object::Real(channel as f32 / 255.0))
+                    .collect(),
+            ),
+            Operation::new("re", [0.0, 0.0, width, height].map(Object::Real).to_vec()),
+            Operation::new("f", vec![]),
+        ],
+        None => Vec::new(),
+    };
+    let mut builder = Builder {
+        operations,
+        opacities: Vec::new(),
+        images: Vec::new(),
+        rasterized: BTreeSet::new(),
+        page: Rect::from_xywh(0.0, 0.0, width, height),
+        resolution,
+    };
+    // PDF's y axis points up
+    builder.cm(Transform::from_row(1.0, 0.0, 0.0, -1.0, 0.0, height));
+    builder.cm(transform);
+    builder.group(tree.root(), transform, 1.0)?;
+    Ok(VectorPage {
+        content: Content {
+            operations: builder.operations,
+        }
+        .encode()?,
+        opacities: builder.opacities,
+        images: builder.images,
+        rasterized: builder.rasterized,
+    })
+}
+
+struct Builder {
+    operations: Vec<Operation>,
+    opacities: Vec<(f32, f32)>,
+    images: Vec<FallbackImage>,
+    rasterized: BTreeSet<&'static str>,
+    // The page in points from the top left, which images are cut to
+    page: Option<Rect>,
+    resolution: f32,
+}
+
+fn reals(values: &[f32]) -> Vec<Object> {
+    values.iter().map(|&value| Object::Real(value)).collect()
+}
+
+fn rgb(color: usvg::Color) -> Vec<Object> {
+    reals(&[color.red, color.green, color.blue].map(|channel| channel as f32 / 255.0))
+}
+
+// Why a group can't be drawn with path operators, if it can't. Opacity of a
+// group is handed down to what it draws, which only comes out the same when
+// nothing in it overlaps.
+fn group_fallback(group: &Group) -> Option<&'static str> {
+    if !group.filters().is_empty() {
+        Some("filters")
+    } else if group.mask().is_some() {
+        Some("masks")
+    } else if group.blend_mode() != usvg::BlendMode::Normal {
+        Some("blend modes")
+    } else if group.opacity().get() < 1.0 && !(group.children().len() <= 1 && fades_alone(group)) {
+        Some("group opacity")
+    } else if group
+        .clip_path()
+        .is_some_and(|clip| clip_shape(clip).is_none())
+    {
+        Some("clip paths")
+    } else {
+        None
+    }
+}
+
+// Whether a group draws at most one shape that doesn't overlap itself
+fn fades_alone(group: &Group) -> bool {
+    group.children().iter().all(|node| match node {
+        Node::Group(group) => group.children().len() <= 1 && fades_alone(group),
+        Node::Path(path) => path.fill().is_none() || path.stroke().is_none(),
+        Node::Image(_) => true,
+        Node::Text(_) => false,
+    })
+}
+
+// Why a path can't be drawn with path operators, if it can't
+fn path_fallback(path: &usvg::Path) -> Option<&'static str> {
+    let paints = [
+        path.fill().map(|fill| fill.paint()),
+        path.stroke().map(|stroke| stroke.paint()),
+    ];
+    paints.into_iter().flatten().find_map(|paint| match paint {
+        Paint::Color(_) => None,
+        Paint::LinearGradient(_) | Paint::RadialGradient(_) => Some("gradients"),
+        Paint::Pattern(_) => Some("patterns"),
+    })
+}
+
+// The path a clip path clips to and its transform, if it is a single
+// path, which is what a PDF clip can be
+fn clip_shape(clip: &ClipPath) -> Option<(Transform, &usvg::Path)> {
+    if clip.clip_path().is_some() {
+        return None;
+    }
+    let (mut transform, mut group) = (clip.transform(), clip.root());
+    loop {
+        if group.clip_path().is_some() || group.mask().is_some() || !group.filters().is_empty() {
+            return None;
+        }
+        transform = transform.pre_concat(group.transform());
+        match group.children() {
+            [Node::Path(path)] => return Some((transform, path)),
+            [Node::Group(inner)] => group = inner,
+            _ => return None,
+        }
+    }
+}
+
+impl Builder {
+    fn cm(&mut self, transform: Transform) {
+        if transform.is_identity() {
+            return;
+        }
+        let Transform {
+            sx,
+            ky,
+            kx,
+            sy,
+            tx,
+            ty,
+        } = transform;
+        self.op("cm", reals(&[sx, ky, kx, sy, tx, ty]));
+    }
+
+    fn op(&mut self, operator: &str, operands: Vec<Object>) {
+        self.operations.push(Operation::new(operator, operands));
+    }
+
+    // Set fill and stroke opacity, through a graphics state shared by
+    // everything drawn with the same ones
+    fn opacity(&mut self, fill: f32, stroke: f32) {
+        if (fill, stroke) == (1.0, 1.0) {
+            return;
+        }
+        let index = match self.opacities.iter().position(|&o| o == (fill, stroke)) {
+            Some(index) => index,
+            None => {
+                self.opacities.push((fill, stroke));
+                self.opacities.len() - 1
+            }
+        };
+        self.op("gs", vec![Object::Name(format!("GS{index}").into_bytes())]);
+    }
+
+    // `transform` takes the coordinates of the node's parent to the page
+    fn node(&mut self, node: &Node, transform: Transform, opacity: f32) -> Result<()> {
+        match node {
+            Node::Group(group) => match group_fallback(group) {
+                Some(reason) => self.rasterize(node, transform, opacity, reason),
+                None => self.group(group, transform, opacity),
+            },
+            Node::Path(path) => match path_fallback(path) {
+                Some(reason) => self.rasterize(node, transform, opacity, reason),
+                None => {
+                    self.path(path, opacity);
+                    Ok(())
+                }
+            },
+            Node::Image(_) => self.rasterize(node, transform, opacity, "embedded images"),
+            // Glyphs come as paths
+            Node::Text(text) => match group_fallback(text.flattened()) {
+                Some(reason) => self.rasterize(node, transform, opacity, reason),
+                None => self.group(text.flattened(), transform, opacity),
+            },
+        }
+    }
+
+    fn group(&mut self, group: &Group, transform: Transform, opacity: f32) -> Result<()> {
+        let wrap = !group.transform().is_identity() || group.clip_path().is_some();
+        if wrap {
+            self.op("q", vec![]);
+            self.cm(group.transform());
+        }
+        let transform = transform.pre_concat(group.transform());
+        let opacity = opacity * group.opacity().get();
+        let visible = group.clip_path().is_none_or(|clip| self.clip(clip));
+        if visible {
+            for child in group.children() {
+                self.node(child, transform, opacity)?;
+            }
+        }
+        if wrap {
+            self.op("Q", vec![]);
+        }
+        Ok(())
+    }
+
+    // Clip what follows to `clip`, see clip_shape. False when nothing is
+    // left to draw.
+    fn clip(&mut self, clip: &ClipPath) -> bool {
+        let Some((transform, path)) = clip_shape(clip) else {
+            return false;
+        };
+        let Some(inverse) = transform.invert().filter(|_| path.is_visible()) else {
+            return false;
+        };
+        self.cm(transform);
+        self.segments(path.data());
+        let rule = path.fill().map_or(FillRule::NonZero, |fill| fill.rule());
+        self.op(if rule == FillRule::EvenOdd { "W*" } else { "W" }, vec![]);
+        self.op("n", vec![]);
+        self.cm(inverse);
+        true
+    }
+
+    fn path(&mut self, path: &usvg::Path, opacity: f32) {
+        let (fill, stroke) = (path.fill(), path.stroke());
+        if !path.is_visible() || (fill.is_none() && stroke.is_none()) {
+            return;
+        }
+        self.op("q", vec![]);
+        self.opacity(
+            fill.map_or(1.0, |fill| fill.opacity().get()) * opacity,
+            stroke.map_or(1.0, |stroke| stroke.opacity().get()) * opacity,
+        );
+        if let Some(Paint::Color(color)) = fill.map(|fill| fill.paint()) {
+            self.op("rg", rgb(*color));
+        }
+        if let Some(stroke) = stroke {
+            if let Paint::Color(color) = stroke.paint() {
+                self.op("RG", rgb(*color));
+            }
+            self.op("w", reals(&[stroke.width().get()]));
+            let cap = match stroke.linecap() {
+                LineCap::Butt => 0,
+                LineCap::Round => 1,
+                LineCap::Square => 2,
+            };
+            let join = match stroke.linejoin() {
+                LineJoin::Miter | LineJoin::MiterClip => 0,
+                LineJoin::Round => 1,
+                LineJoin::Bevel => 2,
+            };
+            self.op("J", vec![Object::Integer(cap)]);
+            self.op("j", vec![Object::Integer(join)]);
+            self.op("M", reals(&[stroke.miterlimit().get()]));
+            if let Some(dashes) = stroke.dasharray() {
+                let dashes = Object::Array(reals(dashes));
+                self.op("d", vec![dashes, Object::Real(stroke.dashoffset())]);
+            }
+        }
+        let even_odd = fill.is_some_and(|fill| fill.rule() == FillRule::EvenOdd);
+        let fill_op = if even_odd { "f*" } else { "f" };
+        self.segments(path.data());
+        match (fill.is_some(), stroke.is_some(), path.paint_order()) {
+            (true, false, _) => self.op(fill_op, vec![]),
+            (false, _, _) => self.op("S", vec![]),
+            (true, true, PaintOrder::FillAndStroke) => {
+                self.op(if even_odd { "B*" } else { "B" }, vec![])
+            }
+            (true, true, PaintOrder::StrokeAndFill) => {
+                self.op("S", vec![]);
+                self.segments(path.data());
+                self.op(fill_op, vec![]);
+            }
+        }
+        self.op("Q", vec![]);
+    }
+
+    // Quadratic curves become cubic ones, which are all PDF has
+    fn segments(&mut self, data: &usvg::tiny_skia_path::Path) {
+        let (mut start, mut last) = ((0.0, 0.0), (0.0, 0.0));
+        for segment in data.segments() {
+            match segment {
+                PathSegment::MoveTo(p) => {
+                    self.op("m", reals(&[p.x, p.y]));
+                    (start, last) = ((p.x, p.y), (p.x, p.y));
+                }
+                PathSegment::LineTo(p) => {
+                    self.op("l", reals(&[p.x, p.y]));
+                    last = (p.x, p.y);
+                }
+                PathSegment::QuadTo(c, p) => {
+                    let toward = |from: f32, to: f32| from + (to - from) * 2.0 / 3.0;
+                    let controls = [
+                        toward(last.0, c.x),
+                        toward(last.1, c.y),
+                        toward(p.x, c.x),
+                        toward(p.y, c.y),
+                    ];
+                    self.op("c", reals(&[&controls[..], &[p.x, p.y]].concat()));
+                    last = (p.x, p.y);
+                }
+                PathSegment::CubicTo(c1, c2, p) => {
+                    self.op("c", reals(&[c1.x, c1.y, c2.x, c2.y, p.x, p.y]));
+                    last = (p.x, p.y);
+                }
+                PathSegment::Close => {
+                    self.op("h", vec![]);
+                    last = start;
+                }
+            }
+        }
+    }
+
+    // Draw `node` as an image of the part of the page it covers
+    fn rasterize(
+        &mut self,
+        node: &Node,
+        transform: Transform,
+        opacity: f32,
+        reason: &'static str,
+    ) -> Result<()> {
+        self.rasterized.insert(reason);
+        // Where the node draws, in its parent's coordinates
+        let bounds = match node {
+            Node::Group(group) => group
+                .layer_bounding_box()
+                .to_rect()
+                .transform(group.transform()),
+            Node::Path(path) => Some(path.stroke_bounding_box()),
+            Node::Image(image) => Some(image.bounding_box()),
+            Node::Text(text) => {
+                let group = text.flattened();
+                group
+                    .layer_bounding_box()
+                    .to_rect()
+                    .transform(group.transform())
+            }
+        };
+        let Some(bounds) = bounds
+            .and_then(|bounds| bounds.transform(transform))
+            .and_then(|bounds| bounds.intersect(&self.page?))
+        else {
+            return Ok(());
+        };
+        let (Some(inverse), Some(canvas)) = (transform.invert(), node.abs_layer_bounding_box())
+        else {
+            return Ok(());
+        };
+
+        // Whole pixels around the bounds
+        let resolution = self.resolution;
+        let left = (bounds.left() * resolution).floor();
+        let top = (bounds.top() * resolution).floor();
+        let width = ((bounds.right() * resolution).ceil() - left).max(1.0);
+        let height = ((bounds.bottom() * resolution).ceil() - top).max(1.0);
+        let Some(mut pixmap) = Pixmap::new(width as u32, height as u32) else {
+            anyhow::bail!("Failed to create a {width}x{height} pixel buffer for {reason}");
+        };
+        // render_node starts at the node's canvas bounds; undo that
+        let render = Transform::from_translate(-left, -top)
+            .pre_scale(resolution, resolution)
+            .pre_concat(transform)
+            .pre_translate(canvas.x(), canvas.y());
+        resvg::render_node(node, render, &mut pixmap.as_mut());
+
+        let (mut rgb, mut alpha) = (Vec::new(), Vec::new());
+        for pixel in pixmap.pixels() {
+            let pixel = pixel.demultiply();
+            rgb.extend([pixel.red(), pixel.green(), pixel.blue()]);
+            alpha.push(pixel.alpha());
+        }
+        if alpha.iter().all(|&alpha| alpha == 0) {
+            return Ok(());
+        }
+        let deflate = |data: &[u8]| -> Result<Vec<u8>> {
+            let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            Ok(encoder.finish()?)
+        };
+        let index = self.images.len();
+        self.images.push(FallbackImage {
+            width: width as u32,
+            height: height as u32,
+            rgb: deflate(&rgb)?,
+            alpha: deflate(&alpha)?,
+        });
+
+        // Back to page points, where the image's unit square is flipped
+        // onto its pixels
+        let (x, y) = (left / resolution, top / resolution);
+        let (w, h) = (width / resolution, height / resolution);
+        self.op("q", vec![]);
+        self.opacity(opacity, opacity);
+        self.cm(inverse.pre_concat(Transform::from_row(w, 0.0, 0.0, -h, x, y + h)));
+        self.op("Do", vec![Object::Name(format!("Im{index}").into_bytes())]);
+        self.op("Q", vec![]);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_vector_pages() {
+    use lopdf::content::Content;
+
+    let opt = usvg::Options::default();
+    let draw_on = |svg: &str, background: Option<[u8; 3]>| {
+        let tree = Tree::from_str(svg, &opt).unwrap();
+        let size = tree.size();
+        let page = page(
+            &tree,
+            Transform::from_scale(0.5, 0.5),
+            size.width() / 2.0,
+            size.height() / 2.0,
+            1.0,
+            background,
+        )
+        .unwrap();
+        let operators: Vec<String> = Content::decode(&page.content)
+            .unwrap()
+            .operations
+            .into_iter()
+            .map(|op| op.operator)
+            .collect();
+        (page, operators.join(" "))
+    };
+    let draw = |svg: &str| draw_on(svg, None);
+
+    // Shapes and curves are paths, nothing is an image
+    let (page, operators) = draw(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="100">
+<rect x="10" y="10" width="50" height="30" fill="red"/>
+<circle cx="100" cy="50" r="20" fill="none" stroke="blue" stroke-width="4" opacity="0.5"/>
+<path d="M 150 10 Q 190 50 150 90 C 120 60 120 40 150 10 Z" fill-rule="evenodd"/>
+<g clip-path="url(#c)"><clipPath id="c"><rect width="20" height="20"/></clipPath><rect width="40" height="40" fill="green"/></g>
+</svg>"#,
+    );
+    assert!(page.images.is_empty() && page.rasterized.is_empty());
+    assert_eq!(page.opacities, [(0.5, 0.5)]);
+    assert!(operators.starts_with("cm cm "), "{operators}");
+    for expected in [
+        "rg m l l l h f Q",
+        "gs RG w J j M m c c c c h S",
+        "c c h f*",
+        "W n",
+    ] {
+        assert!(operators.contains(expected), "{expected} in {operators}");
+    }
+
+    // Gradients and filters fall back to an image of their area
+    let (page, operators) = draw(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="100">
+<linearGradient id="g"><stop offset="0" stop-color="red"/><stop offset="1" stop-color="blue"/></linearGradient>
+<filter id="blur"><feGaussianBlur stdDeviation="2"/></filter>
+<rect x="20" y="20" width="40" height="20" fill="url(#g)"/>
+<rect x="100" y="20" width="40" height="40" filter="url(#blur)"/>
+<rect x="500" y="20" width="40" height="40" fill="url(#g)"/>
+</svg>"#,
+    );
+    assert_eq!(
+        page.rasterized.into_iter().collect::<Vec<_>>(),
+        ["filters", "gradients"]
+    );
+    // The one off the page is left out
+    assert_eq!(page.images.len(), 2);
+    assert_eq!((page.images[0].width, page.images[0].height), (20, 10));
+    assert_eq!(operators.matches("Do").count(), 2);
+
+    // A background is filled in before the drawing
+    let (_, operators) = draw_on(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10"><rect width="5" height="5"/></svg>"#,
+        Some([30, 30, 30]),
+    );
+    assert!(operators.starts_with("rg re f cm cm "), "{operators}");
+}
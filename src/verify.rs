@@ -0,0 +1,649 @@
+use crate::bilevel;
+use crate::paths;
+use crate::writer::Format;
+use anyhow::{Context, Result};
+use flate2::read::ZlibDecoder;
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+// Problems listed in the error of a document that fails verification
+const MAX_LISTED: usize = 10;
+
+// A page tree deeper than this has a cycle, or was not written by us
+const MAX_DEPTH: usize = 64;
+
+// Read back the document written to `path` and check that it holds
+// `pages` pages that decode. A document that doesn't is moved to
+// `<name>.invalid`, so it is neither used nor lost, and the run fails.
+pub fn verify_output(path: &Path, format: Format, pages: usize) -> Result<()> {
+    let data = fs::read(paths::long_path(path))
+        .with_context(|| format!("Failed to read back output file: {:?}", path))?;
+    let problems = check(format, &data, pages);
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    let invalid = invalid_path(path);
+    fs::rename(paths::long_path(path), paths::long_path(&invalid))
+        .with_context(|| format!("Failed to move aside output file: {:?}", path))?;
+    let mut listed: Vec<_> = problems.iter().take(MAX_LISTED).cloned().collect();
+    if problems.len() > MAX_LISTED {
+        listed.push(format!("and {} more", problems.len() - MAX_LISTED));
+    }
+    anyhow::bail!(
+        "{} {:?} failed verification and was moved to {:?}:\n  {}",
+        format.name(),
+        path,
+        invalid,
+        listed.join("\n  ")
+    )
+}
+
+fn invalid_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".invalid");
+    path.with_file_name(name)
+}
+
+// Everything wrong with a document of `format` that should have `pages`
+// pages; empty when it is sound
+pub fn check(format: Format, data: &[u8], pages: usize) -> Vec<String> {
+    match format {
+        Format::Pdf => check_pdf(data, pages),
+        Format::Tiff => check_tiff(data, pages),
+        Format::Cbz => check_cbz(data, pages),
+    }
+}
+
+fn check_pdf(data: &[u8], pages: usize) -> Vec<String> {
+    let doc = match Document::load_mem(data) {
+        Ok(doc) => doc,
+        Err(err) => return vec![format!("can't be parsed: {err}")],
+    };
+    let mut problems = Vec::new();
+    let Ok(catalog) = doc.catalog() else {
+        return vec!["has no catalog".to_string()];
+    };
+    if !is_name(catalog, b"Type", b"Catalog") {
+        problems.push("catalog is not of type Catalog".to_string());
+    }
+    let Ok(root) = catalog.get(b"Pages").and_then(Object::as_reference) else {
+        problems.push("catalog has no page tree".to_string());
+        return problems;
+    };
+
+    let mut found = Vec::new();
+    let mut visited = HashSet::new();
+    walk_pages(&doc, root, None, 0, &mut visited, &mut found, &mut problems);
+    if found.len() != pages {
+        problems.push(format!("has {} pages, {} were written", found.len(), pages));
+    }
+
+    // Copies share their image, which only needs checking once
+    let mut images = HashSet::new();
+    for (index, &page) in found.iter().enumerate() {
+        if let Err(problem) = check_page(&doc, page, &mut images) {
+            problems.push(format!("page {}: {}", index + 1, problem));
+        }
+    }
+    problems
+}
+
+// Collect the pages below `node` in order, checking that every node links
+// back to its parent and counts the pages below it
+fn walk_pages(
+    doc: &Document,
+    node: ObjectId,
+    parent: Option<ObjectId>,
+    depth: usize,
+    visited: &mut HashSet<ObjectId>,
+    pages: &mut Vec<ObjectId>,
+    problems: &mut Vec<String>,
+) {
+    if depth > MAX_DEPTH || !visited.insert(node) {
+        problems.push(format!("page tree loops back to object {:?}", node));
+        return;
+    }
+    let Ok(dict) = doc.get_dictionary(node) else {
+        problems.push(format!("page tree node {:?} is missing", node));
+        return;
+    };
+    let linked = dict.get(b"Parent").and_then(Object::as_reference).ok();
+    if linked != parent {
+        problems.push(format!("page tree node {:?} has the wrong parent", node));
+    }
+    if is_name(dict, b"Type", b"Page") {
+        pages.push(node);
+        return;
+    }
+    if !is_name(dict, b"Type", b"Pages") {
+        problems.push(format!(
+            "page tree node {:?} is neither Pages nor Page",
+            node
+        ));
+        return;
+    }
+    let Ok(kids) = dict.get(b"Kids").and_then(Object::as_array) else {
+        problems.push(format!("page tree node {:?} has no kids", node));
+        return;
+    };
+    let before = pages.len();
+    for kid in kids {
+        match kid.as_reference() {
+            Ok(kid) => walk_pages(doc, kid, Some(node), depth + 1, visited, pages, problems),
+            Err(_) => problems.push(format!(
+                "page tree node {:?} has a kid that is no reference",
+                node
+            )),
+        }
+    }
+    let count = dict.get(b"Count").and_then(Object::as_i64).ok();
+    if count != Some((pages.len() - before) as i64) {
+        problems.push(format!(
+            "page tree node {:?} counts {:?} pages, has {}",
+            node,
+            count,
+            pages.len() - before
+        ));
+    }
+}
+
+// The first problem of a page: its box, content stream, the images it
+// draws and its annotations
+fn check_page(
+    doc: &Document,
+    page: ObjectId,
+    images: &mut HashSet<ObjectId>,
+) -> Result<(), String> {
+    let dict = doc.get_dictionary(page).map_err(|err| err.to_string())?;
+    let media_box: Vec<f32> = dict
+        .get(b"MediaBox")
+        .and_then(Object::as_array)
+        .map_err(|_| "no MediaBox")?
+        .iter()
+        .map(Object::as_float)
+        .collect::<Result<_, _>>()
+        .map_err(|_| "MediaBox is not numbers")?;
+    match media_box[..] {
+        [x0, y0, x1, y1] if x1 > x0 && y1 > y0 => {}
+        _ => return Err(format!("MediaBox {:?} is empty", media_box)),
+    }
+
+    let resources = resolve(doc, dict.get(b"Resources").map_err(|_| "no Resources")?)
+        .and_then(Object::as_dict)
+        .map_err(|_| "Resources are not a dictionary")?;
+    let contents = resolve(doc, dict.get(b"Contents").map_err(|_| "no Contents")?)
+        .and_then(Object::as_stream)
+        .map_err(|_| "Contents are not a stream")?;
+    let content = match contents.filters().ok().as_deref() {
+        None | Some([]) => contents.content.clone(),
+        Some([filter]) if filter == "FlateDecode" => {
+            inflate(&contents.content).map_err(|err| format!("Contents don't decode: {err}"))?
+        }
+        Some(filters) => return Err(format!("Contents use unexpected filters {:?}", filters)),
+    };
+    let content = Content::decode(&content).map_err(|_| "Contents don't parse")?;
+
+    for operation in content.operations.iter().filter(|op| op.operator == "Do") {
+        let name = operation
+            .operands
+            .first()
+            .and_then(|name| name.as_name().ok())
+            .ok_or("Do without an XObject name")?;
+        let image = resources
+            .get(b"XObject")
+            .and_then(|xobjects| resolve(doc, xobjects))
+            .and_then(Object::as_dict)
+            .and_then(|xobjects| xobjects.get(name))
+            .and_then(Object::as_reference)
+            .map_err(|_| {
+                format!(
+                    "draws XObject {} that isn't in its Resources",
+                    String::from_utf8_lossy(name)
+                )
+            })?;
+        if images.insert(image) {
+            check_image(doc, image)?;
+        }
+    }
+
+    // Annotations belong to the page and have an area on it
+    let Ok(annots) = dict.get(b"Annots") else {
+        return Ok(());
+    };
+    let annots = resolve(doc, annots)
+        .and_then(Object::as_array)
+        .map_err(|_| "Annots are not an array")?;
+    for annot in annots {
+        let id = annot
+            .as_reference()
+            .map_err(|_| "annotation is not an object")?;
+        let annot = doc
+            .get_dictionary(id)
+            .map_err(|_| format!("annotation {:?} is missing", id))?;
+        let rect = annot
+            .get(b"Rect")
+            .and_then(Object::as_array)
+            .ok()
+            .filter(|rect| rect.len() == 4 && rect.iter().all(|v| v.as_float().is_ok()));
+        if annot.get(b"Subtype").and_then(Object::as_name).is_err() || rect.is_none() {
+            return Err(format!("annotation {:?} has no Subtype or Rect", id));
+        }
+        if annot.get(b"P").and_then(Object::as_reference).ok() != Some(page) {
+            return Err(format!("annotation {:?} belongs to another page", id));
+        }
+    }
+    Ok(())
+}
+
+// Whether an image stream decodes to the samples its dictionary declares
+fn check_image(doc: &Document, id: ObjectId) -> Result<(), String> {
+    let stream = doc
+        .get_object(id)
+        .and_then(Object::as_stream)
+        .map_err(|_| format!("image {:?} is not a stream", id))?;
+    let dict = &stream.dict;
+    let number = |key: &[u8]| dict.get(key).and_then(Object::as_i64).ok();
+    let (Some(width), Some(height)) = (number(b"Width"), number(b"Height")) else {
+        return Err(format!("image {:?} has no size", id));
+    };
+    if width <= 0 || height <= 0 {
+        return Err(format!("image {:?} is {}x{}", id, width, height));
+    }
+    let filters = stream.filters().unwrap_or_default();
+    let filters: Vec<&str> = filters.iter().map(String::as_str).collect();
+    // Bilevel pages, the one kind that isn't RGB
+    if filters[..] == ["CCITTFaxDecode"] {
+        if !is_name(dict, b"ColorSpace", b"DeviceGray") || number(b"BitsPerComponent") != Some(1) {
+            return Err(format!("image {:?} is CCITT but not 1-bit gray", id));
+        }
+        let parms = dict.get(b"DecodeParms").and_then(Object::as_dict).ok();
+        let parm = |key: &[u8]| parms.and_then(|parms| parms.get(key).ok()?.as_i64().ok());
+        if parm(b"K") != Some(-1) || parm(b"Columns") != Some(width) {
+            return Err(format!(
+                "image {:?} is not Group 4 {} pixels wide",
+                id, width
+            ));
+        }
+        return match bilevel::decode_g4(&stream.content, width as u32, height as u32) {
+            Some(_) => Ok(()),
+            None => Err(format!(
+                "image {:?} doesn't decode to {} rows of Group 4",
+                id, height
+            )),
+        };
+    }
+    if !is_name(dict, b"ColorSpace", b"DeviceRGB") || number(b"BitsPerComponent") != Some(8) {
+        return Err(format!("image {:?} is not 8-bit RGB", id));
+    }
+    let (width, height) = (width as usize, height as usize);
+    if let Ok(mask) = dict.get(b"SMask") {
+        let mask = mask
+            .as_reference()
+            .map_err(|_| format!("image {:?} has an SMask that is no reference", id))?;
+        check_mask(doc, mask, width, height)?;
+    }
+    match filters[..] {
+        [] => expect_length(id, stream.content.len(), width * height * 3),
+        ["FlateDecode"] => {
+            let samples = inflate(&stream.content)
+                .map_err(|err| format!("image {:?} doesn't inflate: {err}", id))?;
+            let predictor = dict
+                .get(b"DecodeParms")
+                .and_then(Object::as_dict)
+                .and_then(|parms| parms.get(b"Predictor"))
+                .and_then(Object::as_i64)
+                .unwrap_or(1);
+            if predictor < 10 {
+                return expect_length(id, samples.len(), width * height * 3);
+            }
+            // PNG predictors start every row with the filter it uses
+            let row = 1 + width * 3;
+            expect_length(id, samples.len(), row * height)?;
+            if samples.chunks(row).any(|row| row[0] > 4) {
+                return Err(format!("image {:?} has rows with unknown PNG filters", id));
+            }
+            Ok(())
+        }
+        ["DCTDecode"] => match jpeg_size(&stream.content) {
+            Some(size) if size == (width, height, 3) => Ok(()),
+            Some(size) => Err(format!(
+                "image {:?} is declared {}x{} RGB, its JPEG is {}x{} with {} components",
+                id, width, height, size.0, size.1, size.2
+            )),
+            None => Err(format!("image {:?} is not a JPEG", id)),
+        },
+        ["JPXDecode"] => match jpx_size(&stream.content) {
+            Some(size) if size == (width, height, 3) => Ok(()),
+            Some(size) => Err(format!(
+                "image {:?} is declared {}x{} RGB, its JPEG 2000 is {}x{} with {} 8-bit components",
+                id, width, height, size.0, size.1, size.2
+            )),
+            None => Err(format!(
+                "image {:?} is not an 8-bit JPEG 2000 codestream",
+                id
+            )),
+        },
+        _ => Err(format!(
+            "image {:?} uses unexpected filters {:?}",
+            id, filters
+        )),
+    }
+}
+
+// Whether the soft mask of a `width` by `height` image covers it with
+// 8-bit gray samples, as vector pages write their fallback images
+fn check_mask(doc: &Document, id: ObjectId, width: usize, height: usize) -> Result<(), String> {
+    let stream = doc
+        .get_object(id)
+        .and_then(Object::as_stream)
+        .map_err(|_| format!("soft mask {:?} is not a stream", id))?;
+    let dict = &stream.dict;
+    let number = |key: &[u8]| dict.get(key).and_then(Object::as_i64).ok();
+    if number(b"Width") != Some(width as i64) || number(b"Height") != Some(height as i64) {
+        return Err(format!(
+            "soft mask {:?} is not the {}x{} of its image",
+            id, width, height
+        ));
+    }
+    if !is_name(dict, b"ColorSpace", b"DeviceGray") || number(b"BitsPerComponent") != Some(8) {
+        return Err(format!("soft mask {:?} is not 8-bit gray", id));
+    }
+    let filters = stream.filters().unwrap_or_default();
+    match filters.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => expect_length(id, stream.content.len(), width * height),
+        ["FlateDecode"] => {
+            let samples = inflate(&stream.content)
+                .map_err(|err| format!("soft mask {:?} doesn't inflate: {err}", id))?;
+            expect_length(id, samples.len(), width * height)
+        }
+        _ => Err(format!(
+            "soft mask {:?} uses unexpected filters {:?}",
+            id, filters
+        )),
+    }
+}
+
+fn expect_length(id: ObjectId, length: usize, expected: usize) -> Result<(), String> {
+    if length == expected {
+        return Ok(());
+    }
+    Err(format!(
+        "image {:?} decodes to {} bytes, expected {}",
+        id, length, expected
+    ))
+}
+
+fn resolve<'a>(doc: &'a Document, object: &'a Object) -> lopdf::Result<&'a Object> {
+    doc.dereference(object).map(|(_, object)| object)
+}
+
+fn is_name(dict: &Dictionary, key: &[u8], name: &[u8]) -> bool {
+    dict.get(key).and_then(Object::as_name).ok() == Some(name)
+}
+
+// Unlike lopdf, fails on truncated or corrupt data instead of returning
+// what it got
+fn inflate(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut inflated = Vec::with_capacity(data.len() * 2);
+    ZlibDecoder::new(data).read_to_end(&mut inflated)?;
+    Ok(inflated)
+}
+
+// Width, height and components from the SIZ marker of a JPEG 2000
+// codestream, which follows its start; None unless every component has 8
+// unsigned bits
+fn jpx_size(data: &[u8]) -> Option<(usize, usize, usize)> {
+    if !data.starts_with(&[0xff, 0x4f, 0xff, 0x51]) {
+        return None;
+    }
+    let number = |at: usize| {
+        let bytes = data.get(at..at + 4)?;
+        Some(u32::from_be_bytes(bytes.try_into().ok()?) as usize)
+    };
+    let width = number(8)?.checked_sub(number(16)?)?;
+    let height = number(12)?.checked_sub(number(20)?)?;
+    let components = u16::from_be_bytes([*data.get(40)?, *data.get(41)?]) as usize;
+    // Ssiz, then horizontal and vertical sampling, per component
+    let sizes = data.get(42..42 + components * 3)?;
+    if sizes.chunks(3).any(|size| size[0] != 7) {
+        return None;
+    }
+    Some((width, height, components))
+}
+
+// Width, height and color components from the frame header of a JPEG
+fn jpeg_size(data: &[u8]) -> Option<(usize, usize, usize)> {
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return None;
+    }
+    let mut at = 2;
+    while at + 4 <= data.len() {
+        if data[at] != 0xff {
+            return None;
+        }
+        let marker = data[at + 1];
+        let length = u16::from_be_bytes([data[at + 2], data[at + 3]]) as usize;
+        // Start of frame, except the DHT, JPG and DAC markers in that range
+        if (0xc0..=0xcf).contains(&marker) && ![0xc4, 0xc8, 0xcc].contains(&marker) {
+            let frame = data.get(at + 4..at + 10)?;
+            let height = u16::from_be_bytes([frame[1], frame[2]]) as usize;
+            let width = u16::from_be_bytes([frame[3], frame[4]]) as usize;
+            return Some((width, height, frame[5] as usize));
+        }
+        at += 2 + length;
+    }
+    None
+}
+
+fn check_tiff(data: &[u8], pages: usize) -> Vec<String> {
+    let mut decoder = match tiff::decoder::Decoder::new(Cursor::new(data)) {
+        Ok(decoder) => decoder,
+        Err(err) => return vec![format!("can't be parsed: {err}")],
+    };
+    let mut problems = Vec::new();
+    let mut found = 0;
+    loop {
+        found += 1;
+        if let Err(err) = check_tiff_page(&mut decoder, data) {
+            problems.push(format!("page {found}: {err}"));
+        }
+        if !decoder.more_images() {
+            break;
+        }
+        if let Err(err) = decoder.next_image() {
+            problems.push(format!("page {}: {err}", found + 1));
+            break;
+        }
+    }
+    if found != pages {
+        problems.push(format!("has {} pages, {} were written", found, pages));
+    }
+    problems
+}
+
+// Whether the page `decoder` is at decodes. The decoder has no Group 4,
+// so the strip of a bilevel page is decoded here.
+fn check_tiff_page(
+    decoder: &mut tiff::decoder::Decoder<Cursor<&[u8]>>,
+    data: &[u8],
+) -> Result<(), String> {
+    use tiff::tags::Tag;
+
+    if decoder.get_tag_u32(Tag::Compression).ok() != Some(4) {
+        return decoder
+            .read_image()
+            .map(drop)
+            .map_err(|err| err.to_string());
+    }
+    let (width, height) = decoder.dimensions().map_err(|err| err.to_string())?;
+    let mut strip = |tag| decoder.get_tag_u32_vec(tag).map_err(|err| err.to_string());
+    let (offsets, counts) = (strip(Tag::StripOffsets)?, strip(Tag::StripByteCounts)?);
+    let ([offset], [count]) = (&offsets[..], &counts[..]) else {
+        return Err("Group 4 page is not a single strip".to_string());
+    };
+    let coded = data
+        .get(*offset as usize..(*offset as usize).saturating_add(*count as usize))
+        .ok_or("Group 4 strip ends past the file")?;
+    match bilevel::decode_g4(coded, width, height) {
+        Some(_) => Ok(()),
+        None => Err(format!("doesn't decode to {height} rows of Group 4")),
+    }
+}
+
+fn check_cbz(data: &[u8], pages: usize) -> Vec<String> {
+    let mut archive = match zip::ZipArchive::new(Cursor::new(data)) {
+        Ok(archive) => archive,
+        Err(err) => return vec![format!("can't be parsed: {err}")],
+    };
+    let mut problems = Vec::new();
+    for index in 0..archive.len() {
+        let mut png = Vec::new();
+        let read = archive
+            .by_index(index)
+            .map_err(|err| err.to_string())
+            .and_then(|mut file| file.read_to_end(&mut png).map_err(|err| err.to_string()));
+        let decoded = read.and_then(|_| {
+            resvg::tiny_skia::Pixmap::decode_png(&png).map_err(|err| err.to_string())
+        });
+        if let Err(err) = decoded {
+            problems.push(format!("page {}: {err}", index + 1));
+        }
+    }
+    if archive.len() != pages {
+        problems.push(format!(
+            "has {} pages, {} were written",
+            archive.len(),
+            pages
+        ));
+    }
+    problems
+}
+
+#[test]
+fn test_verifies_written_documents() {
+    use crate::convert::RenderedImage;
+    use crate::print::PrintSetup;
+    use crate::writer::{ColorMode, ImageFormat, ImageOptions, TiffCompression};
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    let image = |width: u32| RenderedImage {
+        width,
+        height: 3,
+        rgb_data: (0..width * 9).map(|byte| (byte * 37) as u8).collect(),
+        alpha: None,
+    };
+    let write = |format: Format, images: ImageOptions, copies: u32| {
+        let mut writer = format.writer(
+            TiffCompression::Lzw,
+            images,
+            1.0,
+            None,
+            PrintSetup::default(),
+        );
+        let encoder = writer.encoder();
+        for width in [4, 5] {
+            writer
+                .add_copies(encoder.encode(&image(width), "a.svg").unwrap(), copies)
+                .unwrap();
+        }
+        let fill = encoder.encode_fill(6, 6, [10, 20, 30]);
+        if let Some(fill) = fill {
+            writer.add_page(fill).unwrap();
+        }
+        let mut out = Vec::new();
+        writer.finish(&mut out).unwrap();
+        out
+    };
+    let formats = [ImageFormat::Flate, ImageFormat::Jpeg, ImageFormat::Raw];
+    #[cfg(feature = "jp2")]
+    let formats = [&formats[..], &[ImageFormat::Jp2]].concat();
+    for format in formats {
+        let images = ImageOptions {
+            format,
+            ..ImageOptions::default()
+        };
+        let pdf = write(Format::Pdf, images, 2);
+        assert_eq!(
+            check(Format::Pdf, &pdf, 5),
+            Vec::<String>::new(),
+            "{format:?}"
+        );
+        assert_eq!(check(Format::Pdf, &pdf, 6), ["has 5 pages, 6 were written"]);
+    }
+    let tiff = write(Format::Tiff, ImageOptions::default(), 1);
+    assert!(check(Format::Tiff, &tiff, 2).is_empty());
+    assert_eq!(check(Format::Tiff, &tiff, 3).len(), 1);
+    // Bilevel pages are Group 4 in both
+    let bilevel = ImageOptions {
+        color_mode: ColorMode::Bilevel,
+        ..ImageOptions::default()
+    };
+    let pdf = write(Format::Pdf, bilevel.clone(), 2);
+    assert_eq!(check(Format::Pdf, &pdf, 5), Vec::<String>::new());
+    let tiff = write(Format::Tiff, bilevel, 1);
+    assert_eq!(check(Format::Tiff, &tiff, 2), Vec::<String>::new());
+    let cbz = write(Format::Cbz, ImageOptions::default(), 1);
+    assert!(check(Format::Cbz, &cbz, 2).is_empty());
+    assert!(!check(Format::Cbz, &cbz[..cbz.len() / 2], 2).is_empty());
+
+    // A stream with a wrong length, a page without its parent and a
+    // miscounted tree are all reported
+    let pdf = write(Format::Pdf, ImageOptions::default(), 1);
+    let mut doc = Document::load_mem(&pdf).unwrap();
+    let pages: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    let page = doc.get_dictionary(pages[0]).unwrap();
+    let xobjects = page.get(b"Resources").unwrap().as_reference().unwrap();
+    let xobjects = doc
+        .get_dictionary(xobjects)
+        .unwrap()
+        .get(b"XObject")
+        .unwrap();
+    let image_id = xobjects
+        .as_dict()
+        .unwrap()
+        .get(b"Im1")
+        .unwrap()
+        .as_reference()
+        .unwrap();
+    let stream = doc
+        .get_object_mut(image_id)
+        .unwrap()
+        .as_stream_mut()
+        .unwrap();
+    let mut truncated = ZlibEncoder::new(Vec::new(), Compression::fast());
+    truncated.write_all(&[1; 20]).unwrap();
+    stream.set_content(truncated.finish().unwrap());
+    doc.get_dictionary_mut(pages[1]).unwrap().remove(b"Parent");
+    let mut broken = Vec::new();
+    doc.save_to(&mut broken).unwrap();
+    let problems = check(Format::Pdf, &broken, 3);
+    assert_eq!(problems.len(), 2, "{problems:?}");
+    assert!(problems[0].contains("has the wrong parent"), "{problems:?}");
+    assert!(problems[1].starts_with("page 1: image"), "{problems:?}");
+    assert!(
+        problems[1].contains("decodes to 20 bytes, expected 39"),
+        "{problems:?}"
+    );
+    assert_eq!(check(Format::Pdf, b"%PDF-1.5\nnot really", 1).len(), 1);
+
+    // A failed document is moved aside and fails the run
+    let dir = std::env::temp_dir().join(format!("svg2pdf-verify-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let output = dir.join("out.pdf");
+    fs::write(&output, &pdf).unwrap();
+    verify_output(&output, Format::Pdf, 3).unwrap();
+    fs::write(&output, &broken).unwrap();
+    let err = verify_output(&output, Format::Pdf, 3)
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("failed verification"), "{err}");
+    assert!(!output.exists());
+    assert_eq!(fs::read(dir.join("out.pdf.invalid")).unwrap(), broken);
+    fs::remove_dir_all(&dir).unwrap();
+}
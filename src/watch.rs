@@ -0,0 +1,182 @@
+use crate::convert::{self, RenderArgs, RunOptions};
+use crate::inputs::Inputs;
+use anyhow::{Context, Result};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Quiet period after the last event before a rebuild starts
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+// How often the loop wakes up to check for Ctrl-C
+const POLL: Duration = Duration::from_millis(200);
+
+// Build once, then rebuild `output` whenever an SVG `inputs` may name is
+// added, changed or removed, until interrupted with Ctrl-C. Every build
+// runs with `run`, as a single conversion would; unchanged pages are kept
+// in its cache between builds, which needs a memory layer for that.
+pub fn run(
+    opt: &Arc<resvg::usvg::Options<'static>>,
+    inputs: &Inputs,
+    output: &Path,
+    args: &RenderArgs,
+    run: &RunOptions,
+) -> Result<()> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    {
+        let interrupted = Arc::clone(&interrupted);
+        ctrlc::set_handler(move || interrupted.store(true, Ordering::SeqCst))
+            .context("Failed to install Ctrl-C handler")?;
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            let _ = tx.send(event);
+        }
+    })
+    .context("Failed to start file watcher")?;
+    let watched = inputs.watched();
+    for (path, recursive) in &watched {
+        let mode = match recursive {
+            true => RecursiveMode::Recursive,
+            false => RecursiveMode::NonRecursive,
+        };
+        watcher
+            .watch(path, mode)
+            .with_context(|| format!("Failed to watch {:?}", path))?;
+    }
+
+    let mut build = 1;
+    rebuild(build, inputs, output, args, opt, run);
+    let watched: Vec<_> = watched
+        .iter()
+        .map(|(path, _)| format!("{path:?}"))
+        .collect();
+    println!(
+        "Watching {} for changes (Ctrl-C to stop)",
+        watched.join(", ")
+    );
+
+    while !interrupted.load(Ordering::SeqCst) {
+        let event = match rx.recv_timeout(POLL) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if !is_relevant(&event) {
+            continue;
+        }
+
+        // Swallow the rest of the burst so one save triggers one rebuild
+        let mut quiet_since = Instant::now();
+        while quiet_since.elapsed() < DEBOUNCE && !interrupted.load(Ordering::SeqCst) {
+            if let Ok(event) = rx.recv_timeout(DEBOUNCE - quiet_since.elapsed()) {
+                if is_relevant(&event) {
+                    quiet_since = Instant::now();
+                }
+            }
+        }
+        if interrupted.load(Ordering::SeqCst) {
+            break;
+        }
+
+        build += 1;
+        rebuild(build, inputs, output, args, opt, run);
+    }
+
+    println!("Stopped watching.");
+    Ok(())
+}
+
+fn is_relevant(event: &notify::Event) -> bool {
+    matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+    ) && event.paths.iter().any(|path| convert::is_svg(path))
+}
+
+// Run one build, reporting failures without stopping the watcher
+fn rebuild(
+    build: usize,
+    inputs: &Inputs,
+    output: &Path,
+    args: &RenderArgs,
+    opt: &Arc<resvg::usvg::Options<'static>>,
+    run: &RunOptions,
+) {
+    let started = Instant::now();
+    match convert::convert_dir(opt, inputs, output, args, run) {
+        Ok(conversion) => println!(
+            "#{build} {:?}: {} pages ({} rendered, {} reused) in {:.2} s",
+            output,
+            conversion.pages.len(),
+            conversion.rendered(),
+            conversion.cache_hits + conversion.dedupe_hits,
+            started.elapsed().as_secs_f64()
+        ),
+        Err(err) => eprintln!("#{build} failed: {:#}", err),
+    }
+}
+
+#[test]
+fn test_only_svg_events_trigger_rebuilds() {
+    use notify::event::{CreateKind, ModifyKind};
+
+    let event = |kind, path: &str| notify::Event::new(kind).add_path(path.into());
+    assert!(is_relevant(&event(
+        EventKind::Modify(ModifyKind::Any),
+        "dir/a.SVG"
+    )));
+    assert!(is_relevant(&event(
+        EventKind::Create(CreateKind::File),
+        "dir/b.svg"
+    )));
+    assert!(!is_relevant(&event(
+        EventKind::Modify(ModifyKind::Any),
+        "dir/out.pdf"
+    )));
+    assert!(!is_relevant(&event(
+        EventKind::Access(notify::event::AccessKind::Any),
+        "dir/a.svg"
+    )));
+}
+
+#[test]
+fn test_rebuilds_write_the_format_asked_for() {
+    use crate::cache::PageCache;
+    use crate::writer::Format;
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("svg2pdf-watch-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("a.svg"),
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100"><rect width="50" height="50"/></svg>"#,
+    )
+    .unwrap();
+    let output = dir.join("out.tiff");
+    let cache = PageCache::new(true, None);
+    let run = RunOptions {
+        cache: Some(&cache),
+        format: Format::Tiff,
+        ..RunOptions::default()
+    };
+    let opt = convert::load_options();
+    for build in 1..=2 {
+        rebuild(
+            build,
+            &Inputs::dir(&dir),
+            &output,
+            &RenderArgs::default(),
+            &opt,
+            &run,
+        );
+        let data = fs::read(&output).unwrap();
+        assert!(data.starts_with(b"II*\0") || data.starts_with(b"MM\0*"));
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,2814 @@
+use crate::annotations::{Kind, PageAnnotation};
+use crate::bilevel::{self, Dither};
+use crate::convert::RenderedImage;
+use crate::export;
+#[cfg(feature = "jp2")]
+use crate::jp2::{self, Jp2Compression};
+use crate::metadata::Metadata;
+use crate::names;
+use crate::nup::{self, Sheet};
+use crate::predictor;
+use crate::print::PrintSetup;
+use crate::spool::{self, Spool};
+use crate::vector::VectorPage;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use flate2::write::ZlibEncoder;
+use lopdf::{
+    content::{Content, Operation},
+    Dictionary, Object, ObjectId, Stream,
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{self, Cursor, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+use tiff::encoder::{colortype, compression, Rational, TiffEncoder};
+use tiff::tags::{ResolutionUnit, Tag};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+// At resolution 1 page pixels map 1:1 to PDF points, i.e. 72 per inch
+const PAGE_DPI: u32 = 72;
+
+// Document format the rendered pages are written to
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Pdf,
+    Tiff,
+    Cbz,
+}
+
+impl Format {
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Pdf => "PDF",
+            Format::Tiff => "TIFF",
+            Format::Cbz => "CBZ",
+        }
+    }
+
+    // `resolution` is the pixels per page point pages are rendered at.
+    // Only PDFs put pages on the pages of `sheet`.
+    pub fn writer(
+        self,
+        tiff_compression: TiffCompression,
+        images: ImageOptions,
+        resolution: f32,
+        sheet: Option<Sheet>,
+        print: PrintSetup,
+    ) -> Box<dyn ContainerWriter> {
+        match self {
+            Format::Pdf => Box::new(
+                PdfWriter::new(resolution, images)
+                    .with_sheet(sheet)
+                    .with_print(print),
+            ),
+            Format::Tiff => Box::new(
+                TiffWriter::new(
+                    tiff_compression,
+                    (PAGE_DPI as f32 * resolution).round() as u32,
+                )
+                .with_color_mode(images.color_mode, images.dither),
+            ),
+            Format::Cbz => Box::new(CbzWriter::new()),
+        }
+    }
+}
+
+// How page images are stored in a PDF
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImageFormat {
+    // Uncompressed samples
+    Raw,
+    // Lossless, with PNG predictors
+    #[default]
+    Flate,
+    Jpeg,
+    // JPEG 2000 codestreams, lossless or at a compression ratio
+    #[cfg(feature = "jp2")]
+    Jp2,
+    // Flate for text and line art, otherwise whichever of Flate and JPEG is smaller
+    Auto,
+}
+
+// Used unless the quality preset or --jpeg-quality say otherwise
+pub const DEFAULT_JPEG_QUALITY: u8 = 85;
+
+// Color samples JPEG pages keep per 4 brightness samples. Fewer smear
+// colored text and hairlines, which 4:4:4 keeps sharp at about one and a
+// half times the bytes of 4:2:0; photos rarely show the difference.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JpegSubsampling {
+    #[value(name = "444")]
+    Full,
+    // Half the color samples across
+    #[value(name = "422")]
+    Half,
+    // Half across and half down
+    #[value(name = "420")]
+    Quarter,
+}
+
+impl JpegSubsampling {
+    pub fn name(self) -> &'static str {
+        match self {
+            JpegSubsampling::Full => "4:4:4",
+            JpegSubsampling::Half => "4:2:2",
+            JpegSubsampling::Quarter => "4:2:0",
+        }
+    }
+
+    fn factor(self) -> jpeg_encoder::SamplingFactor {
+        match self {
+            JpegSubsampling::Full => jpeg_encoder::SamplingFactor::R_4_4_4,
+            JpegSubsampling::Half => jpeg_encoder::SamplingFactor::R_4_2_2,
+            JpegSubsampling::Quarter => jpeg_encoder::SamplingFactor::R_4_2_0,
+        }
+    }
+}
+
+// Colors pages keep
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    #[default]
+    Color,
+    // Black and white only, 1 bit a pixel in CCITT Group 4
+    Bilevel,
+}
+
+// How a PDF stores its page images. The color mode applies to TIFF pages
+// too.
+#[derive(Clone, Debug)]
+pub struct ImageOptions {
+    pub format: ImageFormat,
+    // Bilevel pages are Group 4 whatever the format says
+    pub color_mode: ColorMode,
+    // How bilevel pages show grays
+    pub dither: Dither,
+    pub jpeg_quality: u8,
+    // None keeps all color samples of line art and a quarter of them for
+    // photographic pages
+    pub jpeg_subsampling: Option<JpegSubsampling>,
+    #[cfg(feature = "jp2")]
+    pub jp2: Jp2Compression,
+    // Formats for pages whose id matches a pattern, the first match wins
+    pub overrides: Vec<(String, ImageFormat)>,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions {
+            format: ImageFormat::default(),
+            color_mode: ColorMode::default(),
+            dither: Dither::default(),
+            jpeg_quality: DEFAULT_JPEG_QUALITY,
+            jpeg_subsampling: None,
+            #[cfg(feature = "jp2")]
+            jp2: Jp2Compression::default(),
+            overrides: Vec::new(),
+        }
+    }
+}
+
+impl ImageOptions {
+    fn format_for(&self, id: &str) -> ImageFormat {
+        self.overrides
+            .iter()
+            .find(|(pattern, _)| names::matches(pattern, id))
+            .map_or(self.format, |&(_, format)| format)
+    }
+}
+
+// Parse a per-file override such as `photos/*=jpeg`
+pub fn parse_override(value: &str) -> Result<(String, ImageFormat), String> {
+    let (pattern, format) = value.rsplit_once('=').ok_or("expected PATTERN=FORMAT")?;
+    let format = ImageFormat::from_str(format, true)?;
+    Ok((pattern.to_string(), format))
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TiffCompression {
+    #[default]
+    Lzw,
+    Deflate,
+}
+
+// How the bytes of an encoded page are to be read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    // 8-bit RGB samples
+    Raw,
+    // zlib-compressed 8-bit RGB samples with PNG predictors, PDF's
+    // FlateDecode with /Predictor 15
+    Flate,
+    Jpeg,
+    // A JPEG 2000 codestream, PDF's JPXDecode
+    #[cfg_attr(not(feature = "jp2"), allow(dead_code))]
+    Jpx,
+    Png,
+    // CCITT Group 4 coding of 1-bit pixels, PDF's CCITTFaxDecode with /K -1
+    Fax,
+    // A page of a single color, the 3 bytes of which are the data
+    Fill,
+    // PDF path operators, the content stream of which is the data
+    Vector,
+}
+
+// A page ready to be added to a document
+#[derive(Clone)]
+pub struct EncodedPage {
+    pub width: u32,
+    pub height: u32,
+    pub encoding: Encoding,
+    pub data: Vec<u8>,
+    // Why this encoding was chosen, when it was picked automatically
+    pub reason: Option<String>,
+    // The drawing of Encoding::Vector pages
+    pub vector: Option<Arc<VectorPage>>,
+    // Deflated alpha of a page with transparency, for its image's soft mask
+    pub mask: Option<Vec<u8>>,
+}
+
+// Turns rendered pages into what a writer stores. Runs in the render
+// workers, so the costly part of writing happens in parallel.
+pub trait PageEncoder: Sync {
+    // `id` is the page id, see names::page_id
+    fn encode(&self, image: &RenderedImage, id: &str) -> Result<EncodedPage>;
+
+    // Everything the encoded pages depend on besides their pixels, which
+    // tells pages in the cache apart that other encoders stored
+    fn options(&self) -> String;
+
+    // A page of a single color without its pixels, if the format has a way
+    // to store one
+    fn encode_fill(&self, _width: u32, _height: u32, _color: [u8; 3]) -> Option<EncodedPage> {
+        None
+    }
+
+    // A page drawn with path operators, if the format can store one
+    fn encode_vector(
+        &self,
+        _width: u32,
+        _height: u32,
+        _page: Arc<VectorPage>,
+    ) -> Option<EncodedPage> {
+        None
+    }
+}
+
+// Collects rendered pages, in order, into an output document
+pub trait ContainerWriter {
+    // The encoder for pages given to add_page
+    fn encoder(&self) -> Box<dyn PageEncoder>;
+
+    fn add_page(&mut self, page: EncodedPage) -> Result<()>;
+
+    // Put `annotations` on the page added last, and on every copy of it.
+    // Formats without annotations leave them out.
+    fn annotate(&mut self, _annotations: Vec<PageAnnotation>) {}
+
+    // Say what the document is, for formats with document metadata
+    fn describe(&mut self, _metadata: &Metadata) {}
+
+    // Add a bookmark to the page added last, `level` deep from 1, after
+    // the bookmarks added before it. Formats without an outline leave it
+    // out.
+    fn bookmark(&mut self, _title: String, _level: usize) {}
+
+    // Name the page added last, under its cell of --nup pages
+    fn label(&mut self, _label: String) {}
+
+    // Add `page` `copies` times in a row. Formats that can show one image
+    // on several pages store it only once.
+    fn add_copies(&mut self, page: EncodedPage, copies: u32) -> Result<()> {
+        for _ in 1..copies {
+            self.add_page(page.clone())?;
+        }
+        self.add_page(page)
+    }
+
+    // Write the finished document to `out`
+    fn finish(self: Box<Self>, out: &mut dyn Write) -> Result<()>;
+}
+
+// Keeps the samples as they are
+struct RawEncoder;
+
+impl PageEncoder for RawEncoder {
+    fn encode(&self, image: &RenderedImage, _id: &str) -> Result<EncodedPage> {
+        Ok(raw_page(image))
+    }
+
+    fn options(&self) -> String {
+        "raw".to_string()
+    }
+}
+
+fn raw_page(image: &RenderedImage) -> EncodedPage {
+    EncodedPage {
+        width: image.width,
+        height: image.height,
+        encoding: Encoding::Raw,
+        data: image.rgb_data.clone(),
+        reason: None,
+        vector: None,
+        mask: None,
+    }
+}
+
+fn flate_page(image: &RenderedImage) -> Result<EncodedPage> {
+    let predicted = predictor::filter_rows(&image.rgb_data, image.width);
+    let mut encoder = ZlibEncoder::new(
+        Vec::with_capacity(predicted.len() / 4),
+        flate2::Compression::default(),
+    );
+    encoder.write_all(&predicted)?;
+    Ok(EncodedPage {
+        width: image.width,
+        height: image.height,
+        encoding: Encoding::Flate,
+        data: encoder.finish()?,
+        reason: None,
+        vector: None,
+        mask: None,
+    })
+}
+
+fn jpeg_page(
+    image: &RenderedImage,
+    quality: u8,
+    subsampling: JpegSubsampling,
+) -> Result<EncodedPage> {
+    let (Ok(width), Ok(height)) = (u16::try_from(image.width), u16::try_from(image.height)) else {
+        anyhow::bail!(
+            "A {}x{} page is too large for JPEG",
+            image.width,
+            image.height
+        );
+    };
+    let mut data = Vec::new();
+    let mut encoder = jpeg_encoder::Encoder::new(&mut data, quality);
+    encoder.set_sampling_factor(subsampling.factor());
+    encoder.encode(&image.rgb_data, width, height, jpeg_encoder::ColorType::Rgb)?;
+    Ok(EncodedPage {
+        width: image.width,
+        height: image.height,
+        encoding: Encoding::Jpeg,
+        data,
+        reason: None,
+        vector: None,
+        mask: None,
+    })
+}
+
+#[cfg(feature = "jp2")]
+fn jp2_page(image: &RenderedImage, compression: Jp2Compression) -> Result<EncodedPage> {
+    Ok(EncodedPage {
+        width: image.width,
+        height: image.height,
+        encoding: Encoding::Jpx,
+        data: jp2::encode(image, compression)?,
+        reason: None,
+        vector: None,
+        mask: None,
+    })
+}
+
+// Below this many colors a page is treated as line art
+const LINE_ART_COLORS: usize = 256;
+
+// ...or when at least this share of pixels repeat their left neighbor
+const LINE_ART_FLATNESS: f64 = 0.75;
+
+// Counting stops here, a photo has far more
+const MAX_COUNTED_COLORS: usize = 4096;
+
+// Colors (up to MAX_COUNTED_COLORS) and the share of pixels equal to
+// their left neighbor
+fn analyze(image: &RenderedImage) -> (usize, f64) {
+    let mut colors = std::collections::HashSet::new();
+    let mut flat = 0usize;
+    for row in image
+        .rgb_data
+        .chunks_exact((image.width as usize * 3).max(1))
+    {
+        let mut left = None;
+        for pixel in row.chunks_exact(3) {
+            if colors.len() < MAX_COUNTED_COLORS {
+                colors.insert([pixel[0], pixel[1], pixel[2]]);
+            }
+            flat += (left == Some(pixel)) as usize;
+            left = Some(pixel);
+        }
+    }
+    let pixels = (image.width as usize * image.height as usize).max(1);
+    (colors.len(), flat as f64 / pixels as f64)
+}
+
+// Whether a page is text and line art rather than photographic, with its
+// colors and flatness as reasons give them
+fn classify(image: &RenderedImage) -> (bool, String) {
+    let (colors, flatness) = analyze(image);
+    let colors_text = match colors {
+        MAX_COUNTED_COLORS => format!("{MAX_COUNTED_COLORS}+ colors"),
+        colors => format!("{colors} colors"),
+    };
+    let line_art = colors < LINE_ART_COLORS || flatness >= LINE_ART_FLATNESS;
+    (
+        line_art,
+        format!("{colors_text}, {:.0}% flat", flatness * 100.0),
+    )
+}
+
+// A JPEG page that keeps every color sample of line art, unless told how
+// to subsample
+fn jpeg_page_for(image: &RenderedImage, images: &ImageOptions) -> Result<EncodedPage> {
+    if let Some(subsampling) = images.jpeg_subsampling {
+        return jpeg_page(image, images.jpeg_quality, subsampling);
+    }
+    let (line_art, measured) = classify(image);
+    let (subsampling, kind) = match line_art {
+        true => (JpegSubsampling::Full, "line art"),
+        false => (JpegSubsampling::Quarter, "photographic"),
+    };
+    let mut page = jpeg_page(image, images.jpeg_quality, subsampling)?;
+    page.reason = Some(format!("{} chroma, {kind}, {measured}", subsampling.name()));
+    Ok(page)
+}
+
+// Pick Flate or JPEG for a page. Text and line art always stay lossless.
+fn auto_page(image: &RenderedImage, images: &ImageOptions) -> Result<EncodedPage> {
+    let (line_art, measured) = classify(image);
+    if line_art {
+        let mut page = flate_page(image)?;
+        page.reason = Some(format!("line art, {measured}"));
+        return Ok(page);
+    }
+
+    let subsampling = images.jpeg_subsampling.unwrap_or(JpegSubsampling::Quarter);
+    let flate = flate_page(image)?;
+    let jpeg = jpeg_page(image, images.jpeg_quality, subsampling)?;
+    let (mut page, other) = if jpeg.data.len() < flate.data.len() {
+        (jpeg, flate)
+    } else {
+        (flate, jpeg)
+    };
+    let chroma = match page.encoding {
+        Encoding::Jpeg => format!(", {} chroma", subsampling.name()),
+        _ => String::new(),
+    };
+    page.reason = Some(format!(
+        "photographic, {measured}, {} bytes against {} as {:?}{chroma}",
+        page.data.len(),
+        other.data.len(),
+        other.encoding
+    ));
+    Ok(page)
+}
+
+// Every pixel black or white, in Group 4. Transparent pixels are put on
+// white first, 1-bit images have no soft mask.
+fn fax_page(image: &RenderedImage, dither: Dither) -> EncodedPage {
+    EncodedPage {
+        width: image.width,
+        height: image.height,
+        encoding: Encoding::Fax,
+        data: bilevel::encode_g4(&bilevel::to_bitmap(image, dither)),
+        reason: None,
+        vector: None,
+        mask: None,
+    }
+}
+
+struct PdfEncoder {
+    images: ImageOptions,
+}
+
+impl PageEncoder for PdfEncoder {
+    fn encode(&self, image: &RenderedImage, id: &str) -> Result<EncodedPage> {
+        if self.images.color_mode == ColorMode::Bilevel {
+            return Ok(fax_page(image, self.images.dither));
+        }
+        let mut page = match self.images.format_for(id) {
+            ImageFormat::Raw => raw_page(image),
+            ImageFormat::Flate => flate_page(image)?,
+            ImageFormat::Jpeg => jpeg_page_for(image, &self.images)?,
+            #[cfg(feature = "jp2")]
+            ImageFormat::Jp2 => jp2_page(image, self.images.jp2)?,
+            ImageFormat::Auto => auto_page(image, &self.images)?,
+        };
+        // The alpha stays lossless whatever the colors are stored as
+        if let Some(alpha) = &image.alpha {
+            let mut encoder = ZlibEncoder::new(
+                Vec::with_capacity(alpha.len() / 8),
+                flate2::Compression::default(),
+            );
+            encoder.write_all(alpha)?;
+            page.mask = Some(encoder.finish()?);
+        }
+        Ok(page)
+    }
+
+    fn options(&self) -> String {
+        format!("pdf {:?}", self.images)
+    }
+
+    fn encode_fill(&self, width: u32, height: u32, color: [u8; 3]) -> Option<EncodedPage> {
+        // Dithered grays are left to encode
+        let color = match self.images.color_mode {
+            ColorMode::Color => color,
+            ColorMode::Bilevel => bilevel::fill_color(color, self.images.dither)?,
+        };
+        Some(EncodedPage {
+            width,
+            height,
+            encoding: Encoding::Fill,
+            data: color.to_vec(),
+            reason: None,
+            vector: None,
+            mask: None,
+        })
+    }
+
+    // Vector pages keep their colors, so bilevel ones are rendered
+    fn encode_vector(&self, width: u32, height: u32, page: Arc<VectorPage>) -> Option<EncodedPage> {
+        if self.images.color_mode == ColorMode::Bilevel {
+            return None;
+        }
+        Some(EncodedPage {
+            width,
+            height,
+            encoding: Encoding::Vector,
+            data: page.content.clone(),
+            reason: None,
+            vector: Some(page),
+            mask: None,
+        })
+    }
+}
+
+struct PngEncoder;
+
+impl PageEncoder for PngEncoder {
+    fn encode(&self, image: &RenderedImage, _id: &str) -> Result<EncodedPage> {
+        let png = export::to_pixmap(image)
+            .context("Cannot store an empty page")?
+            .encode_png()?;
+        Ok(EncodedPage {
+            width: image.width,
+            height: image.height,
+            encoding: Encoding::Png,
+            data: png,
+            reason: None,
+            vector: None,
+            mask: None,
+        })
+    }
+
+    fn options(&self) -> String {
+        "png".to_string()
+    }
+}
+
+// One image XObject per page, or per cell of --nup pages. Pages are written
+// out as they come in, so a document of thousands of them only holds the
+// last one; the page tree above them, the catalog and the cross-reference
+// table follow in `finish`.
+pub struct PdfWriter {
+    resolution: f32,
+    images: ImageOptions,
+    // The document so far, until `finish` has somewhere to copy it
+    spool: Spool,
+    // Where each object starts in the spool, by object number from 1; None
+    // while it is only reserved
+    offsets: Vec<Option<u64>>,
+    // The page added last, written once the next comes in, since its
+    // annotations are added after it
+    last: Option<PendingPage>,
+    // Bottom level of the page tree, every node with its pages
+    leaves: Vec<(ObjectId, Vec<ObjectId>)>,
+    metadata: Metadata,
+    // Title, level and page of every bookmark so far
+    bookmarks: Vec<(String, usize, ObjectId)>,
+    // With --nup, the pages the drawings are put on, and the drawings not
+    // written yet together with the number of them added last, which may
+    // still get annotations
+    sheet: Option<Sheet>,
+    cells: Vec<Cell>,
+    last_cells: usize,
+    // Trim, crop marks and viewer preferences, for print shops and printers
+    print: PrintSetup,
+    // Hash of every object written, which the document ID is taken from,
+    // so the same pages and metadata always get the same ID
+    digest: Sha256,
+}
+
+impl PdfWriter {
+    pub fn new(resolution: f32, images: ImageOptions) -> Self {
+        PdfWriter {
+            resolution,
+            images,
+            spool: Spool::new(spool::MEMORY_LIMIT),
+            offsets: Vec::new(),
+            last: None,
+            leaves: Vec::new(),
+            metadata: Metadata::default(),
+            bookmarks: Vec::new(),
+            sheet: None,
+            cells: Vec::new(),
+            last_cells: 0,
+            print: PrintSetup::default(),
+            digest: Sha256::new(),
+        }
+    }
+
+    // Put the pages into the cells of `sheet`, if given, rather than on
+    // pages of their own
+    pub fn with_sheet(self, sheet: Option<Sheet>) -> Self {
+        PdfWriter { sheet, ..self }
+    }
+
+    // Trim the pages and set the viewer preferences as `print` says. Pages
+    // of --nup sheets aren't trimmed.
+    pub fn with_print(self, print: PrintSetup) -> Self {
+        PdfWriter { print, ..self }
+    }
+
+    // Reserve ids for the next `count` objects, returning the first
+    fn reserve(&mut self, count: u32) -> u32 {
+        let first = self.offsets.len() as u32 + 1;
+        self.offsets
+            .resize(self.offsets.len() + count as usize, None);
+        first
+    }
+
+    fn write_object(&mut self, (number, generation): ObjectId, object: &Object) -> Result<()> {
+        if self.spool.is_empty() {
+            // The comment of high bytes marks the file as binary
+            self.spool.write_all(b"%PDF-1.5\n%\xe2\xe3\xcf\xd3\n")?;
+        }
+        self.offsets[number as usize - 1] = Some(self.spool.len());
+        let mut out = Hashed {
+            out: &mut self.spool,
+            digest: &mut self.digest,
+        };
+        writeln!(out, "{} {} obj", number, generation)?;
+        write_object(&mut out, object)?;
+        out.write_all(b"\nendobj\n")?;
+        Ok(())
+    }
+
+    // The parent of page object `page`: the last bottom node of the page
+    // tree, or a new one once that is full
+    fn parent(&mut self, page: ObjectId) -> ObjectId {
+        if self
+            .leaves
+            .last()
+            .is_none_or(|(_, kids)| kids.len() == PAGE_TREE_FANOUT)
+        {
+            let id = (self.reserve(1), 0);
+            self.leaves.push((id, Vec::new()));
+        }
+        let (id, kids) = self
+            .leaves
+            .last_mut()
+            .expect("a bottom node was just added");
+        kids.push(page);
+        *id
+    }
+
+    fn write_page(&mut self, mut page: PendingPage) -> Result<()> {
+        let first_id = self.reserve(page.objects());
+        let parents: Vec<_> = (0..page.copies)
+            .map(|copy| self.parent((first_id + SHARED_OBJECTS + copy, 0)))
+            .collect();
+        // Bookmarks lead to the first copy
+        let page_id = (first_id + SHARED_OBJECTS, 0);
+        self.bookmarks.extend(
+            std::mem::take(&mut page.bookmarks)
+                .into_iter()
+                .map(|(title, level)| (title, level, page_id)),
+        );
+        for (id, object) in page_objects(page, first_id, &parents, self.resolution, &self.print)? {
+            self.write_object(id, &object)?;
+        }
+        Ok(())
+    }
+
+    // The cells of the page added last
+    fn last_cells(&mut self) -> &mut [Cell] {
+        let start = self.cells.len() - self.last_cells.min(self.cells.len());
+        &mut self.cells[start..]
+    }
+
+    // Write the first `count` cells on a page of `sheet`
+    fn write_sheet(&mut self, sheet: &Sheet, count: usize) -> Result<()> {
+        let cells: Vec<Cell> = self.cells.drain(..count).collect();
+        let objects = 3 + cells.iter().map(Cell::objects).sum::<u32>();
+        let first_id = self.reserve(objects);
+        let page_id = (first_id + 2, 0);
+        let parent = self.parent(page_id);
+        for cell in &cells {
+            self.bookmarks.extend(
+                cell.bookmarks
+                    .iter()
+                    .map(|(title, level)| (title.clone(), *level, page_id)),
+            );
+        }
+        for (id, object) in sheet_objects(cells, sheet, first_id, parent, self.resolution)? {
+            self.write_object(id, &object)?;
+        }
+        Ok(())
+    }
+}
+
+// Objects written once for every page: its image, content stream and
+// resources. Each copy of the page adds a page object showing them, and an
+// object for each of its annotations. Blank and vector pages leave the
+// image id unused; the images of vector pages and their masks, or the soft
+// mask of a transparent page, come last.
+const SHARED_OBJECTS: u32 = 3;
+
+// A page waiting for its annotations and bookmarks
+struct PendingPage {
+    image: EncodedPage,
+    copies: u32,
+    annotations: Vec<PageAnnotation>,
+    bookmarks: Vec<(String, usize)>,
+}
+
+impl PendingPage {
+    fn objects(&self) -> u32 {
+        let images = self
+            .image
+            .vector
+            .as_ref()
+            .map_or(0, |page| page.images.len());
+        let masks = self.image.mask.is_some() as u32;
+        SHARED_OBJECTS
+            + self.copies * (1 + self.annotations.len() as u32)
+            + 2 * images as u32
+            + masks
+    }
+}
+
+// A drawing waiting for its place on a page of --nup
+struct Cell {
+    image: EncodedPage,
+    annotations: Vec<PageAnnotation>,
+    bookmarks: Vec<(String, usize)>,
+    label: Option<String>,
+}
+
+impl Cell {
+    // Objects of the cell besides those of its page: its image and mask, or
+    // the form of a vector page and the images it uses, and every
+    // annotation
+    fn objects(&self) -> u32 {
+        let image = match self.image.encoding {
+            Encoding::Fill => 0,
+            Encoding::Vector => {
+                let images = self
+                    .image
+                    .vector
+                    .as_ref()
+                    .map_or(0, |page| page.images.len());
+                1 + 2 * images as u32
+            }
+            _ => 1 + self.image.mask.is_some() as u32,
+        };
+        image + self.annotations.len() as u32
+    }
+}
+
+// A PDF text string: PDFDocEncoding agrees with ASCII, anything else is
+// UTF-16BE after a byte order mark
+fn text_string(text: &str) -> Object {
+    let bytes = if text.is_ascii() {
+        text.as_bytes().to_vec()
+    } else {
+        let mut bytes = vec![0xfe, 0xff];
+        bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+        bytes
+    };
+    Object::String(bytes, lopdf::StringFormat::Literal)
+}
+
+// The annotation dictionary of `annotation` on page `page_id`, which is
+// `page_height` points high. Annotations are kept in print and have no
+// appearance streams, which viewers make up from the type and color.
+fn annotation_dict(annotation: &PageAnnotation, page_id: ObjectId, page_height: f32) -> Dictionary {
+    let [left, top, right, bottom] = annotation.rect;
+    // PDF's y axis points up
+    let (top, bottom) = (page_height - top, page_height - bottom);
+    let reals = |values: &[f32]| Object::Array(values.iter().map(|&v| Object::Real(v)).collect());
+    let subtype = match annotation.kind {
+        Kind::Note => "Text",
+        Kind::Square => "Square",
+        Kind::Highlight => "Highlight",
+        Kind::FreeText => "FreeText",
+    };
+    let mut dict = Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Annot".to_vec())),
+        ("Subtype", Object::Name(subtype.as_bytes().to_vec())),
+        ("Rect", reals(&[left, bottom, right, top])),
+        ("Contents", text_string(&annotation.contents)),
+        ("P", Object::Reference(page_id)),
+        ("F", Object::Integer(4)),
+    ]);
+    if let Some(author) = &annotation.author {
+        dict.set("T", text_string(author));
+    }
+    if let Some(modified) = &annotation.modified {
+        dict.set("M", text_string(modified));
+    }
+    match annotation.kind {
+        Kind::Note => dict.set("Name", Object::Name(b"Comment".to_vec())),
+        Kind::Square => dict.set("C", reals(&[1.0, 0.0, 0.0])),
+        Kind::Highlight => {
+            dict.set("C", reals(&[1.0, 1.0, 0.0]));
+            dict.set(
+                "QuadPoints",
+                reals(&[left, top, right, top, left, bottom, right, bottom]),
+            );
+        }
+        Kind::FreeText => dict.set("DA", text_string("/Helv 12 Tf 0 g")),
+    }
+    dict
+}
+
+// Most kids of a node in the page tree
+const PAGE_TREE_FANOUT: usize = 32;
+
+// The objects of one page and its copies, given the ids reserved for them
+// and the parent of every copy. The annotations of the copies come after
+// their page objects.
+fn page_objects(
+    PendingPage {
+        image, annotations, ..
+    }: PendingPage,
+    first_id: u32,
+    parents: &[ObjectId],
+    resolution: f32,
+    print: &PrintSetup,
+) -> Result<Vec<(ObjectId, Object)>> {
+    let [image_id, content_id, resources_id] = [0, 1, 2].map(|offset| (first_id + offset, 0));
+    // Not rounded: the box and the image placed in it must match exactly
+    let page_width = image.width as f32 / resolution;
+    let page_height = image.height as f32 / resolution;
+    let mut objects =
+        Vec::with_capacity(SHARED_OBJECTS as usize + parents.len() * (1 + annotations.len()));
+
+    let (content, resources) = match image.encoding {
+        // A uniform page is just filled with its color, without an image
+        Encoding::Fill => {
+            let operations = fill(&image.data, [0.0, 0.0, page_width, page_height]);
+            (Content { operations }.encode()?, Dictionary::new())
+        }
+        // Drawn by its own content stream, with the images and graphics
+        // states it uses after the objects of the copies
+        Encoding::Vector => {
+            let vector = image.vector.context("A vector page without its drawing")?;
+            let first_image =
+                first_id + SHARED_OBJECTS + (parents.len() * (1 + annotations.len())) as u32;
+            let resources = vector_resources(&vector, first_image, &mut objects);
+            (image.data, resources)
+        }
+        _ => {
+            // The alpha of a transparent page, after the objects of the
+            // copies
+            let mask_id = (
+                first_id + SHARED_OBJECTS + (parents.len() * (1 + annotations.len())) as u32,
+                0,
+            );
+            objects.extend(image_objects(image, image_id, mask_id)?);
+
+            // Create content operations
+            let operations = vec![
+                Operation::new("q", vec![]),
+                Operation::new(
+                    "cm",
+                    vec![
+                        Object::Real(page_width),
+                        Object::Real(0.0),
+                        Object::Real(0.0),
+                        Object::Real(page_height),
+                        Object::Real(0.0),
+                        Object::Real(0.0),
+                    ],
+                ),
+                Operation::new("Do", vec![Object::Name("Im1".as_bytes().to_vec())]),
+                Operation::new("Q", vec![]),
+            ];
+
+            // Create resources dictionary
+            let xobjects = Dictionary::from_iter(vec![("Im1", Object::Reference(image_id))]);
+            let resources = Dictionary::from_iter(vec![("XObject", Object::Dictionary(xobjects))]);
+            (Content { operations }.encode()?, resources)
+        }
+    };
+
+    // Crop marks go around the page as it was drawn
+    let marks = print.crop_marks(page_width, page_height);
+    let content = match marks.is_empty() {
+        true => content,
+        false => [
+            &b"q\n"[..],
+            &content,
+            b"\nQ\n",
+            &Content { operations: marks }.encode()?,
+        ]
+        .concat(),
+    };
+
+    // Deflated only when that makes it smaller, which a few operators
+    // rarely are
+    let mut content_stream = Stream::new(Dictionary::new(), content);
+    content_stream.compress()?;
+
+    // Create page objects
+    let page_dict = |parent| {
+        let mut page = Dictionary::from_iter(vec![
+            ("Type", Object::Name("Page".as_bytes().to_vec())),
+            ("Parent", Object::Reference(parent)),
+        ]);
+        for (name, rect) in print.boxes(page_width, page_height) {
+            page.set(name, Object::Array(rect.map(Object::Real).to_vec()));
+        }
+        page.set("Resources", Object::Reference(resources_id));
+        page.set("Contents", Object::Reference(content_id));
+        page
+    };
+    objects.push((content_id, Object::Stream(content_stream)));
+    objects.push((resources_id, Object::Dictionary(resources)));
+    let first_annotation = first_id + SHARED_OBJECTS + parents.len() as u32;
+    for (copy, &parent) in parents.iter().enumerate() {
+        let page_id = (first_id + SHARED_OBJECTS + copy as u32, 0);
+        let mut page = page_dict(parent);
+        if !annotations.is_empty() {
+            let first = first_annotation + (copy * annotations.len()) as u32;
+            let ids: Vec<ObjectId> = (0..annotations.len() as u32)
+                .map(|offset| (first + offset, 0))
+                .collect();
+            for (annotation, &id) in annotations.iter().zip(&ids) {
+                let dict = annotation_dict(annotation, page_id, page_height);
+                objects.push((id, Object::Dictionary(dict)));
+            }
+            page.set(
+                "Annots",
+                Object::Array(ids.into_iter().map(Object::Reference).collect()),
+            );
+        }
+        objects.push((page_id, Object::Dictionary(page)));
+    }
+    Ok(objects)
+}
+
+// Operators filling `rect`, left, bottom, width and height, with the RGB
+// `color` of a Fill page
+fn fill(color: &[u8], rect: [f32; 4]) -> Vec<Operation> {
+    let color = color
+        .iter()
+        .map(|&channel| Object::Real(channel as f32 / 255.0))
+        .collect();
+    vec![
+        Operation::new("rg", color),
+        Operation::new("re", rect.map(Object::Real).to_vec()),
+        Operation::new("f", vec![]),
+    ]
+}
+
+// The image XObject `image_id` of a raster page, and the soft mask
+// `mask_id` of its alpha if it has one
+fn image_objects(
+    image: EncodedPage,
+    image_id: ObjectId,
+    mask_id: ObjectId,
+) -> Result<Vec<(ObjectId, Object)>> {
+    let mut objects = Vec::with_capacity(2);
+    // Create image dictionary
+    let mut image_dict = Dictionary::from_iter(vec![
+        ("Type", Object::Name("XObject".as_bytes().to_vec())),
+        ("Subtype", Object::Name("Image".as_bytes().to_vec())),
+        ("Width", Object::Integer(image.width as i64)),
+        ("Height", Object::Integer(image.height as i64)),
+        ("ColorSpace", Object::Name("DeviceRGB".as_bytes().to_vec())),
+        ("BitsPerComponent", Object::Integer(8)),
+    ]);
+    match image.encoding {
+        Encoding::Flate => {
+            image_dict.set("Filter", Object::Name(b"FlateDecode".to_vec()));
+            image_dict.set(
+                "DecodeParms",
+                Dictionary::from_iter(vec![
+                    ("Predictor", Object::Integer(15)),
+                    ("Colors", Object::Integer(3)),
+                    ("BitsPerComponent", Object::Integer(8)),
+                    ("Columns", Object::Integer(image.width as i64)),
+                ]),
+            );
+        }
+        Encoding::Jpeg => image_dict.set("Filter", Object::Name(b"DCTDecode".to_vec())),
+        // The codestream has three 8-bit sRGB components, which the
+        // ColorSpace and BitsPerComponent above agree with
+        Encoding::Jpx => image_dict.set("Filter", Object::Name(b"JPXDecode".to_vec())),
+        // Decoded, 0 bits are black, as DeviceGray has them
+        Encoding::Fax => {
+            image_dict.set("ColorSpace", Object::Name(b"DeviceGray".to_vec()));
+            image_dict.set("BitsPerComponent", Object::Integer(1));
+            image_dict.set("Filter", Object::Name(b"CCITTFaxDecode".to_vec()));
+            image_dict.set(
+                "DecodeParms",
+                Dictionary::from_iter(vec![
+                    ("K", Object::Integer(-1)),
+                    ("Columns", Object::Integer(image.width as i64)),
+                    ("Rows", Object::Integer(image.height as i64)),
+                ]),
+            );
+        }
+        Encoding::Png => anyhow::bail!("PNG pages can't be embedded in a PDF"),
+        Encoding::Fill | Encoding::Vector => anyhow::bail!("A page without an image"),
+        Encoding::Raw => {}
+    }
+
+    if let Some(mask) = image.mask {
+        let mask_dict = Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"XObject".to_vec())),
+            ("Subtype", Object::Name(b"Image".to_vec())),
+            ("Width", Object::Integer(image.width as i64)),
+            ("Height", Object::Integer(image.height as i64)),
+            ("ColorSpace", Object::Name(b"DeviceGray".to_vec())),
+            ("BitsPerComponent", Object::Integer(8)),
+            ("Filter", Object::Name(b"FlateDecode".to_vec())),
+        ]);
+        image_dict.set("SMask", Object::Reference(mask_id));
+        objects.push((mask_id, Object::Stream(Stream::new(mask_dict, mask))));
+    }
+
+    // Create image stream
+    let image_stream = Stream::new(image_dict, image.data);
+    objects.push((image_id, Object::Stream(image_stream)));
+    Ok(objects)
+}
+
+// The resources of a vector page, adding the images it uses and their
+// masks to `objects` from id `first_image` on
+fn vector_resources(
+    vector: &VectorPage,
+    first_image: u32,
+    objects: &mut Vec<(ObjectId, Object)>,
+) -> Dictionary {
+    let mut xobjects = Dictionary::new();
+    for (index, fallback) in vector.images.iter().enumerate() {
+        let [image_id, mask_id] = [0, 1].map(|offset| (first_image + 2 * index as u32 + offset, 0));
+        let dict = |color_space: &str| {
+            Dictionary::from_iter(vec![
+                ("Type", Object::Name(b"XObject".to_vec())),
+                ("Subtype", Object::Name(b"Image".to_vec())),
+                ("Width", Object::Integer(fallback.width as i64)),
+                ("Height", Object::Integer(fallback.height as i64)),
+                ("ColorSpace", Object::Name(color_space.as_bytes().to_vec())),
+                ("BitsPerComponent", Object::Integer(8)),
+                ("Filter", Object::Name(b"FlateDecode".to_vec())),
+            ])
+        };
+        let mut image_dict = dict("DeviceRGB");
+        image_dict.set("SMask", Object::Reference(mask_id));
+        objects.push((
+            image_id,
+            Object::Stream(Stream::new(image_dict, fallback.rgb.clone())),
+        ));
+        objects.push((
+            mask_id,
+            Object::Stream(Stream::new(dict("DeviceGray"), fallback.alpha.clone())),
+        ));
+        xobjects.set(format!("Im{index}"), Object::Reference(image_id));
+    }
+    let states = vector
+        .opacities
+        .iter()
+        .enumerate()
+        .map(|(index, &(fill, stroke))| {
+            let state = Dictionary::from_iter(vec![
+                ("Type", Object::Name(b"ExtGState".to_vec())),
+                ("ca", Object::Real(fill)),
+                ("CA", Object::Real(stroke)),
+            ]);
+            (format!("GS{index}"), Object::Dictionary(state))
+        });
+    Dictionary::from_iter(vec![
+        ("XObject", Object::Dictionary(xobjects)),
+        (
+            "ExtGState",
+            Object::Dictionary(Dictionary::from_iter(states)),
+        ),
+    ])
+}
+
+// The objects of a page of `sheet` showing `cells`, its content stream,
+// resources and page object first. Every cell is an XObject of its own,
+// /Im1, /Im2, ..., centered in its place, and the pages of vector drawings
+// become forms.
+fn sheet_objects(
+    cells: Vec<Cell>,
+    sheet: &Sheet,
+    first_id: u32,
+    parent: ObjectId,
+    resolution: f32,
+) -> Result<Vec<(ObjectId, Object)>> {
+    let [content_id, resources_id, page_id] = [0, 1, 2].map(|offset| (first_id + offset, 0));
+    let mut next_id = first_id + 3;
+    let mut new_id = || {
+        next_id += 1;
+        (next_id - 1, 0)
+    };
+    let place = sheet.cell();
+    let mut objects = Vec::new();
+    let mut operations = Vec::new();
+    let mut xobjects = Dictionary::new();
+    let mut annotation_ids = Vec::new();
+    let mut labeled = false;
+    for (slot, cell) in cells.into_iter().enumerate() {
+        let Cell {
+            image,
+            annotations,
+            label,
+            ..
+        } = cell;
+        // Pixels round the image to a little more or less than its place
+        let width = image.width as f32 / resolution;
+        let height = image.height as f32 / resolution;
+        let (left, top) = sheet.origin(slot);
+        let left = left + (place.width - width) / 2.0;
+        let top = top + (place.height - height) / 2.0;
+        // PDF's y axis points up
+        let bottom = sheet.height - top - height;
+
+        let name = format!("Im{}", slot + 1);
+        let scale = match image.encoding {
+            Encoding::Fill => {
+                operations.push(Operation::new("q", vec![]));
+                operations.extend(fill(&image.data, [left, bottom, width, height]));
+                operations.push(Operation::new("Q", vec![]));
+                None
+            }
+            // A form of its own content stream and resources, in points
+            Encoding::Vector => {
+                let vector = image.vector.context("A vector page without its drawing")?;
+                let form_id = new_id();
+                let first_image = form_id.0 + 1;
+                for _ in 0..2 * vector.images.len() {
+                    new_id();
+                }
+                let resources = vector_resources(&vector, first_image, &mut objects);
+                let form_dict = Dictionary::from_iter(vec![
+                    ("Type", Object::Name(b"XObject".to_vec())),
+                    ("Subtype", Object::Name(b"Form".to_vec())),
+                    (
+                        "BBox",
+                        Object::Array([0.0, 0.0, width, height].map(Object::Real).to_vec()),
+                    ),
+                    ("Resources", Object::Dictionary(resources)),
+                ]);
+                let mut form = Stream::new(form_dict, image.data);
+                form.compress()?;
+                objects.push((form_id, Object::Stream(form)));
+                xobjects.set(name.as_str(), Object::Reference(form_id));
+                Some((1.0, 1.0))
+            }
+            _ => {
+                let image_id = new_id();
+                let mask_id = match image.mask {
+                    Some(_) => new_id(),
+                    None => (0, 0),
+                };
+                objects.extend(image_objects(image, image_id, mask_id)?);
+                xobjects.set(name.as_str(), Object::Reference(image_id));
+                Some((width, height))
+            }
+        };
+        if let Some((x_scale, y_scale)) = scale {
+            operations.extend([
+                Operation::new("q", vec![]),
+                Operation::new(
+                    "cm",
+                    [x_scale, 0.0, 0.0, y_scale, left, bottom]
+                        .map(Object::Real)
+                        .to_vec(),
+                ),
+                Operation::new("Do", vec![Object::Name(name.into_bytes())]),
+                Operation::new("Q", vec![]),
+            ]);
+        }
+
+        // The file name in the strip under the cell, cut to its width
+        if let Some(label) = label {
+            labeled = true;
+            let (left, top) = sheet.origin(slot);
+            let baseline = sheet.height - top - place.height - nup::LABEL_SIZE;
+            operations.extend([
+                Operation::new("BT", vec![]),
+                Operation::new(
+                    "Tf",
+                    vec![Object::Name(b"F1".to_vec()), Object::Real(nup::LABEL_SIZE)],
+                ),
+                Operation::new("Td", vec![Object::Real(left), Object::Real(baseline)]),
+                Operation::new("Tj", vec![label_string(&label, place.width)]),
+                Operation::new("ET", vec![]),
+            ]);
+        }
+
+        // Annotations move along with their page, from its top left
+        for mut annotation in annotations {
+            let [rect_left, rect_top, rect_right, rect_bottom] = annotation.rect;
+            annotation.rect = [
+                rect_left + left,
+                rect_top + top,
+                rect_right + left,
+                rect_bottom + top,
+            ];
+            let id = new_id();
+            let dict = annotation_dict(&annotation, page_id, sheet.height);
+            objects.push((id, Object::Dictionary(dict)));
+            annotation_ids.push(id);
+        }
+    }
+
+    let mut resources = Dictionary::from_iter(vec![("XObject", Object::Dictionary(xobjects))]);
+    if labeled {
+        let font = Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Font".to_vec())),
+            ("Subtype", Object::Name(b"Type1".to_vec())),
+            ("BaseFont", Object::Name(b"Helvetica".to_vec())),
+            ("Encoding", Object::Name(b"WinAnsiEncoding".to_vec())),
+        ]);
+        let fonts = Dictionary::from_iter(vec![("F1", Object::Dictionary(font))]);
+        resources.set("Font", Object::Dictionary(fonts));
+    }
+    let mut content = Stream::new(Dictionary::new(), Content { operations }.encode()?);
+    content.compress()?;
+    let mut page = Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Page".to_vec())),
+        ("Parent", Object::Reference(parent)),
+        (
+            "MediaBox",
+            Object::Array(
+                [0.0, 0.0, sheet.width, sheet.height]
+                    .map(Object::Real)
+                    .to_vec(),
+            ),
+        ),
+        ("Resources", Object::Reference(resources_id)),
+        ("Contents", Object::Reference(content_id)),
+    ]);
+    if !annotation_ids.is_empty() {
+        page.set(
+            "Annots",
+            Object::Array(annotation_ids.into_iter().map(Object::Reference).collect()),
+        );
+    }
+    objects.push((content_id, Object::Stream(content)));
+    objects.push((resources_id, Object::Dictionary(resources)));
+    objects.push((page_id, Object::Dictionary(page)));
+    Ok(objects)
+}
+
+// A label as a string of the standard Helvetica's WinAnsiEncoding, which
+// has Latin-1 for the most part, with what it lacks as '?'. Cut short to
+// about `width` points going by the font's average character width.
+fn label_string(label: &str, width: f32) -> Object {
+    let fits = (width / (nup::LABEL_SIZE * 0.55)).max(3.0) as usize;
+    let mut chars: Vec<char> = label.chars().collect();
+    if chars.len() > fits {
+        chars.truncate(fits - 3);
+        chars.extend("...".chars());
+    }
+    let bytes = chars
+        .into_iter()
+        .map(|c| match c as u32 {
+            code @ (0x20..=0x7e | 0xa0..=0xff) => code as u8,
+            _ => b'?',
+        })
+        .collect();
+    Object::String(bytes, lopdf::StringFormat::Literal)
+}
+
+// A balanced tree of Pages nodes over the bottom nodes `leaves`, at most
+// PAGE_TREE_FANOUT kids per node, so viewers never have to deal with one
+// huge Kids array. Nodes above the leaves take their ids from `new_id`.
+// Returns the root and every node.
+fn page_tree(
+    mut leaves: Vec<(ObjectId, Vec<ObjectId>)>,
+    mut new_id: impl FnMut() -> ObjectId,
+) -> (ObjectId, Vec<(ObjectId, Object)>) {
+    if leaves.is_empty() {
+        leaves.push((new_id(), Vec::new()));
+    }
+    // (id, pages below, kids) per node, bottom level first
+    let mut nodes: Vec<(ObjectId, usize, Vec<ObjectId>)> = Vec::new();
+    let mut parents: HashMap<ObjectId, ObjectId> = HashMap::new();
+    let mut level: Vec<(ObjectId, usize)> = leaves
+        .into_iter()
+        .map(|(id, kids)| {
+            let count = kids.len();
+            nodes.push((id, count, kids));
+            (id, count)
+        })
+        .collect();
+    while level.len() > 1 {
+        level = level
+            .chunks(PAGE_TREE_FANOUT)
+            .map(|kids| {
+                let id = new_id();
+                let count = kids.iter().map(|&(_, count)| count).sum();
+                for &(kid, _) in kids {
+                    parents.insert(kid, id);
+                }
+                nodes.push((id, count, kids.iter().map(|&(kid, _)| kid).collect()));
+                (id, count)
+            })
+            .collect();
+    }
+    let root = level[0].0;
+    let nodes = nodes
+        .into_iter()
+        .map(|(id, count, kids)| {
+            let mut dict = Dictionary::from_iter(vec![
+                ("Type", Object::Name("Pages".as_bytes().to_vec())),
+                ("Count", Object::Integer(count as i64)),
+                (
+                    "Kids",
+                    Object::Array(kids.into_iter().map(Object::Reference).collect()),
+                ),
+            ]);
+            if let Some(&parent) = parents.get(&id) {
+                dict.set("Parent", Object::Reference(parent));
+            }
+            (id, Object::Dictionary(dict))
+        })
+        .collect();
+    (root, nodes)
+}
+
+// The document outline of `bookmarks`, each a title, its level from 1 and
+// the page it shows. A bookmark nests under the closest one before it of a
+// lower level; one more than a level deeper than that is taken as its
+// child. Every item starts open. Returns the Outlines dictionary and every
+// object, with ids from `new_id`, or None without bookmarks.
+fn outline(
+    bookmarks: Vec<(String, usize, ObjectId)>,
+    mut new_id: impl FnMut() -> ObjectId,
+) -> Option<(ObjectId, Vec<(ObjectId, Object)>)> {
+    if bookmarks.is_empty() {
+        return None;
+    }
+    let root = new_id();
+    let ids: Vec<_> = bookmarks.iter().map(|_| new_id()).collect();
+    // The parent of each item, None for the root, and the kids of each
+    // item with the root's last
+    let mut parents = Vec::with_capacity(bookmarks.len());
+    let mut kids = vec![Vec::new(); bookmarks.len() + 1];
+    let mut open: Vec<(usize, usize)> = Vec::new();
+    for (index, &(_, level, _)) in bookmarks.iter().enumerate() {
+        while open
+            .last()
+            .is_some_and(|&(open_level, _)| open_level >= level)
+        {
+            open.pop();
+        }
+        let parent = open.last().map(|&(_, parent)| parent);
+        parents.push(parent);
+        kids[parent.unwrap_or(bookmarks.len())].push(index);
+        open.push((level, index));
+    }
+    // Items below each, all of which are shown while everything is open;
+    // kids come after their parents, so counting goes backwards
+    let mut counts = vec![0; bookmarks.len() + 1];
+    for index in (0..bookmarks.len()).rev() {
+        counts[parents[index].unwrap_or(bookmarks.len())] += counts[index] + 1;
+    }
+    let ends = |kids: &[usize], dict: &mut Dictionary| {
+        if let (Some(&first), Some(&last)) = (kids.first(), kids.last()) {
+            dict.set("First", Object::Reference(ids[first]));
+            dict.set("Last", Object::Reference(ids[last]));
+        }
+    };
+
+    let mut objects = Vec::with_capacity(bookmarks.len() + 1);
+    let mut dict = Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Outlines".to_vec())),
+        ("Count", Object::Integer(counts[bookmarks.len()] as i64)),
+    ]);
+    ends(&kids[bookmarks.len()], &mut dict);
+    objects.push((root, Object::Dictionary(dict)));
+    for siblings in &kids {
+        for (position, &index) in siblings.iter().enumerate() {
+            let (title, _, page) = &bookmarks[index];
+            let parent = parents[index].map_or(root, |parent| ids[parent]);
+            let mut dict = Dictionary::from_iter(vec![
+                ("Title", text_string(title)),
+                ("Parent", Object::Reference(parent)),
+                (
+                    "Dest",
+                    Object::Array(vec![
+                        Object::Reference(*page),
+                        Object::Name(b"Fit".to_vec()),
+                    ]),
+                ),
+            ]);
+            if position > 0 {
+                dict.set("Prev", Object::Reference(ids[siblings[position - 1]]));
+            }
+            if let Some(&next) = siblings.get(position + 1) {
+                dict.set("Next", Object::Reference(ids[next]));
+            }
+            if !kids[index].is_empty() {
+                ends(&kids[index], &mut dict);
+                dict.set("Count", Object::Integer(counts[index] as i64));
+            }
+            objects.push((ids[index], Object::Dictionary(dict)));
+        }
+    }
+    Some((root, objects))
+}
+
+// Write `object` as PDF syntax
+fn write_object(out: &mut dyn Write, object: &Object) -> io::Result<()> {
+    match object {
+        Object::Null => out.write_all(b"null"),
+        Object::Boolean(value) => write!(out, "{}", value),
+        Object::Integer(value) => write!(out, "{}", value),
+        Object::Real(value) => write!(out, "{}", value),
+        Object::Name(name) => write_name(out, name),
+        Object::String(text, lopdf::StringFormat::Literal) => {
+            out.write_all(b"(")?;
+            for &byte in text {
+                match byte {
+                    b'(' | b')' | b'\\' => out.write_all(&[b'\\', byte])?,
+                    // A bare end of line would read as \n
+                    b'\r' => out.write_all(b"\\r")?,
+                    _ => out.write_all(&[byte])?,
+                }
+            }
+            out.write_all(b")")
+        }
+        Object::String(bytes, lopdf::StringFormat::Hexadecimal) => {
+            out.write_all(b"<")?;
+            for byte in bytes {
+                write!(out, "{:02X}", byte)?;
+            }
+            out.write_all(b">")
+        }
+        Object::Array(items) => {
+            out.write_all(b"[")?;
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.write_all(b" ")?;
+                }
+                write_object(out, item)?;
+            }
+            out.write_all(b"]")
+        }
+        Object::Dictionary(dict) => write_dictionary(out, dict),
+        Object::Stream(stream) => {
+            let mut dict = stream.dict.clone();
+            dict.set("Length", stream.content.len() as i64);
+            write_dictionary(out, &dict)?;
+            out.write_all(b"\nstream\n")?;
+            out.write_all(&stream.content)?;
+            out.write_all(b"\nendstream")
+        }
+        Object::Reference((number, generation)) => write!(out, "{} {} R", number, generation),
+    }
+}
+
+fn write_dictionary(out: &mut dyn Write, dict: &Dictionary) -> io::Result<()> {
+    out.write_all(b"<<")?;
+    for (key, value) in dict.iter() {
+        write_name(out, key)?;
+        out.write_all(b" ")?;
+        write_object(out, value)?;
+    }
+    out.write_all(b">>")
+}
+
+// Delimiters, white space and bytes outside printable ASCII are escaped as
+// #XX
+fn write_name(out: &mut dyn Write, name: &[u8]) -> io::Result<()> {
+    out.write_all(b"/")?;
+    for &byte in name {
+        if b"()<>[]{}/%#".contains(&byte) || !(b'!'..=b'~').contains(&byte) {
+            write!(out, "#{:02X}", byte)?;
+        } else {
+            out.write_all(&[byte])?;
+        }
+    }
+    Ok(())
+}
+
+impl ContainerWriter for PdfWriter {
+    fn encoder(&self) -> Box<dyn PageEncoder> {
+        Box::new(PdfEncoder {
+            images: self.images.clone(),
+        })
+    }
+
+    fn add_page(&mut self, image: EncodedPage) -> Result<()> {
+        self.add_copies(image, 1)
+    }
+
+    // Copies are page objects showing the same image and content
+    fn add_copies(&mut self, image: EncodedPage, copies: u32) -> Result<()> {
+        if image.encoding == Encoding::Png {
+            anyhow::bail!("PNG pages can't be embedded in a PDF");
+        }
+        if let Some(sheet) = self.sheet {
+            // Cells before the last ones are done, so full pages of them go
+            while self.cells.len() >= sheet.slots() {
+                self.write_sheet(&sheet, sheet.slots())
+                    .context("Failed to write PDF")?;
+            }
+            self.cells.extend((0..copies).map(|_| Cell {
+                image: image.clone(),
+                annotations: Vec::new(),
+                bookmarks: Vec::new(),
+                label: None,
+            }));
+            self.last_cells = copies as usize;
+            return Ok(());
+        }
+        if let Some(last) = self.last.take() {
+            self.write_page(last).context("Failed to write PDF")?;
+        }
+        self.last = Some(PendingPage {
+            image,
+            copies,
+            annotations: Vec::new(),
+            bookmarks: Vec::new(),
+        });
+        Ok(())
+    }
+
+    fn annotate(&mut self, annotations: Vec<PageAnnotation>) {
+        if let Some(page) = &mut self.last {
+            page.annotations.extend(annotations);
+        } else {
+            for cell in self.last_cells() {
+                cell.annotations.extend(annotations.iter().cloned());
+            }
+        }
+    }
+
+    fn bookmark(&mut self, title: String, level: usize) {
+        if let Some(page) = &mut self.last {
+            page.bookmarks.push((title, level));
+        } else if let Some(cell) = self.last_cells().first_mut() {
+            cell.bookmarks.push((title, level));
+        }
+    }
+
+    // Copies after the first say which one they are
+    fn label(&mut self, label: String) {
+        for (index, cell) in self.last_cells().iter_mut().enumerate() {
+            cell.label = Some(match index {
+                0 => label.clone(),
+                _ => format!("{label} (copy {})", index + 1),
+            });
+        }
+    }
+
+    fn describe(&mut self, metadata: &Metadata) {
+        self.metadata = metadata.clone();
+    }
+
+    fn finish(mut self: Box<Self>, out: &mut dyn Write) -> Result<()> {
+        let context = "Failed to write PDF";
+        if let Some(last) = self.last.take() {
+            self.write_page(last).context(context)?;
+        }
+        if let Some(sheet) = self.sheet {
+            while !self.cells.is_empty() {
+                let count = self.cells.len().min(sheet.slots());
+                self.write_sheet(&sheet, count).context(context)?;
+            }
+        }
+        let leaves = std::mem::take(&mut self.leaves);
+        let (root, nodes) = page_tree(leaves, || (self.reserve(1), 0));
+        for (id, node) in nodes {
+            self.write_object(id, &node).context(context)?;
+        }
+
+        // The document info, and the same again as XMP. The XMP stays
+        // uncompressed, so tools that don't parse PDF can still find it.
+        let time = self.metadata.time();
+        let info = self
+            .metadata
+            .info(time.as_ref())
+            .into_iter()
+            .map(|(key, value)| (key, text_string(&value)));
+        let info_id = (self.reserve(1), 0);
+        self.write_object(info_id, &Object::Dictionary(Dictionary::from_iter(info)))
+            .context(context)?;
+        let xmp_dict = Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Metadata".to_vec())),
+            ("Subtype", Object::Name(b"XML".to_vec())),
+        ]);
+        let xmp = Stream::new(xmp_dict, self.metadata.xmp(time.as_ref()).into_bytes());
+        let xmp_id = (self.reserve(1), 0);
+        self.write_object(xmp_id, &Object::Stream(xmp))
+            .context(context)?;
+
+        let bookmarks = std::mem::take(&mut self.bookmarks);
+        let outline = outline(bookmarks, || (self.reserve(1), 0));
+
+        // Create catalog
+        let mut catalog_dict = Dictionary::from_iter(vec![
+            ("Type", Object::Name("Catalog".as_bytes().to_vec())),
+            ("Pages", Object::Reference(root)),
+            ("Metadata", Object::Reference(xmp_id)),
+        ]);
+        if let Some((outline_id, objects)) = outline {
+            for (id, object) in objects {
+                self.write_object(id, &object).context(context)?;
+            }
+            // Open with the outline showing
+            catalog_dict.set("Outlines", Object::Reference(outline_id));
+            catalog_dict.set("PageMode", Object::Name(b"UseOutlines".to_vec()));
+        }
+        // Duplex is of PDF 1.7, which the catalog can raise the header to
+        if let Some(preferences) = self.print.viewer_preferences() {
+            catalog_dict.set("ViewerPreferences", preferences);
+            catalog_dict.set("Version", Object::Name(b"1.7".to_vec()));
+        }
+        let catalog_id = (self.reserve(1), 0);
+        self.write_object(catalog_id, &Object::Dictionary(catalog_dict))
+            .context(context)?;
+
+        let id: String = self.digest.clone().finalize()[..16]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let PdfWriter {
+            mut spool, offsets, ..
+        } = *self;
+        let xref = spool.len();
+        let size = offsets.len() + 1;
+        write!(spool, "xref\n0 {}\n0000000000 65535 f \n", size).context(context)?;
+        // Ids reserved but left unused, such as the images of blank pages,
+        // are free entries that can't be reused
+        for offset in offsets {
+            match offset {
+                Some(offset) => writeln!(spool, "{:010} 00000 n ", offset),
+                None => writeln!(spool, "0000000000 65535 f "),
+            }
+            .context(context)?;
+        }
+        write!(
+            spool,
+            "trailer\n<</Size {}/Root {} 0 R/Info {} 0 R/ID[<{}><{}>]>>\nstartxref\n{}\n%%EOF\n",
+            size, catalog_id.0, info_id.0, id, id, xref
+        )
+        .context(context)?;
+        spool.copy_to(out).context(context)
+    }
+}
+
+// Writes to `out`, adding what it writes to `digest`
+struct Hashed<'a> {
+    out: &'a mut dyn Write,
+    digest: &'a mut Sha256,
+}
+
+impl Write for Hashed<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.out.write(buf)?;
+        self.digest.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// In-memory file the TIFF encoder writes to, shared so its contents can be
+// taken back once the encoder is done
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Cursor<Vec<u8>>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for SharedBuffer {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.lock().unwrap().seek(pos)
+    }
+}
+
+// One IFD per page. The encoder needs to seek, so the file is assembled in
+// memory and copied out at the end.
+pub struct TiffWriter {
+    buffer: SharedBuffer,
+    encoder: TiffEncoder<SharedBuffer>,
+    compression: TiffCompression,
+    dpi: u32,
+    color_mode: ColorMode,
+    dither: Dither,
+}
+
+impl TiffWriter {
+    pub fn new(compression: TiffCompression, dpi: u32) -> Self {
+        let buffer = SharedBuffer::default();
+        TiffWriter {
+            encoder: TiffEncoder::new(buffer.clone()).expect("in-memory TIFF header"),
+            buffer,
+            compression,
+            dpi,
+            color_mode: ColorMode::Color,
+            dither: Dither::None,
+        }
+    }
+
+    // Write 1-bit Group 4 pages rather than compressed RGB ones when
+    // `color_mode` is bilevel, their grays dithered as `dither` says
+    pub fn with_color_mode(self, color_mode: ColorMode, dither: Dither) -> Self {
+        TiffWriter {
+            color_mode,
+            dither,
+            ..self
+        }
+    }
+
+    // The encoder has no CCITT compression, so the IFD of a Group 4 page is
+    // put together tag by tag, around a single strip
+    fn add_fax_page(&mut self, image: EncodedPage, resolution: Rational) -> Result<()> {
+        let mut page = self.encoder.new_directory()?;
+        let offset = page.write_data(image.data.as_slice())?;
+        page.write_tag(Tag::ImageWidth, image.width)?;
+        page.write_tag(Tag::ImageLength, image.height)?;
+        page.write_tag(Tag::BitsPerSample, 1u16)?;
+        page.write_tag(Tag::Compression, 4u16)?;
+        // WhiteIsZero, as Group 4 decoders expect
+        page.write_tag(Tag::PhotometricInterpretation, 0u16)?;
+        page.write_tag(Tag::StripOffsets, offset as u32)?;
+        page.write_tag(Tag::SamplesPerPixel, 1u16)?;
+        page.write_tag(Tag::RowsPerStrip, image.height)?;
+        page.write_tag(Tag::StripByteCounts, image.data.len() as u32)?;
+        page.write_tag(Tag::XResolution, resolution.clone())?;
+        page.write_tag(Tag::YResolution, resolution)?;
+        page.write_tag(Tag::ResolutionUnit, ResolutionUnit::Inch.to_u16())?;
+        // T6Options: no uncompressed runs
+        page.write_tag(Tag::Unknown(293), 0u32)?;
+        page.finish()?;
+        Ok(())
+    }
+}
+
+// Group 4 pages for bilevel TIFFs
+struct FaxEncoder {
+    dither: Dither,
+}
+
+impl PageEncoder for FaxEncoder {
+    fn encode(&self, image: &RenderedImage, _id: &str) -> Result<EncodedPage> {
+        Ok(fax_page(image, self.dither))
+    }
+
+    fn options(&self) -> String {
+        format!("fax {:?}", self.dither)
+    }
+}
+
+impl ContainerWriter for TiffWriter {
+    // Color strips are compressed by the TIFF encoder as they are written
+    fn encoder(&self) -> Box<dyn PageEncoder> {
+        match self.color_mode {
+            ColorMode::Color => Box::new(RawEncoder),
+            ColorMode::Bilevel => Box::new(FaxEncoder {
+                dither: self.dither,
+            }),
+        }
+    }
+
+    fn add_page(&mut self, image: EncodedPage) -> Result<()> {
+        let resolution = Rational { n: self.dpi, d: 1 };
+        if image.encoding == Encoding::Fax {
+            return self.add_fax_page(image, resolution);
+        }
+        anyhow::ensure!(
+            image.encoding == Encoding::Raw,
+            "TIFF pages must be raw samples or Group 4"
+        );
+        match self.compression {
+            TiffCompression::Lzw => {
+                let mut page = self
+                    .encoder
+                    .new_image_with_compression::<colortype::RGB8, _>(
+                        image.width,
+                        image.height,
+                        compression::Lzw,
+                    )?;
+                page.resolution(ResolutionUnit::Inch, resolution);
+                page.write_data(&image.data)?;
+            }
+            TiffCompression::Deflate => {
+                let mut page = self
+                    .encoder
+                    .new_image_with_compression::<colortype::RGB8, _>(
+                        image.width,
+                        image.height,
+                        compression::Deflate::default(),
+                    )?;
+                page.resolution(ResolutionUnit::Inch, resolution);
+                page.write_data(&image.data)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>, out: &mut dyn Write) -> Result<()> {
+        // Every IFD is linked as soon as its page is written
+        drop(self.encoder);
+        let tiff = std::mem::take(self.buffer.0.lock().unwrap().get_mut());
+        out.write_all(&tiff).context("Failed to write TIFF")
+    }
+}
+
+// Comic book archive: a ZIP of numbered page images that readers show in
+// name order
+pub struct CbzWriter {
+    zip: ZipWriter<Cursor<Vec<u8>>>,
+    pages: usize,
+}
+
+impl CbzWriter {
+    pub fn new() -> Self {
+        CbzWriter {
+            zip: ZipWriter::new(Cursor::new(Vec::new())),
+            pages: 0,
+        }
+    }
+}
+
+impl ContainerWriter for CbzWriter {
+    fn encoder(&self) -> Box<dyn PageEncoder> {
+        Box::new(PngEncoder)
+    }
+
+    fn add_page(&mut self, image: EncodedPage) -> Result<()> {
+        anyhow::ensure!(image.encoding == Encoding::Png, "CBZ pages must be PNG");
+        self.pages += 1;
+
+        // PNG data is already compressed
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        self.zip
+            .start_file(format!("{:04}.png", self.pages), options)?;
+        self.zip.write_all(&image.data)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>, out: &mut dyn Write) -> Result<()> {
+        let cbz = self.zip.finish()?.into_inner();
+        out.write_all(&cbz).context("Failed to write CBZ")
+    }
+}
+
+#[test]
+fn test_tiff_has_one_ifd_per_page() {
+    use tiff::decoder::{Decoder, DecodingResult};
+    use tiff::tags::Tag;
+
+    let pages = [
+        RenderedImage {
+            width: 2,
+            height: 1,
+            rgb_data: vec![255, 0, 0, 0, 0, 255],
+            alpha: None,
+        },
+        RenderedImage {
+            width: 1,
+            height: 2,
+            rgb_data: vec![0, 255, 0, 9, 9, 9],
+            alpha: None,
+        },
+    ];
+    for compression in [TiffCompression::Lzw, TiffCompression::Deflate] {
+        let mut writer: Box<dyn ContainerWriter> = Box::new(TiffWriter::new(compression, 300));
+        for page in &pages {
+            let page = writer.encoder().encode(page, "page.svg").unwrap();
+            writer.add_page(page).unwrap();
+        }
+        let mut tiff = Vec::new();
+        writer.finish(&mut tiff).unwrap();
+
+        let mut decoder = Decoder::new(Cursor::new(tiff)).unwrap();
+        for (index, page) in pages.iter().enumerate() {
+            if index > 0 {
+                decoder.next_image().unwrap();
+            }
+            assert_eq!(decoder.dimensions().unwrap(), (page.width, page.height));
+            assert_eq!(decoder.get_tag_u32_vec(Tag::XResolution).unwrap(), [300, 1]);
+            match decoder.read_image().unwrap() {
+                DecodingResult::U8(data) => assert_eq!(data, page.rgb_data),
+                _ => panic!("expected 8-bit samples"),
+            }
+        }
+        assert!(!decoder.more_images());
+    }
+}
+
+#[test]
+fn test_bilevel_tiff_pages() {
+    use tiff::decoder::Decoder;
+
+    // Dark gray on light gray, a column of each
+    let page = RenderedImage {
+        width: 10,
+        height: 3,
+        rgb_data: (0..30)
+            .flat_map(|i| if i % 10 < 4 { [60; 3] } else { [200; 3] })
+            .collect(),
+        alpha: None,
+    };
+    let mut writer: Box<dyn ContainerWriter> = Box::new(
+        TiffWriter::new(TiffCompression::Lzw, 200)
+            .with_color_mode(ColorMode::Bilevel, Dither::None),
+    );
+    for _ in 0..2 {
+        let page = writer.encoder().encode(&page, "page.svg").unwrap();
+        assert_eq!(page.encoding, Encoding::Fax);
+        writer.add_page(page).unwrap();
+    }
+    let mut tiff = Vec::new();
+    writer.finish(&mut tiff).unwrap();
+
+    let mut decoder = Decoder::new(Cursor::new(&tiff)).unwrap();
+    for index in 0..2 {
+        if index > 0 {
+            decoder.next_image().unwrap();
+        }
+        assert_eq!(decoder.dimensions().unwrap(), (10, 3));
+        let tag = |decoder: &mut Decoder<_>, tag| decoder.get_tag_u32(tag).unwrap();
+        assert_eq!(tag(&mut decoder, Tag::BitsPerSample), 1);
+        assert_eq!(tag(&mut decoder, Tag::Compression), 4);
+        assert_eq!(tag(&mut decoder, Tag::PhotometricInterpretation), 0);
+        assert_eq!(tag(&mut decoder, Tag::Unknown(293)), 0);
+        assert_eq!(decoder.get_tag_u32_vec(Tag::YResolution).unwrap(), [200, 1]);
+        let offset = tag(&mut decoder, Tag::StripOffsets) as usize;
+        let count = tag(&mut decoder, Tag::StripByteCounts) as usize;
+        let bitmap = bilevel::decode_g4(&tiff[offset..offset + count], 10, 3).unwrap();
+        assert_eq!(bitmap, bilevel::to_bitmap(&page, Dither::None));
+        assert_eq!(bitmap.data[..2], [0b1111_0000, 0]);
+    }
+    assert!(!decoder.more_images());
+}
+
+#[test]
+fn test_cbz_lists_pages_in_order() {
+    use resvg::tiny_skia::Pixmap;
+
+    let mut writer: Box<dyn ContainerWriter> = Box::new(CbzWriter::new());
+    for width in 1..=12 {
+        let page = RenderedImage {
+            width,
+            height: 2,
+            rgb_data: vec![200; width as usize * 2 * 3],
+            alpha: None,
+        };
+        let page = writer.encoder().encode(&page, "page.svg").unwrap();
+        writer.add_page(page).unwrap();
+    }
+    let mut cbz = Vec::new();
+    writer.finish(&mut cbz).unwrap();
+
+    let mut archive = zip::ZipArchive::new(Cursor::new(cbz)).unwrap();
+    let names: Vec<_> = archive.file_names().map(str::to_string).collect();
+    let mut sorted = names.clone();
+    sorted.sort();
+    assert_eq!(names, sorted);
+    assert_eq!(names[0], "0001.png");
+    assert_eq!(names[11], "0012.png");
+
+    let mut png = Vec::new();
+    std::io::Read::read_to_end(&mut archive.by_name("0010.png").unwrap(), &mut png).unwrap();
+    let page = Pixmap::decode_png(&png).unwrap();
+    assert_eq!((page.width(), page.height()), (10, 2));
+}
+
+#[test]
+fn test_pdf_page_size_ignores_resolution() {
+    let page = RenderedImage {
+        width: 8,
+        height: 6,
+        rgb_data: vec![0; 8 * 6 * 3],
+        alpha: None,
+    };
+    for resolution in [0.5, 1.0, 2.0] {
+        let mut writer = Format::Pdf.writer(
+            TiffCompression::Lzw,
+            ImageOptions::default(),
+            resolution,
+            None,
+            PrintSetup::default(),
+        );
+        let page = writer.encoder().encode(&page, "page.svg").unwrap();
+        writer.add_page(page).unwrap();
+        let mut pdf = Vec::new();
+        writer.finish(&mut pdf).unwrap();
+
+        let doc = lopdf::Document::load_mem(&pdf).unwrap();
+        let page_id = doc.get_pages()[&1];
+        let media_box = doc
+            .get_dictionary(page_id)
+            .unwrap()
+            .get(b"MediaBox")
+            .unwrap();
+        let size: Vec<_> = media_box.as_array().unwrap()[2..]
+            .iter()
+            .map(|value| value.as_i64().unwrap())
+            .collect();
+        assert_eq!(size, [(8.0 / resolution) as i64, (6.0 / resolution) as i64]);
+    }
+}
+
+#[test]
+fn test_pdf_page_size_keeps_fractions() {
+    // Pixels, pixels per point and the page size in points: an A4 width
+    let sizes = [
+        (31, 7, 2.0, [15.5, 3.5]),
+        (248, 351, 248.0 / 595.276, [595.276, 842.5076]),
+    ];
+    for (width, height, resolution, expected) in sizes {
+        let page = RenderedImage {
+            width,
+            height,
+            rgb_data: vec![0; (width * height * 3) as usize],
+            alpha: None,
+        };
+        let mut writer = Box::new(PdfWriter::new(resolution, ImageOptions::default()));
+        let page = writer.encoder().encode(&page, "page.svg").unwrap();
+        writer.add_page(page).unwrap();
+        let mut pdf = Vec::new();
+        writer.finish(&mut pdf).unwrap();
+
+        let doc = lopdf::Document::load_mem(&pdf).unwrap();
+        let page_id = doc.get_pages()[&1];
+        let floats = |objects: &[Object]| -> Vec<f32> {
+            objects
+                .iter()
+                .map(|value| value.as_float().unwrap())
+                .collect()
+        };
+        let dict = doc.get_dictionary(page_id).unwrap();
+        let media_box = floats(dict.get(b"MediaBox").unwrap().as_array().unwrap());
+        assert_eq!(media_box[..2], [0.0, 0.0]);
+        assert!(
+            (media_box[2] - expected[0]).abs() < 1e-3 && (media_box[3] - expected[1]).abs() < 1e-3,
+            "{media_box:?}, expected {expected:?}"
+        );
+        // The image fills the box exactly
+        let content =
+            lopdf::content::Content::decode(&doc.get_page_content(page_id).unwrap()).unwrap();
+        let cm = content
+            .operations
+            .iter()
+            .find(|operation| operation.operator == "cm")
+            .unwrap();
+        let cm = floats(&cm.operands);
+        assert_eq!([cm[0], cm[3]], media_box[2..]);
+    }
+}
+
+#[test]
+fn test_pdf_pages_are_written_as_they_come() {
+    let mut writer = Box::new(PdfWriter::new(1.0, ImageOptions::default()));
+    let mut written = Vec::new();
+    for text in ["(1)", "a\\b", "r\u{e9}sum\u{e9}"] {
+        let page = RenderedImage {
+            width: 2,
+            height: 2,
+            rgb_data: vec![90; 12],
+            alpha: None,
+        };
+        let page = writer.encoder().encode(&page, "page.svg").unwrap();
+        writer.add_page(page).unwrap();
+        let annotation = PageAnnotation {
+            kind: Kind::Note,
+            rect: [0.0, 0.0, 1.0, 1.0],
+            contents: text.to_string(),
+            author: None,
+            modified: None,
+        };
+        writer.annotate(vec![annotation]);
+        written.push(writer.spool.len());
+    }
+    // Each page goes out once the next comes in, with its annotations
+    assert_eq!(written[0], 0);
+    assert!(written[1] > 0 && written[2] > written[1]);
+    let mut pdf = Vec::new();
+    writer.finish(&mut pdf).unwrap();
+
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let texts: Vec<_> = doc
+        .get_pages()
+        .into_values()
+        .map(|page_id| {
+            let page = doc.get_dictionary(page_id).unwrap();
+            let annots = page.get(b"Annots").unwrap().as_array().unwrap();
+            let annot = doc
+                .get_dictionary(annots[0].as_reference().unwrap())
+                .unwrap();
+            lopdf::decode_text_string(annot.get(b"Contents").unwrap()).unwrap()
+        })
+        .collect();
+    assert_eq!(texts, ["(1)", "a\\b", "r\u{e9}sum\u{e9}"]);
+
+    let mut out = Vec::new();
+    let strings = [
+        Object::String(b"a(b\\c))\rd".to_vec(), lopdf::StringFormat::Literal),
+        Object::String(vec![0xfe, 0xff], lopdf::StringFormat::Hexadecimal),
+        Object::Name(b"A B#".to_vec()),
+    ];
+    write_object(&mut out, &Object::Array(strings.to_vec())).unwrap();
+    assert_eq!(out, b"[(a\\(b\\\\c\\)\\)\\rd) <FEFF> /A#20B#23]");
+}
+
+#[test]
+fn test_pdf_document_info() {
+    use crate::metadata::PRODUCER;
+
+    let mut writer = Box::new(PdfWriter::new(1.0, ImageOptions::default()));
+    writer.describe(&Metadata {
+        title: Some("Übersicht (Entwurf)".to_string()),
+        keywords: Some("svg, pdf".to_string()),
+        ..Metadata::default()
+    });
+    let page = writer.encoder().encode_fill(2, 2, [255; 3]).unwrap();
+    writer.add_page(page).unwrap();
+    let mut pdf = Vec::new();
+    writer.finish(&mut pdf).unwrap();
+
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let info_id = doc.trailer.get(b"Info").unwrap().as_reference().unwrap();
+    let info = doc.get_dictionary(info_id).unwrap();
+    let text = |key: &[u8]| lopdf::decode_text_string(info.get(key).unwrap()).unwrap();
+    assert_eq!(text(b"Title"), "Übersicht (Entwurf)");
+    // Non-ASCII text is UTF-16BE after a byte order mark
+    let title = info.get(b"Title").unwrap().as_str().unwrap();
+    assert_eq!(title[..4], [0xfe, 0xff, 0x00, 0xdc]);
+    assert_eq!(text(b"Keywords"), "svg, pdf");
+    assert_eq!(text(b"Producer"), PRODUCER);
+    assert!(info.get(b"Author").is_err());
+    let created = text(b"CreationDate");
+    assert!(
+        created.starts_with("D:20") && created.len() >= 17,
+        "{created}"
+    );
+    assert_eq!(text(b"ModDate"), created);
+
+    let catalog = doc.catalog().unwrap();
+    let xmp_id = catalog.get(b"Metadata").unwrap().as_reference().unwrap();
+    let xmp = doc.get_object(xmp_id).unwrap().as_stream().unwrap();
+    assert_eq!(xmp.dict.get(b"Subtype").unwrap().as_name().unwrap(), b"XML");
+    let xmp = String::from_utf8(xmp.content.clone()).unwrap();
+    assert!(xmp.contains(">Übersicht (Entwurf)</rdf:li>"), "{xmp}");
+}
+
+#[test]
+fn test_pdf_outline() {
+    let mut writer = Box::new(PdfWriter::new(1.0, ImageOptions::default()));
+    // A page with a section and its own bookmark, one nested under that,
+    // one jumping two levels deeper, a top-level one and a page without
+    for bookmarks in [
+        &[("Größen", 1), ("Intro", 2)][..],
+        &[("Details", 4)],
+        &[("Ende", 1)],
+        &[],
+    ] {
+        let page = writer.encoder().encode_fill(2, 2, [255; 3]).unwrap();
+        writer.add_copies(page, 2).unwrap();
+        for &(title, level) in bookmarks {
+            writer.bookmark(title.to_string(), level);
+        }
+    }
+    let mut pdf = Vec::new();
+    writer.finish(&mut pdf).unwrap();
+
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let pages: Vec<_> = doc.get_pages().into_values().collect();
+    let catalog = doc.catalog().unwrap();
+    assert_eq!(
+        catalog.get(b"PageMode").unwrap().as_name().unwrap(),
+        b"UseOutlines"
+    );
+    let root_id = catalog.get(b"Outlines").unwrap().as_reference().unwrap();
+    let root = doc.get_dictionary(root_id).unwrap();
+    assert_eq!(root.get(b"Count").unwrap().as_i64().unwrap(), 4);
+
+    // Items in order, depth first, by title, with their page and count
+    fn walk(
+        doc: &lopdf::Document,
+        parent: ObjectId,
+        depth: usize,
+        items: &mut Vec<(String, usize, ObjectId, i64)>,
+    ) {
+        let parent_dict = doc.get_dictionary(parent).unwrap();
+        let Ok(first) = parent_dict.get(b"First") else {
+            return;
+        };
+        let mut next = Some(first.as_reference().unwrap());
+        while let Some(id) = next {
+            let item = doc.get_dictionary(id).unwrap();
+            let title = lopdf::decode_text_string(item.get(b"Title").unwrap()).unwrap();
+            let dest = item.get(b"Dest").unwrap().as_array().unwrap();
+            assert_eq!(dest[1].as_name().unwrap(), b"Fit");
+            assert_eq!(item.get(b"Parent").unwrap().as_reference().unwrap(), parent);
+            let count = item
+                .get(b"Count")
+                .map_or(0, |count| count.as_i64().unwrap());
+            items.push((title, depth, dest[0].as_reference().unwrap(), count));
+            walk(doc, id, depth + 1, items);
+            next = item
+                .get(b"Next")
+                .ok()
+                .map(|next| next.as_reference().unwrap());
+        }
+    }
+    let mut items = Vec::new();
+    walk(&doc, root_id, 1, &mut items);
+    assert_eq!(
+        items,
+        [
+            ("Größen".to_string(), 1, pages[0], 2),
+            ("Intro".to_string(), 2, pages[0], 1),
+            ("Details".to_string(), 3, pages[2], 0),
+            ("Ende".to_string(), 1, pages[4], 0),
+        ]
+    );
+
+    // Without bookmarks there is no outline
+    let mut writer = Box::new(PdfWriter::new(1.0, ImageOptions::default()));
+    let page = writer.encoder().encode_fill(2, 2, [255; 3]).unwrap();
+    writer.add_page(page).unwrap();
+    let mut pdf = Vec::new();
+    writer.finish(&mut pdf).unwrap();
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    assert!(doc.catalog().unwrap().get(b"Outlines").is_err());
+}
+
+#[test]
+fn test_pdf_page_tree_is_balanced() {
+    // Too many pages for a single node
+    let count = PAGE_TREE_FANOUT * 3 + 5;
+    let mut writer = Box::new(PdfWriter::new(1.0, ImageOptions::default()));
+    for index in 0..count {
+        let page = RenderedImage {
+            width: index as u32 + 1,
+            height: 1,
+            rgb_data: vec![0; (index + 1) * 3],
+            alpha: None,
+        };
+        let page = writer.encoder().encode(&page, "page.svg").unwrap();
+        writer.add_page(page).unwrap();
+    }
+    let mut pdf = Vec::new();
+    writer.finish(&mut pdf).unwrap();
+
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let pages = doc.get_pages();
+    assert_eq!(pages.len(), count);
+    for (number, page_id) in pages {
+        // Pages keep their order, and every node stays within the fanout
+        let page = doc.get_dictionary(page_id).unwrap();
+        let media_box = page.get(b"MediaBox").unwrap().as_array().unwrap();
+        assert_eq!(media_box[2].as_i64().unwrap(), number as i64);
+        let mut depth = 0;
+        let mut node = page.get(b"Parent").unwrap().as_reference().unwrap();
+        loop {
+            let dict = doc.get_dictionary(node).unwrap();
+            assert!(dict.get(b"Kids").unwrap().as_array().unwrap().len() <= PAGE_TREE_FANOUT);
+            depth += 1;
+            match dict.get(b"Parent") {
+                Ok(parent) => node = parent.as_reference().unwrap(),
+                Err(_) => break,
+            }
+        }
+        assert_eq!(depth, 2);
+    }
+}
+
+#[test]
+fn test_pdf_copies_share_the_page() {
+    let mut writer = Box::new(PdfWriter::new(1.0, ImageOptions::default()));
+    // The copies of the second page span several nodes of the page tree
+    for (index, copies) in [1, PAGE_TREE_FANOUT as u32 + 3, 2].into_iter().enumerate() {
+        let page = RenderedImage {
+            width: index as u32 + 1,
+            height: 1,
+            rgb_data: vec![index as u8 * 50; (index + 1) * 3],
+            alpha: None,
+        };
+        let page = writer.encoder().encode(&page, "page.svg").unwrap();
+        writer.add_copies(page, copies).unwrap();
+    }
+    let mut pdf = Vec::new();
+    writer.finish(&mut pdf).unwrap();
+
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let pages: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    assert_eq!(pages.len(), PAGE_TREE_FANOUT + 6);
+    let shown = |page_id| {
+        let page = doc.get_dictionary(page_id).unwrap();
+        let width = page.get(b"MediaBox").unwrap().as_array().unwrap()[2].as_i64();
+        let contents = page.get(b"Contents").unwrap().as_reference().unwrap();
+        let resources = page.get(b"Resources").unwrap().as_reference().unwrap();
+        (width.unwrap(), contents, resources)
+    };
+    let widths: Vec<_> = pages.iter().map(|&page| shown(page).0).collect();
+    assert_eq!(widths[..2], [1, 2]);
+    assert_eq!(widths[PAGE_TREE_FANOUT + 3..], [2, 3, 3]);
+    // Every copy shows the very same objects
+    assert!(pages[1..PAGE_TREE_FANOUT + 4]
+        .iter()
+        .all(|&page| shown(page) == shown(pages[1])));
+    assert_ne!(shown(pages[0]), shown(pages[1]));
+    let images = doc
+        .objects
+        .values()
+        .filter(|object| {
+            object.as_stream().is_ok_and(|stream| {
+                stream.dict.get(b"Subtype").ok() == Some(&Object::Name(b"Image".to_vec()))
+            })
+        })
+        .count();
+    assert_eq!(images, 3);
+}
+
+#[test]
+fn test_pdf_print_setup() {
+    use crate::convert::MM;
+    use crate::print::Duplex;
+
+    let bleed = 3.0 * MM;
+    let print = PrintSetup {
+        bleed,
+        crop_marks: true,
+        no_print_scaling: true,
+        duplex: Some(Duplex::ShortEdge),
+    };
+    let mut writer = Box::new(PdfWriter::new(1.0, ImageOptions::default()).with_print(print));
+    let page = RenderedImage {
+        width: 100,
+        height: 50,
+        rgb_data: vec![255; 100 * 50 * 3],
+        alpha: None,
+    };
+    let page = writer.encoder().encode(&page, "page.svg").unwrap();
+    writer.add_page(page).unwrap();
+    let mut pdf = Vec::new();
+    writer.finish(&mut pdf).unwrap();
+
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let page = doc.get_dictionary(doc.get_pages()[&1]).unwrap();
+    let numbers = |name: &[u8]| -> Vec<f32> {
+        let values = page.get(name).unwrap().as_array().unwrap();
+        values
+            .iter()
+            .map(|value| value.as_float().unwrap())
+            .collect()
+    };
+    assert_eq!(numbers(b"BleedBox"), [0.0, 0.0, 100.0, 50.0]);
+    assert_eq!(
+        numbers(b"TrimBox"),
+        [bleed, bleed, 100.0 - bleed, 50.0 - bleed]
+    );
+    // The marks sit outside the bleed, on a larger page
+    let media_box = numbers(b"MediaBox");
+    assert!(media_box[0] < 0.0 && media_box[2] > 100.0, "{media_box:?}");
+    let content = doc.get_page_content(doc.get_pages()[&1]).unwrap();
+    let content = lopdf::content::Content::decode(&content).unwrap();
+    assert!(content
+        .operations
+        .iter()
+        .any(|operation| operation.operator == "S"));
+
+    let preferences = doc
+        .catalog()
+        .unwrap()
+        .get(b"ViewerPreferences")
+        .unwrap()
+        .as_dict()
+        .unwrap();
+    assert_eq!(
+        preferences.get(b"PrintScaling").unwrap().as_name().unwrap(),
+        b"None"
+    );
+    assert_eq!(
+        preferences.get(b"Duplex").unwrap().as_name().unwrap(),
+        b"DuplexFlipShortEdge"
+    );
+}
+
+#[test]
+fn test_pdf_sheets() {
+    use crate::nup::Grid;
+
+    let sheet = Sheet {
+        grid: Grid {
+            columns: 2,
+            rows: 2,
+        },
+        width: 200.0,
+        height: 200.0,
+        margin: 0.0,
+        gutter: 0.0,
+        labels: true,
+    };
+    let mut writer = Box::new(PdfWriter::new(1.0, ImageOptions::default()).with_sheet(Some(sheet)));
+    let image = RenderedImage {
+        width: 100,
+        height: 88,
+        rgb_data: vec![0; 100 * 88 * 3],
+        alpha: None,
+    };
+    let encoder = writer.encoder();
+    writer
+        .add_page(encoder.encode(&image, "a.svg").unwrap())
+        .unwrap();
+    writer.label("a.svg".to_string());
+    // Narrower than its cell, so centered in it
+    writer
+        .add_page(encoder.encode_fill(50, 88, [255; 3]).unwrap())
+        .unwrap();
+    writer.label("b.svg".to_string());
+    // Three copies, the last alone on the second page
+    writer
+        .add_copies(encoder.encode(&image, "c.svg").unwrap(), 3)
+        .unwrap();
+    writer.label("c.svg".to_string());
+    writer.bookmark("C".to_string(), 1);
+    writer.annotate(vec![PageAnnotation {
+        kind: Kind::Square,
+        rect: [10.0, 10.0, 20.0, 20.0],
+        author: None,
+        contents: "Look".to_string(),
+        modified: None,
+    }]);
+    let mut pdf = Vec::new();
+    writer.finish(&mut pdf).unwrap();
+
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let pages: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    assert_eq!(pages.len(), 2);
+    let operations = |page| {
+        let content = doc.get_page_content(page).unwrap();
+        Content::decode(&content).unwrap().operations
+    };
+    let operands = |page, operator: &str| -> Vec<Vec<f32>> {
+        operations(page)
+            .into_iter()
+            .filter(|operation| operation.operator == operator)
+            .map(|operation| {
+                let numbers = operation.operands.iter();
+                numbers.map(|number| number.as_float().unwrap()).collect()
+            })
+            .collect()
+    };
+    // Images at their own size from the top left, row by row
+    assert_eq!(
+        operands(pages[0], "cm"),
+        [
+            [100.0, 0.0, 0.0, 88.0, 0.0, 112.0],
+            [100.0, 0.0, 0.0, 88.0, 0.0, 12.0],
+            [100.0, 0.0, 0.0, 88.0, 100.0, 12.0]
+        ]
+    );
+    assert_eq!(operands(pages[0], "re"), [[125.0, 112.0, 50.0, 88.0]]);
+    assert_eq!(
+        operands(pages[1], "cm"),
+        [[100.0, 0.0, 0.0, 88.0, 0.0, 112.0]]
+    );
+    let names = |page| -> Vec<String> {
+        let page = doc.get_dictionary(page).unwrap();
+        let resources = page.get(b"Resources").unwrap().as_reference().unwrap();
+        let xobjects = doc
+            .get_dictionary(resources)
+            .unwrap()
+            .get(b"XObject")
+            .unwrap();
+        let names = xobjects.as_dict().unwrap().iter();
+        names
+            .map(|(name, _)| String::from_utf8_lossy(name).into_owned())
+            .collect()
+    };
+    assert_eq!(names(pages[0]), ["Im1", "Im3", "Im4"]);
+    assert_eq!(names(pages[1]), ["Im1"]);
+    let labels = |page| -> Vec<_> {
+        operations(page)
+            .into_iter()
+            .filter(|operation| operation.operator == "Tj")
+            .map(|operation| operation.operands[0].as_str().unwrap().to_vec())
+            .collect()
+    };
+    assert_eq!(
+        labels(pages[0]),
+        [&b"a.svg"[..], b"b.svg", b"c.svg", b"c.svg (copy 2)"]
+    );
+    assert_eq!(labels(pages[1]), [b"c.svg (copy 3)"]);
+
+    // Annotations of every copy moved along with it, bookmarks only on the
+    // first
+    let annotations = |page| {
+        let page = doc.get_dictionary(page).unwrap();
+        let annots = page.get(b"Annots").unwrap().as_array().unwrap();
+        let rects = annots.iter().map(|annot| {
+            let annot = doc.get_dictionary(annot.as_reference().unwrap()).unwrap();
+            let rect = annot.get(b"Rect").unwrap().as_array().unwrap();
+            rect.iter()
+                .map(|side| side.as_float().unwrap())
+                .collect::<Vec<f32>>()
+        });
+        rects.collect::<Vec<_>>()
+    };
+    assert_eq!(
+        annotations(pages[0]),
+        [[10.0, 80.0, 20.0, 90.0], [110.0, 80.0, 120.0, 90.0]]
+    );
+    assert_eq!(annotations(pages[1]), [[10.0, 180.0, 20.0, 190.0]]);
+    let outline = doc.catalog().unwrap().get(b"Outlines").unwrap();
+    let outline = doc.get_dictionary(outline.as_reference().unwrap()).unwrap();
+    let first = outline.get(b"First").unwrap().as_reference().unwrap();
+    let dest = doc.get_dictionary(first).unwrap().get(b"Dest").unwrap();
+    assert_eq!(
+        dest.as_array().unwrap()[0].as_reference().unwrap(),
+        pages[0]
+    );
+}
+
+#[test]
+fn test_flate_pages_decode_to_samples() {
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    let image = RenderedImage {
+        width: 4,
+        height: 4,
+        rgb_data: (0..48).collect(),
+        alpha: None,
+    };
+    let writer = PdfWriter::new(1.0, ImageOptions::default());
+    let page = writer.encoder().encode(&image, "page.svg").unwrap();
+    assert_eq!(page.encoding, Encoding::Flate);
+
+    let mut predicted = Vec::new();
+    ZlibDecoder::new(page.data.as_slice())
+        .read_to_end(&mut predicted)
+        .unwrap();
+    assert_eq!(
+        predictor::unfilter_rows(&predicted, 4).unwrap(),
+        image.rgb_data
+    );
+}
+
+#[test]
+fn test_image_formats() {
+    // Flat areas with a hard edge, and noise nothing can predict
+    let line_art = RenderedImage {
+        width: 64,
+        height: 64,
+        rgb_data: (0..64 * 64)
+            .flat_map(|i| {
+                if i % 64 < 20 {
+                    [0, 0, 0]
+                } else {
+                    [255, 255, 255]
+                }
+            })
+            .collect(),
+        alpha: None,
+    };
+    let mut state = 1u32;
+    let photo = RenderedImage {
+        width: 64,
+        height: 64,
+        rgb_data: (0..64 * 64 * 3)
+            .map(|i| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                ((i / 3 % 64) as u32 * 2 + (state >> 27)) as u8
+            })
+            .collect(),
+        alpha: None,
+    };
+    let images = ImageOptions {
+        format: ImageFormat::Auto,
+        overrides: vec![parse_override("scans/*=jpeg").unwrap()],
+        ..ImageOptions::default()
+    };
+    let encoder = PdfEncoder { images };
+
+    let page = encoder.encode(&line_art, "slides/a.svg").unwrap();
+    assert_eq!(page.encoding, Encoding::Flate);
+    assert!(page.reason.unwrap().starts_with("line art, 2 colors"));
+
+    let page = encoder.encode(&photo, "photo.svg").unwrap();
+    assert_eq!(page.encoding, Encoding::Jpeg);
+    assert!(page.reason.unwrap().starts_with("photographic"));
+
+    // Overrides win, even over the line-art guard, which still keeps the
+    // colors of the page
+    let page = encoder.encode(&line_art, "scans/b.svg").unwrap();
+    assert_eq!(page.encoding, Encoding::Jpeg);
+    assert!(page.reason.unwrap().starts_with("4:4:4 chroma, line art"));
+    assert!(parse_override("nope").is_err());
+    assert!(parse_override("a=gif").is_err());
+}
+
+#[test]
+fn test_bilevel_pdf_pages() {
+    let images = ImageOptions {
+        format: ImageFormat::Jpeg,
+        color_mode: ColorMode::Bilevel,
+        ..ImageOptions::default()
+    };
+    let mut writer: Box<dyn ContainerWriter> = Box::new(PdfWriter::new(1.0, images));
+    // Red is dark enough to turn black
+    let page = RenderedImage {
+        width: 9,
+        height: 2,
+        rgb_data: (0..18)
+            .flat_map(|i| if i % 9 == 8 { [255, 0, 0] } else { [255; 3] })
+            .collect(),
+        alpha: Some(vec![255; 18]),
+    };
+    let encoded = writer.encoder().encode(&page, "page.svg").unwrap();
+    assert_eq!(encoded.encoding, Encoding::Fax);
+    assert!(encoded.mask.is_none());
+    writer.add_page(encoded).unwrap();
+    // Fill pages turn black or white too
+    let fill = writer.encoder().encode_fill(9, 2, [90, 90, 200]).unwrap();
+    assert_eq!(fill.data, [0, 0, 0]);
+    writer.add_page(fill).unwrap();
+    let mut pdf = Vec::new();
+    writer.finish(&mut pdf).unwrap();
+
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    let image = doc
+        .objects
+        .values()
+        .filter_map(|object| object.as_stream().ok())
+        .find(|stream| stream.dict.get(b"Subtype").ok() == Some(&Object::Name(b"Image".to_vec())))
+        .unwrap();
+    let name = |key: &[u8]| image.dict.get(key).unwrap().as_name().unwrap();
+    assert_eq!(name(b"ColorSpace"), b"DeviceGray");
+    assert_eq!(name(b"Filter"), b"CCITTFaxDecode");
+    assert_eq!(
+        image
+            .dict
+            .get(b"BitsPerComponent")
+            .unwrap()
+            .as_i64()
+            .unwrap(),
+        1
+    );
+    let parms = image.dict.get(b"DecodeParms").unwrap().as_dict().unwrap();
+    assert_eq!(parms.get(b"K").unwrap().as_i64().unwrap(), -1);
+    assert_eq!(parms.get(b"Columns").unwrap().as_i64().unwrap(), 9);
+    let bitmap = bilevel::decode_g4(&image.content, 9, 2).unwrap();
+    assert_eq!(bitmap.data, [0, 0x80, 0, 0x80]);
+}
+
+#[test]
+fn test_jpeg_subsampling() {
+    // Small red text: 1 px strokes of 3x5 px glyphs on white
+    let (width, height) = (96, 32);
+    let mut text = RenderedImage {
+        width,
+        height,
+        rgb_data: vec![255; (width * height * 3) as usize],
+        alpha: None,
+    };
+    for glyph in 0..20 {
+        let (left, top) = (4 + (glyph % 10) * 9, 6 + (glyph / 10) * 12);
+        for (x, y) in (0..5)
+            .map(|y| (left, top + y))
+            .chain((0..3).map(|x| (left + x, top + glyph % 5)))
+        {
+            let at = ((y * width + x) * 3) as usize;
+            text.rgb_data[at..at + 3].copy_from_slice(&[220, 0, 0]);
+        }
+    }
+    let decode = |page: &EncodedPage| {
+        let pixels = jpeg_decoder::Decoder::new(page.data.as_slice())
+            .decode()
+            .unwrap();
+        // Horizontal sampling of the brightness, from the baseline frame
+        // header: its length, size and component count come first
+        let frame = page
+            .data
+            .windows(2)
+            .position(|marker| marker == [0xff, 0xc0]);
+        (pixels, page.data[frame.unwrap() + 11] >> 4)
+    };
+    // Mean error of the channels on the strokes, which smear into the white
+    // around them when colors are subsampled
+    let error = |pixels: &[u8]| {
+        let strokes = text.rgb_data.chunks(3).zip(pixels.chunks(3));
+        let (count, total) = strokes.filter(|(rendered, _)| rendered[1] == 0).fold(
+            (0, 0),
+            |(count, total), (rendered, decoded)| {
+                let diff: u32 = rendered
+                    .iter()
+                    .zip(decoded)
+                    .map(|(&a, &b)| a.abs_diff(b) as u32)
+                    .sum();
+                (count + 1, total + diff)
+            },
+        );
+        total as f64 / count as f64
+    };
+
+    let encode = |subsampling| {
+        let images = ImageOptions {
+            format: ImageFormat::Jpeg,
+            jpeg_subsampling: subsampling,
+            ..ImageOptions::default()
+        };
+        PdfEncoder { images }.encode(&text, "text.svg").unwrap()
+    };
+    let full = encode(Some(JpegSubsampling::Full));
+    let quarter = encode(Some(JpegSubsampling::Quarter));
+    let ((full_pixels, 1), (quarter_pixels, 2)) = (decode(&full), decode(&quarter)) else {
+        panic!("wrong sampling factors");
+    };
+    let (full_error, quarter_error) = (error(&full_pixels), error(&quarter_pixels));
+    assert!(
+        full_error * 3.0 < quarter_error,
+        "{full_error} against {quarter_error}"
+    );
+    assert!(full.data.len() > quarter.data.len());
+    assert_eq!(decode(&encode(Some(JpegSubsampling::Half))).1, 2);
+
+    // Left to the page, text keeps its colors and photos give them up
+    let page = encode(None);
+    assert_eq!((decode(&page).1, page.data), (1, full.data));
+    let mut state = 7u32;
+    let photo = RenderedImage {
+        width: 64,
+        height: 64,
+        rgb_data: (0..64 * 64 * 3)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 24) as u8
+            })
+            .collect(),
+        alpha: None,
+    };
+    let images = ImageOptions {
+        format: ImageFormat::Jpeg,
+        ..ImageOptions::default()
+    };
+    let page = PdfEncoder { images }.encode(&photo, "photo.svg").unwrap();
+    assert_eq!(decode(&page).1, 2);
+    assert!(page
+        .reason
+        .unwrap()
+        .starts_with("4:2:0 chroma, photographic"));
+}
+
+// cargo test --release bench_pdf_assembly -- --ignored --nocapture
+#[test]
+#[ignore]
+fn bench_pdf_assembly() {
+    use std::time::Instant;
+
+    let image = RenderedImage {
+        width: 96,
+        height: 72,
+        rgb_data: vec![255; 96 * 72 * 3],
+        alpha: None,
+    };
+    let mut writer = Box::new(PdfWriter::new(1.0, ImageOptions::default()));
+    let page = writer.encoder().encode(&image, "page.svg").unwrap();
+    let start = Instant::now();
+    for _ in 0..5000 {
+        writer.add_page(page.clone()).unwrap();
+    }
+    let added = start.elapsed();
+    let mut pdf = Vec::new();
+    let start = Instant::now();
+    writer.finish(&mut pdf).unwrap();
+    println!(
+        "5000 pages: adding {:?}, finishing {:?}, {} bytes",
+        added,
+        start.elapsed(),
+        pdf.len()
+    );
+}